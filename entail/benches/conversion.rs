@@ -0,0 +1,145 @@
+//! Benchmarks of the conversions dominating the CPU time of bulk jobs: entities to and from
+//! the Datastore API types, models to and from entities, and key construction.
+//!
+//! Run with `cargo bench -p entail`.
+
+use criterion::{BatchSize, Criterion, black_box, criterion_group, criterion_main};
+use entail::{Entail, EntityModel, ds};
+use google_datastore1::api;
+
+#[derive(Entail, Debug, Default, Clone)]
+#[entail(rename_all = "camelCase")]
+struct Order {
+    #[entail(key)]
+    id: i64,
+    #[entail]
+    customer: String,
+    #[entail]
+    status: String,
+    #[entail]
+    total_cents: i64,
+    #[entail]
+    paid: bool,
+    #[entail]
+    tags: Vec<String>,
+    #[entail(text)]
+    notes: String,
+    #[entail(unindexed)]
+    payload: Vec<u8>,
+}
+
+fn order(id: i64) -> Order {
+    Order {
+        id,
+        customer: format!("customer-{}", id % 97),
+        status: "SHIPPED".to_string(),
+        total_cents: id * 137,
+        paid: id % 2 == 0,
+        tags: vec!["express".to_string(), "gift".to_string()],
+        notes: "Leave the parcel at the reception desk.".to_string(),
+        payload: vec![7; 64],
+    }
+}
+
+fn api_page(size: i64) -> Vec<api::Entity> {
+    (1..=size)
+        .map(|id| order(id).to_ds_entity().unwrap().into())
+        .collect()
+}
+
+fn entity_conversion(c: &mut Criterion) {
+    let page = api_page(500);
+    c.bench_function("entity from api, page of 500", |b| {
+        b.iter_batched(
+            || page.clone(),
+            |page| {
+                page.into_iter()
+                    .map(|entity| ds::Entity::try_from(entity).unwrap())
+                    .collect::<Vec<ds::Entity>>()
+            },
+            BatchSize::LargeInput,
+        )
+    });
+    let mut decoder = ds::EntityDecoder::for_model::<Order>();
+    c.bench_function("entity decoder, page of 500", |b| {
+        b.iter_batched(
+            || page.clone(),
+            |page| decoder.decode(page).unwrap().len(),
+            BatchSize::LargeInput,
+        )
+    });
+    c.bench_function("models from api, page of 500", |b| {
+        b.iter_batched(
+            || page.clone(),
+            |page| decoder.decode_models::<Order>(page).unwrap().len(),
+            BatchSize::LargeInput,
+        )
+    });
+    let entities: Vec<ds::Entity> = page
+        .into_iter()
+        .map(|entity| ds::Entity::try_from(entity).unwrap())
+        .collect();
+    c.bench_function("entity into api, page of 500", |b| {
+        b.iter_batched(
+            || entities.clone(),
+            |entities| {
+                entities
+                    .into_iter()
+                    .map(Into::into)
+                    .collect::<Vec<api::Entity>>()
+            },
+            BatchSize::LargeInput,
+        )
+    });
+}
+
+fn model_conversion(c: &mut Criterion) {
+    let model = order(42);
+    let entity = model.to_ds_entity().unwrap();
+    c.bench_function("model to entity", |b| {
+        b.iter(|| black_box(&model).to_ds_entity().unwrap())
+    });
+    c.bench_function("model from entity", |b| {
+        b.iter(|| Order::from_ds_entity(black_box(&entity)).unwrap())
+    });
+}
+
+fn key_construction(c: &mut Criterion) {
+    c.bench_function("key with parent", |b| {
+        b.iter(|| {
+            ds::Key::new("Order")
+                .with_id(black_box(42))
+                .with_parent(ds::Key::new("Customer").with_name(black_box("customer-42")))
+        })
+    });
+    let key: api::Key = ds::Key::new("Order")
+        .with_id(42)
+        .with_parent(ds::Key::new("Customer").with_name("customer-42"))
+        .into();
+    c.bench_function("key from api", |b| {
+        b.iter_batched(
+            || key.clone(),
+            |key| ds::Key::try_from(key).unwrap(),
+            BatchSize::SmallInput,
+        )
+    });
+    let parent = ds::Key::new("Customer")
+        .with_name("customer-42")
+        .with_namespace("tenant");
+    c.bench_function("key clone", |b| b.iter(|| black_box(&parent).clone()));
+    c.bench_function("keys with a shared parent", |b| {
+        b.iter(|| {
+            ds::Key::new("Order")
+                .with_id(black_box(42))
+                .with_parent(parent.clone())
+        })
+    });
+}
+
+criterion_group!(
+    benches,
+    entity_conversion,
+    model_conversion,
+    key_construction
+);
+criterion_main!(benches);
@@ -0,0 +1,47 @@
+use std::borrow::Cow;
+
+use crate::{EntailError, EntailErrorKind, EntityModel};
+
+/// The properties of a model returned by [`crate::EntityAdapter::fetch_selected`] and
+/// [`crate::EntityAdapter::query_selected`], like the field selection of GraphQL.
+///
+/// The selected properties are validated against the model (see
+/// [`EntityModel::has_property`]) before anything is fetched, so a typo is reported instead of
+/// silently returning entities without the property.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct FetchOptions {
+    /// The names of the selected properties. If empty, the whole entities are fetched.
+    pub properties: Vec<Cow<'static, str>>,
+}
+
+impl FetchOptions {
+    /// Creates options selecting the whole entities.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Creates options selecting the given properties.
+    ///
+    /// ## Parameters
+    /// - `properties`: The property names, e.g. `&[User::NAME, User::EMAIL]`.
+    pub fn select(properties: &[&'static str]) -> Self {
+        Self {
+            properties: properties.iter().map(|&name| name.into()).collect(),
+        }
+    }
+
+    /// Checks that every selected property belongs to the model `T`.
+    ///
+    /// ## Returns
+    /// A [`Result`] indicating success, or an [`EntailError`] of the kind
+    /// [`EntailErrorKind::PropertyMappingError`] naming the first unknown property.
+    pub fn validate<T: EntityModel>(&self) -> Result<(), EntailError> {
+        match self.properties.iter().find(|name| !T::has_property(name)) {
+            None => Ok(()),
+            Some(name) => Err(EntailError::simple(
+                EntailErrorKind::PropertyMappingError,
+                format!("{} has no property {:?} to select", T::KIND, name),
+            )),
+        }
+    }
+}
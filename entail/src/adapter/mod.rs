@@ -1,13 +1,19 @@
+mod fetch_options;
 mod model_update;
+mod projection;
+mod typed_query;
 
 use std::borrow::{Borrow, Cow};
 use std::collections::HashMap;
 use std::marker::PhantomData;
 
 use crate::ds;
-use crate::{EntailError, EntailErrorKind, EntityModel};
+use crate::{EntailError, EntailErrorKind, EntityModel, KeyStrategy};
 
+pub use fetch_options::*;
 pub use model_update::*;
+pub use projection::*;
+pub use typed_query::*;
 
 /// The `EntityAdapter` provides model-specific utility methods for interacting
 /// with the Datastore kind of its type.
@@ -26,6 +32,10 @@ impl<T> EntityAdapter<T>
 where
     T: EntityModel,
 {
+    /// The maximum number of keys deleted by a single commit in [`Self::delete_all_models`],
+    /// which is the limit of mutations in a Datastore commit.
+    pub const DELETE_CHUNK_SIZE: usize = 500;
+
     /// Creates a new `EntityAdapter` instance.
     ///
     /// This is an internal constant function used by the `#[derive(Entail)]`
@@ -97,12 +107,15 @@ where
     /// The resulting Key contains only the **Kind** component, which is derived
     /// from the struct name or the `#[entail(name = "...")]` attribute.
     /// This is typically used as a base for creating complete Keys with
-    /// `with_name()` or `with_id()`.
+    /// `with_name()` or `with_id()`. The Key is in the namespace of the model, if it has one.
     ///
     /// ## Returns
     /// A new, incomplete [`ds::Key`] instance for the model's Kind.
     pub fn create_key(&self) -> ds::Key {
-        ds::Key::new(self.kind)
+        match T::namespace() {
+            None => ds::Key::new(self.kind),
+            Some(namespace) => ds::Key::new(self.kind).with_namespace(namespace),
+        }
     }
 
     /// Checks if the Kind of the provided object matches the Kind associated with this adapter.
@@ -125,12 +138,11 @@ where
     /// queries (e.g., adding filters, limits, and orders) that target this model.
     ///
     /// ## Returns
-    /// A [`ds::Query`] object pre-configured with the model's Kind.
+    /// A [`ds::Query`] object pre-configured with the model's Kind (and namespace, if any).
     pub fn query(&self) -> ds::Query {
-        ds::Query {
-            kind: self.kind.into(),
-            ..ds::Query::default()
-        }
+        let mut query = ds::Query::new(self.kind);
+        query.namespace = T::namespace().map(Cow::Borrowed);
+        query
     }
 
     /// Fetches a single entity from Datastore using the provided **Key** and
@@ -165,6 +177,40 @@ where
             .and_then(|e| T::from_ds_entity(&e))
     }
 
+    /// Fetches a single entity like [`Self::fetch_single`], retrying failed lookups
+    /// according to the given [`ds::RetryPolicy`].
+    ///
+    /// A missing entity or a failed deserialization is never retried, only the lookup itself.
+    ///
+    /// ## Parameters
+    /// - `ds`: A reference to the Datastore client shell.
+    /// - `key`: The complete [`ds::Key`] of the entity to fetch.
+    /// - `policy`: The retry policy to apply to this call.
+    ///
+    /// ## Returns
+    /// A [`Result`] containing the populated struct instance **T** on success,
+    /// or an [`EntailError`] if the entity is not found, the lookup failed even after
+    /// retrying, or the deserialization via [`EntityModel::from_ds_entity`] fails.
+    pub async fn fetch_single_with_policy(
+        &self,
+        ds: &ds::DatastoreShell,
+        key: ds::Key,
+        policy: &ds::RetryPolicy,
+    ) -> Result<T, EntailError> {
+        let key_string = key.to_string();
+        policy
+            .run(|| ds.get_single(key.clone()))
+            .await
+            .transpose()
+            .unwrap_or_else(|| {
+                Err(EntailError::simple(
+                    crate::EntailErrorKind::RequiredEntityNotFound,
+                    format!("Required {} not found", key_string),
+                ))
+            })
+            .and_then(|e| T::from_ds_entity(&e))
+    }
+
     /// Fetches a batch of entities from Datastore using the provided `keys` and
     /// automatically maps the results to instances of the Rust struct `T`.
     ///
@@ -205,10 +251,88 @@ where
         Ok(map)
     }
 
+    /// Fetches a batch of entities like [`Self::fetch_all`], retrying failed lookups
+    /// according to the given [`ds::RetryPolicy`].
+    ///
+    /// ## Parameters
+    /// - `ds`: A reference to the Datastore client shell.
+    /// - `keys`: A collection of complete [`ds::Key`]s (or references to them) to fetch.
+    /// - `policy`: The retry policy to apply to this call.
+    ///
+    /// ## Returns
+    /// A [`Result`] containing a `HashMap<ds::Key, T>` on success, or an [`EntailError`]
+    /// if the batch fetch fails even after retrying or if any *found* entity fails the
+    /// deserialization process via [`EntityModel::from_ds_entity`].
+    pub async fn fetch_all_with_policy<I>(
+        &self,
+        ds: &ds::DatastoreShell,
+        keys: I,
+        policy: &ds::RetryPolicy,
+    ) -> Result<HashMap<ds::Key, T>, EntailError>
+    where
+        I: IntoIterator,
+        I::Item: Borrow<ds::Key>,
+    {
+        let keys: Vec<ds::Key> = keys.into_iter().map(|key| key.borrow().clone()).collect();
+        let result = policy.run(|| ds.get_all(&keys)).await?;
+        let mut map = HashMap::with_capacity(result.len());
+        for entity in result.into_iter() {
+            let model = T::from_ds_entity(&entity)?;
+            let key = entity.just_key();
+            map.insert(key, model);
+        }
+        Ok(map)
+    }
+
+    /// Applies the defaults of the adapter to a query: the Kind of the model if the query is
+    /// kindless, the namespace of the model if the query has none, and the
+    /// [default order](EntityModel::DEFAULT_ORDER) if the query has no orders. If the model
+    /// has a [soft delete flag](EntityModel::SOFT_DELETE_PROPERTY), the query is restricted to
+    /// the entities where it is `false`.
+    ///
+    /// This is the single preparation step of the queries run by the adapter
+    /// ([`Self::fetch_query`], [`Self::fetch_projection`], [`Self::query_selected`]), so the
+    /// policies of the model apply to queries built directly as a [`ds::Query`] as well. Use
+    /// [`Self::raw_query`] to run a query exactly as it is.
+    ///
+    /// ## Parameters
+    /// - `query`: The query to prepare.
+    ///
+    /// ## Returns
+    /// The query with the defaults filled in, or an [`EntailError`] of the kind
+    /// [`EntailErrorKind::EntityKindMismatch`] if the query targets another Kind.
+    pub fn prepare_query(&self, mut query: ds::Query) -> Result<ds::Query, EntailError> {
+        if query.kind.is_empty() {
+            query.kind = self.kind.into();
+        } else if query.kind != self.kind {
+            return Err(EntailError::simple(
+                EntailErrorKind::EntityKindMismatch,
+                format!(
+                    "Expected a query of the Kind {}, but got {}",
+                    self.kind, query.kind
+                ),
+            ));
+        }
+        if query.namespace.is_none() {
+            query.namespace = T::namespace().map(Cow::Borrowed);
+        }
+        if query.order.is_empty() {
+            query.order = T::DEFAULT_ORDER
+                .iter()
+                .map(|&(name, direction)| ds::PropertyOrder::new(name, direction))
+                .collect();
+        }
+        if let Some(property) = T::SOFT_DELETE_PROPERTY {
+            query = query.and_filter(ds::FilterOperator::Equal.of(property, false));
+        }
+        Ok(query)
+    }
+
     /// Executes a Datastore query and automatically maps all resulting entities to the struct `T`.
     ///
-    /// This function performs the query execution and then uses the `consume_entity`
-    /// function to map every fetched entity to the model type `T`.
+    /// This function prepares the query with [`Self::prepare_query`], performs the query
+    /// execution and then uses the `consume_entity` function to map every fetched entity to
+    /// the model type `T`.
     ///
     /// ## Parameters
     /// - `ds`: A reference to the Datastore client shell.
@@ -221,12 +345,138 @@ where
         &self,
         ds: &ds::DatastoreShell,
         query: ds::Query,
+    ) -> Result<ds::QueryResult<T>, EntailError> {
+        self.raw_query(ds, self.prepare_query(query)?).await
+    }
+
+    /// Executes a Datastore query exactly as it is, without the defaults of
+    /// [`Self::prepare_query`], and maps all resulting entities to the struct `T`.
+    ///
+    /// ## Parameters
+    /// - `ds`: A reference to the Datastore client shell.
+    /// - `query`: The complete [`ds::Query`] definition to execute.
+    ///
+    /// ## Returns
+    /// A [`Result`] containing a [`ds::QueryResult`] where the entities are instances of `T`,
+    /// or an [`EntailError`] if the query fails or any entity mapping fails.
+    pub async fn raw_query(
+        &self,
+        ds: &ds::DatastoreShell,
+        query: ds::Query,
     ) -> Result<ds::QueryResult<T>, EntailError> {
         ds.run_query(query)
             .await
             .and_then(|query_result| query_result.try_map(Self::consume_entity))
     }
 
+    /// Executes a Datastore query as a projection query and maps the results to the
+    /// projection `P` of the model.
+    ///
+    /// The projection of the query is replaced by the properties of `P`, so the query only
+    /// needs the filters, the orders and the limit. The query is prepared with
+    /// [`Self::prepare_query`]. The projected properties have to be
+    /// indexed, and a composite index is needed if there is more than one, or if the query
+    /// has filters or orders on other properties.
+    ///
+    /// ## Parameters
+    /// - `ds`: A reference to the Datastore client shell.
+    /// - `query`: The [`ds::Query`] to execute, see [`ModelProjection::query`].
+    ///
+    /// ## Returns
+    /// A [`Result`] containing a [`ds::QueryResult`] of the projections, or an
+    /// [`EntailError`] if the query fails or any entity mapping fails.
+    pub async fn fetch_projection<P>(
+        &self,
+        ds: &ds::DatastoreShell,
+        mut query: ds::Query,
+    ) -> Result<ds::QueryResult<P>, EntailError>
+    where
+        P: ModelProjection<Model = T>,
+    {
+        query.projection = P::projection();
+        ds.run_query(self.prepare_query(query)?)
+            .await
+            .and_then(|query_result| query_result.try_map(|e| P::from_projected(&e)))
+    }
+
+    /// Fetches the selected properties of a batch of entities, e.g. for an endpoint that lets
+    /// the client choose the returned fields.
+    ///
+    /// The lookup has a property mask, so only the selected properties are transferred. The
+    /// results are entities (and not models), as they lack the other properties of the model.
+    ///
+    /// ## Parameters
+    /// - `ds`: A reference to the Datastore client shell.
+    /// - `keys`: An iterable collection of [`ds::Key`]s or references to them.
+    /// - `options`: The selected properties, the whole entities are fetched if there are none.
+    ///
+    /// ## Returns
+    /// A [`Result`] containing the entities that were found (in no particular order), or an
+    /// [`EntailError`] if a selected property is not a property of the model or the lookup
+    /// fails.
+    pub async fn fetch_selected<I>(
+        &self,
+        ds: &ds::DatastoreShell,
+        keys: I,
+        options: &FetchOptions,
+    ) -> Result<Vec<ds::Entity>, EntailError>
+    where
+        I: IntoIterator,
+        I::Item: Borrow<ds::Key>,
+    {
+        options.validate::<T>()?;
+        if options.properties.is_empty() {
+            ds.get_all(keys).await
+        } else {
+            ds.get_all_selected(keys, &options.properties).await
+        }
+    }
+
+    /// Runs a query (prepared with [`Self::prepare_query`]) returning the selected properties
+    /// of the entities, e.g. for a list endpoint that lets the client choose the returned
+    /// fields.
+    ///
+    /// Outside of a transaction the query is run as a projection query, which reads the
+    /// indexes only: the selected properties have to be indexed (with a composite index if
+    /// there are several, or the query has filters or orders), and an entity is returned for
+    /// every value of an array property. A transactional shell reads the entities with a
+    /// property mask instead, so the results are the entities read by the transaction.
+    ///
+    /// ## Parameters
+    /// - `ds`: A reference to the Datastore client shell.
+    /// - `query`: The [`ds::Query`] to execute, its projection is replaced.
+    /// - `options`: The selected properties, the whole entities are fetched if there are none.
+    ///
+    /// ## Returns
+    /// A [`Result`] containing a [`ds::QueryResult`] of the entities, or an [`EntailError`] if
+    /// a selected property is not a property of the model or the query fails.
+    pub async fn query_selected(
+        &self,
+        ds: &ds::DatastoreShell,
+        query: ds::Query,
+        options: &FetchOptions,
+    ) -> Result<ds::QueryResult<ds::Entity>, EntailError> {
+        options.validate::<T>()?;
+        let mut query = self.prepare_query(query)?;
+        if options.properties.is_empty() {
+            return ds.run_query(query).await;
+        }
+        if ds.transaction.is_some() {
+            return ds.run_query_selected(query, &options.properties).await;
+        }
+        // the key is always part of the results of a projection
+        query.projection = options
+            .properties
+            .iter()
+            .filter(|name| *name != ds::KEY_PROPERTY)
+            .cloned()
+            .collect();
+        if query.projection.is_empty() {
+            query.projection = vec![ds::KEY_PROPERTY.into()];
+        }
+        ds.run_query(query).await
+    }
+
     /// Fetches a single entity by key and wraps it in a [`ModeledUpdate`] for partial updates.
     ///
     /// This is a convenience method that combines a Datastore lookup with model deserialization. 
@@ -319,4 +569,377 @@ where
                 ))
             })
     }
+
+    /// Checks whether the checksum stored in an entity matches its modeled properties.
+    ///
+    /// The entity is converted to the model and back, and the checksum of the result is
+    /// compared to the one stored in the [`EntityModel::CHECKSUM_PROPERTY`]. A mismatch means
+    /// that a modeled property was modified without going through the model (or the data is
+    /// corrupt).
+    ///
+    /// ## Parameters
+    /// - `entity`: The Datastore entity to verify.
+    ///
+    /// ## Returns
+    /// A [`Result`] containing `None` if the model has no `#[entail(checksum)]` field or the
+    /// entity has no stored checksum, otherwise `Some(true)` if the checksums match. Returns
+    /// an [`EntailError`] if the entity cannot be mapped to the model.
+    pub fn verify_checksum(&self, entity: &ds::Entity) -> Result<Option<bool>, EntailError> {
+        let Some(property) = T::CHECKSUM_PROPERTY else {
+            return Ok(None);
+        };
+        let Some(stored) = entity.get_value(property).and_then(|v| match v {
+            ds::Value::Integer(checksum) => Some(*checksum),
+            _ => None,
+        }) else {
+            return Ok(None);
+        };
+        let recomputed = T::from_ds_entity(entity)?.to_ds_entity()?;
+        Ok(Some(recomputed.get_value(property) == Some(&ds::Value::integer(stored))))
+    }
+
+    /// Upserts the model unless the stored entity already has the same checksum.
+    ///
+    /// The stored entity is looked up first, and if its checksum equals the checksum of the
+    /// model, the write (including all its index updates) is skipped. Models without an
+    /// `#[entail(checksum)]` field and models with incomplete keys are always written.
+    ///
+    /// Note that the lookup and the write are not atomic, run this in a transaction if
+    /// concurrent modifications are possible.
+    ///
+    /// ## Parameters
+    /// - `ds`: A reference to the Datastore client shell.
+    /// - `model`: The model to write.
+    ///
+    /// ## Returns
+    /// A [`Result`] containing `true` if the entity was written, `false` if the write was
+    /// skipped, or an [`EntailError`] if the lookup, the serialization or the commit failed.
+    pub async fn upsert_if_changed(
+        &self,
+        ds: &ds::DatastoreShell,
+        model: &T,
+    ) -> Result<bool, EntailError> {
+        let entity = model.to_ds_entity()?;
+        if let Some(property) = T::CHECKSUM_PROPERTY.filter(|_| entity.key().is_complete()) {
+            let stored = ds.get_single(entity.key().clone()).await?;
+            if stored.is_some_and(|e| e.get_value(property) == entity.get_value(property)) {
+                return Ok(false);
+            }
+        }
+        ds.commit(ds::MutationBatch::new().upsert(entity)).await?;
+        Ok(true)
+    }
+
+    /// Converts the model to an entity, completing an empty key according to the
+    /// [`EntityModel::KEY_STRATEGY`] of the model.
+    ///
+    /// With [`KeyStrategy::Allocate`], an incomplete key is completed with an ID allocated by
+    /// Datastore, and with [`KeyStrategy::Uuid`], the name generated by
+    /// [`EntityModel::to_ds_entity`] is kept. In both cases the key is written back into the
+    /// model, so the model and the entity have the same key. With [`KeyStrategy::Manual`] the
+    /// entity is returned as it is.
+    ///
+    /// ## Parameters
+    /// - `ds`: A reference to the Datastore client shell.
+    /// - `model`: The model to convert, its key field is updated.
+    ///
+    /// ## Returns
+    /// A [`Result`] containing the entity, or an [`EntailError`] if the serialization or the
+    /// allocation failed.
+    pub async fn prepare_entity(
+        &self,
+        ds: &ds::DatastoreShell,
+        model: &mut T,
+    ) -> Result<ds::Entity, EntailError> {
+        let entity = model.to_ds_entity()?;
+        match T::KEY_STRATEGY {
+            KeyStrategy::Uuid if entity.key().is_complete() => {
+                model.assign_key(entity.key())?;
+                Ok(entity)
+            }
+            KeyStrategy::Allocate if !entity.key().is_complete() => {
+                let key = ds
+                    .allocate_ids([entity.key()])
+                    .await?
+                    .pop()
+                    .ok_or_else(|| {
+                        EntailError::simple(
+                            EntailErrorKind::RequestFailure,
+                            format!("No ID was allocated for {}", entity.key()),
+                        )
+                    })?;
+                model.assign_key(&key)?;
+                model.to_ds_entity()
+            }
+            _ => Ok(entity),
+        }
+    }
+
+    /// Inserts the model as a new entity, completing an empty key first (see
+    /// [`Self::prepare_entity`]).
+    ///
+    /// A key that is still incomplete (with [`KeyStrategy::Manual`]) is completed by the
+    /// commit, and the allocated key is written back into the model as well. If `ds` is part
+    /// of a transaction, the insert commits it.
+    ///
+    /// ## Parameters
+    /// - `ds`: A reference to the Datastore client shell.
+    /// - `model`: The model to insert, its key field is updated.
+    ///
+    /// ## Returns
+    /// A [`Result`] containing the key of the inserted entity, or an [`EntailError`] if the
+    /// serialization, the allocation or the commit failed (e.g. the entity already exists).
+    pub async fn insert_model(
+        &self,
+        ds: &ds::DatastoreShell,
+        model: &mut T,
+    ) -> Result<ds::Key, EntailError> {
+        self.commit_model(ds, model, ds::MutationBatch::insert).await
+    }
+
+    /// Writes the model, inserting or overwriting its entity, after completing an empty key
+    /// (see [`Self::prepare_entity`]).
+    ///
+    /// Like with [`Self::insert_model`], a key completed by the commit is written back into
+    /// the model. If `ds` is part of a transaction, the write commits it.
+    ///
+    /// ## Parameters
+    /// - `ds`: A reference to the Datastore client shell.
+    /// - `model`: The model to write, its key field is updated.
+    ///
+    /// ## Returns
+    /// A [`Result`] containing the key of the written entity, or an [`EntailError`] if the
+    /// serialization, the allocation or the commit failed.
+    pub async fn upsert_model(
+        &self,
+        ds: &ds::DatastoreShell,
+        model: &mut T,
+    ) -> Result<ds::Key, EntailError> {
+        self.commit_model(ds, model, ds::MutationBatch::upsert).await
+    }
+
+    async fn commit_model(
+        &self,
+        ds: &ds::DatastoreShell,
+        model: &mut T,
+        mutation: fn(ds::MutationBatch, ds::Entity) -> ds::MutationBatch,
+    ) -> Result<ds::Key, EntailError> {
+        let entity = self.prepare_entity(ds, model).await?;
+        let key = entity.key().clone();
+        let response = ds.commit(mutation(ds::MutationBatch::new(), entity)).await?;
+        match response.mutation_results.into_iter().next().and_then(|r| r.key) {
+            Some(allocated) => {
+                model.assign_key(&allocated)?;
+                Ok(allocated)
+            }
+            None => Ok(key),
+        }
+    }
+
+    /// Deletes the entity of the model. The key is built with [`EntityModel::to_ds_entity`],
+    /// including the parent. If `ds` is part of a transaction, the delete commits it.
+    ///
+    /// ## Parameters
+    /// - `ds`: A reference to the Datastore client shell.
+    /// - `model`: The model to delete.
+    ///
+    /// ## Returns
+    /// A [`Result`] indicating success (deleting a missing entity succeeds too), or an
+    /// [`EntailError`] if the serialization or the commit failed.
+    pub async fn delete_model(
+        &self,
+        ds: &ds::DatastoreShell,
+        model: &T,
+    ) -> Result<(), EntailError> {
+        let key = model.to_ds_entity()?.just_key();
+        ds.commit(ds::MutationBatch::new().delete(key)).await?;
+        Ok(())
+    }
+
+    /// Writes the model only if the stored entity still has the version of the model.
+    ///
+    /// The model must have an `#[entail(version)]` field. The stored entity is looked up and
+    /// its version compared to the version of the model (a missing entity or property counts
+    /// as version `0`), then the model is written with the version incremented by one. The
+    /// lookup and the write run in a new [`ds::Transaction`], which is retried if it is
+    /// aborted by a concurrent write. To update a model as part of an existing transaction,
+    /// use [`Self::add_versioned_update`] instead.
+    ///
+    /// ## Parameters
+    /// - `ds`: A reference to the Datastore client shell, which must not be part of a
+    ///   transaction.
+    /// - `model`: The model to write, with the version it was read with.
+    ///
+    /// ## Returns
+    /// A [`Result`] containing the new version, or an [`EntailError`] of the kind
+    /// [`EntailErrorKind::VersionConflict`] if the stored version differs, or of the kind
+    /// [`EntailErrorKind::TransactionNotAllowed`] if `ds` is part of a transaction. Updating
+    /// the model with the new version is the responsibility of the caller.
+    pub async fn update_versioned(
+        &self,
+        ds: &ds::DatastoreShell,
+        model: &T,
+    ) -> Result<i64, EntailError> {
+        if ds.transaction.is_some() {
+            return Err(EntailError::simple(
+                EntailErrorKind::TransactionNotAllowed,
+                "update_versioned runs its own transaction, use add_versioned_update in a \
+                 transaction",
+            ));
+        }
+        let property = self.version_property()?;
+        let entity = model.to_ds_entity()?;
+        ds::Transaction::new(ds)
+            .run(|ts| {
+                let entity = entity.clone();
+                async move {
+                    let version = check_version(&ts, &entity, property).await?;
+                    ts.commit(ds::MutationBatch::new().upsert(entity)).await?;
+                    Ok(version)
+                }
+            })
+            .await
+    }
+
+    /// Checks the version of the model like [`Self::update_versioned`] does, and adds the
+    /// write of the model to a batch the caller commits.
+    ///
+    /// `ds` should be part of a transaction committing the batch, so the stored entity can't
+    /// change between the check and the write. If the commit of the transaction is aborted,
+    /// the check has to be repeated on the next attempt, which [`ds::Transaction::run`] and
+    /// [`ds::Transaction::run_mutations`] do by running their closure again.
+    ///
+    /// ## Parameters
+    /// - `ds`: A reference to the Datastore client shell, usually a transactional one.
+    /// - `model`: The model to write, with the version it was read with.
+    /// - `batch`: The batch to add the write of the model to.
+    ///
+    /// ## Returns
+    /// A [`Result`] containing the new version and the batch with the write, or an
+    /// [`EntailError`] of the kind [`EntailErrorKind::VersionConflict`] if the stored version
+    /// differs.
+    pub async fn add_versioned_update(
+        &self,
+        ds: &ds::DatastoreShell,
+        model: &T,
+        batch: ds::MutationBatch,
+    ) -> Result<(i64, ds::MutationBatch), EntailError> {
+        let property = self.version_property()?;
+        let entity = model.to_ds_entity()?;
+        let version = check_version(ds, &entity, property).await?;
+        Ok((version, batch.upsert(entity)))
+    }
+
+    fn version_property(&self) -> Result<&'static str, EntailError> {
+        T::VERSION_PROPERTY.ok_or_else(|| {
+            EntailError::simple(
+                EntailErrorKind::PropertyMappingError,
+                format!("{} has no #[entail(version)] field", self.kind),
+            )
+        })
+    }
+
+    /// Deletes the entities of a collection of models, committing the deletes in chunks of at
+    /// most [`Self::DELETE_CHUNK_SIZE`] keys.
+    ///
+    /// The keys are built with [`EntityModel::to_ds_entity`], so they are the same keys the
+    /// models are stored with, including their parents. All keys are extracted before anything
+    /// is deleted, so a model that cannot be converted fails the whole call. The chunks are
+    /// committed one after the other and a failing chunk does not stop the remaining ones;
+    /// the caller can inspect (and retry) the failed chunks using the returned keys.
+    ///
+    /// If `ds` is part of a transaction, every delete is committed in a single chunk, since a
+    /// transaction can only be committed once.
+    ///
+    /// ## Parameters
+    /// - `ds`: A reference to the Datastore client shell.
+    /// - `models`: The models to delete, or references to them.
+    ///
+    /// ## Returns
+    /// A [`Result`] containing a [`DeleteChunk`] for every commit in order, or an
+    /// [`EntailError`] if a model cannot be converted to an entity.
+    pub async fn delete_all_models<I>(
+        &self,
+        ds: &ds::DatastoreShell,
+        models: I,
+    ) -> Result<Vec<DeleteChunk>, EntailError>
+    where
+        I: IntoIterator,
+        I::Item: Borrow<T>,
+    {
+        let mut keys = Vec::new();
+        for model in models {
+            keys.push(model.borrow().to_ds_entity()?.just_key());
+        }
+        let chunk_size = match ds.transaction {
+            Some(_) => keys.len().max(1),
+            None => Self::DELETE_CHUNK_SIZE,
+        };
+        let mut chunks = Vec::with_capacity(keys.len().div_ceil(chunk_size));
+        for chunk in keys.chunks(chunk_size) {
+            let batch = chunk
+                .iter()
+                .fold(ds::MutationBatch::new(), |batch, key| batch.delete(key.clone()));
+            chunks.push(DeleteChunk {
+                keys: chunk.to_vec(),
+                result: ds.commit(batch).await,
+            });
+        }
+        Ok(chunks)
+    }
+}
+
+/// The outcome of deleting one chunk of keys with [`EntityAdapter::delete_all_models`].
+#[derive(Debug)]
+pub struct DeleteChunk {
+    /// The keys that were deleted (or failed to be deleted) by a single commit.
+    pub keys: Vec<ds::Key>,
+    /// The response of the commit, or the error it failed with.
+    pub result: Result<ds::MutationResponse, EntailError>,
+}
+
+impl DeleteChunk {
+    /// Returns `true` if the commit of the chunk succeeded.
+    pub fn is_ok(&self) -> bool {
+        self.result.is_ok()
+    }
+}
+
+/// Returns the version stored in an entity, a missing entity or property counts as `0`.
+/// Checks that the stored entity has the version preceding the one of the entity to write.
+///
+/// ## Returns
+/// The version of the entity to write, or an [`EntailErrorKind::VersionConflict`] error.
+async fn check_version(
+    ds: &ds::DatastoreShell,
+    entity: &ds::Entity,
+    property: &str,
+) -> Result<i64, EntailError> {
+    let version = version_of(Some(entity), property);
+    let stored = if entity.key().is_complete() {
+        ds.get_single(entity.key().clone()).await?
+    } else {
+        None
+    };
+    let stored_version = version_of(stored.as_ref(), property);
+    if stored_version == version - 1 {
+        Ok(version)
+    } else {
+        Err(EntailError::simple(
+            EntailErrorKind::VersionConflict,
+            format!(
+                "Expected version {} of {}, but found {}",
+                version - 1,
+                entity.key(),
+                stored_version
+            ),
+        ))
+    }
+}
+
+fn version_of(entity: Option<&ds::Entity>, property: &str) -> i64 {
+    match entity.and_then(|e| e.get_value(property)) {
+        Some(ds::Value::Integer(version)) => *version,
+        _ => 0,
+    }
 }
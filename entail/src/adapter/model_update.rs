@@ -1,4 +1,5 @@
-use crate::{EntailError, EntityModel, ds::Entity};
+use crate::ds::{Entity, MergeStrategy};
+use crate::{EntailError, EntityModel};
 
 /// A container that synchronizes a Rust model with its underlying Datastore [`Entity`].
 ///
@@ -37,7 +38,20 @@ impl<T: EntityModel> ModeledUpdate<T> {
     /// A [`Result`] containing a reference to the updated [`Entity`] ready for commit, 
     /// or an [`EntailError`] if serialization fails.
     pub fn update_entity(&mut self) -> Result<&Entity, EntailError> {
-        self.entity.consume_properties_from(self.model.to_ds_entity()?);
+        self.update_entity_with(MergeStrategy::OverwriteAll)
+    }
+
+    /// Synchronizes the internal `entity` with the current state of the `model`, resolving
+    /// the properties of the model with a [`MergeStrategy`].
+    ///
+    /// For example, [`MergeStrategy::OverwriteNonNull`] keeps the stored value of a property
+    /// whose field is `None` in the model.
+    ///
+    /// ## Returns
+    /// A [`Result`] containing a reference to the updated [`Entity`] ready for commit,
+    /// or an [`EntailError`] if serialization fails.
+    pub fn update_entity_with(&mut self, strategy: MergeStrategy) -> Result<&Entity, EntailError> {
+        self.entity.merge(self.model.to_ds_entity()?, strategy);
         Ok(&self.entity)
     }
 
@@ -54,4 +68,45 @@ impl<T: EntityModel> ModeledUpdate<T> {
         self.update_entity()?;
         Ok(self.entity)
     }
+
+    /// Applies a partial update to the entity and re-reads the model from it.
+    ///
+    /// Unlike [`ModelPatch::apply_to`], this keeps the checksum and the version of the model
+    /// consistent, because they are computed from the model by [`Self::update_entity`].
+    ///
+    /// ## Returns
+    /// A [`Result`] indicating success, or an [`EntailError`] if a value of the patch cannot
+    /// be converted or the patched entity cannot be mapped to the model.
+    pub fn apply_patch<P>(&mut self, patch: &P) -> Result<(), EntailError>
+    where
+        P: ModelPatch<Model = T>,
+    {
+        patch.apply_to(&mut self.entity)?;
+        self.model = T::from_ds_entity(&self.entity)?;
+        Ok(())
+    }
+}
+
+/// A partial update of a model, generated by `#[derive(Entail)]` as the `{Model}Patch` struct.
+///
+/// The patch has an `Option` of every property of the model (the key, the parent, the checksum
+/// and the version excluded), and only the fields that are `Some` are written.
+pub trait ModelPatch {
+    /// The model the patch belongs to.
+    type Model: EntityModel;
+
+    /// Sets the properties of the fields that are `Some` on the entity, with the same
+    /// conversion, indexing and validation (`#[entail(validate = ...)]`) as the model. Other
+    /// properties are left untouched, and so is the entity if a new value is invalid.
+    ///
+    /// The checksum and the version of the model are not updated, use
+    /// [`ModeledUpdate::apply_patch`] for models that have them.
+    ///
+    /// ## Returns
+    /// A [`Result`] indicating success, or an [`EntailError`] if a value cannot be converted,
+    /// of the kind [`crate::EntailErrorKind::ValidationFailed`] if it is invalid.
+    fn apply_to(&self, entity: &mut Entity) -> Result<(), EntailError>;
+
+    /// Returns `true` if no field is set, i.e. applying the patch changes nothing.
+    fn is_empty(&self) -> bool;
 }
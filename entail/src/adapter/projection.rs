@@ -0,0 +1,50 @@
+use std::borrow::Cow;
+
+use crate::{EntailError, EntityModel, ds};
+
+/// A struct holding some of the properties of a model, read from the results of a projection
+/// query. It is implemented by `#[derive(Entail)]` for structs marked with
+/// `#[entail(projection_of = "Model")]`.
+///
+/// The entities returned by a projection query only have the projected properties, so they
+/// cannot be mapped to the full model. The fields of a projection have the names of the model
+/// fields they are read from, and the property names (with the renaming of the model) come from
+/// the property constants of the model.
+pub trait ModelProjection: Sized {
+    /// The model the projection reads the properties of.
+    type Model: EntityModel;
+
+    /// The names of the projected properties. The key is always part of the results, so it
+    /// is not listed.
+    const PROPERTIES: &'static [&'static str];
+
+    /// Converts an entity returned by a projection query.
+    ///
+    /// ## Parameters
+    /// - `e`: A reference to the projected entity.
+    ///
+    /// ## Returns
+    /// A [`Result`] containing the populated struct instance or an [`EntailError`] if the
+    /// Kind is not the one of the model, or a property cannot be mapped.
+    fn from_projected(e: &ds::Entity) -> Result<Self, EntailError>;
+
+    /// Returns the projection of a query: the projected properties, or only the key if
+    /// there are none.
+    fn projection() -> Vec<Cow<'static, str>> {
+        if Self::PROPERTIES.is_empty() {
+            vec![ds::KEY_PROPERTY.into()]
+        } else {
+            Self::PROPERTIES.iter().map(|&name| name.into()).collect()
+        }
+    }
+
+    /// Creates a query of the Kind of the model with the projection filled in.
+    fn query() -> ds::Query {
+        ds::Query {
+            kind: Self::Model::KIND.into(),
+            projection: Self::projection(),
+            namespace: Self::Model::namespace().map(Cow::Borrowed),
+            ..Default::default()
+        }
+    }
+}
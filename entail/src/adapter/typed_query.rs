@@ -0,0 +1,147 @@
+use std::marker::PhantomData;
+
+use crate::{EntailError, EntityModel, IntoValue, ds};
+
+/// A property of the model `M` whose values are of the type `V`.
+///
+/// The `#[derive(Entail)]` macro generates a constant of this type for every modeled field in
+/// the `{Model}Fields` struct, holding the property name after renaming. For array fields
+/// `V` is the type of the elements, since filters match the individual elements.
+pub struct TypedProperty<M, V> {
+    name: &'static str,
+    _marker: PhantomData<fn() -> (M, V)>,
+}
+
+impl<M, V> TypedProperty<M, V> {
+    /// Creates a new property descriptor. This is used by the `#[derive(Entail)]` macro.
+    pub const fn new(name: &'static str) -> Self {
+        Self {
+            name,
+            _marker: PhantomData,
+        }
+    }
+
+    /// Returns the name of the Datastore property.
+    pub const fn name(&self) -> &'static str {
+        self.name
+    }
+}
+
+impl<M, V> Clone for TypedProperty<M, V> {
+    fn clone(&self) -> Self {
+        *self
+    }
+}
+
+impl<M, V> Copy for TypedProperty<M, V> {}
+
+impl<M, V> std::fmt::Debug for TypedProperty<M, V> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_tuple("TypedProperty").field(&self.name).finish()
+    }
+}
+
+/// A query builder for the model `M` that only accepts the properties of the model, with
+/// values of the matching types.
+///
+/// It is created by the `query()` function generated for every model, e.g.
+/// `Task::query().filter(TaskFields::DONE, ds::FilterOperator::Equal, false)?`. The filters
+/// are combined with `AND`.
+///
+/// The methods consume and return the query, allowing for method chaining.
+pub struct TypedQuery<M> {
+    query: ds::Query,
+    _marker: PhantomData<fn() -> M>,
+}
+
+impl<M> std::fmt::Debug for TypedQuery<M> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_tuple("TypedQuery").field(&self.query).finish()
+    }
+}
+
+impl<M: EntityModel + 'static> Default for TypedQuery<M> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<M: EntityModel + 'static> TypedQuery<M> {
+    /// Creates a new query for the Kind of the model with the default limit.
+    pub fn new() -> Self {
+        Self {
+            query: M::adapter().query(),
+            _marker: PhantomData,
+        }
+    }
+
+    /// Adds a filter on a property of the model.
+    ///
+    /// ## Parameters
+    /// - `property`: The property to filter on, e.g. `TaskFields::DONE`.
+    /// - `op`: The comparison operator.
+    /// - `value`: The value to compare to, converted with [`IntoValue::try_to_value`].
+    ///
+    /// ## Returns
+    /// A [`Result`] containing the query with the filter, or an [`EntailError`] of the kind
+    /// [`crate::EntailErrorKind::PropertyMappingError`] if the value cannot be represented,
+    /// e.g. a `u64` greater than `i64::MAX`.
+    pub fn filter<V: IntoValue>(
+        self,
+        property: TypedProperty<M, V>,
+        op: ds::FilterOperator,
+        value: impl Into<V>,
+    ) -> Result<Self, EntailError> {
+        let value = value.into().try_to_value()?;
+        Ok(self.and(op.of(property.name, value)))
+    }
+
+    /// Adds an arbitrary filter, e.g. an `OR` of several property filters.
+    pub fn and(mut self, filter: ds::Filter) -> Self {
+        self.query.filter = match self.query.filter.take() {
+            Some(existing) => Some(existing & filter),
+            None => Some(filter),
+        };
+        self
+    }
+
+    /// Orders the results by a property of the model in ascending order.
+    pub fn order_by_asc<V>(self, property: TypedProperty<M, V>) -> Self {
+        self.order_by(property, ds::OrderDirection::ASCENDING)
+    }
+
+    /// Orders the results by a property of the model in descending order.
+    pub fn order_by_desc<V>(self, property: TypedProperty<M, V>) -> Self {
+        self.order_by(property, ds::OrderDirection::DESCENDING)
+    }
+
+    fn order_by<V>(mut self, property: TypedProperty<M, V>, direction: ds::OrderDirection) -> Self {
+        self.query
+            .order
+            .push(ds::PropertyOrder::new(property.name, direction));
+        self
+    }
+
+    /// Sets the maximum number of results.
+    pub fn limit(mut self, limit: i32) -> Self {
+        self.query.limit = limit;
+        self
+    }
+
+    /// Continues the query from a cursor returned by a previous page.
+    pub fn start_cursor(mut self, cursor: Option<Vec<u8>>) -> Self {
+        self.query.start_cursor = cursor;
+        self
+    }
+
+    /// Returns the built [`ds::Query`].
+    pub fn build(self) -> ds::Query {
+        self.query
+    }
+
+    /// Runs the query and maps the results to the model, see
+    /// [`crate::EntityAdapter::fetch_query`].
+    pub async fn fetch(self, ds: &ds::DatastoreShell) -> Result<ds::QueryResult<M>, EntailError> {
+        M::adapter().fetch_query(ds, self.query).await
+    }
+}
@@ -0,0 +1,323 @@
+use std::borrow::Cow;
+use std::collections::HashSet;
+
+use crate::ds;
+use crate::{EntailError, EntailErrorKind};
+
+/// A property of a Kind that may hold the key of a moved entity, either directly or as an
+/// element of an array.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct KeyReference {
+    /// The **Kind** of the referencing entities.
+    pub kind: Cow<'static, str>,
+    /// The name of the (indexed) property holding the key.
+    pub property: Cow<'static, str>,
+}
+
+/// The configuration of [`move_entity`].
+///
+/// The `with_*` methods and [`MoveOptions::dry_run`] consume and return the options, so they
+/// can be chained.
+#[derive(Clone, Debug)]
+pub struct MoveOptions {
+    /// The properties to rewrite to the new key.
+    pub references: Vec<KeyReference>,
+    /// If `true`, nothing is written, only the report is produced. Defaults to `false`.
+    pub dry_run: bool,
+    /// The maximum number of referencing entities updated by a single transaction.
+    /// Defaults to `25`.
+    pub chunk_size: usize,
+}
+
+impl Default for MoveOptions {
+    fn default() -> Self {
+        Self {
+            references: Vec::new(),
+            dry_run: false,
+            chunk_size: 25,
+        }
+    }
+}
+
+impl MoveOptions {
+    /// Creates the default options, without any references.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Adds a property that may hold the key of the moved entity.
+    ///
+    /// ## Parameters
+    /// - `kind`: The Kind of the referencing entities.
+    /// - `property`: The indexed property holding the key or an array of keys.
+    pub fn with_reference(
+        mut self,
+        kind: impl Into<Cow<'static, str>>,
+        property: impl Into<Cow<'static, str>>,
+    ) -> Self {
+        self.references.push(KeyReference {
+            kind: kind.into(),
+            property: property.into(),
+        });
+        self
+    }
+
+    /// Only reports what would be changed, without writing anything.
+    pub fn dry_run(mut self) -> Self {
+        self.dry_run = true;
+        self
+    }
+
+    /// Sets the maximum number of referencing entities updated by a single transaction.
+    pub fn with_chunk_size(mut self, chunk_size: usize) -> Self {
+        self.chunk_size = chunk_size.max(1);
+        self
+    }
+}
+
+/// The outcome of [`move_entity`].
+#[derive(Clone, Debug)]
+pub struct MoveReport {
+    /// The key the entity was moved from.
+    pub old_key: ds::Key,
+    /// The key the entity was moved to.
+    pub new_key: ds::Key,
+    /// `true` if nothing was written.
+    pub dry_run: bool,
+    /// The keys of the entities whose references were (or would be) rewritten.
+    pub updated_references: Vec<ds::Key>,
+    /// The number of committed transactions, `0` for a dry run.
+    pub transactions: usize,
+}
+
+/// Moves an entity to a new key, which may have a different Kind or parent, and rewrites the
+/// configured reference properties pointing at it.
+///
+/// The move is done in several transactional steps, so it is **not** atomic as a whole:
+/// 1. The entity is copied to the new key, failing if the new key is already taken.
+/// 2. The referencing entities, found by querying every [`KeyReference`] for the old key,
+///    are updated in chunks of [`MoveOptions::chunk_size`], one transaction per chunk.
+/// 3. The original entity is deleted.
+///
+/// Because the original is only deleted at the end, the references always point to an
+/// existing entity, and an interrupted move can be completed by running it again with
+/// the new key removed first.
+///
+/// ## Parameters
+/// - `ds`: A reference to the [`ds::DatastoreShell`] to be used for Datastore access.
+/// - `old_key`: The complete key of the entity to move.
+/// - `new_key`: The complete key to move it to.
+/// - `options`: The references to rewrite and whether this is a dry run.
+///
+/// ## Returns
+/// A [`MoveReport`] describing the changes, or an [`EntailError`] of the kind
+/// [`EntailErrorKind::RequiredEntityNotFound`] if the entity does not exist,
+/// [`EntailErrorKind::EntityAlreadyExists`] if the new key is taken, or the error of the
+/// first failing step.
+pub async fn move_entity(
+    ds: &ds::DatastoreShell,
+    old_key: ds::Key,
+    new_key: ds::Key,
+    options: &MoveOptions,
+) -> Result<MoveReport, EntailError> {
+    let Some(entity) = ds.get_single(old_key.clone()).await? else {
+        return Err(EntailError::simple(
+            EntailErrorKind::RequiredEntityNotFound,
+            format!("Required {} not found", old_key),
+        ));
+    };
+    if ds.get_single(new_key.clone()).await?.is_some() {
+        return Err(EntailError::simple(
+            EntailErrorKind::EntityAlreadyExists,
+            format!("Cannot move {} to {}, it already exists", old_key, new_key),
+        ));
+    }
+    let updated_references = find_references(ds, &old_key, &options.references).await?;
+    let mut report = MoveReport {
+        old_key,
+        new_key,
+        dry_run: options.dry_run,
+        updated_references,
+        transactions: 0,
+    };
+    if options.dry_run {
+        return Ok(report);
+    }
+
+    let mut copy = entity;
+    copy.set_key(report.new_key.clone());
+    // an insert fails if the new key has been taken since the check above
+    ds::Transaction::new(ds)
+        .run(|ts| {
+            let batch = ds::MutationBatch::new().insert(copy.clone());
+            async move { ts.commit(batch).await.map(|_| ()) }
+        })
+        .await?;
+    report.transactions += 1;
+
+    for chunk in report.updated_references.chunks(options.chunk_size) {
+        let (old_key, new_key) = (&report.old_key, &report.new_key);
+        // a chunk whose references have all been rewritten since the query has nothing to
+        // commit, and its transaction is rolled back
+        let committed = ds::Transaction::new(ds)
+            .run(|ts| {
+                let chunk = chunk.to_vec();
+                let references = options.references.clone();
+                let (old_key, new_key) = (old_key.clone(), new_key.clone());
+                async move {
+                    let mut batch = ds::MutationBatch::new();
+                    for mut entity in ts.get_all(&chunk).await? {
+                        if rewrite_references(&mut entity, &references, &old_key, &new_key) {
+                            batch = batch.update(entity);
+                        }
+                    }
+                    let committed = !batch.mutations.is_empty();
+                    ts.commit(batch).await.map(|_| committed)
+                }
+            })
+            .await?;
+        if committed {
+            report.transactions += 1;
+        }
+    }
+
+    ds::Transaction::new(ds)
+        .run(|ts| {
+            let batch = ds::MutationBatch::new().delete(report.old_key.clone());
+            async move { ts.commit(batch).await.map(|_| ()) }
+        })
+        .await?;
+    report.transactions += 1;
+    Ok(report)
+}
+
+/// Returns the keys of the entities referencing `key` through any of the `references`,
+/// without duplicates.
+async fn find_references(
+    ds: &ds::DatastoreShell,
+    key: &ds::Key,
+    references: &[KeyReference],
+) -> Result<Vec<ds::Key>, EntailError> {
+    let mut seen = HashSet::new();
+    let mut keys = Vec::new();
+    for reference in references {
+        let mut query = ds::Query {
+            kind: reference.kind.clone(),
+            filter: Some(ds::FilterOperator::Equal.of(reference.property.clone(), key.clone())),
+            projection: vec!["__key__".into()],
+            ..Default::default()
+        };
+        loop {
+            let page = ds.run_query(query.clone()).await?;
+            if page.items.is_empty() {
+                break;
+            }
+            for entity in page.items {
+                if seen.insert(entity.key().clone()) {
+                    keys.push(entity.just_key());
+                }
+            }
+            match page.end_cursor {
+                Some(cursor) => query.start_cursor = Some(cursor),
+                None => break,
+            }
+        }
+    }
+    Ok(keys)
+}
+
+/// Replaces `old_key` with `new_key` in the referencing properties of the entity's Kind.
+/// Returns `true` if anything was replaced.
+fn rewrite_references(
+    entity: &mut ds::Entity,
+    references: &[KeyReference],
+    old_key: &ds::Key,
+    new_key: &ds::Key,
+) -> bool {
+    let kind = entity.key().kind().to_string();
+    let mut changed = false;
+    for reference in references.iter().filter(|r| r.kind == kind) {
+        if let Some(value) = entity.get_mut_value(&reference.property) {
+            changed |= replace_key(value, old_key, new_key);
+        }
+    }
+    changed
+}
+
+fn replace_key(value: &mut ds::Value, old_key: &ds::Key, new_key: &ds::Key) -> bool {
+    match value {
+        ds::Value::Key(key) if key == old_key => {
+            *key = new_key.clone();
+            true
+        }
+        ds::Value::Array(values) => {
+            // every element has to be visited, so this can't short-circuit
+            let mut changed = false;
+            for value in values {
+                changed |= replace_key(value, old_key, new_key);
+            }
+            changed
+        }
+        _ => false,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_rewrite_references() {
+        let old_key = ds::Key::new("User").with_name("alice");
+        let new_key = ds::Key::new("Member").with_name("alice");
+        let other = ds::Key::new("User").with_name("bob");
+        let references = MoveOptions::new()
+            .with_reference("Post", "author")
+            .with_reference("Post", "readers")
+            .with_reference("Comment", "author")
+            .references;
+
+        let mut post = ds::Entity::new(ds::Key::new("Post").with_id(1));
+        post.set_indexed("author", ds::Value::key(old_key.clone()));
+        post.set_indexed(
+            "readers",
+            ds::Value::array(vec![
+                ds::Value::key(other.clone()),
+                ds::Value::key(old_key.clone()),
+            ]),
+        );
+        // not a configured reference, left alone
+        post.set_indexed("editor", ds::Value::key(old_key.clone()));
+        assert!(rewrite_references(
+            &mut post,
+            &references,
+            &old_key,
+            &new_key
+        ));
+        assert_eq!(
+            post.get_value("author"),
+            Some(&ds::Value::key(new_key.clone()))
+        );
+        assert_eq!(
+            post.get_value("readers"),
+            Some(&ds::Value::array(vec![
+                ds::Value::key(other.clone()),
+                ds::Value::key(new_key.clone()),
+            ]))
+        );
+        assert!(post.is_indexed("readers"));
+        assert_eq!(
+            post.get_value("editor"),
+            Some(&ds::Value::key(old_key.clone()))
+        );
+
+        let mut comment = ds::Entity::new(ds::Key::new("Comment").with_id(2));
+        comment.set_indexed("author", ds::Value::key(other));
+        assert!(!rewrite_references(
+            &mut comment,
+            &references,
+            &old_key,
+            &new_key
+        ));
+    }
+}
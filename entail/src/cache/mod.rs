@@ -0,0 +1,372 @@
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+use tokio::time::Instant;
+
+use crate::ds;
+use crate::{EntailError, EntailErrorKind, EntityModel};
+
+struct CacheEntry {
+    entity: Option<ds::Entity>,
+    fetched_at: Instant,
+    refreshing: bool,
+}
+
+#[derive(Default)]
+struct CacheState {
+    entries: HashMap<ds::Key, CacheEntry>,
+    /// The lookups in flight, by key. A lookup only stores its result if its ticket is still
+    /// the one of its key, so a [`EntityCache::prime`], [`EntityCache::invalidate`] or
+    /// [`EntityCache::clear`] made during the lookup (which drops the ticket) is not
+    /// overwritten with the stale result.
+    pending: HashMap<ds::Key, u64>,
+    next_ticket: u64,
+}
+
+impl CacheState {
+    fn start_lookup(&mut self, key: &ds::Key) -> u64 {
+        self.next_ticket += 1;
+        self.pending.insert(key.clone(), self.next_ticket);
+        self.next_ticket
+    }
+
+    /// Ends a lookup, returning `true` if its result can be stored.
+    fn finish_lookup(&mut self, key: &ds::Key, ticket: u64) -> bool {
+        if self.pending.get(key) == Some(&ticket) {
+            self.pending.remove(key);
+            true
+        } else {
+            false
+        }
+    }
+}
+
+type SharedState = Arc<Mutex<CacheState>>;
+
+/// A cache of entity lookups shared between requests, with an expiry time per **Kind**.
+///
+/// Unlike [`crate::RequestScope`], which lives for a single request, an `EntityCache` is meant
+/// to be kept for the lifetime of the service and serves lookups of hot entities (settings,
+/// tenants, feature flags) without hitting Datastore. Every entry expires after the TTL of its
+/// Kind, so writes made elsewhere become visible within that time. Writes made by the service
+/// itself can be applied right away with [`EntityCache::prime`] or
+/// [`EntityCache::invalidate`].
+///
+/// With [`EntityCache::with_stale_while_revalidate`], an expired entry is still returned
+/// immediately for a while, and a background task refreshes it, so hot read paths never wait
+/// for Datastore once an entity has been cached.
+///
+/// Lookups that found nothing are cached as well. Expired entries are kept until they are
+/// looked up again, evicted to make room (see [`EntityCache::with_max_entries`]) or removed
+/// with [`EntityCache::purge_expired`].
+///
+/// The `with_*` methods consume and return the cache, so they are chained when it is created.
+pub struct EntityCache {
+    ds: ds::DatastoreShell,
+    default_ttl: Duration,
+    ttls: HashMap<String, Duration>,
+    stale_while_revalidate: Option<Duration>,
+    max_entries: Option<usize>,
+    state: SharedState,
+}
+
+impl EntityCache {
+    /// Creates a new, empty cache.
+    ///
+    /// ## Parameters
+    /// - `ds`: The [`ds::DatastoreShell`] used for the lookups, it should not be a
+    ///   transactional shell.
+    /// - `default_ttl`: The time an entry is fresh for, unless its Kind has its own TTL.
+    pub fn new(ds: ds::DatastoreShell, default_ttl: Duration) -> Self {
+        Self {
+            ds,
+            default_ttl,
+            ttls: HashMap::new(),
+            stale_while_revalidate: None,
+            max_entries: None,
+            state: SharedState::default(),
+        }
+    }
+
+    /// Sets the time the entries of a Kind are fresh for.
+    ///
+    /// ## Parameters
+    /// - `kind`: The Kind of the entities.
+    /// - `ttl`: The time an entry of the Kind is fresh for.
+    pub fn with_kind_ttl(mut self, kind: impl Into<String>, ttl: Duration) -> Self {
+        self.ttls.insert(kind.into(), ttl);
+        self
+    }
+
+    /// Sets the time the entries of a model's Kind are fresh for.
+    pub fn with_model_ttl<T: EntityModel + 'static>(self, ttl: Duration) -> Self {
+        self.with_kind_ttl(T::adapter().kind(), ttl)
+    }
+
+    /// Serves expired entries for a while longer, refreshing them in the background.
+    ///
+    /// An entry that expired less than `window` ago is returned as it is, and a background
+    /// task (at most one per key) looks the entity up again and updates the cache. Older
+    /// entries are looked up before returning, as without this option. If the refresh fails,
+    /// the stale entry is kept and the next read tries again.
+    pub fn with_stale_while_revalidate(mut self, window: Duration) -> Self {
+        self.stale_while_revalidate = Some(window);
+        self
+    }
+
+    /// Limits the number of entries. When a new entry would exceed the limit, the expired
+    /// entries are evicted, then the least recently fetched ones if the cache is still full.
+    pub fn with_max_entries(mut self, max_entries: usize) -> Self {
+        self.max_entries = Some(max_entries);
+        self
+    }
+
+    /// Returns the time the entries of a Kind are fresh for.
+    pub fn ttl(&self, kind: &str) -> Duration {
+        self.ttls.get(kind).copied().unwrap_or(self.default_ttl)
+    }
+
+    /// Looks up a single entity, or returns it from the cache if it is fresh (or stale within
+    /// the stale-while-revalidate window).
+    ///
+    /// ## Parameters
+    /// - `key`: The complete [`ds::Key`] of the entity.
+    ///
+    /// ## Returns
+    /// A [`Result`] containing the entity or `None` if it doesn't exist, or an
+    /// [`EntailError`] if the lookup failed. Failed lookups are not cached.
+    pub async fn get_single(&self, key: ds::Key) -> Result<Option<ds::Entity>, EntailError> {
+        let ttl = self.ttl(key.kind());
+        let ticket = {
+            let mut state = self.state.lock().unwrap();
+            if let Some(entry) = state.entries.get_mut(&key) {
+                let age = entry.fetched_at.elapsed();
+                if age < ttl {
+                    return Ok(entry.entity.clone());
+                }
+                let serve_stale = self
+                    .stale_while_revalidate
+                    .is_some_and(|window| age < ttl + window);
+                if serve_stale {
+                    let entity = entry.entity.clone();
+                    if !entry.refreshing {
+                        entry.refreshing = true;
+                        let ticket = state.start_lookup(&key);
+                        self.spawn_refresh(key, ticket);
+                    }
+                    return Ok(entity);
+                }
+            }
+            state.start_lookup(&key)
+        };
+        let entity = match self.ds.get_single(key.clone()).await {
+            Ok(entity) => entity,
+            Err(err) => {
+                self.state.lock().unwrap().finish_lookup(&key, ticket);
+                return Err(err);
+            }
+        };
+        let mut state = self.state.lock().unwrap();
+        if state.finish_lookup(&key, ticket) {
+            self.insert(&mut state, key, entity.clone());
+        }
+        Ok(entity)
+    }
+
+    /// Fetches a single model like [`crate::EntityAdapter::fetch_single`], using the cache.
+    ///
+    /// ## Parameters
+    /// - `key`: The complete [`ds::Key`] of the entity.
+    ///
+    /// ## Returns
+    /// A [`Result`] containing the model, or an [`EntailError`] if the entity is not found,
+    /// the lookup failed or the entity cannot be mapped to the model.
+    pub async fn fetch_single<T: EntityModel>(&self, key: ds::Key) -> Result<T, EntailError> {
+        let key_string = key.to_string();
+        match self.get_single(key).await? {
+            Some(entity) => T::from_ds_entity(&entity),
+            None => Err(EntailError::simple(
+                EntailErrorKind::RequiredEntityNotFound,
+                format!("Required {} not found", key_string),
+            )),
+        }
+    }
+
+    /// Stores an entity in the cache as fresh, e.g. one that has just been written. A lookup
+    /// of the key in flight doesn't overwrite it.
+    pub fn prime(&self, entity: ds::Entity) {
+        let key = entity.key().clone();
+        let mut state = self.state.lock().unwrap();
+        state.pending.remove(&key);
+        self.insert(&mut state, key, Some(entity));
+    }
+
+    /// Removes the entry of a key, so the next lookup fetches it again. A lookup of the key in
+    /// flight doesn't store its result.
+    pub fn invalidate(&self, key: &ds::Key) {
+        let mut state = self.state.lock().unwrap();
+        state.pending.remove(key);
+        state.entries.remove(key);
+    }
+
+    /// Removes all the entries, the lookups in flight don't store their results.
+    pub fn clear(&self) {
+        let mut state = self.state.lock().unwrap();
+        state.pending.clear();
+        state.entries.clear();
+    }
+
+    /// Removes the entries that expired, including their stale-while-revalidate window.
+    pub fn purge_expired(&self) {
+        let mut state = self.state.lock().unwrap();
+        state
+            .entries
+            .retain(|key, entry| !self.is_expired(key, entry));
+    }
+
+    /// Returns the number of keys (found or not) in the cache. The expired entries are
+    /// counted until they are removed, see [`EntityCache::purge_expired`].
+    pub fn len(&self) -> usize {
+        self.state.lock().unwrap().entries.len()
+    }
+
+    /// Returns `true` if the cache has no entries.
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    fn is_expired(&self, key: &ds::Key, entry: &CacheEntry) -> bool {
+        let window = self.stale_while_revalidate.unwrap_or_default();
+        entry.fetched_at.elapsed() >= self.ttl(key.kind()) + window
+    }
+
+    fn insert(&self, state: &mut CacheState, key: ds::Key, entity: Option<ds::Entity>) {
+        if let Some(max_entries) = self.max_entries {
+            if !state.entries.contains_key(&key) && state.entries.len() >= max_entries {
+                state
+                    .entries
+                    .retain(|key, entry| !self.is_expired(key, entry));
+            }
+            while !state.entries.contains_key(&key) && state.entries.len() >= max_entries {
+                let oldest = state
+                    .entries
+                    .iter()
+                    .min_by_key(|(_, entry)| entry.fetched_at)
+                    .map(|(key, _)| key.clone());
+                match oldest {
+                    Some(oldest) => state.entries.remove(&oldest),
+                    None => break,
+                };
+            }
+        }
+        state.entries.insert(
+            key,
+            CacheEntry {
+                entity,
+                fetched_at: Instant::now(),
+                refreshing: false,
+            },
+        );
+    }
+
+    fn spawn_refresh(&self, key: ds::Key, ticket: u64) {
+        let ds = self.ds.clone();
+        let state = self.state.clone();
+        tokio::spawn(async move {
+            let result = ds.get_single(key.clone()).await;
+            let mut state = state.lock().unwrap();
+            if !state.finish_lookup(&key, ticket) {
+                // primed, invalidated or cleared during the lookup
+                return;
+            }
+            match result {
+                // an evicted entry is not brought back, so the cache can't grow past its limit
+                Ok(entity) if state.entries.contains_key(&key) => {
+                    state.entries.insert(
+                        key,
+                        CacheEntry {
+                            entity,
+                            fetched_at: Instant::now(),
+                            refreshing: false,
+                        },
+                    );
+                }
+                _ => {
+                    if let Some(entry) = state.entries.get_mut(&key) {
+                        entry.refreshing = false;
+                    }
+                }
+            }
+        });
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn test_ttl_and_stale_reads() {
+        let ds = crate::test_support::offline_shell().await;
+        let key = ds::Key::new("Setting").with_name("theme");
+        let mut entity = ds::Entity::new(key.clone());
+        entity.set_indexed("value", ds::Value::unicode_string("dark"));
+
+        let cache = EntityCache::new(ds.clone(), Duration::from_secs(3600));
+        assert_eq!(cache.ttl("Setting"), Duration::from_secs(3600));
+        cache.prime(entity.clone());
+        // fresh, no request is made
+        let found = cache.get_single(key.clone()).await.unwrap().unwrap();
+        assert_eq!(found.get_value("value"), Some(&"dark".into()));
+
+        // expired right away and served stale while the refresh runs in the background
+        let cache = EntityCache::new(ds.clone(), Duration::from_secs(3600))
+            .with_kind_ttl("Setting", Duration::ZERO)
+            .with_stale_while_revalidate(Duration::from_secs(3600));
+        assert_eq!(cache.ttl("Setting"), Duration::ZERO);
+        cache.prime(entity.clone());
+        let found = cache.get_single(key.clone()).await.unwrap().unwrap();
+        assert_eq!(found.get_value("value"), Some(&"dark".into()));
+        assert_eq!(cache.len(), 1);
+
+        // expired without a stale window, so it is looked up again
+        let cache = EntityCache::new(ds, Duration::ZERO);
+        cache.prime(entity);
+        assert!(!matches!(cache.get_single(key.clone()).await, Ok(Some(_))));
+        cache.invalidate(&key);
+        assert!(cache.is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_lookup_tickets_and_eviction() {
+        let ds = crate::test_support::offline_shell().await;
+        let key = |id: i64| ds::Key::new("Setting").with_id(id);
+
+        // a prime or an invalidate during a lookup drops its ticket
+        let cache = EntityCache::new(ds.clone(), Duration::from_secs(3600));
+        let ticket = cache.state.lock().unwrap().start_lookup(&key(1));
+        cache.prime(ds::Entity::new(key(1)));
+        assert!(!cache.state.lock().unwrap().finish_lookup(&key(1), ticket));
+        let ticket = cache.state.lock().unwrap().start_lookup(&key(1));
+        cache.invalidate(&key(1));
+        assert!(!cache.state.lock().unwrap().finish_lookup(&key(1), ticket));
+        let ticket = cache.state.lock().unwrap().start_lookup(&key(1));
+        assert!(cache.state.lock().unwrap().finish_lookup(&key(1), ticket));
+
+        // the least recently fetched entries are evicted first
+        let cache = EntityCache::new(ds.clone(), Duration::from_secs(3600)).with_max_entries(2);
+        for id in 1..=3 {
+            cache.prime(ds::Entity::new(key(id)));
+            tokio::time::sleep(Duration::from_millis(2)).await;
+        }
+        assert_eq!(cache.len(), 2);
+        assert!(!cache.state.lock().unwrap().entries.contains_key(&key(1)));
+
+        // expired entries are counted until they are purged
+        let cache = EntityCache::new(ds, Duration::ZERO).with_max_entries(2);
+        cache.prime(ds::Entity::new(key(1)));
+        assert_eq!(cache.len(), 1);
+        cache.purge_expired();
+        assert!(cache.is_empty());
+    }
+}
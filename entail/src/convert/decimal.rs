@@ -0,0 +1,171 @@
+use super::*;
+
+use rust_decimal::Decimal;
+
+/// A decimal is stored as its string representation, keeping its scale (`"1.50"` is read
+/// back as `1.50`). When reading, integers are accepted as well, and with
+/// `#[entail(coerce)]`, floating point numbers.
+impl IntoValue for Decimal {
+    fn to_value(&self) -> ds::Value {
+        ds::Value::unicode_string(self.to_string())
+    }
+}
+
+impl FromValue for Decimal {
+    fn from_value(value: &ds::Value) -> Result<Self, EntailError> {
+        match value {
+            ds::Value::UnicodeString(val) => {
+                Decimal::from_str_exact(val.trim()).map_err(|_| mismatch("a decimal UnicodeString"))
+            }
+            ds::Value::Integer(val) => Ok(Decimal::from(*val)),
+            _ => Err(mismatch("a decimal UnicodeString or Integer")),
+        }
+    }
+
+    fn from_value_lenient(value: &ds::Value) -> Result<Self, EntailError> {
+        match value {
+            ds::Value::FloatingPoint(val) => {
+                Decimal::try_from(*val).map_err(|_| mismatch("a decimal FloatingPoint"))
+            }
+            _ => Self::from_value(value),
+        }
+    }
+}
+
+/// Converts decimals to and from integers holding the value multiplied by `10^scale`, e.g.
+/// the cents of an amount with a scale of `2`. This is used for the fields marked with
+/// `#[entail(decimal_scale = N)]`, it is implemented for [`Decimal`], and `Option`s and
+/// `Vec`s of them.
+pub trait ScaledDecimal: Sized {
+    /// Converts the value into an integer (or an array or a null) [`ds::Value`].
+    ///
+    /// ## Returns
+    /// A [`Result`] containing the converted value or an [`EntailError`] of the kind
+    /// [`EntailErrorKind::PropertyMappingError`] if the value has more decimal places than
+    /// the scale, or it does not fit into an `i64` once scaled.
+    fn to_scaled_value(&self, scale: u32) -> Result<ds::Value, EntailError>;
+
+    /// Converts an integer [`ds::Value`] written by [`ScaledDecimal::to_scaled_value`].
+    fn from_scaled_value(value: &ds::Value, scale: u32) -> Result<Self, EntailError>;
+}
+
+impl ScaledDecimal for Decimal {
+    fn to_scaled_value(&self, scale: u32) -> Result<ds::Value, EntailError> {
+        let mut scaled = *self;
+        scaled.rescale(scale);
+        // rescaling rounds the decimal places that don't fit
+        if scaled != *self {
+            return Err(EntailError::simple(
+                EntailErrorKind::PropertyMappingError,
+                format!("Decimal {} has more than {} decimal places", self, scale),
+            ));
+        }
+        i64::try_from(scaled.mantissa())
+            .map(ds::Value::integer)
+            .map_err(|_| {
+                EntailError::simple(
+                    EntailErrorKind::PropertyMappingError,
+                    format!(
+                        "Decimal {} is out of the range of i64 with {} decimal places",
+                        self, scale
+                    ),
+                )
+            })
+    }
+
+    fn from_scaled_value(value: &ds::Value, scale: u32) -> Result<Self, EntailError> {
+        match value {
+            ds::Value::Integer(val) => Decimal::try_from_i128_with_scale(*val as i128, scale)
+                .map_err(|_| mismatch("a scaled decimal Integer")),
+            _ => Err(mismatch("Integer")),
+        }
+    }
+}
+
+impl<T: ScaledDecimal> ScaledDecimal for Option<T> {
+    fn to_scaled_value(&self, scale: u32) -> Result<ds::Value, EntailError> {
+        match self {
+            Some(val) => val.to_scaled_value(scale),
+            None => Ok(ds::Value::null()),
+        }
+    }
+
+    fn from_scaled_value(value: &ds::Value, scale: u32) -> Result<Self, EntailError> {
+        match value {
+            ds::Value::Null => Ok(None),
+            _ => T::from_scaled_value(value, scale).map(Some),
+        }
+    }
+}
+
+impl<T: ScaledDecimal> ScaledDecimal for Vec<T> {
+    fn to_scaled_value(&self, scale: u32) -> Result<ds::Value, EntailError> {
+        self.iter()
+            .map(|val| val.to_scaled_value(scale))
+            .collect::<Result<_, _>>()
+            .map(ds::Value::array)
+    }
+
+    fn from_scaled_value(value: &ds::Value, scale: u32) -> Result<Self, EntailError> {
+        match value {
+            ds::Value::Null => Ok(Vec::new()),
+            ds::Value::Array(vals) => vals
+                .iter()
+                .map(|val| T::from_scaled_value(val, scale))
+                .collect(),
+            _ => T::from_scaled_value(value, scale).map(|val| vec![val]),
+        }
+    }
+}
+
+/// Converts a value with [`ScaledDecimal::to_scaled_value`], for the code generated by
+/// `#[derive(Entail)]`.
+pub fn to_scaled_value<T: ScaledDecimal>(value: &T, scale: u32) -> Result<ds::Value, EntailError> {
+    value.to_scaled_value(scale)
+}
+
+/// Converts a value with [`ScaledDecimal::from_scaled_value`], for the code generated by
+/// `#[derive(Entail)]`.
+pub fn from_scaled_value<T: ScaledDecimal>(
+    value: &ds::Value,
+    scale: u32,
+) -> Result<T, EntailError> {
+    T::from_scaled_value(value, scale)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_decimal_conversion() {
+        let price = Decimal::new(1050, 2);
+        assert_eq!(price.to_value(), ds::Value::unicode_string("10.50"));
+        let read = Decimal::from_value(&ds::Value::unicode_string("10.50")).unwrap();
+        assert_eq!((read, read.scale()), (price, 2));
+        assert_eq!(
+            Decimal::from_value(&ds::Value::integer(3)).unwrap(),
+            Decimal::new(3, 0)
+        );
+        assert!(Decimal::from_value(&ds::Value::floating_point(0.1)).is_err());
+        assert_eq!(
+            Decimal::from_value_lenient(&ds::Value::floating_point(0.5)).unwrap(),
+            Decimal::new(5, 1)
+        );
+
+        assert_eq!(price.to_scaled_value(3).unwrap(), ds::Value::integer(10500));
+        let err = Decimal::new(10505, 3).to_scaled_value(2).unwrap_err();
+        assert_eq!(err.message, "Decimal 10.505 has more than 2 decimal places");
+        assert!(Decimal::MAX.to_scaled_value(2).is_err());
+        let read = Decimal::from_scaled_value(&ds::Value::integer(1050), 2).unwrap();
+        assert_eq!(read.to_string(), "10.50");
+        assert_eq!(
+            Option::<Decimal>::from_scaled_value(&ds::Value::null(), 2).unwrap(),
+            None
+        );
+        assert_eq!(
+            vec![price].to_scaled_value(2).unwrap(),
+            ds::Value::array(vec![ds::Value::integer(1050)])
+        );
+    }
+}
@@ -0,0 +1,769 @@
+use super::*;
+
+use std::borrow::Cow;
+use std::collections::{BTreeSet, HashSet};
+use std::hash::{BuildHasher, Hash};
+use std::sync::Arc;
+
+#[cfg(feature = "decimal")]
+mod decimal;
+#[cfg(feature = "decimal")]
+pub use decimal::*;
+
+/// Converts a Rust value into a Datastore [`ds::Value`].
+///
+/// The `#[derive(Entail)]` macro uses this trait to convert every non-key field, so
+/// implementing it (together with [`FromValue`]) for your own types, like newtypes around
+/// primitives, makes them usable as fields, including inside `Option` and `Vec`.
+///
+/// ## Example
+/// ```
+/// use entail::{ds, EntailError, FromValue, IntoValue};
+///
+/// struct Cents(i64);
+///
+/// impl IntoValue for Cents {
+///     fn to_value(&self) -> ds::Value {
+///         self.0.to_value()
+///     }
+/// }
+///
+/// impl FromValue for Cents {
+///     fn from_value(value: &ds::Value) -> Result<Self, EntailError> {
+///         i64::from_value(value).map(Cents)
+///     }
+/// }
+/// ```
+pub trait IntoValue {
+    /// Converts the value into a [`ds::Value`].
+    fn to_value(&self) -> ds::Value;
+
+    /// Converts the value into a [`ds::Value`], failing if it cannot be represented (e.g. a
+    /// `u64` that does not fit into a Datastore integer).
+    ///
+    /// This is what [`EntityModel::to_ds_entity`] uses. The default implementation never
+    /// fails and returns the result of [`IntoValue::to_value`].
+    ///
+    /// ## Returns
+    /// A [`Result`] containing the converted value or an [`EntailError`] of the kind
+    /// [`EntailErrorKind::PropertyMappingError`] if the value is out of range.
+    fn try_to_value(&self) -> Result<ds::Value, EntailError> {
+        Ok(self.to_value())
+    }
+}
+
+/// Converts a Datastore [`ds::Value`] into a Rust value.
+///
+/// This is the counterpart of [`IntoValue`]. An absent property is passed to the
+/// implementation as [`ds::Value::Null`], so types that have a natural empty
+/// representation (like `Option<T>` or `Vec<T>`) can accept it, while the others
+/// should reject it.
+pub trait FromValue: Sized {
+    /// Converts a [`ds::Value`] reference into the implementing type.
+    ///
+    /// ## Returns
+    /// A [`Result`] containing the converted value or an [`EntailError`] of the kind
+    /// [`EntailErrorKind::PropertyMappingError`] if the value has an unexpected type.
+    fn from_value(value: &ds::Value) -> Result<Self, EntailError>;
+
+    /// Converts a [`ds::Value`] reference into the implementing type, accepting values
+    /// of a different type if they can be converted without ambiguity.
+    ///
+    /// This is used for the fields marked with `#[entail(coerce)]`. The built-in
+    /// implementations read integers, floating point numbers and booleans from their
+    /// string representations (e.g. `"42"` or `"true"`) and strings from any of these
+    /// values. The default implementation is the same as [`FromValue::from_value`].
+    fn from_value_lenient(value: &ds::Value) -> Result<Self, EntailError> {
+        Self::from_value(value)
+    }
+}
+
+fn mismatch(expected: &str) -> EntailError {
+    EntailError::simple(
+        EntailErrorKind::PropertyMappingError,
+        format!("Expected a value of {}", expected),
+    )
+}
+
+macro_rules! impl_value_conversion {
+    ($ty:ty, $variant:ident($val:ident) => $to_value:expr, $from_value:expr) => {
+        impl_value_conversion!($ty, $variant($val) => $to_value, $from_value; lenient(_value) => None);
+    };
+    ($ty:ty, $variant:ident($val:ident) => $to_value:expr, $from_value:expr; lenient($lenient_val:ident) => $lenient:expr) => {
+        impl IntoValue for $ty {
+            fn to_value(&self) -> ds::Value {
+                let $val = self;
+                $to_value
+            }
+        }
+
+        impl FromValue for $ty {
+            fn from_value(value: &ds::Value) -> Result<Self, EntailError> {
+                match value {
+                    ds::Value::$variant($val) => Ok($from_value),
+                    _ => Err(mismatch(stringify!($variant))),
+                }
+            }
+
+            fn from_value_lenient($lenient_val: &ds::Value) -> Result<Self, EntailError> {
+                match Self::from_value($lenient_val) {
+                    Ok(val) => Ok(val),
+                    Err(err) => {
+                        let coerced: Option<Self> = $lenient;
+                        coerced.ok_or(err)
+                    }
+                }
+            }
+        }
+    };
+}
+
+fn parse_string<T: std::str::FromStr>(value: &ds::Value) -> Option<T> {
+    value.string_value().and_then(|s| s.trim().parse().ok())
+}
+
+fn parse_float(value: &ds::Value) -> Option<f64> {
+    match value {
+        ds::Value::Integer(val) => Some(*val as f64),
+        _ => parse_string(value),
+    }
+}
+
+fn parse_bool(value: &ds::Value) -> Option<bool> {
+    match value.string_value()?.trim() {
+        s if s.eq_ignore_ascii_case("true") => Some(true),
+        s if s.eq_ignore_ascii_case("false") => Some(false),
+        _ => None,
+    }
+}
+
+fn format_scalar(value: &ds::Value) -> Option<String> {
+    match value {
+        ds::Value::Integer(val) => Some(val.to_string()),
+        ds::Value::FloatingPoint(val) => Some(val.to_string()),
+        ds::Value::Boolean(val) => Some(val.to_string()),
+        _ => None,
+    }
+}
+
+impl_value_conversion!(String, UnicodeString(val) => ds::Value::unicode_string(val.clone()), String::from(val.as_ref());
+    lenient(value) => format_scalar(value));
+impl_value_conversion!(Cow<'static, str>, UnicodeString(val) => ds::Value::unicode_string(val.clone()), val.clone();
+    lenient(value) => format_scalar(value).map(Cow::Owned));
+impl_value_conversion!(i64, Integer(val) => ds::Value::integer(*val), *val;
+    lenient(value) => parse_string(value));
+impl_value_conversion!(f32, FloatingPoint(val) => ds::Value::floating_point(*val as f64), *val as f32;
+    lenient(value) => parse_float(value).map(|val| val as f32));
+impl_value_conversion!(f64, FloatingPoint(val) => ds::Value::floating_point(*val), *val;
+    lenient(value) => parse_float(value));
+impl_value_conversion!(bool, Boolean(val) => ds::Value::boolean(*val), *val;
+    lenient(value) => parse_bool(value));
+impl_value_conversion!(ds::Key, Key(val) => ds::Value::key(val.clone()), val.clone());
+impl_value_conversion!(Vec<u8>, Blob(val) => ds::Value::blob(val.clone()), val.to_vec());
+impl_value_conversion!(Cow<'static, [u8]>, Blob(val) => ds::Value::blob(val.to_vec()), Cow::Owned(val.to_vec()));
+impl_value_conversion!(bytes::Bytes, Blob(val) => ds::Value::Blob(val.clone()), val.clone());
+
+/// Fixed size byte arrays are stored as blobs, and only blobs of the same length are accepted
+/// when reading.
+impl<const N: usize> IntoValue for [u8; N] {
+    fn to_value(&self) -> ds::Value {
+        ds::Value::blob(self.to_vec())
+    }
+}
+
+impl<const N: usize> FromValue for [u8; N] {
+    fn from_value(value: &ds::Value) -> Result<Self, EntailError> {
+        let ds::Value::Blob(val) = value else {
+            return Err(mismatch("Blob"));
+        };
+        val.as_ref().try_into().map_err(|_| {
+            EntailError::simple(
+                EntailErrorKind::PropertyMappingError,
+                format!("Expected a Blob of {} bytes, got {} bytes", N, val.len()),
+            )
+        })
+    }
+}
+
+/// The type `#[derive(Entail)]` maps `[u8; N]` fields as, the macro handles the types of the
+/// fields as paths.
+#[doc(hidden)]
+pub type ByteArray<const N: usize> = [u8; N];
+
+fn out_of_range(value: impl std::fmt::Display, ty: &str) -> EntailError {
+    EntailError::simple(
+        EntailErrorKind::PropertyMappingError,
+        format!("Integer {} is out of the range of {}", value, ty),
+    )
+}
+
+/// Integer types that are converted with a range check instead of a cast, so values that
+/// don't fit are mapping errors rather than silently truncated.
+macro_rules! impl_checked_integer_conversion {
+    ($($ty:ty),*) => {
+        $(
+            impl IntoValue for $ty {
+                fn to_value(&self) -> ds::Value {
+                    ds::Value::integer(i64::from(*self))
+                }
+            }
+
+            impl FromValue for $ty {
+                fn from_value(value: &ds::Value) -> Result<Self, EntailError> {
+                    let val = i64::from_value(value)?;
+                    Self::try_from(val).map_err(|_| out_of_range(val, stringify!($ty)))
+                }
+
+                fn from_value_lenient(value: &ds::Value) -> Result<Self, EntailError> {
+                    let val = i64::from_value_lenient(value)?;
+                    Self::try_from(val).map_err(|_| out_of_range(val, stringify!($ty)))
+                }
+            }
+        )*
+    };
+}
+
+impl_checked_integer_conversion!(i8, i16, i32, u16, u32);
+
+/// Datastore integers are signed 64 bit values, so only `u64` values up to `i64::MAX` can be
+/// stored.
+impl IntoValue for u64 {
+    /// Converts the value into a [`ds::Value`].
+    ///
+    /// ## Panics
+    /// If the value is greater than `i64::MAX`, use [`IntoValue::try_to_value`] to get an
+    /// error instead.
+    fn to_value(&self) -> ds::Value {
+        match self.try_to_value() {
+            Ok(value) => value,
+            Err(err) => panic!("{}", err.message),
+        }
+    }
+
+    fn try_to_value(&self) -> Result<ds::Value, EntailError> {
+        i64::try_from(*self)
+            .map(ds::Value::integer)
+            .map_err(|_| out_of_range(self, "Datastore integers"))
+    }
+}
+
+impl FromValue for u64 {
+    fn from_value(value: &ds::Value) -> Result<Self, EntailError> {
+        let val = i64::from_value(value)?;
+        Self::try_from(val).map_err(|_| out_of_range(val, "u64"))
+    }
+
+    fn from_value_lenient(value: &ds::Value) -> Result<Self, EntailError> {
+        let val = i64::from_value_lenient(value)?;
+        Self::try_from(val).map_err(|_| out_of_range(val, "u64"))
+    }
+}
+
+/// A `Duration` is stored as an integer number of microseconds, the sub-microsecond part is
+/// truncated. Negative integers are mapping errors when reading.
+impl IntoValue for std::time::Duration {
+    /// Converts the value into a [`ds::Value`].
+    ///
+    /// ## Panics
+    /// If the number of microseconds is greater than `i64::MAX`, use
+    /// [`IntoValue::try_to_value`] to get an error instead.
+    fn to_value(&self) -> ds::Value {
+        match self.try_to_value() {
+            Ok(value) => value,
+            Err(err) => panic!("{}", err.message),
+        }
+    }
+
+    fn try_to_value(&self) -> Result<ds::Value, EntailError> {
+        let micros = self.as_micros();
+        i64::try_from(micros)
+            .map(ds::Value::integer)
+            .map_err(|_| out_of_range(micros, "Datastore integers"))
+    }
+}
+
+impl FromValue for std::time::Duration {
+    fn from_value(value: &ds::Value) -> Result<Self, EntailError> {
+        let val = i64::from_value(value)?;
+        u64::try_from(val)
+            .map(Self::from_micros)
+            .map_err(|_| out_of_range(val, "Duration"))
+    }
+
+    fn from_value_lenient(value: &ds::Value) -> Result<Self, EntailError> {
+        let val = i64::from_value_lenient(value)?;
+        u64::try_from(val)
+            .map(Self::from_micros)
+            .map_err(|_| out_of_range(val, "Duration"))
+    }
+}
+
+/// Maps `u8` values to integers.
+///
+/// `u8` cannot implement [`IntoValue`] and [`FromValue`] itself, because `Vec<u8>` is mapped
+/// to a blob. The `#[derive(Entail)]` macro wraps `u8` and `Option<u8>` fields in this type,
+/// and it can be used the same way in hand-written conversions.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct ByteValue<T>(pub T);
+
+impl<T> From<T> for ByteValue<T> {
+    fn from(value: T) -> Self {
+        Self(value)
+    }
+}
+
+impl IntoValue for ByteValue<u8> {
+    fn to_value(&self) -> ds::Value {
+        ds::Value::integer(i64::from(self.0))
+    }
+}
+
+impl FromValue for ByteValue<u8> {
+    fn from_value(value: &ds::Value) -> Result<Self, EntailError> {
+        let val = i64::from_value(value)?;
+        u8::try_from(val)
+            .map(ByteValue)
+            .map_err(|_| out_of_range(val, "u8"))
+    }
+
+    fn from_value_lenient(value: &ds::Value) -> Result<Self, EntailError> {
+        let val = i64::from_value_lenient(value)?;
+        u8::try_from(val)
+            .map(ByteValue)
+            .map_err(|_| out_of_range(val, "u8"))
+    }
+}
+
+impl IntoValue for ByteValue<Option<u8>> {
+    fn to_value(&self) -> ds::Value {
+        self.0.map(ByteValue).to_value()
+    }
+}
+
+impl FromValue for ByteValue<Option<u8>> {
+    fn from_value(value: &ds::Value) -> Result<Self, EntailError> {
+        Option::<ByteValue<u8>>::from_value(value).map(|val| ByteValue(val.map(|val| val.0)))
+    }
+
+    fn from_value_lenient(value: &ds::Value) -> Result<Self, EntailError> {
+        Option::<ByteValue<u8>>::from_value_lenient(value)
+            .map(|val| ByteValue(val.map(|val| val.0)))
+    }
+}
+
+macro_rules! impl_non_zero_conversion {
+    ($ty:ty, $int:ty) => {
+        /// Mapped to an integer, a stored zero is a mapping error.
+        impl IntoValue for $ty {
+            fn to_value(&self) -> ds::Value {
+                self.get().to_value()
+            }
+        }
+
+        impl FromValue for $ty {
+            fn from_value(value: &ds::Value) -> Result<Self, EntailError> {
+                non_zero(<$int>::from_value(value)?)
+            }
+
+            fn from_value_lenient(value: &ds::Value) -> Result<Self, EntailError> {
+                non_zero(<$int>::from_value_lenient(value)?)
+            }
+        }
+    };
+}
+
+fn non_zero<T: TryFrom<I>, I>(value: I) -> Result<T, EntailError> {
+    T::try_from(value).map_err(|_| {
+        EntailError::simple(
+            EntailErrorKind::PropertyMappingError,
+            "Expected a non-zero Integer, but got 0",
+        )
+    })
+}
+
+impl_non_zero_conversion!(std::num::NonZeroI64, i64);
+impl_non_zero_conversion!(std::num::NonZeroU32, u32);
+
+/// `None` is converted to [`ds::Value::Null`] and a [`ds::Value::Null`] is read as `None`.
+impl<T: IntoValue> IntoValue for Option<T> {
+    fn to_value(&self) -> ds::Value {
+        match self {
+            Some(val) => val.to_value(),
+            None => ds::Value::null(),
+        }
+    }
+
+    fn try_to_value(&self) -> Result<ds::Value, EntailError> {
+        match self {
+            Some(val) => val.try_to_value(),
+            None => Ok(ds::Value::null()),
+        }
+    }
+}
+
+impl<T: FromValue> FromValue for Option<T> {
+    fn from_value(value: &ds::Value) -> Result<Self, EntailError> {
+        match value {
+            ds::Value::Null => Ok(None),
+            _ => T::from_value(value).map(Some),
+        }
+    }
+
+    fn from_value_lenient(value: &ds::Value) -> Result<Self, EntailError> {
+        match value {
+            ds::Value::Null => Ok(None),
+            _ => T::from_value_lenient(value).map(Some),
+        }
+    }
+}
+
+/// A vector is converted to a [`ds::Value::Array`]. When reading, a [`ds::Value::Null`]
+/// becomes an empty vector and a single non-array value a vector of one.
+impl<T: IntoValue> IntoValue for Vec<T> {
+    fn to_value(&self) -> ds::Value {
+        ds::Value::array(self.iter().map(IntoValue::to_value).collect())
+    }
+
+    fn try_to_value(&self) -> Result<ds::Value, EntailError> {
+        let values: Result<Vec<ds::Value>, EntailError> =
+            self.iter().map(IntoValue::try_to_value).collect();
+        Ok(ds::Value::array(values?))
+    }
+}
+
+impl<T: FromValue> FromValue for Vec<T> {
+    fn from_value(value: &ds::Value) -> Result<Self, EntailError> {
+        match value {
+            ds::Value::Null => Ok(vec![]),
+            ds::Value::Array(arr) => arr.iter().map(T::from_value).collect(),
+            _ => Ok(vec![T::from_value(value)?]),
+        }
+    }
+
+    fn from_value_lenient(value: &ds::Value) -> Result<Self, EntailError> {
+        match value {
+            ds::Value::Null => Ok(vec![]),
+            ds::Value::Array(arr) => arr.iter().map(T::from_value_lenient).collect(),
+            _ => Ok(vec![T::from_value_lenient(value)?]),
+        }
+    }
+}
+
+/// Sets are converted to a [`ds::Value::Array`] like vectors. A `BTreeSet` is stored in
+/// sorted order, a `HashSet` in its iteration order. Duplicates are dropped when reading.
+macro_rules! impl_set_conversion {
+    ($set:ident<T $(, $hasher:ident)?> where $($bound:path),*) => {
+        impl<T: IntoValue $(, $hasher)?> IntoValue for $set<T $(, $hasher)?> {
+            fn to_value(&self) -> ds::Value {
+                ds::Value::array(self.iter().map(IntoValue::to_value).collect())
+            }
+
+            fn try_to_value(&self) -> Result<ds::Value, EntailError> {
+                let values: Result<Vec<ds::Value>, EntailError> =
+                    self.iter().map(IntoValue::try_to_value).collect();
+                Ok(ds::Value::array(values?))
+            }
+        }
+
+        impl<T: FromValue $(+ $bound)* $(, $hasher: BuildHasher + Default)?> FromValue
+            for $set<T $(, $hasher)?>
+        {
+            fn from_value(value: &ds::Value) -> Result<Self, EntailError> {
+                Vec::<T>::from_value(value).map(|values| values.into_iter().collect())
+            }
+
+            fn from_value_lenient(value: &ds::Value) -> Result<Self, EntailError> {
+                Vec::<T>::from_value_lenient(value).map(|values| values.into_iter().collect())
+            }
+        }
+    };
+}
+
+impl_set_conversion!(BTreeSet<T> where Ord);
+impl_set_conversion!(HashSet<T, S> where Eq, Hash);
+
+/// A string slice is converted to a [`ds::Value::UnicodeString`], so that `Box<str>` and
+/// `Arc<str>` can be converted as well.
+impl IntoValue for str {
+    fn to_value(&self) -> ds::Value {
+        ds::Value::unicode_string(self.to_string())
+    }
+}
+
+/// Smart pointers are converted the same way as the value they point to.
+macro_rules! impl_pointer_conversion {
+    ($($pointer:ident),*) => {
+        $(
+            impl<T: IntoValue + ?Sized> IntoValue for $pointer<T> {
+                fn to_value(&self) -> ds::Value {
+                    self.as_ref().to_value()
+                }
+
+                fn try_to_value(&self) -> Result<ds::Value, EntailError> {
+                    self.as_ref().try_to_value()
+                }
+            }
+
+            impl<T: FromValue> FromValue for $pointer<T> {
+                fn from_value(value: &ds::Value) -> Result<Self, EntailError> {
+                    T::from_value(value).map($pointer::new)
+                }
+
+                fn from_value_lenient(value: &ds::Value) -> Result<Self, EntailError> {
+                    T::from_value_lenient(value).map($pointer::new)
+                }
+            }
+
+            impl FromValue for $pointer<str> {
+                fn from_value(value: &ds::Value) -> Result<Self, EntailError> {
+                    String::from_value(value).map($pointer::from)
+                }
+
+                fn from_value_lenient(value: &ds::Value) -> Result<Self, EntailError> {
+                    String::from_value_lenient(value).map($pointer::from)
+                }
+            }
+        )*
+    };
+}
+
+impl_pointer_conversion!(Box, Arc);
+
+/// A UUID is stored as its hyphenated string representation. When reading, 16 byte blobs are
+/// accepted as well.
+#[cfg(feature = "uuid")]
+impl IntoValue for uuid::Uuid {
+    fn to_value(&self) -> ds::Value {
+        ds::Value::unicode_string(self.hyphenated().to_string())
+    }
+}
+
+#[cfg(feature = "uuid")]
+impl FromValue for uuid::Uuid {
+    fn from_value(value: &ds::Value) -> Result<Self, EntailError> {
+        let uuid = match value {
+            ds::Value::UnicodeString(val) => uuid::Uuid::parse_str(val).ok(),
+            ds::Value::Blob(val) => uuid::Uuid::from_slice(val).ok(),
+            _ => None,
+        };
+        uuid.ok_or_else(|| mismatch("a UUID UnicodeString or 16 byte Blob"))
+    }
+}
+
+/// Serializes a value with `serde_json` into a string [`ds::Value`]. This is used for the
+/// fields marked with `#[entail(json)]`.
+///
+/// ## Returns
+/// A [`Result`] containing the string value, or an [`EntailError`] of the kind
+/// [`EntailErrorKind::PropertyMappingError`] if the value cannot be serialized.
+pub fn to_json_value<T: serde::Serialize + ?Sized>(value: &T) -> Result<ds::Value, EntailError> {
+    match serde_json::to_string(value) {
+        Ok(json) => Ok(ds::Value::unicode_string(json)),
+        Err(err) => Err(EntailError::simple(
+            EntailErrorKind::PropertyMappingError,
+            format!("Cannot serialize to JSON: {}", err),
+        )),
+    }
+}
+
+/// Deserializes a value written by [`to_json_value`]. Blobs holding UTF-8 JSON are
+/// accepted as well, and [`ds::Value::Null`] (an absent property) is read as the JSON `null`.
+///
+/// ## Returns
+/// A [`Result`] containing the deserialized value, or an [`EntailError`] of the kind
+/// [`EntailErrorKind::PropertyMappingError`] if the value is not a string or a blob, or it
+/// cannot be deserialized into `T`.
+pub fn from_json_value<T: serde::de::DeserializeOwned>(
+    value: &ds::Value,
+) -> Result<T, EntailError> {
+    let result = match value {
+        ds::Value::Null => serde_json::from_str("null"),
+        ds::Value::UnicodeString(json) => serde_json::from_str(json),
+        ds::Value::Blob(json) => serde_json::from_slice(json),
+        _ => return Err(mismatch("JSON UnicodeString or Blob")),
+    };
+    result.map_err(|err| {
+        EntailError::simple(
+            EntailErrorKind::PropertyMappingError,
+            format!("Invalid JSON: {}", err),
+        )
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_conversions() {
+        assert_eq!(42i32.to_value(), ds::Value::integer(42));
+        assert_eq!(u32::from_value(&ds::Value::integer(7)).unwrap(), 7);
+        assert_eq!(Some(true).to_value(), ds::Value::boolean(true));
+        assert_eq!(None::<bool>.to_value(), ds::Value::null());
+        assert_eq!(
+            Option::<String>::from_value(&ds::Value::null()).unwrap(),
+            None
+        );
+        assert_eq!(
+            Vec::<i64>::from_value(&ds::Value::integer(3)).unwrap(),
+            vec![3]
+        );
+        assert_eq!(
+            Vec::<i64>::from_value(&ds::Value::array(vec![1.into(), 2.into()])).unwrap(),
+            vec![1, 2]
+        );
+        assert_eq!(vec![1u8, 2].to_value(), ds::Value::blob(vec![1u8, 2]));
+        assert_eq!([1u8, 2].to_value(), ds::Value::blob(vec![1u8, 2]));
+        assert_eq!(
+            <[u8; 2]>::from_value(&ds::Value::blob(vec![1u8, 2])).unwrap(),
+            [1, 2]
+        );
+        let err = <[u8; 3]>::from_value(&ds::Value::blob(vec![1u8, 2])).unwrap_err();
+        assert_eq!(err.message, "Expected a Blob of 3 bytes, got 2 bytes");
+        assert_eq!(
+            Cow::<'static, [u8]>::from_value(&ds::Value::blob(vec![1u8])).unwrap(),
+            Cow::Borrowed(&[1u8][..])
+        );
+        let err = i64::from_value(&ds::Value::null()).unwrap_err();
+        assert_eq!(err.kind, EntailErrorKind::PropertyMappingError);
+        assert!(Vec::<i64>::from_value(&ds::Value::array(vec![true.into()])).is_err());
+        let id = std::num::NonZeroI64::new(-5).unwrap();
+        assert_eq!(id.to_value(), ds::Value::integer(-5));
+        assert_eq!(
+            std::num::NonZeroI64::from_value(&ds::Value::integer(-5)).unwrap(),
+            id
+        );
+        let err = std::num::NonZeroU32::from_value(&ds::Value::integer(0)).unwrap_err();
+        assert_eq!(err.kind, EntailErrorKind::PropertyMappingError);
+        assert_eq!(
+            Option::<std::num::NonZeroU32>::from_value(&ds::Value::null()).unwrap(),
+            None
+        );
+    }
+
+    #[test]
+    fn test_checked_integer_conversions() {
+        assert_eq!((-3i8).to_value(), ds::Value::integer(-3));
+        assert_eq!(u16::from_value(&ds::Value::integer(65535)).unwrap(), 65535);
+        assert!(u16::from_value(&ds::Value::integer(65536)).is_err());
+        let err = u32::from_value(&ds::Value::integer(-1)).unwrap_err();
+        assert_eq!(err.message, "Integer -1 is out of the range of u32");
+        assert!(i32::from_value(&ds::Value::integer(i64::from(i32::MAX) + 1)).is_err());
+        assert!(i32::from_value_lenient(&"3000000000".into()).is_err());
+        assert!(std::num::NonZeroU32::from_value(&ds::Value::integer(-1)).is_err());
+        assert_eq!(i16::from_value_lenient(&"-7".into()).unwrap(), -7);
+        assert_eq!(
+            (i64::MAX as u64).try_to_value().unwrap(),
+            ds::Value::integer(i64::MAX)
+        );
+        let err = u64::MAX.try_to_value().unwrap_err();
+        assert_eq!(err.kind, EntailErrorKind::PropertyMappingError);
+        assert!(vec![1u64, u64::MAX].try_to_value().is_err());
+        assert!(u64::from_value(&ds::Value::integer(-1)).is_err());
+        assert_eq!(ByteValue(200u8).to_value(), ds::Value::integer(200));
+        assert!(ByteValue::<u8>::from_value(&ds::Value::integer(256)).is_err());
+        assert_eq!(
+            ByteValue::<Option<u8>>::from_value(&ds::Value::null()).unwrap(),
+            ByteValue(None)
+        );
+    }
+
+    #[test]
+    fn test_duration_conversions() {
+        use std::time::Duration;
+        let duration = Duration::from_nanos(1_500_999);
+        assert_eq!(duration.to_value(), ds::Value::integer(1500));
+        assert_eq!(
+            Duration::from_value(&ds::Value::integer(1500)).unwrap(),
+            Duration::from_micros(1500)
+        );
+        assert_eq!(
+            Option::<Duration>::from_value(&ds::Value::null()).unwrap(),
+            None
+        );
+        let err = Duration::from_value(&ds::Value::integer(-1)).unwrap_err();
+        assert_eq!(err.kind, EntailErrorKind::PropertyMappingError);
+        assert!(Duration::MAX.try_to_value().is_err());
+    }
+
+    #[cfg(feature = "uuid")]
+    #[test]
+    fn test_uuid_conversions() {
+        let uuid = uuid::Uuid::from_u128(0x67e55044_10b1_426f_9247_bb680e5fe0c8);
+        let value = uuid.to_value();
+        assert_eq!(
+            value,
+            ds::Value::unicode_string("67e55044-10b1-426f-9247-bb680e5fe0c8")
+        );
+        assert_eq!(uuid::Uuid::from_value(&value).unwrap(), uuid);
+        let blob = ds::Value::blob(uuid.as_bytes().to_vec());
+        assert_eq!(uuid::Uuid::from_value(&blob).unwrap(), uuid);
+        let err = uuid::Uuid::from_value(&"not-a-uuid".into()).unwrap_err();
+        assert_eq!(err.kind, EntailErrorKind::PropertyMappingError);
+        assert!(uuid::Uuid::from_value(&ds::Value::blob(vec![1u8, 2])).is_err());
+    }
+
+    #[test]
+    fn test_pointer_conversions() {
+        assert_eq!(Box::new(42i64).to_value(), ds::Value::integer(42));
+        let value = ds::Value::unicode_string("boxed");
+        assert_eq!(*Box::<String>::from_value(&value).unwrap(), "boxed");
+        assert_eq!(&*Arc::<str>::from_value(&value).unwrap(), "boxed");
+        assert_eq!(Arc::<str>::from("boxed").to_value(), value);
+        assert_eq!(Box::<str>::from("boxed").to_value(), value);
+        assert_eq!(
+            Option::<Arc<i64>>::from_value_lenient(&"7".into()).unwrap(),
+            Some(Arc::new(7))
+        );
+        assert!(Box::<str>::from_value(&ds::Value::integer(1)).is_err());
+    }
+
+    #[test]
+    fn test_json_conversions() {
+        let map: std::collections::BTreeMap<String, Vec<i64>> =
+            [("a".to_string(), vec![1, 2])].into();
+        let value = to_json_value(&map).unwrap();
+        assert_eq!(value, ds::Value::unicode_string(r#"{"a":[1,2]}"#));
+        assert_eq!(
+            from_json_value::<std::collections::BTreeMap<String, Vec<i64>>>(&value).unwrap(),
+            map
+        );
+        assert_eq!(
+            from_json_value::<Vec<bool>>(&ds::Value::blob(b"[true]".to_vec())).unwrap(),
+            vec![true]
+        );
+        assert_eq!(
+            from_json_value::<Option<i64>>(&ds::Value::null()).unwrap(),
+            None
+        );
+        let err = from_json_value::<i64>(&ds::Value::null()).unwrap_err();
+        assert_eq!(err.kind, EntailErrorKind::PropertyMappingError);
+        assert!(from_json_value::<i64>(&ds::Value::integer(1)).is_err());
+    }
+
+    #[test]
+    fn test_lenient_conversions() {
+        assert_eq!(i64::from_value_lenient(&" 42".into()).unwrap(), 42);
+        assert!(i64::from_value(&"42".into()).is_err());
+        assert!(i64::from_value_lenient(&"forty-two".into()).is_err());
+        assert_eq!(
+            f64::from_value_lenient(&ds::Value::integer(2)).unwrap(),
+            2.0
+        );
+        assert!(bool::from_value_lenient(&"TRUE".into()).unwrap());
+        assert!(!bool::from_value_lenient(&"false".into()).unwrap());
+        assert_eq!(
+            String::from_value_lenient(&ds::Value::integer(7)).unwrap(),
+            "7"
+        );
+        assert_eq!(
+            Option::<u32>::from_value_lenient(&"12".into()).unwrap(),
+            Some(12)
+        );
+        assert_eq!(
+            Vec::<bool>::from_value_lenient(&ds::Value::array(vec!["true".into(), true.into()]))
+                .unwrap(),
+            vec![true, true]
+        );
+    }
+}
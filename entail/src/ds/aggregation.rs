@@ -0,0 +1,289 @@
+use super::*;
+
+use std::borrow::Cow;
+use std::collections::HashMap;
+
+use crate::{EntailError, EntailErrorKind};
+
+/// An aggregation computed by an [`AggregationQuery`], under an alias naming its result.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Aggregation {
+    /// The name of the result of the aggregation, see [`AggregationResult::get`].
+    pub alias: Cow<'static, str>,
+    /// What is computed.
+    pub operator: AggregationOperator,
+}
+
+/// The computation of an [`Aggregation`].
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum AggregationOperator {
+    /// Counts the entities, up to a limit if there is one, which makes the count cheaper.
+    Count { up_to: Option<i64> },
+    /// Sums the numeric values of a property. The result is an integer if all the values
+    /// are integers and the sum doesn't overflow, a floating point number otherwise.
+    Sum { property: Cow<'static, str> },
+    /// Averages the numeric values of a property. The result is a floating point number, or
+    /// null if there are no numeric values.
+    Avg { property: Cow<'static, str> },
+}
+
+/// A query computing aggregations (counts, sums and averages) over the entities matching a
+/// [`Query`] without reading them, run with [`DatastoreShell::run_aggregation`].
+///
+/// The limit of the query caps the number of aggregated entities. [`AggregationQuery::new`]
+/// clears it, so that the default limit of a [`Query`] (`1000`) doesn't cap a count at
+/// `1000` as well: all the matching entities are aggregated unless a limit is set with
+/// [`AggregationQuery::with_limit`]. Projections and `distinct_on` are not supported by
+/// Datastore.
+///
+/// The aggregation methods and [`AggregationQuery::with_limit`] consume and return `Self`,
+/// allowing for method chaining.
+#[derive(Clone, Debug)]
+pub struct AggregationQuery {
+    /// The query selecting the aggregated entities.
+    pub query: Query,
+    /// The aggregations to compute, Datastore accepts at most 5.
+    pub aggregations: Vec<Aggregation>,
+}
+
+impl AggregationQuery {
+    /// Creates an aggregation query over the results of a query, with no aggregations yet.
+    /// The limit of the query is cleared, see [`AggregationQuery::with_limit`].
+    ///
+    /// ## Parameters
+    /// - `query`: The query selecting the aggregated entities.
+    pub fn new(query: Query) -> Self {
+        Self {
+            query: Query { limit: 0, ..query },
+            aggregations: Vec::new(),
+        }
+    }
+
+    /// Caps the number of aggregated entities, `0` aggregates all of them.
+    pub fn with_limit(mut self, limit: i32) -> Self {
+        self.query.limit = limit;
+        self
+    }
+
+    /// Adds an aggregation.
+    pub fn aggregate(
+        mut self,
+        alias: impl Into<Cow<'static, str>>,
+        operator: AggregationOperator,
+    ) -> Self {
+        self.aggregations.push(Aggregation {
+            alias: alias.into(),
+            operator,
+        });
+        self
+    }
+
+    /// Adds a count of the entities.
+    pub fn count(self, alias: impl Into<Cow<'static, str>>) -> Self {
+        self.aggregate(alias, AggregationOperator::Count { up_to: None })
+    }
+
+    /// Adds a count of the entities that stops at `up_to`, e.g. to check whether there are
+    /// more than a few.
+    pub fn count_up_to(self, alias: impl Into<Cow<'static, str>>, up_to: i64) -> Self {
+        self.aggregate(alias, AggregationOperator::Count { up_to: Some(up_to) })
+    }
+
+    /// Adds the sum of the values of a property.
+    pub fn sum(
+        self,
+        alias: impl Into<Cow<'static, str>>,
+        property: impl Into<Cow<'static, str>>,
+    ) -> Self {
+        let property = property.into();
+        self.aggregate(alias, AggregationOperator::Sum { property })
+    }
+
+    /// Adds the average of the values of a property.
+    pub fn avg(
+        self,
+        alias: impl Into<Cow<'static, str>>,
+        property: impl Into<Cow<'static, str>>,
+    ) -> Self {
+        let property = property.into();
+        self.aggregate(alias, AggregationOperator::Avg { property })
+    }
+}
+
+impl From<Query> for AggregationQuery {
+    fn from(query: Query) -> Self {
+        Self::new(query)
+    }
+}
+
+impl From<AggregationQuery> for google_datastore1::api::AggregationQuery {
+    fn from(query: AggregationQuery) -> Self {
+        let property = |name: Cow<'static, str>| {
+            Some(google_datastore1::api::PropertyReference {
+                name: Some(name.into_owned()),
+            })
+        };
+        google_datastore1::api::AggregationQuery {
+            aggregations: Some(
+                query
+                    .aggregations
+                    .into_iter()
+                    .map(|aggregation| {
+                        let mut api = google_datastore1::api::Aggregation {
+                            alias: Some(aggregation.alias.into_owned()),
+                            ..Default::default()
+                        };
+                        match aggregation.operator {
+                            AggregationOperator::Count { up_to } => {
+                                api.count = Some(google_datastore1::api::Count { up_to });
+                            }
+                            AggregationOperator::Sum { property: name } => {
+                                api.sum = Some(google_datastore1::api::Sum {
+                                    property: property(name),
+                                });
+                            }
+                            AggregationOperator::Avg { property: name } => {
+                                api.avg = Some(google_datastore1::api::Avg {
+                                    property: property(name),
+                                });
+                            }
+                        }
+                        api
+                    })
+                    .collect(),
+            ),
+            nested_query: Some(query.query.into()),
+        }
+    }
+}
+
+/// The results of an [`AggregationQuery`], by their aliases.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct AggregationResult {
+    /// The results of the aggregations.
+    pub values: HashMap<String, Value>,
+    /// The time the aggregations were computed at.
+    pub read_time: Option<chrono::DateTime<chrono::offset::Utc>>,
+}
+
+impl AggregationResult {
+    /// Gets the result of an aggregation.
+    pub fn get(&self, alias: &str) -> Option<&Value> {
+        self.values.get(alias)
+    }
+
+    /// Gets the result of a count.
+    pub fn count(&self, alias: &str) -> Option<i64> {
+        self.get(alias).and_then(Value::integer_value)
+    }
+
+    /// Gets the result of a sum as a floating point number, whether it is an integer or not.
+    /// Integer sums above 2^53 lose precision, [`AggregationResult::sum_integer`] returns them
+    /// as they are.
+    pub fn sum(&self, alias: &str) -> Option<f64> {
+        match self.get(alias)? {
+            Value::Integer(sum) => Some(*sum as f64),
+            Value::FloatingPoint(sum) => Some(*sum),
+            _ => None,
+        }
+    }
+
+    /// Gets the result of a sum if it is an integer, which it is if all the summed values are
+    /// integers and the sum doesn't overflow.
+    pub fn sum_integer(&self, alias: &str) -> Option<i64> {
+        self.get(alias).and_then(Value::integer_value)
+    }
+
+    /// Gets the result of an average, `None` if there were no values to average.
+    pub fn avg(&self, alias: &str) -> Option<f64> {
+        self.get(alias).and_then(Value::double_value)
+    }
+}
+
+impl TryFrom<google_datastore1::api::AggregationResultBatch> for AggregationResult {
+    type Error = EntailError;
+
+    /// Converts the single result of the batch returned for an aggregation query.
+    fn try_from(
+        batch: google_datastore1::api::AggregationResultBatch,
+    ) -> Result<Self, EntailError> {
+        let result = batch
+            .aggregation_results
+            .and_then(|results| results.into_iter().next())
+            .ok_or_else(|| {
+                EntailError::simple(
+                    EntailErrorKind::RequestFailure,
+                    "Malformed aggregation response: missing the result",
+                )
+            })?;
+        let values = result
+            .aggregate_properties
+            .unwrap_or_default()
+            .into_iter()
+            .map(|(alias, value)| Ok((alias, Value::try_from(value)?)))
+            .collect::<Result<_, EntailError>>()?;
+        Ok(Self {
+            values,
+            read_time: batch.read_time,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_aggregation_query() {
+        let query = Query {
+            kind: "Order".into(),
+            ..Default::default()
+        };
+        let unlimited = AggregationQuery::new(query.clone());
+        assert_eq!(unlimited.query.limit, 0);
+        assert_eq!(unlimited.with_limit(10).query.limit, 10);
+        let api: google_datastore1::api::AggregationQuery = AggregationQuery::new(query)
+            .count("orders")
+            .count_up_to("some", 5)
+            .sum("total", "amount")
+            .avg("average", "amount")
+            .into();
+        let json = serde_json::to_value(&api).unwrap();
+        assert_eq!(json["nestedQuery"]["kind"][0]["name"], "Order");
+        assert_eq!(json["aggregations"][0]["alias"], "orders");
+        assert!(json["aggregations"][0]["count"].is_object());
+        assert_eq!(json["aggregations"][1]["count"]["upTo"], "5");
+        assert_eq!(json["aggregations"][2]["sum"]["property"]["name"], "amount");
+        assert_eq!(json["aggregations"][3]["avg"]["property"]["name"], "amount");
+
+        let batch: google_datastore1::api::AggregationResultBatch =
+            serde_json::from_value(serde_json::json!({
+                "aggregationResults": [{
+                    "aggregateProperties": {
+                        "orders": {"integerValue": "3"},
+                        "total": {"integerValue": "12"},
+                        "large": {"integerValue": "9007199254740993"},
+                        "fractional": {"doubleValue": 2.5},
+                        "average": {"doubleValue": 4.0},
+                        "empty": {"nullValue": null}
+                    }
+                }],
+                "moreResults": "NO_MORE_RESULTS"
+            }))
+            .unwrap();
+        let result = AggregationResult::try_from(batch).unwrap();
+        assert_eq!(result.count("orders"), Some(3));
+        assert_eq!(result.sum("total"), Some(12.0));
+        assert_eq!(result.sum_integer("large"), Some(9_007_199_254_740_993));
+        assert_eq!(result.sum("fractional"), Some(2.5));
+        assert_eq!(result.sum_integer("fractional"), None);
+        assert_eq!(result.avg("average"), Some(4.0));
+        assert_eq!(result.get("empty"), Some(&Value::Null));
+        assert_eq!(result.avg("empty"), None);
+        assert_eq!(result.count("missing"), None);
+
+        let empty = google_datastore1::api::AggregationResultBatch::default();
+        let err = AggregationResult::try_from(empty).unwrap_err();
+        assert_eq!(err.kind, EntailErrorKind::RequestFailure);
+    }
+}
@@ -0,0 +1,138 @@
+use std::future::Future;
+use std::ops::Range;
+use std::pin::Pin;
+use std::time::Duration;
+
+/// The source of the waits between the attempts of a [`super::Transaction`].
+///
+/// The default is [`TokioClock`]. Tests can inject a clock that records the requested
+/// delays, or returns immediately, to check the retry behavior without waiting.
+pub trait Clock: Send + Sync {
+    /// Returns a future completing after `duration`.
+    fn sleep(&self, duration: Duration) -> Pin<Box<dyn Future<Output = ()> + Send>>;
+}
+
+/// A [`Clock`] sleeping with [`tokio::time::sleep`], so it follows the paused time of
+/// `#[tokio::test(start_paused = true)]` tests as well.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct TokioClock;
+
+impl Clock for TokioClock {
+    fn sleep(&self, duration: Duration) -> Pin<Box<dyn Future<Output = ()> + Send>> {
+        Box::pin(tokio::time::sleep(duration))
+    }
+}
+
+/// The source of the random numbers used for the jitter of the retry delays.
+///
+/// It is implemented for [`fastrand::Rng`], which is the default. A seeded `Rng` or a fixed
+/// implementation makes the delays deterministic.
+pub trait JitterSource {
+    /// Returns a number in `range`, which is never empty.
+    fn next_in(&mut self, range: Range<u64>) -> u64;
+}
+
+impl JitterSource for fastrand::Rng {
+    fn next_in(&mut self, range: Range<u64>) -> u64 {
+        self.u64(range)
+    }
+}
+
+/// How the random jitter is applied to the exponentially growing retry delays of a
+/// [`super::Transaction`].
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum Jitter {
+    /// The delay is picked between the previous and the next step of the exponential schedule
+    /// (or between half of the step and the step for retries without backoff).
+    #[default]
+    Bounded,
+    /// The delay is picked between zero and the next step of the exponential schedule, which
+    /// spreads competing retries the most.
+    Full,
+    /// The delay is picked between the first retry delay and three times the previous delay,
+    /// capped by the maximum retry delay only, so consecutive delays are not correlated with
+    /// the schedule of other clients. The exponential schedule is not used.
+    Decorrelated,
+}
+
+impl Jitter {
+    /// Picks the delay of a retry.
+    ///
+    /// ## Parameters
+    /// - `source`: The source of the random numbers.
+    /// - `floor`: The lower bound of the [`Jitter::Bounded`] delay.
+    /// - `step`: The next step of the exponential schedule, the upper bound of the delay.
+    /// - `base`: The delay of the first retry.
+    /// - `previous`: The previous delay, or `base` before the first retry.
+    /// - `max_delay`: The upper bound of the delays, if there is one.
+    pub fn delay(
+        self,
+        source: &mut dyn JitterSource,
+        floor: Duration,
+        step: Duration,
+        base: Duration,
+        previous: Duration,
+        max_delay: Option<Duration>,
+    ) -> Duration {
+        let (min, max) = match self {
+            Jitter::Bounded => (floor, step),
+            Jitter::Full => (Duration::ZERO, step),
+            Jitter::Decorrelated => (base, previous.saturating_mul(3)),
+        };
+        let (min, max) = (min.as_micros() as u64, max.as_micros() as u64);
+        let micros = if max > min {
+            source.next_in(min..max)
+        } else {
+            max
+        };
+        capped(Duration::from_micros(micros), max_delay)
+    }
+}
+
+/// Limits a step of the exponential schedule to the maximum delay, if there is one.
+pub(crate) fn capped(delay: Duration, max_delay: Option<Duration>) -> Duration {
+    max_delay.map_or(delay, |max_delay| delay.min(max_delay))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Always picks the upper end of the range.
+    struct Highest;
+
+    impl JitterSource for Highest {
+        fn next_in(&mut self, range: Range<u64>) -> u64 {
+            range.end - 1
+        }
+    }
+
+    #[test]
+    fn test_jitter() {
+        let ms = Duration::from_millis;
+        let us = Duration::from_micros;
+        let mut seeded = fastrand::Rng::with_seed(7);
+        let mut highest = Highest;
+        let bounded = Jitter::Bounded.delay(&mut highest, ms(25), ms(50), ms(25), ms(25), None);
+        assert_eq!(bounded, ms(50) - us(1));
+        let full = Jitter::Full.delay(&mut seeded, ms(25), ms(50), ms(25), ms(25), None);
+        assert!(full < ms(50));
+        // the step of the schedule doesn't bound it
+        let decorrelated =
+            Jitter::Decorrelated.delay(&mut highest, ms(25), ms(50), ms(25), ms(40), None);
+        assert_eq!(decorrelated, ms(120) - us(1));
+        // capped by the maximum delay
+        let capped_delay =
+            Jitter::Decorrelated.delay(&mut highest, ms(25), ms(50), ms(25), ms(40), Some(ms(100)));
+        assert_eq!(capped_delay, ms(100));
+        // an empty range results in its bound
+        assert_eq!(
+            Jitter::Bounded.delay(&mut highest, ms(50), ms(50), ms(25), ms(25), None),
+            ms(50)
+        );
+
+        assert_eq!(capped(ms(800), Some(ms(500))), ms(500));
+        assert_eq!(capped(ms(200), Some(ms(500))), ms(200));
+        assert_eq!(capped(ms(800), None), ms(800));
+    }
+}
@@ -0,0 +1,180 @@
+use super::*;
+
+use bytes::Bytes;
+use std::borrow::Cow;
+
+impl Entity {
+    /// Starts assembling an entity with typed setters, e.g.
+    /// `Entity::build(key).string("name", "Lamp").int("count", 3).finish()`.
+    ///
+    /// ## Parameters
+    /// - `key`: The key of the entity.
+    pub fn build(key: Key) -> EntityBuilder {
+        EntityBuilder {
+            entity: Entity::new(key),
+        }
+    }
+}
+
+/// Assembles an [`Entity`] property by property, created with [`Entity::build`].
+///
+/// The properties are indexed unless they are set with an `unindexed` method. Setting a
+/// property again replaces it, the same way [`Entity::set`] does.
+///
+/// Every setter consumes and returns the builder, allowing for method chaining.
+#[derive(Debug, Clone)]
+pub struct EntityBuilder {
+    entity: Entity,
+}
+
+impl EntityBuilder {
+    /// Sets an indexed property of any value.
+    pub fn value(mut self, name: impl Into<Cow<'static, str>>, value: impl Into<Value>) -> Self {
+        self.entity.set_indexed(name, value.into());
+        self
+    }
+
+    /// Sets an unindexed property of any value.
+    pub fn unindexed(
+        mut self,
+        name: impl Into<Cow<'static, str>>,
+        value: impl Into<Value>,
+    ) -> Self {
+        self.entity.set_unindexed(name, value.into());
+        self
+    }
+
+    /// Sets a property with its indexing and meaning, see [`PropertyValue::of`].
+    pub fn property(mut self, name: impl Into<Cow<'static, str>>, property: PropertyValue) -> Self {
+        self.entity.set_property(name, property);
+        self
+    }
+
+    /// Sets an indexed string property.
+    pub fn string(
+        self,
+        name: impl Into<Cow<'static, str>>,
+        value: impl Into<Cow<'static, str>>,
+    ) -> Self {
+        self.value(name, Value::unicode_string(value))
+    }
+
+    /// Sets an unindexed string property, e.g. a text longer than [`MAX_INDEXED_BYTES`].
+    pub fn unindexed_string(
+        self,
+        name: impl Into<Cow<'static, str>>,
+        value: impl Into<Cow<'static, str>>,
+    ) -> Self {
+        self.unindexed(name, Value::unicode_string(value))
+    }
+
+    /// Sets an indexed integer property.
+    pub fn int(self, name: impl Into<Cow<'static, str>>, value: i64) -> Self {
+        self.value(name, Value::integer(value))
+    }
+
+    /// Sets an indexed floating point property.
+    pub fn float(self, name: impl Into<Cow<'static, str>>, value: f64) -> Self {
+        self.value(name, Value::floating_point(value))
+    }
+
+    /// Sets an indexed boolean property.
+    pub fn bool(self, name: impl Into<Cow<'static, str>>, value: bool) -> Self {
+        self.value(name, Value::boolean(value))
+    }
+
+    /// Sets an indexed blob property. A [`Bytes`] value is stored without copying it.
+    pub fn blob(self, name: impl Into<Cow<'static, str>>, value: impl Into<Bytes>) -> Self {
+        self.value(name, Value::Blob(value.into()))
+    }
+
+    /// Sets an unindexed blob property, e.g. a payload longer than [`MAX_INDEXED_BYTES`].
+    pub fn unindexed_blob(
+        self,
+        name: impl Into<Cow<'static, str>>,
+        value: impl Into<Bytes>,
+    ) -> Self {
+        self.unindexed(name, Value::Blob(value.into()))
+    }
+
+    /// Sets an indexed key property.
+    pub fn key(self, name: impl Into<Cow<'static, str>>, value: Key) -> Self {
+        self.value(name, Value::key(value))
+    }
+
+    /// Sets an indexed array property, see [`Value::array_of`].
+    pub fn array<T: Into<Value>>(
+        self,
+        name: impl Into<Cow<'static, str>>,
+        values: impl IntoIterator<Item = T>,
+    ) -> Self {
+        self.value(name, Value::array_of(values))
+    }
+
+    /// Sets an indexed null property.
+    pub fn null(self, name: impl Into<Cow<'static, str>>) -> Self {
+        self.value(name, Value::null())
+    }
+
+    /// Returns the assembled entity.
+    pub fn finish(self) -> Entity {
+        self.entity
+    }
+}
+
+impl From<EntityBuilder> for Entity {
+    fn from(builder: EntityBuilder) -> Self {
+        builder.finish()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_entity_builder() {
+        let owner = Key::new("User").with_id(7);
+        let entity = Entity::build(Key::new("Item").with_id(1))
+            .string("name", "Lamp")
+            .int("count", 3)
+            .float("price", 9.5)
+            .bool("active", true)
+            .blob("hash", Bytes::from_static(b"ab"))
+            .key("owner", owner.clone())
+            .array("tags", ["new", "sale"])
+            .null("discount")
+            .unindexed_string("notes", "fragile")
+            .unindexed_blob("data", vec![1u8, 2])
+            .property("body", PropertyValue::of("text").with_meaning(MEANING_TEXT))
+            .finish();
+
+        let mut expected = Entity::new(Key::new("Item").with_id(1));
+        expected
+            .set_indexed("name", Value::unicode_string("Lamp"))
+            .set_indexed("count", Value::integer(3))
+            .set_indexed("price", Value::floating_point(9.5))
+            .set_indexed("active", Value::boolean(true))
+            .set_indexed("hash", Value::blob(b"ab".to_vec()))
+            .set_indexed("owner", Value::key(owner))
+            .set_indexed("tags", Value::from(vec!["new", "sale"]))
+            .set_indexed("discount", Value::null())
+            .set_unindexed("notes", Value::unicode_string("fragile"))
+            .set_unindexed("data", Value::blob(vec![1u8, 2]))
+            .set(
+                "body",
+                Value::unicode_string("text"),
+                true,
+                Some(MEANING_TEXT),
+            );
+        assert_eq!(entity, expected);
+        assert!(!entity.is_indexed("data"));
+
+        let replaced: Entity = Entity::build(Key::new("Item").with_id(1))
+            .int("count", 3)
+            .unindexed("count", 4)
+            .into();
+        assert_eq!(replaced.get_value("count"), Some(&Value::integer(4)));
+        assert!(!replaced.is_indexed("count"));
+    }
+}
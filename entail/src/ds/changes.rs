@@ -0,0 +1,128 @@
+use super::*;
+
+/// The kind of write reported by a [`ChangeEvent`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum ChangeOperation {
+    Insert,
+    Update,
+    Upsert,
+    Delete,
+}
+
+/// A write committed through a [`DatastoreShell`] with a change feed, see
+/// [`DatastoreShell::with_change_feed`].
+#[derive(Debug, Clone, PartialEq)]
+pub struct ChangeEvent {
+    /// The **Kind** of the written entity.
+    pub kind: String,
+    /// The key of the written entity. For inserts of incomplete keys this is the key
+    /// allocated by the Datastore.
+    pub key: Key,
+    /// The operation applied to the entity.
+    pub operation: ChangeOperation,
+}
+
+/// The operations of a batch, captured before the batch is sent so that the events can be
+/// emitted once the commit succeeded.
+pub(crate) struct PendingChanges(Vec<(ChangeOperation, Option<google_datastore1::api::Key>)>);
+
+impl PendingChanges {
+    pub(crate) fn capture(mutations: &[google_datastore1::api::Mutation]) -> Self {
+        Self(
+            mutations
+                .iter()
+                .map(|mutation| {
+                    let (operation, key) = if let Some(entity) = &mutation.insert {
+                        (ChangeOperation::Insert, entity.key.clone())
+                    } else if let Some(entity) = &mutation.update {
+                        (ChangeOperation::Update, entity.key.clone())
+                    } else if let Some(entity) = &mutation.upsert {
+                        (ChangeOperation::Upsert, entity.key.clone())
+                    } else {
+                        (ChangeOperation::Delete, mutation.delete.clone())
+                    };
+                    (operation, key)
+                })
+                .collect(),
+        )
+    }
+
+    /// Pairs the captured operations with the results of the commit, preferring the
+    /// allocated keys over the incomplete ones.
+    pub(crate) fn into_events(self, response: &MutationResponse) -> Vec<ChangeEvent> {
+        self.0
+            .into_iter()
+            .enumerate()
+            .filter_map(|(i, (operation, key))| {
+                let key = response
+                    .mutation_results
+                    .get(i)
+                    .and_then(|result| result.key.clone())
+                    .or_else(|| key.and_then(|key| Key::try_from(key).ok()))?;
+                Some(ChangeEvent {
+                    kind: key.kind().to_string(),
+                    key,
+                    operation,
+                })
+            })
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_change_events() {
+        let batch = MutationBatch::new()
+            .insert(Entity::new(Key::new("Post")))
+            .upsert(Entity::new(Key::new("Post").with_id(7)))
+            .update(Entity::new(Key::new("User").with_name("alice")))
+            .delete(Key::new("User").with_name("bob"));
+        let mutations: Vec<google_datastore1::api::Mutation> = batch.into();
+        let pending = PendingChanges::capture(&mutations);
+        let response = MutationResponse {
+            mutation_results: vec![
+                MutationResult {
+                    key: Some(Key::new("Post").with_id(42)),
+                    version: 1,
+                    create_time: None,
+                    update_time: None,
+                },
+                MutationResult {
+                    key: None,
+                    version: 1,
+                    create_time: None,
+                    update_time: None,
+                },
+            ],
+            ..Default::default()
+        };
+        let events = pending.into_events(&response);
+        assert_eq!(
+            events
+                .iter()
+                .map(|e| (e.kind.as_str(), e.key.clone(), e.operation))
+                .collect::<Vec<_>>(),
+            vec![
+                (
+                    "Post",
+                    Key::new("Post").with_id(42),
+                    ChangeOperation::Insert
+                ),
+                ("Post", Key::new("Post").with_id(7), ChangeOperation::Upsert),
+                (
+                    "User",
+                    Key::new("User").with_name("alice"),
+                    ChangeOperation::Update
+                ),
+                (
+                    "User",
+                    Key::new("User").with_name("bob"),
+                    ChangeOperation::Delete
+                ),
+            ]
+        );
+    }
+}
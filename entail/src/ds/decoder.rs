@@ -0,0 +1,167 @@
+use super::super::*;
+use super::*;
+
+/// Converts batches of lower-level API entities, e.g. the pages of a large query.
+///
+/// The Kinds and property names known to the decoder (see [`EntityDecoder::with_names`] and
+/// [`EntityDecoder::for_model`]) are stored as borrowed `&'static str`s in the converted
+/// entities, instead of a copy of the name in every entity. The property maps are allocated
+/// for the largest entity seen so far, and the models are read through a buffer of entities
+/// kept between the batches.
+///
+/// [`EntityDecoder::with_names`] consumes and returns the decoder, allowing for method chaining.
+#[derive(Debug, Default)]
+pub struct EntityDecoder {
+    /// The known names, grouped by their length.
+    names: Vec<Vec<&'static str>>,
+    property_capacity: usize,
+    entities: Vec<Entity>,
+}
+
+impl EntityDecoder {
+    /// Creates a decoder without known names.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Creates a decoder knowing the Kind and the property names of a model.
+    pub fn for_model<T: EntityModel>() -> Self {
+        Self::new()
+            .with_names([T::KIND])
+            .with_names(T::PROPERTY_NAMES.iter().copied())
+    }
+
+    /// Adds Kinds and property names to the known names.
+    pub fn with_names(mut self, names: impl IntoIterator<Item = &'static str>) -> Self {
+        for name in names {
+            if self.names.len() <= name.len() {
+                self.names.resize_with(name.len() + 1, Vec::new);
+            }
+            if !self.names[name.len()].contains(&name) {
+                self.names[name.len()].push(name);
+            }
+        }
+        self
+    }
+
+    /// Converts a batch of API entities.
+    ///
+    /// ## Returns
+    /// A [`Result`] containing the converted entities in the order of `entities`, or the
+    /// [`EntailError`] of the first entity that cannot be converted (see the conversion of a
+    /// single [`Entity`]).
+    pub fn decode(
+        &mut self,
+        entities: Vec<google_datastore1::api::Entity>,
+    ) -> Result<Vec<Entity>, EntailError> {
+        let mut result = Vec::with_capacity(entities.len());
+        self.decode_into(entities, &mut result)?;
+        Ok(result)
+    }
+
+    /// Converts a batch of API entities, appending them to `out`, so a single vector can
+    /// collect several batches.
+    ///
+    /// ## Returns
+    /// A [`Result`] indicating success, or the [`EntailError`] of the first entity that
+    /// cannot be converted. The entities converted before it are left in `out`.
+    pub fn decode_into(
+        &mut self,
+        entities: Vec<google_datastore1::api::Entity>,
+        out: &mut Vec<Entity>,
+    ) -> Result<(), EntailError> {
+        out.reserve(entities.len());
+        let names = &self.names;
+        let mut intern = |name: String| {
+            let known = names
+                .get(name.len())
+                .and_then(|names| names.iter().find(|&&known| known == name));
+            match known {
+                Some(&known) => Cow::Borrowed(known),
+                None => Cow::Owned(name),
+            }
+        };
+        for entity in entities {
+            let entity = convert_entity(entity, &mut intern, self.property_capacity)?;
+            self.property_capacity = self.property_capacity.max(entity.property_count());
+            out.push(entity);
+        }
+        Ok(())
+    }
+
+    /// Converts a page of query results, as [`QueryResult::try_from`] does.
+    pub fn decode_page(
+        &mut self,
+        batch: google_datastore1::api::QueryResultBatch,
+    ) -> Result<QueryResult<Entity>, EntailError> {
+        let entities = batch
+            .entity_results
+            .unwrap_or_default()
+            .into_iter()
+            .map(|e| e.entity.ok_or_else(missing_entity))
+            .collect::<Result<_, _>>()?;
+        Ok(QueryResult {
+            items: self.decode(entities)?,
+            end_cursor: batch.end_cursor,
+        })
+    }
+
+    /// Converts a batch of API entities into models.
+    ///
+    /// ## Returns
+    /// A [`Result`] containing the models in the order of `entities`, or an [`EntailError`]
+    /// if an entity cannot be converted or mapped to the model.
+    pub fn decode_models<T: EntityModel>(
+        &mut self,
+        entities: Vec<google_datastore1::api::Entity>,
+    ) -> Result<Vec<T>, EntailError> {
+        let mut buffer = std::mem::take(&mut self.entities);
+        let result = self
+            .decode_into(entities, &mut buffer)
+            .and_then(|()| buffer.iter().map(T::from_ds_entity).collect());
+        buffer.clear();
+        self.entities = buffer;
+        result
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_decoder() {
+        let entities: Vec<google_datastore1::api::Entity> = (1..=3)
+            .map(|id| {
+                let mut entity = Entity::new(Key::new("Item").with_id(id));
+                entity
+                    .set_indexed("title", Value::unicode_string("Lamp"))
+                    .set_indexed("stock", Value::integer(id));
+                entity.into()
+            })
+            .collect();
+        let mut decoder = EntityDecoder::new().with_names(["Item", "title"]);
+        let decoded = decoder.decode(entities.clone()).unwrap();
+        let converted: Vec<Entity> = entities
+            .iter()
+            .cloned()
+            .map(|entity| Entity::try_from(entity).unwrap())
+            .collect();
+        assert_eq!(decoded, converted);
+        let names: Vec<_> = decoded[0]
+            .property_iter()
+            .map(|(name, _)| name.clone())
+            .collect();
+        assert!(names.contains(&Cow::Borrowed("title")));
+        assert!(
+            names
+                .iter()
+                .any(|name| matches!(name, Cow::Owned(name) if name == "stock"))
+        );
+        assert_eq!(decoder.property_capacity, 2);
+
+        let mut out = decoded;
+        decoder.decode_into(entities, &mut out).unwrap();
+        assert_eq!(out.len(), 6);
+    }
+}
@@ -0,0 +1,171 @@
+use super::*;
+
+use std::borrow::Cow;
+
+/// A property present in both entities compared by [`Entity::diff`], with a different value,
+/// indexing or meaning.
+#[derive(Debug, Clone, PartialEq)]
+pub struct PropertyChange {
+    /// The name of the property.
+    pub name: Cow<'static, str>,
+    /// The property of the entity `diff` was called on.
+    pub old: PropertyValue,
+    /// The property of the other entity.
+    pub new: PropertyValue,
+}
+
+impl PropertyChange {
+    /// Returns `true` if the value changed, not only the indexing or the meaning.
+    pub fn value_changed(&self) -> bool {
+        self.old.value() != self.new.value()
+    }
+
+    /// Returns `true` if the property became indexed or unindexed.
+    pub fn indexing_changed(&self) -> bool {
+        self.old.is_indexed() != self.new.is_indexed()
+    }
+}
+
+/// The differences between the properties of two entities, see [`Entity::diff`].
+///
+/// Every list is sorted by the property name. The keys of the entities are not compared.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct EntityDiff {
+    /// The properties only the other entity has.
+    pub added: Vec<(Cow<'static, str>, PropertyValue)>,
+    /// The properties only the entity `diff` was called on has.
+    pub removed: Vec<(Cow<'static, str>, PropertyValue)>,
+    /// The properties both entities have, with a different value, indexing or meaning.
+    pub changed: Vec<PropertyChange>,
+}
+
+impl EntityDiff {
+    /// Returns `true` if the entities have the same properties.
+    pub fn is_empty(&self) -> bool {
+        self.added.is_empty() && self.removed.is_empty() && self.changed.is_empty()
+    }
+
+    /// Returns the names of the added, removed and changed properties.
+    pub fn property_names(&self) -> impl Iterator<Item = &str> {
+        self.added
+            .iter()
+            .chain(self.removed.iter())
+            .map(|(name, _)| name.as_ref())
+            .chain(self.changed.iter().map(|change| change.name.as_ref()))
+    }
+
+    /// Applies the differences to an entity: the added and changed properties are set with
+    /// their new values, and the removed ones are removed. Applied to the entity `diff` was
+    /// called on, this results in the properties of the other entity.
+    pub fn apply_to(&self, entity: &mut Entity) {
+        let set = self
+            .added
+            .iter()
+            .map(|(name, property)| (name, property))
+            .chain(
+                self.changed
+                    .iter()
+                    .map(|change| (&change.name, &change.new)),
+            );
+        for (name, property) in set {
+            entity.set(
+                name.clone(),
+                property.value().clone(),
+                property.is_indexed(),
+                property.meaning(),
+            );
+        }
+        for (name, _) in &self.removed {
+            entity.remove(name);
+        }
+    }
+}
+
+impl Entity {
+    /// Compares the properties of this entity to the ones of `other`, e.g. the stored and
+    /// the updated state of an entity, for audit logs or minimal updates.
+    ///
+    /// Values are compared the way Datastore compares them (see [`Value`]), so a `NaN` is not
+    /// reported as a change.
+    ///
+    /// ## Returns
+    /// An [`EntityDiff`] listing the properties `other` added, removed or changed.
+    pub fn diff(&self, other: &Entity) -> EntityDiff {
+        let mut diff = EntityDiff::default();
+        for (name, old) in self.property_iter_raw() {
+            match other.get(name) {
+                Some(new) if new == old => {}
+                Some(new) => diff.changed.push(PropertyChange {
+                    name: name.clone(),
+                    old: old.clone(),
+                    new: new.clone(),
+                }),
+                None => diff.removed.push((name.clone(), old.clone())),
+            }
+        }
+        for (name, new) in other.property_iter_raw() {
+            if !self.has(name) {
+                diff.added.push((name.clone(), new.clone()));
+            }
+        }
+        diff.added.sort_by(|(a, _), (b, _)| a.cmp(b));
+        diff.removed.sort_by(|(a, _), (b, _)| a.cmp(b));
+        diff.changed.sort_by(|a, b| a.name.cmp(&b.name));
+        diff
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_diff() {
+        let mut old = Entity::new(Key::new("Item").with_id(1));
+        old.set_indexed("title", Value::unicode_string("Lamp"))
+            .set_indexed("stock", Value::integer(3))
+            .set_indexed("weight", Value::floating_point(f64::NAN))
+            .set_indexed("notes", Value::unicode_string("Fragile"));
+        let mut new = Entity::new(Key::new("Item").with_id(1));
+        new.set_indexed("title", Value::unicode_string("Lamp"))
+            .set_indexed("stock", Value::integer(5))
+            .set_indexed("weight", Value::floating_point(f64::NAN))
+            .set_unindexed("notes", Value::unicode_string("Fragile"))
+            .set_indexed("color", Value::unicode_string("red"));
+
+        assert!(old.diff(&old).is_empty());
+        let diff = old.diff(&new);
+        assert_eq!(diff.added.len(), 1);
+        assert_eq!(diff.added[0].0, "color");
+        assert!(diff.removed.is_empty());
+        let changes: Vec<_> = diff
+            .changed
+            .iter()
+            .map(|change| {
+                (
+                    change.name.as_ref(),
+                    change.value_changed(),
+                    change.indexing_changed(),
+                )
+            })
+            .collect();
+        assert_eq!(
+            changes,
+            vec![("notes", false, true), ("stock", true, false)]
+        );
+        assert_eq!(
+            diff.property_names().collect::<Vec<_>>(),
+            vec!["color", "notes", "stock"]
+        );
+
+        let reverse = new.diff(&old);
+        assert_eq!(reverse.removed.len(), 1);
+        assert_eq!(reverse.removed[0].0, "color");
+
+        let mut patched = old.clone();
+        diff.apply_to(&mut patched);
+        assert_eq!(patched, new);
+        reverse.apply_to(&mut patched);
+        assert!(patched.diff(&old).is_empty());
+    }
+}
@@ -1,5 +1,14 @@
 use super::super::*;
+use super::{DisplayOptions, PartitionId};
+use bytes::Bytes;
 use std::collections::HashMap;
+use std::sync::Arc;
+
+/// The name the key of an entity has in projections, filters and orders.
+///
+/// An entity converted from the API never has a property of this name: a `__key__` value in
+/// the results of a projection becomes the key of the entity.
+pub const KEY_PROPERTY: &str = "__key__";
 
 /// A trait for types that are associated with a Datastore **Kind**.
 ///
@@ -23,25 +32,122 @@ pub enum KeyVariant {
     Incomplete,
 }
 
+/// Formats the variant as it appears in the string form of a [`Key`]: `id:123`,
+/// `name:"name"` (a JSON string literal), or nothing for an incomplete key.
+impl fmt::Display for KeyVariant {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            KeyVariant::Name(name) => {
+                let lit = serde_json::to_string(&name).map_err(|_| fmt::Error)?;
+                write!(f, "name:{}", lit)
+            }
+            KeyVariant::Id(id) => write!(f, "id:{}", id),
+            KeyVariant::Incomplete => Ok(()),
+        }
+    }
+}
+
+/// The ID or the name of a complete [`Key`], see [`Key::id_or_name`].
+///
+/// With the `serde` feature, it is serialized as an integer or a string.
+#[derive(PartialEq, Eq, Hash, Debug, Clone, Copy)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize), serde(untagged))]
+pub enum IdOrName<'a> {
+    /// An integer ID.
+    Id(i64),
+    /// A string name.
+    Name(&'a str),
+}
+
+/// Formats the ID or the name like [`KeyVariant`] does.
+impl fmt::Display for IdOrName<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            IdOrName::Id(id) => write!(f, "id:{}", id),
+            IdOrName::Name(name) => {
+                let lit = serde_json::to_string(name).map_err(|_| fmt::Error)?;
+                write!(f, "name:{}", lit)
+            }
+        }
+    }
+}
+
+impl From<i64> for KeyVariant {
+    fn from(id: i64) -> Self {
+        KeyVariant::Id(id)
+    }
+}
+
+impl From<&'static str> for KeyVariant {
+    fn from(name: &'static str) -> Self {
+        KeyVariant::Name(name.into())
+    }
+}
+
+impl From<String> for KeyVariant {
+    fn from(name: String) -> Self {
+        KeyVariant::Name(name.into())
+    }
+}
+
+impl From<Cow<'static, str>> for KeyVariant {
+    fn from(name: Cow<'static, str>) -> Self {
+        KeyVariant::Name(name)
+    }
+}
+
 /// A representation of a Google Cloud Datastore Key.
 ///
 /// This structure encapsulates the **kind** of the entity, its **ID or name**,
-/// and an optional **parent Key** to establish entity hierarchy.
-#[derive(PartialEq, Eq, Hash, Debug, Clone)]
+/// an optional **parent Key** to establish entity hierarchy, and the [`PartitionId`] (the
+/// namespace, and possibly the project and the database) the entity belongs to.
+///
+/// The components are reference counted, so cloning a Key is cheap, and the keys created
+/// with the same parent (e.g. by cloning it) share the parent chain instead of copying it.
+/// The methods consuming the Key only copy the components if they are shared.
+#[derive(PartialEq, Eq, Hash, Clone)]
 pub struct Key {
+    inner: Arc<KeyInner>,
+}
+
+#[derive(PartialEq, Eq, Hash, Clone)]
+struct KeyInner {
     kind: Cow<'static, str>,
     variant: KeyVariant,
-    parent: Option<Box<Key>>,
+    parent: Option<Key>,
+    partition: PartitionId,
+}
+
+impl fmt::Debug for Key {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("Key")
+            .field("kind", &self.inner.kind)
+            .field("variant", &self.inner.variant)
+            .field("parent", &self.inner.parent)
+            .field("partition", &self.inner.partition)
+            .finish()
+    }
 }
 
 impl Kind for Key {
     /// Gets a string slice reference to the kind of the entity represented by this Key.
     fn kind(&self) -> &str {
-        self.kind.as_ref()
+        self.inner.kind.as_ref()
     }
 }
 
 impl Key {
+    fn from_inner(inner: KeyInner) -> Self {
+        Key {
+            inner: Arc::new(inner),
+        }
+    }
+
+    /// Gets the components to be modified, copying them first if they are shared.
+    fn inner_mut(&mut self) -> &mut KeyInner {
+        Arc::make_mut(&mut self.inner)
+    }
+
     /// Creates a new **incomplete** Key with only the specified kind.
     ///
     /// This key cannot be used to fetch or update an entity but is the base for
@@ -50,32 +156,42 @@ impl Key {
     /// ## Parameters
     /// - `kind`: The Datastore kind name (e.g., `"User"`, `"Product"`).
     pub fn new(kind: impl Into<Cow<'static, str>>) -> Self {
-        Key {
+        Key::from_inner(KeyInner {
             kind: kind.into(),
             variant: KeyVariant::Incomplete,
             parent: None,
-        }
+            partition: PartitionId::new(),
+        })
     }
 
     /// Creates a new `Key` from its raw components.
     ///
-    /// This is a constant constructor that allows for the manual assembly of a [`Key`]
-    /// using a specific [`Cow`] kind, [`KeyVariant`], and an optional parent.
+    /// This allows for the manual assembly of a [`Key`] using a specific [`Cow`] kind,
+    /// [`KeyVariant`], and an optional parent.
+    ///
+    /// This used to be a `const fn`. It no longer is, as the components of a Key are reference
+    /// counted, so keys cannot be built in constants or statics anymore, which is a breaking
+    /// change. A `static` key can be created on first use with [`std::sync::LazyLock`].
     ///
     /// ## Parameters
     /// - `kind`: The Datastore kind name as a [`Cow<'static, str>`].
     /// - `variant`: The specific [`KeyVariant`] (Name, Id, or Incomplete) for this key.
     /// - `parent`: An optional boxed parent [`Key`] to establish an entity hierarchy.
-    pub const fn const_new(
+    #[deprecated(
+        note = "not a const fn anymore, use Key::new with Key::with_id, Key::with_name and \
+                Key::with_parent"
+    )]
+    pub fn const_new(
         kind: Cow<'static, str>,
         variant: KeyVariant,
         parent: Option<Box<Key>>,
     ) -> Self {
-        Key {
+        Key::from_inner(KeyInner {
             kind,
             variant,
-            parent,
-        }
+            parent: parent.map(|parent| *parent),
+            partition: PartitionId::new(),
+        })
     }
 
     /// Gets a string slice reference to the kind of the entity represented by this Key.
@@ -86,7 +202,7 @@ impl Key {
 
     /// Gets the string name component of the Key, if it has one.
     pub fn name(&self) -> Option<&str> {
-        if let KeyVariant::Name(name) = &self.variant {
+        if let KeyVariant::Name(name) = &self.inner.variant {
             Some(name.as_ref())
         } else {
             None
@@ -95,13 +211,38 @@ impl Key {
 
     /// Gets the integer ID component of the Key, if it has one.
     pub fn id(&self) -> Option<i64> {
-        if let KeyVariant::Id(id) = &self.variant {
+        if let KeyVariant::Id(id) = &self.inner.variant {
             Some(*id)
         } else {
             None
         }
     }
 
+    /// Gets the variant of the last path element of the Key: its ID, its name, or
+    /// [`KeyVariant::Incomplete`].
+    pub fn variant(&self) -> &KeyVariant {
+        &self.inner.variant
+    }
+
+    /// Gets the ID or the name of the Key, or `None` if the Key is incomplete.
+    pub fn id_or_name(&self) -> Option<IdOrName<'_>> {
+        match &self.inner.variant {
+            KeyVariant::Id(id) => Some(IdOrName::Id(*id)),
+            KeyVariant::Name(name) => Some(IdOrName::Name(name)),
+            KeyVariant::Incomplete => None,
+        }
+    }
+
+    /// Returns whether the Key has an integer ID.
+    pub fn has_id(&self) -> bool {
+        matches!(self.inner.variant, KeyVariant::Id(_))
+    }
+
+    /// Returns whether the Key has a string name.
+    pub fn has_name(&self) -> bool {
+        matches!(self.inner.variant, KeyVariant::Name(_))
+    }
+
     /// Returns whether the key is **complete**.
     ///
     /// A key is considered complete if its last path element has
@@ -113,77 +254,212 @@ impl Key {
     /// Returns `false` if the key is **incomplete** (i.e., the
     /// variant is [`KeyVariant::Incomplete`]).
     pub fn is_complete(&self) -> bool {
-        self.variant != KeyVariant::Incomplete
+        self.inner.variant != KeyVariant::Incomplete
     }
 
     /// Gets a reference to the parent Key, if this Key is part of a key path.
     pub fn parent(&self) -> Option<&Key> {
-        self.parent.as_deref()
+        self.inner.parent.as_ref()
+    }
+
+    /// Gets the root of the key path, i.e. the key of the entity group the Key belongs to.
+    /// A Key without a parent is its own root.
+    pub fn root(&self) -> &Key {
+        self.ancestors().last().unwrap_or(self)
+    }
+
+    /// Returns an iterator over the ancestors of the Key, from its parent to the root.
+    pub fn ancestors(&self) -> impl Iterator<Item = &Key> {
+        std::iter::successors(self.parent(), |key| key.parent())
+    }
+
+    /// Returns whether `ancestor` is the parent of the Key, or an ancestor of its parent.
+    /// A Key is not its own ancestor.
+    pub fn has_ancestor(&self, ancestor: &Key) -> bool {
+        self.ancestors().any(|key| key == ancestor)
+    }
+
+    /// Returns the number of the elements of the key path, `1` for a Key without a parent.
+    pub fn depth(&self) -> usize {
+        self.ancestors().count() + 1
+    }
+
+    /// Returns an iterator over the elements of the key path, from the root to this Key,
+    /// as Kind and variant pairs, like the ones [`Key::path`] takes.
+    pub fn path_elements(&self) -> impl DoubleEndedIterator<Item = (&str, &KeyVariant)> {
+        let mut keys: Vec<&Key> = std::iter::successors(Some(self), |key| key.parent()).collect();
+        keys.reverse();
+        keys.into_iter().map(|key| (key.kind(), key.variant()))
+    }
+
+    /// Gets the namespace of the Key, or `None` for the default namespace.
+    pub fn namespace(&self) -> Option<&str> {
+        self.inner.partition.namespace.as_deref()
+    }
+
+    /// Gets the partition of the Key.
+    pub fn partition_id(&self) -> &PartitionId {
+        &self.inner.partition
+    }
+
+    /// Consumes the current Key and returns a new one in the specified partition, e.g. in
+    /// another database of the project of the shell.
+    ///
+    /// The whole key path belongs to a single partition, so the parents are moved to the
+    /// partition as well.
+    pub fn with_partition_id(mut self, partition: PartitionId) -> Self {
+        let inner = self.inner_mut();
+        inner.parent = inner
+            .parent
+            .take()
+            .map(|parent| parent.with_partition_id(partition.clone()));
+        inner.partition = partition;
+        self
+    }
+
+    /// Consumes the current Key and returns a new one in the specified **namespace**.
+    ///
+    /// The whole key path belongs to a single namespace, so the parents are moved to the
+    /// namespace as well. An empty string is the default namespace.
+    pub fn with_namespace(self, namespace: impl Into<Cow<'static, str>>) -> Self {
+        let namespace: Cow<'static, str> = namespace.into();
+        self.with_optional_namespace((!namespace.is_empty()).then_some(namespace))
+    }
+
+    /// Consumes the current Key and returns a new one in the specified namespace, or in the
+    /// default namespace if it is `None`. The parents are moved to the namespace as well.
+    pub fn with_optional_namespace(self, namespace: Option<Cow<'static, str>>) -> Self {
+        let partition = PartitionId {
+            namespace,
+            ..self.inner.partition.clone()
+        };
+        self.with_partition_id(partition)
     }
 
     /// Consumes the current Key and returns a new one with the specified **string name**.
     ///
     /// This replaces any existing ID or name component.
-    pub fn with_name(self, name: impl Into<Cow<'static, str>>) -> Self {
-        Key {
-            variant: KeyVariant::Name(name.into()),
-            ..self
-        }
+    pub fn with_name(mut self, name: impl Into<Cow<'static, str>>) -> Self {
+        self.inner_mut().variant = KeyVariant::Name(name.into());
+        self
+    }
+
+    /// Consumes the current Key and returns a new one with a random (version 4) UUID as its
+    /// **string name**, in the hyphenated lowercase form.
+    ///
+    /// This replaces any existing ID or name component.
+    pub fn with_uuid_name(self) -> Self {
+        let mut bytes = fastrand::u128(..).to_be_bytes();
+        // the version (4) and the variant (RFC 4122) bits
+        bytes[6] = (bytes[6] & 0x0f) | 0x40;
+        bytes[8] = (bytes[8] & 0x3f) | 0x80;
+        let hex: String = bytes.iter().map(|b| format!("{:02x}", b)).collect();
+        let name = format!(
+            "{}-{}-{}-{}-{}",
+            &hex[0..8],
+            &hex[8..12],
+            &hex[12..16],
+            &hex[16..20],
+            &hex[20..32]
+        );
+        self.with_name(name)
     }
 
     /// Consumes the current Key and returns a new one with the specified **integer ID**.
     ///
     /// This replaces any existing ID or name component.
-    pub fn with_id(self, id: i64) -> Self {
-        Key {
-            variant: KeyVariant::Id(id),
-            ..self
-        }
+    pub fn with_id(mut self, id: i64) -> Self {
+        self.inner_mut().variant = KeyVariant::Id(id);
+        self
     }
 
     /// Consumes the current Key and returns a new one with a single parent Key.
     ///
-    /// The parent Key is boxed internally.
-    pub fn with_parent(self, parent: Key) -> Self {
-        Key {
-            parent: Some(Box::new(parent)),
-            ..self
+    /// The parent Key is shared, not copied, so the children of a cloned parent share its
+    /// key path. A key without a namespace (or project, or database) takes the one of the
+    /// parent.
+    pub fn with_parent(mut self, parent: Key) -> Self {
+        let inner = self.inner_mut();
+        inner.partition = std::mem::take(&mut inner.partition).or(&parent.inner.partition);
+        inner.parent = Some(parent);
+        self
+    }
+
+    /// Creates a Key from its path, starting from the root, e.g.
+    /// `Key::path([("Account", 7.into()), ("Order", "o-1".into())])`.
+    ///
+    /// The last element may be [`KeyVariant::Incomplete`], the others should be complete.
+    ///
+    /// ## Parameters
+    /// - `path`: The Kinds and the IDs or names of the path elements.
+    ///
+    /// ## Panics
+    /// If the path is empty.
+    pub fn path<K: Into<Cow<'static, str>>>(
+        path: impl IntoIterator<Item = (K, KeyVariant)>,
+    ) -> Self {
+        let mut key_opt: Option<Key> = None;
+        for (kind, variant) in path {
+            let mut key = Key::new(kind);
+            key.inner_mut().variant = variant;
+            key_opt = Some(match key_opt {
+                Some(parent) => key.with_parent(parent),
+                None => key,
+            });
         }
+        key_opt.expect("a key path must have at least one element")
     }
 
     /// Consumes the current Key and returns a new one with no parent.
     ///
     /// This clears any existing parent Key.
-    pub fn with_no_parent(self) -> Self {
-        Key {
-            parent: None,
-            ..self
-        }
+    pub fn with_no_parent(mut self) -> Self {
+        self.inner_mut().parent = None;
+        self
     }
 
     /// Convenience method that consumes the current Key and returns a new one with an optional boxed parent.
-    pub fn with_boxed_parent(self, parent: Option<Box<Key>>) -> Self {
-        Key {
-            parent: parent,
-            ..self
-        }
+    pub fn with_boxed_parent(mut self, parent: Option<Box<Key>>) -> Self {
+        self.inner_mut().parent = parent.map(|parent| *parent);
+        self
     }
 
     /// Convenience method to consume the key and keep the name (if there's any)
     pub fn into_name(self) -> Option<Cow<'static, str>> {
-        match self.variant {
-            KeyVariant::Name(name) => Some(name),
-            _ => None,
+        match Arc::try_unwrap(self.inner) {
+            Ok(inner) => match inner.variant {
+                KeyVariant::Name(name) => Some(name),
+                _ => None,
+            },
+            Err(inner) => match &inner.variant {
+                KeyVariant::Name(name) => Some(name.clone()),
+                _ => None,
+            },
         }
     }
 
+    /// Returns an estimate of the number of bytes this key occupies in Datastore.
+    ///
+    /// The estimate follows the Datastore storage size rules: every path element is
+    /// counted with the size of its kind and its name (or 8 bytes for an ID).
+    pub fn approximate_size(&self) -> usize {
+        let own = self.inner.kind.len()
+            + 1
+            + match &self.inner.variant {
+                KeyVariant::Name(name) => name.len() + 1,
+                KeyVariant::Id(_) => 8,
+                KeyVariant::Incomplete => 0,
+            };
+        own + self.parent().map_or(0, |p| p.approximate_size())
+    }
+
     /// Converts this `entail::ds::Key` reference into the lower-level
     /// `google_datastore1::api::Key` representation.
     pub fn to_api(&self) -> google_datastore1::api::Key {
         let mut path = Vec::new();
         self.push_path_elements(&mut path);
         google_datastore1::api::Key {
-            partition_id: None,
+            partition_id: self.inner.partition.to_api(),
             path: Some(path),
         }
     }
@@ -191,22 +467,23 @@ impl Key {
     /// Recursively traverses the key path (starting from the root parent) and pushes
     /// the path elements (kind + ID/name) into the output vector.
     fn push_path_elements(&self, out: &mut Vec<google_datastore1::api::PathElement>) {
-        if let Some(parent) = &self.parent {
+        if let Some(parent) = self.parent() {
             parent.push_path_elements(out);
         }
-        out.push(match &self.variant {
+        let kind = &self.inner.kind;
+        out.push(match &self.inner.variant {
             KeyVariant::Id(id) => google_datastore1::api::PathElement {
-                kind: Some(self.kind.to_string()),
+                kind: Some(kind.to_string()),
                 id: Some(*id),
                 ..Default::default()
             },
             KeyVariant::Name(name) => google_datastore1::api::PathElement {
-                kind: Some(self.kind.to_string()),
+                kind: Some(kind.to_string()),
                 name: Some(name.to_string()),
                 ..Default::default()
             },
             KeyVariant::Incomplete => google_datastore1::api::PathElement {
-                kind: Some(self.kind.to_string()),
+                kind: Some(kind.to_string()),
                 ..Default::default()
             },
         });
@@ -214,13 +491,16 @@ impl Key {
 
     /// Recursively traverses and consumes the key path, pushing owned path elements
     /// into the output vector. Used for `Into<google_datastore1::api::Key>`.
+    ///
+    /// The components are only copied if they are shared with another Key.
     fn consume_and_push_path_elements(self, out: &mut Vec<google_datastore1::api::PathElement>) {
-        if let Some(parent) = &self.parent {
-            parent.push_path_elements(out);
+        let inner = Arc::unwrap_or_clone(self.inner);
+        if let Some(parent) = inner.parent {
+            parent.consume_and_push_path_elements(out);
         }
 
-        let kind = self.kind.into_owned();
-        out.push(match self.variant {
+        let kind = inner.kind.into_owned();
+        out.push(match inner.variant {
             KeyVariant::Id(id) => google_datastore1::api::PathElement {
                 kind: Some(kind),
                 id: Some(id),
@@ -239,66 +519,300 @@ impl Key {
     }
 }
 
+/// Creates a root key with an ID, e.g. `Key::from(("Account", 7))`.
+impl<K: Into<Cow<'static, str>>> From<(K, i64)> for Key {
+    fn from((kind, id): (K, i64)) -> Self {
+        Key::new(kind).with_id(id)
+    }
+}
+
+/// Creates a root key with a name, e.g. `Key::from(("Account", "alice"))`.
+///
+/// The name is copied, so it can be borrowed from a request. [`Key::with_name`] keeps a
+/// `&'static str` name without copying it.
+impl<K: Into<Cow<'static, str>>> From<(K, &str)> for Key {
+    fn from((kind, name): (K, &str)) -> Self {
+        Key::new(kind).with_name(name.to_owned())
+    }
+}
+
+/// Creates a root key with a name.
+impl<K: Into<Cow<'static, str>>> From<(K, String)> for Key {
+    fn from((kind, name): (K, String)) -> Self {
+        Key::new(kind).with_name(name)
+    }
+}
+
 impl Into<google_datastore1::api::Key> for Key {
     /// Converts `entail::ds::Key` into the lower-level API `Key` by consuming it.
     fn into(self) -> google_datastore1::api::Key {
+        let partition_id = self.inner.partition.to_api();
         let mut path = Vec::new();
         self.consume_and_push_path_elements(&mut path);
         google_datastore1::api::Key {
-            partition_id: None,
+            partition_id,
             path: Some(path),
         }
     }
 }
 
-impl From<google_datastore1::api::Key> for Key {
+impl TryFrom<google_datastore1::api::Key> for Key {
+    type Error = EntailError;
+
     /// Converts the lower-level API `Key` into the higher-level `entail::Key`.
     ///
-    /// This reconstructs the parent-child key hierarchy from the API's path elements.
-    fn from(value: google_datastore1::api::Key) -> Key {
-        let mut key_opt = None;
-        for element in value.path.expect("Missing key path") {
-            let mut key = Key::new(element.kind.expect("Kindless key"));
-            if let Some(id) = element.id {
-                key = key.with_id(id);
-            } else if let Some(name) = element.name {
-                key = key.with_name(name)
+    /// This reconstructs the parent-child key hierarchy from the API's path elements. A key
+    /// without a path, or with a path element without a Kind, fails with an error of the kind
+    /// [`EntailErrorKind::RequestFailure`], as only a malformed response can hold one.
+    ///
+    /// Only the namespace of the partition is kept: the keys of a response are in the
+    /// project and the database of the request.
+    fn try_from(value: google_datastore1::api::Key) -> Result<Key, EntailError> {
+        convert_key(value, &mut Cow::Owned)
+    }
+}
+
+/// Converts an API key, turning its Kinds into `Cow`s with `intern`.
+pub(crate) fn convert_key(
+    value: google_datastore1::api::Key,
+    intern: &mut impl FnMut(String) -> Cow<'static, str>,
+) -> Result<Key, EntailError> {
+    let namespace = value
+        .partition_id
+        .and_then(|partition_id| partition_id.namespace_id)
+        .filter(|namespace| !namespace.is_empty());
+    let mut key_opt = None;
+    for element in value.path.unwrap_or_default() {
+        let kind = element.kind.ok_or_else(|| malformed_key("a Kind"))?;
+        let mut key = Key::new(intern(kind));
+        if let Some(id) = element.id {
+            key = key.with_id(id);
+        } else if let Some(name) = element.name {
+            key = key.with_name(name)
+        }
+        if let Some(parent) = key_opt {
+            key = key.with_boxed_parent(Some(Box::new(parent)));
+        }
+        key_opt = Some(key);
+    }
+    key_opt
+        .map(|key| key.with_optional_namespace(namespace.map(Cow::Owned)))
+        .ok_or_else(|| malformed_key("a path"))
+}
+
+fn malformed_key(missing: &str) -> EntailError {
+    EntailError::simple(
+        EntailErrorKind::RequestFailure,
+        format!(
+            "Malformed key in the Datastore response: missing {}",
+            missing
+        ),
+    )
+}
+
+impl PartialOrd for Key {
+    fn partial_cmp(&self, other: &Self) -> Option<std::cmp::Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl Ord for Key {
+    /// Orders keys the same way Datastore orders the `__key__` property.
+    ///
+    /// Keys are compared path element by path element starting from the root. Each element
+    /// is compared by kind first, then by its identifier, where integer IDs sort before
+    /// names. An ancestor sorts before all of its descendants.
+    fn cmp(&self, other: &Self) -> std::cmp::Ordering {
+        fn path(key: &Key) -> Vec<&Key> {
+            let mut path = vec![key];
+            while let Some(parent) = path.last().and_then(|k| k.parent()) {
+                path.push(parent);
             }
-            if let Some(parent) = key_opt {
-                key = key.with_boxed_parent(Some(Box::new(parent)));
+            path.reverse();
+            path
+        }
+        fn variant_rank(variant: &KeyVariant) -> u8 {
+            match variant {
+                KeyVariant::Incomplete => 0,
+                KeyVariant::Id(_) => 1,
+                KeyVariant::Name(_) => 2,
             }
-            key_opt = Some(key);
         }
-        key_opt.expect("Empty path")
+        // keys of different partitions are never compared by Datastore
+        let partitions = self.inner.partition.cmp(&other.inner.partition);
+        if partitions != std::cmp::Ordering::Equal {
+            return partitions;
+        }
+        let (left, right) = (path(self), path(other));
+        for (l, r) in left.iter().zip(right.iter()) {
+            let ordering = l
+                .kind()
+                .cmp(r.kind())
+                .then_with(|| variant_rank(l.variant()).cmp(&variant_rank(r.variant())))
+                .then_with(|| match (l.variant(), r.variant()) {
+                    (KeyVariant::Id(a), KeyVariant::Id(b)) => a.cmp(b),
+                    (KeyVariant::Name(a), KeyVariant::Name(b)) => a.cmp(b),
+                    _ => std::cmp::Ordering::Equal,
+                });
+            if ordering != std::cmp::Ordering::Equal {
+                return ordering;
+            }
+        }
+        left.len().cmp(&right.len())
     }
 }
 
 impl fmt::Display for Key {
     /// Formats the Key into a canonical Datastore-like string representation
-    /// (e.g., `ParentKind("name") / ChildKind(id:123)`).
+    /// (e.g., `ParentKind(name:"name")/ChildKind(id:123)`), prefixed with the namespace in
+    /// brackets if it is not the default one (e.g., `[tenant]Kind(id:123)`).
+    ///
+    /// The names are JSON string literals, and so are the Kinds that are empty or contain any
+    /// of `/()[]"`. The string can be parsed back with [`str::parse`].
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
-        if let Some(pk) = &self.parent {
-            pk.fmt(f)?;
+        self.fmt_with(f, false)
+    }
+}
+
+impl Key {
+    /// Formats the key like its `Display` implementation, with the names of the key and its
+    /// ancestors replaced by [`REDACTED`] if `mask_names` is set.
+    pub(super) fn fmt_with(&self, f: &mut fmt::Formatter, mask_names: bool) -> fmt::Result {
+        if let Some(namespace) = self.namespace() {
+            write!(f, "[{}]", namespace)?;
+        }
+        self.fmt_path(f, mask_names)
+    }
+
+    fn fmt_path(&self, f: &mut fmt::Formatter, mask_names: bool) -> fmt::Result {
+        if let Some(pk) = self.parent() {
+            pk.fmt_path(f, mask_names)?;
             write!(f, "/")?;
         }
-        write!(f, "{}(", self.kind)?;
-        match &self.variant {
-            KeyVariant::Name(name) => {
-                let lit = serde_json::to_string(&name).map_err(|_| fmt::Error)?;
-                write!(f, "name:{})", lit)
-            }
-            KeyVariant::Id(id) => {
-                write!(f, "id:{})", id)
+        let kind = self.kind();
+        if kind.is_empty() || kind.contains(KIND_SPECIAL_CHARS) {
+            let lit = serde_json::to_string(kind).map_err(|_| fmt::Error)?;
+            write!(f, "{}(", lit)?;
+        } else {
+            write!(f, "{}(", kind)?;
+        }
+        match self.variant() {
+            KeyVariant::Name(_) if mask_names => write!(f, "name:{})", REDACTED),
+            variant => write!(f, "{})", variant),
+        }
+    }
+}
+
+/// The characters that make a Kind quoted in the string form of a key.
+const KIND_SPECIAL_CHARS: &[char] = &['/', '(', ')', '[', ']', '"'];
+
+impl std::str::FromStr for Key {
+    type Err = EntailError;
+
+    /// Parses the string form of a key written by its [`fmt::Display`] implementation.
+    ///
+    /// ## Returns
+    /// A [`Result`] containing the key, or an [`EntailError`] of the kind
+    /// [`EntailErrorKind::MalformedKey`] if the string is not a key.
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let mut rest = s;
+        let mut namespace = None;
+        if let Some(bracketed) = rest.strip_prefix('[') {
+            let end = bracketed
+                .find(']')
+                .ok_or_else(|| malformed_key_string(s, "the namespace is not closed"))?;
+            namespace = Some(bracketed[..end].to_string());
+            rest = &bracketed[end + 1..];
+        }
+        let mut key_opt: Option<Key> = None;
+        loop {
+            let (kind, after_kind) = if rest.starts_with('"') {
+                parse_string_literal(rest).ok_or_else(|| malformed_key_string(s, "bad Kind"))?
+            } else {
+                let end = rest.find(KIND_SPECIAL_CHARS).unwrap_or(rest.len());
+                (rest[..end].to_string(), &rest[end..])
+            };
+            let element = after_kind
+                .strip_prefix('(')
+                .ok_or_else(|| malformed_key_string(s, "missing `(` after the Kind"))?;
+            let (variant, after_element) = if let Some(name) = element.strip_prefix("name:") {
+                let (name, after_name) = parse_string_literal(name)
+                    .ok_or_else(|| malformed_key_string(s, "bad name"))?;
+                (KeyVariant::Name(name.into()), after_name)
+            } else if let Some(id) = element.strip_prefix("id:") {
+                let end = id.find(')').unwrap_or(id.len());
+                let id = id[..end]
+                    .parse()
+                    .map_err(|_| malformed_key_string(s, "bad ID"))?;
+                (KeyVariant::Id(id), &element[3 + end..])
+            } else {
+                (KeyVariant::Incomplete, element)
+            };
+            rest = after_element
+                .strip_prefix(')')
+                .ok_or_else(|| malformed_key_string(s, "missing `)` after the ID or name"))?;
+            let mut key = Key::new(kind);
+            key.inner_mut().variant = variant;
+            key_opt = Some(match key_opt {
+                Some(parent) => key.with_parent(parent),
+                None => key,
+            });
+            match rest.strip_prefix('/') {
+                Some(next) => rest = next,
+                None if rest.is_empty() => break,
+                None => return Err(malformed_key_string(s, "unexpected text after the path")),
             }
-            KeyVariant::Incomplete => {
-                write!(f, ")")
+        }
+        let key = key_opt.ok_or_else(|| malformed_key_string(s, "empty path"))?;
+        Ok(match namespace {
+            Some(namespace) => key.with_namespace(namespace),
+            None => key,
+        })
+    }
+}
+
+/// Parses the JSON string literal at the start of `s`, returning it with the rest of `s`.
+fn parse_string_literal(s: &str) -> Option<(String, &str)> {
+    let mut escaped = false;
+    for (i, c) in s.char_indices().skip(1) {
+        match c {
+            _ if escaped => escaped = false,
+            '\\' => escaped = true,
+            '"' => {
+                let lit = serde_json::from_str(&s[..=i]).ok()?;
+                return Some((lit, &s[i + 1..]));
             }
+            _ => {}
         }
     }
+    None
+}
+
+fn malformed_key_string(s: &str, reason: &str) -> EntailError {
+    EntailError::simple(
+        EntailErrorKind::MalformedKey,
+        format!("Malformed key {:?}: {}", s, reason),
+    )
+}
+
+/// The type of a [`Value`], without the value itself.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash, Display)]
+pub enum ValueType {
+    Null,
+    Integer,
+    Boolean,
+    Blob,
+    UnicodeString,
+    FloatingPoint,
+    Array,
+    Key,
 }
 
 /// Represents the various data types that a single Datastore property can hold.
-#[derive(PartialEq, Debug, Clone)]
+///
+/// Values are compared the way Datastore orders the values of a property (see the [`Ord`]
+/// implementation), so two floating point `NaN`s are equal, and so are `0.0` and `-0.0`.
+#[derive(Debug, Clone)]
 pub enum Value {
     /// Represents the Datastore Null value.
     Null,
@@ -306,8 +820,9 @@ pub enum Value {
     Integer(i64),
     /// A boolean value.
     Boolean(bool),
-    /// Binary data.
-    Blob(Vec<u8>),
+    /// Binary data. The bytes are reference counted, so cloning a blob (or an entity holding
+    /// one) doesn't copy them.
+    Blob(Bytes),
     /// A string value, represented as owned or borrowed static string.
     UnicodeString(Cow<'static, str>),
     /// A floating point value, mapped to `f64`.
@@ -334,9 +849,10 @@ impl Value {
         Value::Boolean(val)
     }
 
-    /// Creates a `Value::Blob` from anything that can be converted into `Vec<u8>`.
+    /// Creates a `Value::Blob` from anything that can be converted into `Vec<u8>`. The vector
+    /// is taken over without copying the bytes.
     pub fn blob(val: impl Into<Vec<u8>>) -> Value {
-        Value::Blob(val.into())
+        Value::Blob(Bytes::from(val.into()))
     }
 
     /// Creates a `Value::UnicodeString` from anything that can be converted into `Cow<'static, str>`.
@@ -354,11 +870,59 @@ impl Value {
         Value::Array(val)
     }
 
+    /// Creates a `Value::Array` from anything convertible to values, e.g.
+    /// `Value::array_of(["red", "green"])` or `Value::array_of(ids.iter().copied())`.
+    pub fn array_of<T: Into<Value>>(values: impl IntoIterator<Item = T>) -> Value {
+        Value::Array(values.into_iter().map(Into::into).collect())
+    }
+
     /// Creates a `Value::Key`.
     pub fn key(key: Key) -> Value {
         Value::Key(key)
     }
 
+    /// Returns the integer if the value is `Integer`.
+    pub fn integer_value(&self) -> Option<i64> {
+        match self {
+            Self::Integer(val) => Some(*val),
+            _ => None,
+        }
+    }
+
+    /// Returns the boolean if the value is `Boolean`.
+    pub fn boolean_value(&self) -> Option<bool> {
+        match self {
+            Self::Boolean(val) => Some(*val),
+            _ => None,
+        }
+    }
+
+    /// Returns the floating point number if the value is `FloatingPoint`.
+    pub fn double_value(&self) -> Option<f64> {
+        match self {
+            Self::FloatingPoint(val) => Some(*val),
+            _ => None,
+        }
+    }
+
+    /// Returns a slice of the elements if the value is `Array`.
+    pub fn array_value(&self) -> Option<&[Value]> {
+        match self {
+            Self::Array(values) => Some(values),
+            _ => None,
+        }
+    }
+
+    /// Converts the value into any type implementing [`FromValue`], the same way the
+    /// fields of the models generated by `#[derive(Entail)]` are read.
+    ///
+    /// ## Returns
+    /// A [`Result`] containing the converted value, or an [`EntailError`] of the kind
+    /// [`EntailErrorKind::PropertyMappingError`] if the value has an unexpected type.
+    pub fn get<T: FromValue>(&self) -> Result<T, EntailError> {
+        T::from_value(self)
+    }
+
     /// Returns a string slice of the value if it is `UnicodeString`.
     pub fn string_value(&self) -> Option<&str> {
         match self {
@@ -383,6 +947,55 @@ impl Value {
         }
     }
 
+    /// Converts this `entail::ds::Value` reference into the lower-level
+    /// `google_datastore1::api::Value` representation, keeping the value.
+    pub fn to_api(&self) -> google_datastore1::api::Value {
+        // the keys and the blobs are reference counted, the strings are copied either way
+        self.clone().into()
+    }
+
+    /// Returns an estimate of the number of bytes this value occupies in Datastore.
+    ///
+    /// Strings and blobs are counted by their length (plus one), numbers by 8 bytes, booleans
+    /// and nulls by a single byte. Arrays are the sum of their elements.
+    pub fn approximate_size(&self) -> usize {
+        match self {
+            Value::Null | Value::Boolean(_) => 1,
+            Value::Integer(_) | Value::FloatingPoint(_) => 8,
+            Value::Blob(b) => b.len() + 1,
+            Value::UnicodeString(s) => s.len() + 1,
+            Value::Array(values) => values.iter().map(Value::approximate_size).sum(),
+            Value::Key(key) => key.approximate_size(),
+        }
+    }
+
+    /// Returns the type of this value.
+    pub fn value_type(&self) -> ValueType {
+        match self {
+            Value::Null => ValueType::Null,
+            Value::Integer(_) => ValueType::Integer,
+            Value::Boolean(_) => ValueType::Boolean,
+            Value::Blob(_) => ValueType::Blob,
+            Value::UnicodeString(_) => ValueType::UnicodeString,
+            Value::FloatingPoint(_) => ValueType::FloatingPoint,
+            Value::Array(_) => ValueType::Array,
+            Value::Key(_) => ValueType::Key,
+        }
+    }
+
+    /// Checks if the value is too large to be indexed, i.e. it is a string or a blob longer
+    /// than [`MAX_INDEXED_BYTES`], or an array holding such a value.
+    ///
+    /// The Datastore rejects the commit of an entity with such an indexed value.
+    pub fn exceeds_index_limit(&self) -> bool {
+        match self {
+            Value::UnicodeString(s) => s.len() > MAX_INDEXED_BYTES,
+            Value::Blob(b) => b.len() > MAX_INDEXED_BYTES,
+            Value::Array(values) => values.iter().any(Value::exceeds_index_limit),
+            _ => false,
+        }
+    }
+
     /// Checks if the value is `Value::Null`.
     pub fn is_null(&self) -> bool {
         match self {
@@ -446,6 +1059,72 @@ impl From<f32> for Value {
     }
 }
 
+impl PartialEq for Value {
+    fn eq(&self, other: &Self) -> bool {
+        self.cmp(other) == std::cmp::Ordering::Equal
+    }
+}
+
+impl Eq for Value {}
+
+impl PartialOrd for Value {
+    fn partial_cmp(&self, other: &Self) -> Option<std::cmp::Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl Ord for Value {
+    /// Orders values the same way Datastore orders the values of a property, so results
+    /// sorted or merged on the client match the order of a query.
+    ///
+    /// Values of different types are ordered by type: nulls, integers, booleans, strings and
+    /// blobs, floating point numbers, then keys. Strings and blobs are compared by their bytes,
+    /// with a string before a blob of the same bytes. `NaN` sorts before all the other floating
+    /// point numbers. Arrays are never compared by Datastore (their elements are indexed one
+    /// by one), they sort last, element by element.
+    fn cmp(&self, other: &Self) -> std::cmp::Ordering {
+        use std::cmp::Ordering;
+        fn type_rank(value: &Value) -> u8 {
+            match value {
+                Value::Null => 0,
+                Value::Integer(_) => 1,
+                Value::Boolean(_) => 2,
+                Value::UnicodeString(_) | Value::Blob(_) => 3,
+                Value::FloatingPoint(_) => 4,
+                Value::Key(_) => 5,
+                Value::Array(_) => 6,
+            }
+        }
+        match (self, other) {
+            (Value::Null, Value::Null) => Ordering::Equal,
+            (Value::Integer(a), Value::Integer(b)) => a.cmp(b),
+            (Value::Boolean(a), Value::Boolean(b)) => a.cmp(b),
+            (Value::FloatingPoint(a), Value::FloatingPoint(b)) => match (a.is_nan(), b.is_nan()) {
+                (true, true) => Ordering::Equal,
+                (true, false) => Ordering::Less,
+                (false, true) => Ordering::Greater,
+                (false, false) => a.partial_cmp(b).unwrap_or(Ordering::Equal),
+            },
+            (Value::Key(a), Value::Key(b)) => a.cmp(b),
+            (Value::Array(a), Value::Array(b)) => a.cmp(b),
+            (
+                Value::UnicodeString(_) | Value::Blob(_),
+                Value::UnicodeString(_) | Value::Blob(_),
+            ) => {
+                fn bytes(value: &Value) -> (&[u8], bool) {
+                    match value {
+                        Value::UnicodeString(s) => (s.as_bytes(), false),
+                        Value::Blob(b) => (b.as_ref(), true),
+                        _ => (&[], false),
+                    }
+                }
+                bytes(self).cmp(&bytes(other))
+            }
+            _ => type_rank(self).cmp(&type_rank(other)),
+        }
+    }
+}
+
 impl From<Vec<u8>> for Value {
     fn from(value: Vec<u8>) -> Self {
         Self::blob(value)
@@ -458,49 +1137,112 @@ impl From<Vec<Value>> for Value {
     }
 }
 
+impl From<Bytes> for Value {
+    fn from(value: Bytes) -> Self {
+        Self::Blob(value)
+    }
+}
+
 impl From<Key> for Value {
     fn from(value: Key) -> Self {
         Self::key(value)
     }
 }
-impl From<google_datastore1::api::Value> for Value {
+
+/// Converts a `None` into `Value::Null`.
+impl<T: Into<Value>> From<Option<T>> for Value {
+    fn from(value: Option<T>) -> Self {
+        value.map_or(Value::Null, Into::into)
+    }
+}
+
+/// Implements the conversion of vectors into `Value::Array`. A generic implementation would
+/// conflict with `Vec<u8>`, which is a blob.
+macro_rules! impl_array_conversion {
+    ($($t:ty),* $(,)?) => {
+        $(
+            impl From<Vec<$t>> for Value {
+                fn from(values: Vec<$t>) -> Self {
+                    Self::array_of(values)
+                }
+            }
+        )*
+    };
+}
+
+impl_array_conversion!(
+    String,
+    &'static str,
+    Cow<'static, str>,
+    bool,
+    i32,
+    u32,
+    i64,
+    f32,
+    f64,
+    Key,
+);
+
+impl From<&[&str]> for Value {
+    fn from(values: &[&str]) -> Self {
+        Self::array_of(values.iter().map(|value| value.to_string()))
+    }
+}
+impl TryFrom<google_datastore1::api::Value> for Value {
+    type Error = EntailError;
+
     /// Converts the lower-level API `Value` into the higher-level `entail::Value`.
-    fn from(value: google_datastore1::api::Value) -> Self {
+    ///
+    /// Entity, geo point and timestamp values have no `entail::Value` counterpart, they fail
+    /// with an error of the kind [`EntailErrorKind::PropertyMappingError`].
+    fn try_from(value: google_datastore1::api::Value) -> Result<Self, EntailError> {
         if let Some(integer_value) = value.integer_value {
-            Value::Integer(integer_value)
+            Ok(Value::Integer(integer_value))
         } else if let Some(boolean_value) = value.boolean_value {
-            Value::Boolean(boolean_value)
+            Ok(Value::Boolean(boolean_value))
         } else if let Some(blob_value) = value.blob_value {
-            Value::Blob(blob_value.clone())
+            Ok(Value::Blob(Bytes::from(blob_value)))
         } else if let Some(string_value) = value.string_value {
-            Value::UnicodeString(Cow::Owned(string_value))
+            Ok(Value::UnicodeString(Cow::Owned(string_value)))
         } else if let Some(double_value) = value.double_value {
-            Value::FloatingPoint(double_value)
+            Ok(Value::FloatingPoint(double_value))
         } else if let Some(array_value) = value.array_value {
-            let values: Vec<Value> = array_value
+            array_value
                 .values
                 .unwrap_or_default()
                 .into_iter()
-                .map(|e| e.into())
-                .collect();
-            Value::Array(values)
+                .map(Value::try_from)
+                .collect::<Result<_, _>>()
+                .map(Value::Array)
         } else if let Some(key_value) = value.key_value {
-            Value::Key(key_value.into())
-        } else if value.entity_value.is_some()
-            || value.geo_point_value.is_some()
-            || value.timestamp_value.is_some()
-        {
-            // Panic for unsupported types like `entityValue`, `geoPointValue`,
-            // `timestampValue`, and others.
-            panic!("Unsupported Datastore value type");
+            Key::try_from(key_value).map(Value::Key)
+        } else if let Some(value_type) = unsupported_value_type(&value) {
+            Err(EntailError::simple(
+                EntailErrorKind::PropertyMappingError,
+                format!("Unsupported Datastore value type {}", value_type),
+            ))
         } else {
             // Sometimes Cloud Datastore sends `{}`` as value JSON instead of null, but this
             // branch covers the normal null value case (`{"nullValue": "NULL_VALUE"}``)
-            Value::Null
+            Ok(Value::Null)
         }
     }
 }
 
+/// Returns the name of the value type that has no `entail::Value` counterpart, if the API
+/// value holds one.
+fn unsupported_value_type(value: &google_datastore1::api::Value) -> Option<&'static str> {
+    if value.entity_value.is_some() {
+        Some("entityValue")
+    } else if value.geo_point_value.is_some() {
+        Some("geoPointValue")
+    } else if value.timestamp_value.is_some() {
+        Some("timestampValue")
+    } else {
+        None
+    }
+}
+
 impl Into<google_datastore1::api::Value> for Value {
     /// Converts `entail::Value` into the lower-level API `Value` by consuming it.
     fn into(self) -> google_datastore1::api::Value {
@@ -518,8 +1260,8 @@ impl Into<google_datastore1::api::Value> for Value {
                 ds_value.boolean_value = Some(b);
             }
             Value::Blob(b) => {
-                // Clone Vec<u8>
-                ds_value.blob_value = Some(b.clone());
+                // only copies the bytes if they are shared with another value
+                ds_value.blob_value = Some(Vec::from(b));
             }
             Value::UnicodeString(s) => {
                 // Convert Cow<'static, str> to String
@@ -567,10 +1309,26 @@ impl fmt::Display for Value {
     }
 }
 
+/// The meaning of the long strings stored by App Engine as `TEXT` (e.g. `Text` in Java).
 pub static MEANING_TEXT: i32 = 15;
+/// The meaning of the timestamps stored by App Engine as `GD_WHEN` (e.g. `Date` in Java).
+pub static MEANING_GD_WHEN: i32 = 7;
+/// The meaning of the unindexed blobs stored by App Engine as `BLOB` (e.g. `Blob` in Java).
+pub static MEANING_BLOB: i32 = 14;
+/// The meaning of the Blobstore keys stored by App Engine as `BLOBKEY`.
+pub static MEANING_BLOBKEY: i32 = 17;
+
+/// The maximum size in bytes of an indexed string or blob value.
+pub const MAX_INDEXED_BYTES: usize = 1500;
+
+/// The string shown instead of the masked values and key names, see [`Entity::redacted`].
+pub const REDACTED: &str = "<redacted>";
 
 /// Represents a single Datastore property, which includes the `Value`,
 /// its **indexing** status, and an optional **meaning** hint.
+///
+/// The methods adjusting the indexing status and the meaning consume and return `Self`,
+/// allowing for method chaining.
 #[derive(PartialEq, Debug, Clone)]
 pub struct PropertyValue {
     value: Value,
@@ -579,6 +1337,34 @@ pub struct PropertyValue {
 }
 
 impl PropertyValue {
+    /// Creates an **indexed** property value without a meaning, to be configured with the
+    /// builder methods and set with [`Entity::set_property`], e.g.
+    /// `PropertyValue::of(text).unindexed().with_meaning(MEANING_TEXT)`.
+    pub fn of(value: impl Into<Value>) -> Self {
+        Self {
+            value: value.into(),
+            indexed: true,
+            meaning: None,
+        }
+    }
+
+    /// Makes the property value **unindexed**.
+    pub fn unindexed(self) -> Self {
+        self.with_indexed(false)
+    }
+
+    /// Sets whether the property value is indexed.
+    pub fn with_indexed(mut self, indexed: bool) -> Self {
+        self.indexed = indexed;
+        self
+    }
+
+    /// Sets the meaning of the property value, e.g. [`MEANING_TEXT`].
+    pub fn with_meaning(mut self, meaning: i32) -> Self {
+        self.meaning = Some(meaning);
+        self
+    }
+
     /// Gets a reference to the raw `Value` held by the property.
     pub fn value(&self) -> &Value {
         &self.value
@@ -614,10 +1400,26 @@ impl fmt::Display for PropertyValue {
     }
 }
 
+/// How [`Entity::merge`] resolves a property present in both entities.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum MergeStrategy {
+    /// The property of the merged entity replaces the existing one.
+    #[default]
+    OverwriteAll,
+    /// The existing property is kept, only the missing properties are added.
+    KeepExisting,
+    /// The property of the merged entity replaces the existing one unless its value is null.
+    OverwriteNonNull,
+}
+
 /// A representation of a Google Cloud Datastore **Entity**.
 ///
 /// It holds the unique `Key` for the entity and a `HashMap` of all its properties.
-#[derive(Debug, Clone)]
+///
+/// The order of the properties in the map is not stable, so [`Entity::property_iter_sorted`]
+/// is to be used wherever the order matters. The [`fmt::Display`] and [`fmt::Debug`] output
+/// lists the properties sorted by their names.
+#[derive(Clone, PartialEq)]
 pub struct Entity {
     key: Key,
     properties: HashMap<Cow<'static, str>, PropertyValue>,
@@ -651,6 +1453,28 @@ impl Entity {
         (self.key, self.properties)
     }
 
+    /// Converts this `entail::ds::Entity` reference into the lower-level
+    /// `google_datastore1::api::Entity` representation, e.g. to commit the entity while
+    /// keeping it, see [`MutationBatch::upsert_ref`](super::MutationBatch::upsert_ref).
+    pub fn to_api(&self) -> google_datastore1::api::Entity {
+        google_datastore1::api::Entity {
+            key: Some(self.key.to_api()),
+            properties: Some(
+                self.properties
+                    .iter()
+                    .map(|(name, property)| {
+                        let value = property_to_api(
+                            property.value.to_api(),
+                            property.indexed,
+                            property.meaning,
+                        );
+                        (name.to_string(), value)
+                    })
+                    .collect(),
+            ),
+        }
+    }
+
     /// Gets a reference to the entity's unique `Key`.
     pub fn key(&self) -> &Key {
         &self.key
@@ -667,11 +1491,32 @@ impl Entity {
         Kind::kind(self)
     }
 
+    /// Gets the namespace of the entity (the one of its key), or `None` for the default
+    /// namespace.
+    pub fn namespace(&self) -> Option<&str> {
+        self.key.namespace()
+    }
+
+    /// Gets the partition of the entity (the one of its key).
+    pub fn partition_id(&self) -> &PartitionId {
+        self.key.partition_id()
+    }
+
     /// Returns an iterator over all raw property entries (name and `PropertyValue`).
     pub fn property_iter_raw(&self) -> impl Iterator<Item = (&Cow<'static, str>, &PropertyValue)> {
         self.properties.iter()
     }
 
+    /// Returns an iterator over all raw property entries sorted by the property names, e.g.
+    /// for golden tests or for logs that are compared with each other.
+    pub fn property_iter_sorted(
+        &self,
+    ) -> impl DoubleEndedIterator<Item = (&Cow<'static, str>, &PropertyValue)> {
+        let mut properties: Vec<_> = self.properties.iter().collect();
+        properties.sort_unstable_by_key(|(name, _)| *name);
+        properties.into_iter()
+    }
+
     /// Returns an iterator over property names and their raw `Value` (excluding indexing info).
     pub fn property_iter(&self) -> impl Iterator<Item = (&Cow<'static, str>, &Value)> {
         self.properties
@@ -710,6 +1555,16 @@ impl Entity {
         self
     }
 
+    /// Sets a property assembled with the builder methods of [`PropertyValue`], keeping its
+    /// indexing and meaning.
+    pub fn set_property(
+        &mut self,
+        name: impl Into<Cow<'static, str>>,
+        property: PropertyValue,
+    ) -> &mut Self {
+        self.set(name, property.value, property.indexed, property.meaning)
+    }
+
     /// Sets a property, forcing it to be **unindexed** (convenience function).
     pub fn set_unindexed(&mut self, name: impl Into<Cow<'static, str>>, value: Value) -> &mut Self {
         self.set(name, value, false, None)
@@ -777,6 +1632,32 @@ impl Entity {
         self.properties.contains_key(name)
     }
 
+    /// Returns whether a property with the given name exists on this entity, even with a null
+    /// value. The same as [`Self::has`].
+    pub fn contains_property(&self, name: &str) -> bool {
+        self.has(name)
+    }
+
+    /// Returns the number of properties of this entity, the key excluded.
+    pub fn property_count(&self) -> usize {
+        self.properties.len()
+    }
+
+    /// Returns `true` if the entity has no properties, e.g. a result of a keys-only query.
+    pub fn is_key_only(&self) -> bool {
+        self.properties.is_empty()
+    }
+
+    /// Gets the value of a property the way a projection names it: [`KEY_PROPERTY`]
+    /// (`__key__`) is the key of the entity, any other name is a property.
+    pub fn value_or_key(&self, name: &str) -> Option<Cow<'_, Value>> {
+        if name == KEY_PROPERTY {
+            Some(Cow::Owned(Value::Key(self.key.clone())))
+        } else {
+            self.get_value(name).map(Cow::Borrowed)
+        }
+    }
+
     /// Gets a reference to the raw `Value` of a property by name.
     pub fn get_value(&self, name: &str) -> Option<&Value> {
         self.properties.get(name).map(|ev| &ev.value)
@@ -807,6 +1688,16 @@ impl Entity {
         self.properties.remove(name)
     }
 
+    /// Moves the value of a property out without cloning it, leaving a null value behind.
+    ///
+    /// Unlike [`Self::remove_value`], the property is kept with its indexing, so writing the
+    /// entity stores a null instead of dropping the property.
+    pub fn take_value(&mut self, name: &str) -> Option<Value> {
+        self.properties
+            .get_mut(name)
+            .map(|property| std::mem::replace(&mut property.value, Value::Null))
+    }
+
     /// Copies all properties from another entity into this one.
     ///
     /// If a property with the same name already exists in this entity, its value,
@@ -830,37 +1721,154 @@ impl Entity {
     /// ## Parameters
     /// - `other`: The [`Entity`] to consume properties from.
     pub fn consume_properties_from(&mut self, other: Entity) {
+        self.merge(other, MergeStrategy::OverwriteAll);
+    }
+
+    /// Moves the properties of another entity into this one, resolving the properties present
+    /// in both with a [`MergeStrategy`]. The key of the other entity is ignored.
+    ///
+    /// ## Parameters
+    /// - `other`: The [`Entity`] to consume properties from.
+    /// - `strategy`: Whether the properties of `other` replace the existing ones.
+    pub fn merge(&mut self, other: Entity, strategy: MergeStrategy) {
         for (key, value) in other.properties {
-            self.properties.insert(key, value);
+            match strategy {
+                MergeStrategy::OverwriteAll => {
+                    self.properties.insert(key, value);
+                }
+                MergeStrategy::KeepExisting => {
+                    self.properties.entry(key).or_insert(value);
+                }
+                MergeStrategy::OverwriteNonNull => {
+                    if value.value.is_null() && self.properties.contains_key(&key) {
+                        continue;
+                    }
+                    self.properties.insert(key, value);
+                }
+            }
         }
     }
+
+    /// Returns an estimate of the number of bytes this entity occupies in Datastore.
+    ///
+    /// The estimate is the size of the key plus the size of every property name and
+    /// value (see [`Value::approximate_size`]). It does not include index entries.
+    pub fn approximate_size(&self) -> usize {
+        self.key.approximate_size()
+            + self
+                .properties
+                .iter()
+                .map(|(name, value)| name.len() + 1 + value.value.approximate_size())
+                .sum::<usize>()
+    }
+
+    /// Computes a stable checksum of the properties of this entity.
+    ///
+    /// The checksum covers the names and values of all the properties except the one named
+    /// `excluded` (which is typically the property the checksum is stored in), but not the
+    /// key, the indexing flags or the meanings. It doesn't depend on the order in which the
+    /// properties were set, and it doesn't change between processes or library versions,
+    /// so it can be persisted. This is what `#[entail(checksum)]` stores.
+    ///
+    /// ## Parameters
+    /// - `excluded`: The name of a property to leave out of the checksum.
+    pub fn checksum(&self, excluded: &str) -> i64 {
+        let mut names: Vec<&Cow<'static, str>> = self
+            .properties
+            .keys()
+            .filter(|name| name.as_ref() != excluded)
+            .collect();
+        names.sort();
+        let mut hasher = super::hash::StableHasher::new();
+        for name in names {
+            hasher.write_prefixed(name.as_bytes());
+            hasher.write_value(&self.properties[name].value);
+        }
+        hasher.finish() as i64
+    }
 }
 
 impl fmt::Display for Entity {
-    /// Formats the Entity, showing its Key and a list of all its properties.
+    /// Formats the Entity, showing its Key and a list of all its properties sorted by their
+    /// names. [`Entity::redacted`] formats it without the property values, for logging.
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
-        self.key.fmt(f)?;
-        write!(f, " {{")?;
-        for (key, value) in self.properties.iter() {
-            write!(f, "\n  {}: {},", key, value)?;
-        }
-        write!(f, "\n}}")
+        self.display_with(&DisplayOptions::reveal_all()).fmt(f)
+    }
+}
+
+impl fmt::Debug for Entity {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("Entity")
+            .field("key", &self.key)
+            .field(
+                "properties",
+                &DebugProperties(self.property_iter_sorted().collect()),
+            )
+            .finish()
+    }
+}
+
+/// Formats the properties of an entity like a map, in the given order.
+struct DebugProperties<'a>(Vec<(&'a Cow<'static, str>, &'a PropertyValue)>);
+
+impl fmt::Debug for DebugProperties<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_map().entries(self.0.iter().copied()).finish()
     }
 }
 
-impl From<google_datastore1::api::Entity> for Entity {
+impl TryFrom<google_datastore1::api::Entity> for Entity {
+    type Error = EntailError;
+
     /// Converts the lower-level API `Entity` into the higher-level `entail::ds::Entity`.
-    fn from(value: google_datastore1::api::Entity) -> Entity {
-        let mut result = Entity::new(value.key.expect("Missing key").into());
-        if let Some(props) = value.properties {
-            for (key, value) in props.into_iter() {
-                let indexed = !value.exclude_from_indexes.unwrap_or(false);
-                let meaning = value.meaning.clone();
-                result.set(key, value.into(), indexed, meaning);
-            }
+    ///
+    /// Fails with an error of the kind [`EntailErrorKind::PropertyMappingError`] if the entity
+    /// has no key, or a property has an unsupported value type (see the conversion of
+    /// [`Value`]).
+    fn try_from(value: google_datastore1::api::Entity) -> Result<Entity, EntailError> {
+        convert_entity(value, &mut Cow::Owned, 0)
+    }
+}
+
+/// Converts an API entity, turning its Kinds and property names into `Cow`s with `intern`.
+/// The property map is allocated for at least `capacity` properties.
+pub(crate) fn convert_entity(
+    value: google_datastore1::api::Entity,
+    intern: &mut impl FnMut(String) -> Cow<'static, str>,
+    capacity: usize,
+) -> Result<Entity, EntailError> {
+    let mut properties = value.properties;
+    let key = take_key(value.key, properties.as_mut()).ok_or_else(|| {
+        EntailError::simple(EntailErrorKind::PropertyMappingError, "Entity without a key")
+    })?;
+    let mut result = Entity::new(convert_key(key, intern)?);
+    if let Some(props) = properties {
+        result.properties.reserve(props.len().max(capacity));
+        for (key, value) in props.into_iter() {
+            let indexed = !value.exclude_from_indexes.unwrap_or(false);
+            let meaning = value.meaning;
+            let value = Value::try_from(value).map_err(|err| {
+                EntailError::simple(
+                    err.kind,
+                    format!("{} in the property {} of {}", err.message, key, result.key),
+                )
+            })?;
+            result.set(intern(key), value, indexed, meaning);
         }
-        result
     }
+    Ok(result)
+}
+
+/// Returns the key of an API entity, removing the `__key__` value projection results may have
+/// among their properties, which stands in for a missing key.
+fn take_key(
+    key: Option<google_datastore1::api::Key>,
+    properties: Option<&mut HashMap<String, google_datastore1::api::Value>>,
+) -> Option<google_datastore1::api::Key> {
+    let projected = properties
+        .and_then(|props| props.remove(KEY_PROPERTY))
+        .and_then(|value| value.key_value);
+    key.or(projected)
 }
 
 impl Into<google_datastore1::api::Entity> for Entity {
@@ -872,23 +1880,7 @@ impl Into<google_datastore1::api::Entity> for Entity {
                 self.properties
                     .into_iter()
                     .map(|(key, value)| {
-                        let indexed = value.indexed;
-                        let meaning = value.meaning.clone();
-                        let mut val: google_datastore1::api::Value = value.value.into();
-                        // Special handling for Array values, where indexing is set on array elements.
-                        if let Some(array) = &mut val.array_value {
-                            if let Some(values) = &mut array.values {
-                                for item in values.iter_mut() {
-                                    // The API uses `exclude_from_indexes`, so we negate `indexed`.
-                                    item.exclude_from_indexes = Some(!indexed);
-                                    item.meaning = meaning;
-                                }
-                            }
-                        } else {
-                            // Set indexing flag for non-Array values.
-                            val.exclude_from_indexes = Some(!indexed);
-                            val.meaning = meaning;
-                        }
+                        let val = property_to_api(value.value.into(), value.indexed, value.meaning);
                         (key.into_owned(), val)
                     })
                     .collect(),
@@ -898,6 +1890,29 @@ impl Into<google_datastore1::api::Entity> for Entity {
     }
 }
 
+/// Sets the indexing and the meaning of a property on its converted value.
+fn property_to_api(
+    mut val: google_datastore1::api::Value,
+    indexed: bool,
+    meaning: Option<i32>,
+) -> google_datastore1::api::Value {
+    // Special handling for Array values, where indexing is set on array elements.
+    if let Some(array) = &mut val.array_value {
+        if let Some(values) = &mut array.values {
+            for item in values.iter_mut() {
+                // The API uses `exclude_from_indexes`, so we negate `indexed`.
+                item.exclude_from_indexes = Some(!indexed);
+                item.meaning = meaning;
+            }
+        }
+    } else {
+        // Set indexing flag for non-Array values.
+        val.exclude_from_indexes = Some(!indexed);
+        val.meaning = meaning;
+    }
+    val
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -938,6 +1953,549 @@ mod tests {
         assert_eq!(key4.to_string(), "Foo(name:\"parent\")/Bar(name:\"child\")");
     }
 
+    #[test]
+    fn test_key_path() {
+        let built = Key::new("Item").with_name("i-2").with_parent(
+            Key::new("Order")
+                .with_name("o-1")
+                .with_parent(Key::new("Account").with_id(7)),
+        );
+        let key = Key::path([
+            ("Account", 7.into()),
+            ("Order", "o-1".into()),
+            ("Item", String::from("i-2").into()),
+        ]);
+        assert_eq!(key, built);
+        assert!(
+            !Key::path([("Account", 7.into()), ("Order", KeyVariant::Incomplete)]).is_complete()
+        );
+        assert_eq!(Key::from(("Account", 7)), Key::new("Account").with_id(7));
+        assert_eq!(
+            Key::from(("Account", "alice")),
+            Key::new("Account").with_name("alice")
+        );
+        assert_eq!(
+            Key::from((String::from("Account"), String::from("alice"))),
+            Key::new("Account").with_name("alice")
+        );
+        let name = String::from("bob");
+        assert_eq!(
+            Key::from(("Account", name.as_str())),
+            Key::new("Account").with_name("bob")
+        );
+    }
+
+    #[test]
+    fn test_uuid_name() {
+        let key = Key::new("Foo").with_id(1).with_uuid_name();
+        let name = key.name().unwrap();
+        assert_eq!(name.len(), 36);
+        let groups: Vec<&str> = name.split('-').collect();
+        assert_eq!(
+            groups.iter().map(|g| g.len()).collect::<Vec<_>>(),
+            vec![8, 4, 4, 4, 12]
+        );
+        assert!(groups[2].starts_with('4'));
+        assert!(matches!(&groups[3][..1], "8" | "9" | "a" | "b"));
+        assert!(name.chars().all(|c| c == '-' || c.is_ascii_hexdigit() && !c.is_ascii_uppercase()));
+        assert_ne!(Key::new("Foo").with_uuid_name(), key);
+    }
+
+    #[test]
+    fn test_key_sharing() {
+        let parent = Key::new("Account").with_name("a").with_namespace("tenant");
+        let first = Key::new("Order").with_id(1).with_parent(parent.clone());
+        let second = Key::new("Order").with_id(2).with_parent(parent.clone());
+        assert!(Arc::ptr_eq(&first.parent().unwrap().inner, &parent.inner));
+        assert!(Arc::ptr_eq(
+            &first.parent().unwrap().inner,
+            &second.parent().unwrap().inner
+        ));
+        assert!(Arc::ptr_eq(&first.clone().inner, &first.inner));
+
+        // changing a shared key leaves the other owners intact
+        let renamed = first.clone().with_name("o-1");
+        assert_eq!(first.id(), Some(1));
+        assert_eq!(renamed.name(), Some("o-1"));
+        let moved = first.clone().with_namespace("other");
+        assert_eq!(first.namespace(), Some("tenant"));
+        assert_eq!(parent.namespace(), Some("tenant"));
+        assert_eq!(moved.parent().and_then(Key::namespace), Some("other"));
+        assert_eq!(first.clone().into_name(), None);
+        assert_eq!(renamed.clone().into_name(), Some(Cow::Borrowed("o-1")));
+
+        let api: google_datastore1::api::Key = second.clone().into();
+        assert_eq!(Key::try_from(api).unwrap(), second);
+    }
+
+    #[test]
+    fn test_key_root() {
+        let root = Key::new("Account").with_id(1);
+        let customer = Key::new("Customer")
+            .with_name("c")
+            .with_parent(root.clone());
+        let key = Key::new("Order").with_id(5).with_parent(customer);
+        assert_eq!(key.root(), &root);
+        assert_eq!(root.root(), &root);
+        assert_eq!((key.depth(), root.depth()), (3, 1));
+        assert!(key.has_ancestor(&root) && key.has_ancestor(key.parent().unwrap()));
+        assert!(!key.has_ancestor(&key) && !root.has_ancestor(&key));
+        assert_eq!(
+            key.ancestors().map(Key::kind).collect::<Vec<_>>(),
+            vec!["Customer", "Account"]
+        );
+        let elements: Vec<_> = key.path_elements().collect();
+        assert_eq!(elements[0], ("Account", &KeyVariant::Id(1)));
+        assert_eq!(elements[2], ("Order", &KeyVariant::Id(5)));
+        let rebuilt = Key::path(
+            key.path_elements()
+                .map(|(kind, variant)| (kind.to_string(), variant.clone())),
+        );
+        assert_eq!(rebuilt, key);
+
+        let other = Key::new("Account").with_name("a");
+        let groups = crate::ds::entity_groups([&other, &key, &root]);
+        assert_eq!(groups, vec![root, other]);
+    }
+
+    #[test]
+    fn test_key_namespace() {
+        let parent = Key::new("Foo").with_name("parent").with_namespace("tenant");
+        let key = Key::new("Bar").with_id(2).with_parent(parent.clone());
+        assert_eq!(key.namespace(), Some("tenant"));
+        assert_eq!(key.to_string(), "[tenant]Foo(name:\"parent\")/Bar(id:2)");
+
+        let native = key.to_api();
+        assert_eq!(
+            native
+                .partition_id
+                .as_ref()
+                .and_then(|p| p.namespace_id.as_deref()),
+            Some("tenant")
+        );
+        assert_eq!(Key::try_from(native).unwrap(), key);
+        let native: google_datastore1::api::Key = key.clone().into();
+        assert_eq!(Key::try_from(native).unwrap(), key);
+        let entity = Entity::try_from(google_datastore1::api::Entity {
+            key: Some(key.to_api()),
+            properties: None,
+        })
+        .unwrap();
+        assert_eq!(entity.namespace(), Some("tenant"));
+        assert_eq!(entity.partition_id(), key.partition_id());
+
+        let moved = key.clone().with_namespace("other");
+        assert_eq!(moved.parent().and_then(Key::namespace), Some("other"));
+        assert_ne!(moved, key);
+        let default = key.with_namespace("");
+        assert_eq!(default.namespace(), None);
+        assert!(default.to_api().partition_id.is_none());
+        assert!(default < moved && moved < parent);
+    }
+
+    #[test]
+    fn test_key_variant() {
+        let named = Key::new("Account").with_name("alice");
+        assert_eq!(named.variant(), &KeyVariant::Name("alice".into()));
+        assert_eq!(named.id_or_name(), Some(IdOrName::Name("alice")));
+        assert!(named.has_name() && !named.has_id());
+        let numbered = Key::new("Account").with_id(7);
+        assert_eq!(numbered.id_or_name(), Some(IdOrName::Id(7)));
+        assert!(numbered.has_id() && !numbered.has_name());
+        let incomplete = Key::new("Account");
+        assert_eq!(incomplete.id_or_name(), None);
+        assert!(!incomplete.has_id() && !incomplete.has_name());
+
+        assert_eq!(named.variant().to_string(), "name:\"alice\"");
+        assert_eq!(IdOrName::Name("alice").to_string(), "name:\"alice\"");
+        assert_eq!(IdOrName::Id(7).to_string(), "id:7");
+        assert_eq!(incomplete.variant().to_string(), "");
+    }
+
+    #[test]
+    fn test_key_parsing() {
+        let key = Key::new("Item").with_name("a \"b\"/(c)").with_parent(
+            Key::new("Order")
+                .with_id(-12)
+                .with_parent(Key::new("Account").with_name("alice")),
+        );
+        for key in [
+            key.clone(),
+            key.clone().with_namespace("tenant"),
+            Key::new("Order").with_parent(Key::new("Account").with_id(7)),
+            Key::new("odd/Kind(1)").with_name(""),
+            Key::new("").with_id(1),
+        ] {
+            assert_eq!(key.to_string().parse::<Key>().unwrap(), key);
+        }
+        assert_eq!(
+            Key::new("odd/Kind").with_id(1).to_string(),
+            "\"odd/Kind\"(id:1)"
+        );
+        assert_eq!(
+            "Account(name:\"alice\")/Order()".parse::<Key>().unwrap(),
+            Key::new("Order").with_parent(Key::new("Account").with_name("alice"))
+        );
+
+        for malformed in [
+            "",
+            "Account",
+            "Account(id:x)",
+            "Account(name:\"a)",
+            "Account(id:1)Order(id:2)",
+            "Account(id:1)/",
+            "[tenant",
+        ] {
+            let err = malformed.parse::<Key>().unwrap_err();
+            assert_eq!(err.kind, EntailErrorKind::MalformedKey, "{}", malformed);
+        }
+    }
+
+    #[test]
+    fn test_key_ordering() {
+        let parent = Key::new("Foo").with_name("parent");
+        let mut keys = vec![
+            Key::new("Foo").with_name("b"),
+            Key::new("Bar").with_id(2).with_parent(parent.clone()),
+            Key::new("Foo").with_id(10),
+            parent.clone(),
+            Key::new("Foo").with_name("a"),
+            Key::new("Bar").with_id(1),
+            Key::new("Foo").with_id(2),
+        ];
+        keys.sort();
+        assert_eq!(
+            keys,
+            vec![
+                Key::new("Bar").with_id(1),
+                Key::new("Foo").with_id(2),
+                Key::new("Foo").with_id(10),
+                Key::new("Foo").with_name("a"),
+                Key::new("Foo").with_name("b"),
+                parent.clone(),
+                Key::new("Bar").with_id(2).with_parent(parent),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_value_accessors() {
+        assert_eq!(Value::integer(7).integer_value(), Some(7));
+        assert_eq!(Value::boolean(true).boolean_value(), Some(true));
+        assert_eq!(Value::floating_point(0.5).double_value(), Some(0.5));
+        assert_eq!(Value::unicode_string("7").integer_value(), None);
+        let tags = Value::array(vec![Value::unicode_string("a"), Value::unicode_string("b")]);
+        assert_eq!(tags.array_value().map(<[Value]>::len), Some(2));
+        assert_eq!(Value::null().array_value(), None);
+
+        assert_eq!(Value::integer(7).get::<i64>().unwrap(), 7);
+        assert_eq!(Value::null().get::<Option<i64>>().unwrap(), None);
+        assert_eq!(tags.get::<Vec<String>>().unwrap(), vec!["a", "b"]);
+        assert!(Value::boolean(true).get::<String>().is_err());
+    }
+
+    #[test]
+    fn test_key_property() {
+        let key = Key::new("Item").with_id(1);
+        let mut entity = Entity::new(key.clone());
+        assert!(entity.is_key_only());
+        assert_eq!(
+            entity.value_or_key(KEY_PROPERTY).as_deref(),
+            Some(&Value::key(key.clone()))
+        );
+        entity.set_indexed("title", Value::unicode_string("Lamp"));
+        assert!(!entity.is_key_only());
+        assert_eq!(
+            entity.value_or_key("title").as_deref(),
+            Some(&Value::unicode_string("Lamp"))
+        );
+
+        let mut api_entity: google_datastore1::api::Entity = entity.clone().into();
+        let api_key = api_entity.key.take();
+        api_entity.properties.get_or_insert_default().insert(
+            KEY_PROPERTY.to_string(),
+            google_datastore1::api::Value {
+                key_value: api_key,
+                ..Default::default()
+            },
+        );
+        let converted = Entity::try_from(api_entity.clone()).unwrap();
+        assert_eq!(converted, entity);
+        assert!(!converted.has(KEY_PROPERTY));
+        api_entity.key = Some(key.into());
+        assert_eq!(Entity::try_from(api_entity).unwrap(), entity);
+
+        // a plain setter, the key of the entity is left alone
+        let other = Key::new("Item").with_id(2);
+        entity.set_indexed(KEY_PROPERTY, Value::key(other.clone()));
+        assert_eq!(entity.key().id(), Some(1));
+        assert_eq!(entity.get_value(KEY_PROPERTY), Some(&Value::key(other)));
+    }
+
+    #[test]
+    fn test_merge() {
+        let mut existing = Entity::new(Key::new("Item").with_id(1));
+        existing
+            .set_indexed("title", Value::unicode_string("Lamp"))
+            .set_indexed("stock", Value::integer(3));
+        let mut other = Entity::new(Key::new("Item").with_id(2));
+        other
+            .set_unindexed("title", Value::null())
+            .set_indexed("stock", Value::integer(5))
+            .set_indexed("color", Value::unicode_string("red"));
+
+        let mut overwritten = existing.clone();
+        overwritten.merge(other.clone(), MergeStrategy::OverwriteAll);
+        assert_eq!(overwritten.key(), existing.key());
+        assert_eq!(overwritten.get_value("title"), Some(&Value::Null));
+        assert_eq!(overwritten.get_value("stock"), Some(&Value::integer(5)));
+
+        let mut kept = existing.clone();
+        kept.merge(other.clone(), MergeStrategy::KeepExisting);
+        assert_eq!(kept.get_value("title"), existing.get_value("title"));
+        assert_eq!(kept.get_value("stock"), Some(&Value::integer(3)));
+        assert_eq!(kept.get_value("color"), other.get_value("color"));
+
+        let mut non_null = existing.clone();
+        non_null.merge(other, MergeStrategy::OverwriteNonNull);
+        assert_eq!(non_null.get_value("title"), existing.get_value("title"));
+        assert!(non_null.is_indexed("title"));
+        assert_eq!(non_null.get_value("stock"), Some(&Value::integer(5)));
+        assert_eq!(non_null.property_count(), 3);
+    }
+
+    #[test]
+    fn test_unsupported_api_values() {
+        let timestamp = google_datastore1::api::Value {
+            timestamp_value: Some(chrono::Utc::now()),
+            ..Default::default()
+        };
+        let err = Value::try_from(timestamp.clone()).unwrap_err();
+        assert_eq!(err.kind, EntailErrorKind::PropertyMappingError);
+        assert_eq!(
+            err.message,
+            "Unsupported Datastore value type timestampValue"
+        );
+
+        let mut entity: google_datastore1::api::Entity =
+            Entity::new(Key::new("Event").with_id(1)).into();
+        entity
+            .properties
+            .get_or_insert_default()
+            .insert("at".to_string(), timestamp);
+        let err = Entity::try_from(entity.clone()).unwrap_err();
+        assert_eq!(
+            err.message,
+            "Unsupported Datastore value type timestampValue in the property at of Event(id:1)"
+        );
+        entity.properties = None;
+        assert!(Entity::try_from(entity.clone()).is_ok());
+        entity.key = None;
+        assert!(Entity::try_from(entity).is_err());
+
+        let kindless = google_datastore1::api::Key {
+            path: Some(vec![google_datastore1::api::PathElement {
+                id: Some(1),
+                ..Default::default()
+            }]),
+            ..Default::default()
+        };
+        let err = Key::try_from(kindless).unwrap_err();
+        assert_eq!(err.kind, EntailErrorKind::RequestFailure);
+        assert_eq!(
+            err.message,
+            "Malformed key in the Datastore response: missing a Kind"
+        );
+        assert!(Key::try_from(google_datastore1::api::Key::default()).is_err());
+    }
+
+    #[test]
+    fn test_value_ordering() {
+        let mut values = vec![
+            Value::key(Key::new("Item").with_id(1)),
+            Value::floating_point(1.5),
+            Value::blob(b"a".to_vec()),
+            Value::unicode_string("b"),
+            Value::boolean(false),
+            Value::integer(10),
+            Value::integer(-3),
+            Value::unicode_string("a"),
+            Value::floating_point(f64::NAN),
+            Value::null(),
+        ];
+        values.sort();
+        assert_eq!(
+            values.iter().map(Value::value_type).collect::<Vec<_>>(),
+            vec![
+                ValueType::Null,
+                ValueType::Integer,
+                ValueType::Integer,
+                ValueType::Boolean,
+                ValueType::UnicodeString,
+                ValueType::Blob,
+                ValueType::UnicodeString,
+                ValueType::FloatingPoint,
+                ValueType::FloatingPoint,
+                ValueType::Key,
+            ]
+        );
+        assert_eq!(values[1], Value::integer(-3));
+        assert_eq!(values[7], Value::floating_point(f64::NAN));
+        assert_eq!(Value::floating_point(0.0), Value::floating_point(-0.0));
+        assert!(Value::integer(i64::MAX) < Value::boolean(false));
+        assert!(Value::floating_point(f64::MIN) > Value::unicode_string("z"));
+    }
+
+    #[test]
+    fn test_property_value_builder() {
+        let property = PropertyValue::of("long text")
+            .unindexed()
+            .with_meaning(MEANING_TEXT);
+        assert_eq!(property.value(), &Value::unicode_string("long text"));
+        assert!(!property.is_indexed());
+        assert_eq!(property.meaning(), Some(MEANING_TEXT));
+        assert!(PropertyValue::of(3).is_indexed());
+        let reindexed = PropertyValue::of(3).unindexed().with_indexed(true);
+        assert!(reindexed.is_indexed());
+
+        let mut entity = Entity::new(Key::new("Article").with_id(1));
+        entity.set_property("body", property.clone());
+        assert_eq!(entity.get("body"), Some(&property));
+        assert_eq!(entity.property_count(), 1);
+    }
+
+    #[test]
+    fn test_container_conversions() {
+        let tags = vec!["red".to_string(), "green".to_string()];
+        let expected = Value::array(vec![
+            Value::unicode_string("red"),
+            Value::unicode_string("green"),
+        ]);
+        assert_eq!(Value::from(tags), expected);
+        assert_eq!(Value::from(vec!["red", "green"]), expected);
+        let borrowed = ["red".to_string(), "green".to_string()];
+        let names: Vec<&str> = borrowed.iter().map(String::as_str).collect();
+        assert_eq!(Value::from(names.as_slice()), expected);
+        assert_eq!(Value::array_of(["red", "green"]), expected);
+
+        assert_eq!(
+            Value::from(vec![1i64, 2]),
+            Value::array(vec![Value::integer(1), Value::integer(2)])
+        );
+        assert_eq!(
+            Value::array_of((1..=2).map(|n| Key::new("Tag").with_id(n))),
+            Value::array(vec![
+                Value::key(Key::new("Tag").with_id(1)),
+                Value::key(Key::new("Tag").with_id(2)),
+            ])
+        );
+        // a vector of bytes is still a blob
+        assert_eq!(Value::from(vec![1u8, 2]), Value::blob(vec![1, 2]));
+
+        assert_eq!(Value::from(Some(3)), Value::integer(3));
+        assert_eq!(Value::from(None::<String>), Value::Null);
+        assert_eq!(
+            Value::from(Some(vec![true])),
+            Value::array(vec![Value::boolean(true)])
+        );
+    }
+
+    #[test]
+    fn test_shared_blob() {
+        let blob = Value::blob(vec![7u8; 1024]);
+        let copy = blob.clone();
+        assert_eq!(
+            blob.blob_value().unwrap().as_ptr(),
+            copy.blob_value().unwrap().as_ptr()
+        );
+        let api: google_datastore1::api::Value = copy.into();
+        assert_eq!(api.blob_value.as_deref(), blob.blob_value());
+        assert_eq!(Value::try_from(api).unwrap(), blob);
+        assert_eq!(
+            Value::from(Bytes::from_static(b"ab")),
+            Value::blob(b"ab".to_vec())
+        );
+    }
+
+    #[test]
+    fn test_entity_to_api() {
+        let mut entity = Entity::new(Key::new("Article").with_id(1));
+        entity
+            .set_indexed("tags", Value::from(vec!["a", "b"]))
+            .set_property(
+                "body",
+                PropertyValue::of("text")
+                    .unindexed()
+                    .with_meaning(MEANING_TEXT),
+            );
+        let borrowed = serde_json::to_value(entity.to_api()).unwrap();
+        let consumed: google_datastore1::api::Entity = entity.clone().into();
+        assert_eq!(borrowed, serde_json::to_value(consumed).unwrap());
+        assert_eq!(borrowed["properties"]["body"]["meaning"], MEANING_TEXT);
+
+        let batch = ds::MutationBatch::new()
+            .insert_ref(&entity)
+            .update_ref(&entity)
+            .upsert_ref(&entity);
+        entity.set_indexed("tags", Value::Null);
+        let upserted = batch.mutations[2].upsert.as_ref().unwrap();
+        assert_eq!(serde_json::to_value(upserted).unwrap(), borrowed);
+        assert!(batch.mutations[0].insert.is_some() && batch.mutations[1].update.is_some());
+    }
+
+    #[test]
+    fn test_sorted_properties() {
+        let mut entity = Entity::new(Key::new("Item").with_id(1));
+        for name in ["stock", "title", "notes", "color", "weight", "added"] {
+            entity.set_indexed(name, Value::integer(name.len() as i64));
+        }
+        let names: Vec<&str> = entity
+            .property_iter_sorted()
+            .map(|(name, _)| name.as_ref())
+            .collect();
+        assert_eq!(
+            names,
+            vec!["added", "color", "notes", "stock", "title", "weight"]
+        );
+        assert!(
+            entity.to_string().starts_with(
+                "Item(id:1) {\n  added: int(5) (indexed),\n  color: int(5) (indexed),\n"
+            )
+        );
+        let debug = format!("{:?}", entity);
+        assert!(debug.find("\"added\"").unwrap() < debug.find("\"weight\"").unwrap());
+        // the output doesn't depend on the order the properties were set in
+        let mut reversed = Entity::new(Key::new("Item").with_id(1));
+        for (name, value) in entity.property_iter_sorted().rev() {
+            reversed.set_property(name.clone(), value.clone());
+        }
+        assert_eq!(reversed.to_string(), entity.to_string());
+        assert_eq!(format!("{:?}", reversed), debug);
+    }
+
+    #[test]
+    fn test_property_removal() {
+        let mut entity = Entity::new(Key::new("Item").with_id(1));
+        entity
+            .set_indexed("title", Value::unicode_string("Lamp"))
+            .set_unindexed("notes", Value::unicode_string("Fragile"))
+            .set_indexed("stock", Value::integer(3));
+        assert_eq!(entity.property_count(), 3);
+
+        assert_eq!(
+            entity.take_value("notes"),
+            Some(Value::unicode_string("Fragile"))
+        );
+        assert!(entity.contains_property("notes"));
+        assert_eq!(entity.get_value("notes"), Some(&Value::Null));
+        assert!(!entity.is_indexed("notes"));
+
+        assert_eq!(entity.remove_value("stock"), Some(Value::integer(3)));
+        assert!(!entity.contains_property("stock"));
+        assert_eq!(entity.take_value("stock"), None);
+        assert_eq!(entity.property_count(), 2);
+    }
+
     #[test]
     fn test_entity_building() {
         let key = Key::new("Bizz")
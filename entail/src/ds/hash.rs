@@ -0,0 +1,83 @@
+use super::*;
+
+/// A 64 bit FNV-1a hasher whose output is stable across processes, platforms and
+/// Rust versions (unlike [`std::hash::DefaultHasher`]), so it can be persisted.
+pub(crate) struct StableHasher(u64);
+
+impl StableHasher {
+    const OFFSET_BASIS: u64 = 0xcbf29ce484222325;
+    const PRIME: u64 = 0x100000001b3;
+
+    pub(crate) fn new() -> Self {
+        Self(Self::OFFSET_BASIS)
+    }
+
+    pub(crate) fn write(&mut self, bytes: &[u8]) {
+        for byte in bytes {
+            self.0 = (self.0 ^ *byte as u64).wrapping_mul(Self::PRIME);
+        }
+    }
+
+    /// Writes a length prefixed byte sequence, so that consecutive values can't be
+    /// confused with each other (e.g. `"ab", "c"` and `"a", "bc"`).
+    pub(crate) fn write_prefixed(&mut self, bytes: &[u8]) {
+        self.write(&(bytes.len() as u64).to_le_bytes());
+        self.write(bytes);
+    }
+
+    pub(crate) fn write_key(&mut self, key: &Key) {
+        if let Some(parent) = key.parent() {
+            self.write_key(parent);
+        }
+        self.write_prefixed(key.kind().as_bytes());
+        match (key.name(), key.id()) {
+            (Some(name), _) => {
+                self.write(b"n");
+                self.write_prefixed(name.as_bytes());
+            }
+            (None, Some(id)) => {
+                self.write(b"i");
+                self.write(&id.to_le_bytes());
+            }
+            (None, None) => self.write(b"-"),
+        }
+    }
+
+    pub(crate) fn write_value(&mut self, value: &Value) {
+        match value {
+            Value::Null => self.write(b"N"),
+            Value::Integer(val) => {
+                self.write(b"I");
+                self.write(&val.to_le_bytes());
+            }
+            Value::Boolean(val) => self.write(if *val { b"T" } else { b"F" }),
+            Value::Blob(bytes) => {
+                self.write(b"B");
+                self.write_prefixed(bytes);
+            }
+            Value::UnicodeString(s) => {
+                self.write(b"S");
+                self.write_prefixed(s.as_bytes());
+            }
+            Value::FloatingPoint(val) => {
+                self.write(b"D");
+                self.write(&val.to_bits().to_le_bytes());
+            }
+            Value::Array(values) => {
+                self.write(b"A");
+                self.write(&(values.len() as u64).to_le_bytes());
+                for value in values {
+                    self.write_value(value);
+                }
+            }
+            Value::Key(key) => {
+                self.write(b"K");
+                self.write_key(key);
+            }
+        }
+    }
+
+    pub(crate) fn finish(&self) -> u64 {
+        self.0
+    }
+}
@@ -0,0 +1,134 @@
+use super::super::*;
+
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use std::time::Duration;
+use tokio::sync::Notify;
+
+/// The outcome of [`DatastoreShell::shutdown`](super::DatastoreShell::shutdown).
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ShutdownReport {
+    /// `true` if every in-flight operation finished within the grace period.
+    pub drained: bool,
+    /// The number of operations that were still running when the grace period elapsed.
+    /// Their results are lost as far as the shutdown is concerned, although they may still
+    /// complete (or fail) in the background.
+    pub abandoned_operations: usize,
+    /// The time spent waiting for the in-flight operations.
+    pub elapsed: Duration,
+}
+
+/// Tracks the in-flight operations of a shell and all the transactional shells created
+/// from it.
+#[derive(Default)]
+pub(crate) struct Lifecycle {
+    shutting_down: AtomicBool,
+    in_flight: AtomicUsize,
+    idle: Notify,
+}
+
+/// Marks an operation as in-flight for as long as it is alive.
+pub(crate) struct InFlightGuard<'a>(&'a Lifecycle);
+
+impl Drop for InFlightGuard<'_> {
+    fn drop(&mut self) {
+        if self.0.in_flight.fetch_sub(1, Ordering::AcqRel) == 1 {
+            self.0.idle.notify_waiters();
+        }
+    }
+}
+
+impl Lifecycle {
+    /// Registers a new in-flight operation.
+    ///
+    /// Once the shutdown has started only operations that are part of an already running
+    /// transaction (`in_transaction`) are admitted, so that those transactions can still
+    /// commit or roll back within the grace period.
+    pub(crate) fn enter(&self, in_transaction: bool) -> Result<InFlightGuard<'_>, EntailError> {
+        self.in_flight.fetch_add(1, Ordering::AcqRel);
+        let guard = InFlightGuard(self);
+        if !in_transaction && self.shutting_down.load(Ordering::Acquire) {
+            return Err(EntailError::simple(
+                EntailErrorKind::ShuttingDown,
+                "The Datastore shell is shutting down",
+            ));
+        }
+        Ok(guard)
+    }
+
+    pub(crate) fn is_shutting_down(&self) -> bool {
+        self.shutting_down.load(Ordering::Acquire)
+    }
+
+    pub(crate) fn in_flight(&self) -> usize {
+        self.in_flight.load(Ordering::Acquire)
+    }
+
+    pub(crate) async fn shutdown(&self, grace: Duration) -> ShutdownReport {
+        self.shutting_down.store(true, Ordering::Release);
+        let start = tokio::time::Instant::now();
+        let deadline = start + grace;
+        loop {
+            // register for the notification before checking the counter, otherwise the
+            // last operation could finish in between and the wake-up would be missed
+            let idle = self.idle.notified();
+            tokio::pin!(idle);
+            idle.as_mut().enable();
+            let in_flight = self.in_flight();
+            if in_flight == 0 {
+                return ShutdownReport {
+                    drained: true,
+                    abandoned_operations: 0,
+                    elapsed: start.elapsed(),
+                };
+            }
+            if tokio::time::timeout_at(deadline, idle).await.is_err() {
+                let abandoned_operations = self.in_flight();
+                return ShutdownReport {
+                    drained: abandoned_operations == 0,
+                    abandoned_operations,
+                    elapsed: start.elapsed(),
+                };
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::Arc;
+
+    #[tokio::test]
+    async fn test_shutdown() {
+        let lifecycle = Arc::new(Lifecycle::default());
+        let report = lifecycle.shutdown(Duration::from_secs(1)).await;
+        assert!(report.drained);
+        let err = lifecycle.enter(false).err().expect("Expected a rejection");
+        assert_eq!(err.kind, EntailErrorKind::ShuttingDown);
+        assert_eq!(lifecycle.in_flight(), 0);
+
+        let lifecycle = Arc::new(Lifecycle::default());
+        let finishing = lifecycle.clone();
+        let (started_tx, started_rx) = tokio::sync::oneshot::channel();
+        tokio::spawn(async move {
+            let guard = finishing.enter(false).unwrap();
+            started_tx.send(()).unwrap();
+            tokio::time::sleep(Duration::from_millis(100)).await;
+            // a running transaction can still finish its work
+            drop(finishing.enter(true).unwrap());
+            drop(guard);
+        });
+        started_rx.await.unwrap();
+        let report = lifecycle.shutdown(Duration::from_secs(1)).await;
+        assert!(report.drained);
+        assert!(report.elapsed >= Duration::from_millis(100));
+        assert!(report.elapsed < Duration::from_secs(1));
+
+        let lifecycle = Lifecycle::default();
+        let _stuck = lifecycle.enter(false).unwrap();
+        let report = lifecycle.shutdown(Duration::from_millis(200)).await;
+        assert!(!report.drained);
+        assert_eq!(report.abandoned_operations, 1);
+        assert!(report.elapsed >= Duration::from_millis(200));
+    }
+}
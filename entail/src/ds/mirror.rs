@@ -0,0 +1,190 @@
+use super::*;
+
+use crate::EntailError;
+use google_datastore1::api;
+use std::sync::Arc;
+
+/// The report passed to the failure callback of a write mirror, see
+/// [`DatastoreShell::with_write_mirror`].
+#[derive(Debug)]
+pub struct MirrorFailure {
+    /// The keys of the entities the mirrored commit would have written or deleted, empty if
+    /// one of them is invalid, which the error reports.
+    pub keys: Vec<Key>,
+    /// The error of the mirrored commit.
+    pub error: EntailError,
+}
+
+type FailureCallback = Arc<dyn Fn(MirrorFailure) + Send + Sync>;
+
+/// The secondary target the commits of a shell are mirrored to.
+pub(crate) struct WriteMirror {
+    shell: DatastoreShell,
+    on_failure: FailureCallback,
+}
+
+impl WriteMirror {
+    pub(crate) fn new(
+        mut shell: DatastoreShell,
+        on_failure: impl Fn(MirrorFailure) + Send + Sync + 'static,
+    ) -> Self {
+        // the mirrored commits are independent of the transactions of the primary
+        shell.transaction = None;
+        Self {
+            shell,
+            on_failure: Arc::new(on_failure),
+        }
+    }
+
+    /// Commits the mutations of a successful primary commit to the secondary target,
+    /// reporting the failure (if any) to the callback.
+    pub(crate) async fn apply(&self, mutations: Vec<api::Mutation>, response: &MutationResponse) {
+        let mutations = mirrored_mutations(mutations, response);
+        let keys = mutations
+            .iter()
+            .filter_map(|mutation| {
+                mutation
+                    .upsert
+                    .as_ref()
+                    .and_then(|entity| entity.key.clone())
+                    .or_else(|| mutation.delete.clone())
+            })
+            .map(Key::try_from)
+            .collect::<Result<Vec<Key>, EntailError>>();
+        let keys = match keys {
+            Ok(keys) => keys,
+            // the secondary would reject the batch as well, it is not sent
+            Err(error) => {
+                (self.on_failure)(MirrorFailure {
+                    keys: Vec::new(),
+                    error,
+                });
+                return;
+            }
+        };
+        let batch = MutationBatch { mutations };
+        // boxed, as the commit of the secondary shell could be mirrored again
+        if let Err(error) = Box::pin(self.shell.commit(batch)).await {
+            (self.on_failure)(MirrorFailure { keys, error });
+        }
+    }
+}
+
+/// Rewrites the mutations of a primary commit for the secondary target: the keys allocated by
+/// the primary replace the incomplete ones, and inserts and updates become upserts, so that
+/// entities already copied to (or missing from) the secondary don't make the commit fail.
+/// The project and the database of the primary are cleared from the keys, the secondary
+/// fills in its own.
+fn mirrored_mutations(
+    mutations: Vec<api::Mutation>,
+    response: &MutationResponse,
+) -> Vec<api::Mutation> {
+    mutations
+        .into_iter()
+        .enumerate()
+        .map(|(i, mutation)| {
+            let allocated = response
+                .mutation_results
+                .get(i)
+                .and_then(|result| result.key.as_ref());
+            match mutation.insert.or(mutation.update).or(mutation.upsert) {
+                Some(mut entity) => {
+                    if let Some(key) = allocated {
+                        entity.key = Some(key.to_api());
+                    }
+                    entity.key = entity.key.map(in_secondary);
+                    api::Mutation {
+                        upsert: Some(entity),
+                        ..Default::default()
+                    }
+                }
+                None => api::Mutation {
+                    delete: mutation.delete.map(in_secondary),
+                    ..Default::default()
+                },
+            }
+        })
+        .collect()
+}
+
+/// Keeps only the namespace of the partition of a key.
+fn in_secondary(mut key: api::Key) -> api::Key {
+    if let Some(partition_id) = key.partition_id.as_mut() {
+        partition_id.project_id = None;
+        partition_id.database_id = None;
+    }
+    key
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_mirrored_mutations() {
+        let batch = MutationBatch::new()
+            .insert(Entity::new(Key::new("Post")))
+            .update(Entity::new(Key::new("Post").with_id(7)))
+            .delete(
+                Key::new("Post")
+                    .with_id(8)
+                    .with_partition_id(PartitionId::new().with_project_id("primary")),
+            );
+        let response = MutationResponse {
+            mutation_results: vec![MutationResult {
+                key: Some(Key::new("Post").with_id(42)),
+                version: 1,
+                create_time: None,
+                update_time: None,
+            }],
+            ..Default::default()
+        };
+        let mutations = mirrored_mutations(batch.mutations, &response);
+        let upserted: Vec<Key> = mutations
+            .iter()
+            .filter_map(|m| m.upsert.as_ref().and_then(|e| e.key.clone()))
+            .map(|key| Key::try_from(key).unwrap())
+            .collect();
+        assert_eq!(
+            upserted,
+            vec![Key::new("Post").with_id(42), Key::new("Post").with_id(7)]
+        );
+        assert!(
+            mutations
+                .iter()
+                .all(|m| m.insert.is_none() && m.update.is_none())
+        );
+        assert_eq!(
+            mutations[2]
+                .delete
+                .clone()
+                .map(|key| Key::try_from(key).unwrap()),
+            Some(Key::new("Post").with_id(8))
+        );
+        let partition_id = mutations[2].delete.as_ref().unwrap().partition_id.as_ref();
+        assert_eq!(partition_id.unwrap().project_id, None);
+    }
+
+    #[tokio::test]
+    async fn test_malformed_key_reported() {
+        let secondary = crate::test_support::offline_shell().await;
+        let failures = Arc::new(std::sync::Mutex::new(Vec::new()));
+        let reported = failures.clone();
+        let mirror = WriteMirror::new(secondary, move |failure| {
+            reported.lock().unwrap().push(failure);
+        });
+        let mutations = vec![api::Mutation {
+            delete: Some(api::Key::default()),
+            ..Default::default()
+        }];
+        // reported without sending the commit
+        mirror.apply(mutations, &MutationResponse::default()).await;
+        let failures = failures.lock().unwrap();
+        assert_eq!(failures.len(), 1);
+        assert!(failures[0].keys.is_empty());
+        assert_eq!(
+            failures[0].error.kind,
+            crate::EntailErrorKind::RequestFailure
+        );
+    }
+}
@@ -1,11 +1,45 @@
+mod aggregation;
+mod backoff;
+mod builder;
+mod changes;
+mod decoder;
+mod diff;
 mod entity;
+mod hash;
+mod lifecycle;
+mod mirror;
 mod mutation;
+mod observer;
+mod options;
+mod partition;
 mod query;
+mod redaction;
+mod retry;
+#[cfg(feature = "serde")]
+mod serialization;
 mod shell;
+mod stats;
 mod transaction;
+mod validation;
+mod websafe;
 
+pub use aggregation::*;
+pub use backoff::*;
+pub use builder::*;
+pub use changes::*;
+pub use decoder::*;
+pub use diff::*;
 pub use entity::*;
+pub use lifecycle::*;
+pub use mirror::*;
 pub use mutation::*;
+pub use observer::*;
+pub use options::*;
+pub use partition::*;
 pub use query::*;
+pub use redaction::*;
+pub use retry::*;
 pub use shell::*;
+pub use stats::*;
 pub use transaction::*;
+pub use validation::*;
@@ -1,4 +1,5 @@
 use super::*;
+use crate::EntailError;
 
 /// Represents a single mutation operation to be applied to the Datastore.
 ///
@@ -62,18 +63,20 @@ pub struct MutationResponse {
     pub commit_time: Option<chrono::DateTime<chrono::offset::Utc>>,
 }
 
-impl From<google_datastore1::api::CommitResponse> for MutationResponse {
-    fn from(value: google_datastore1::api::CommitResponse) -> Self {
-        Self {
+impl TryFrom<google_datastore1::api::CommitResponse> for MutationResponse {
+    type Error = EntailError;
+
+    fn try_from(value: google_datastore1::api::CommitResponse) -> Result<Self, EntailError> {
+        Ok(Self {
             mutation_results: value
                 .mutation_results
                 .unwrap_or_default()
                 .into_iter()
-                .map(|e| e.into())
-                .collect(),
+                .map(MutationResult::try_from)
+                .collect::<Result<_, _>>()?,
             index_updates: value.index_updates.unwrap_or_default(),
             commit_time: value.commit_time,
-        }
+        })
     }
 }
 
@@ -98,14 +101,16 @@ pub struct MutationResult {
     pub update_time: Option<chrono::DateTime<chrono::offset::Utc>>,
 }
 
-impl From<google_datastore1::api::MutationResult> for MutationResult {
-    fn from(value: google_datastore1::api::MutationResult) -> Self {
-        Self {
-            key: value.key.map(|key| key.into()),
+impl TryFrom<google_datastore1::api::MutationResult> for MutationResult {
+    type Error = EntailError;
+
+    fn try_from(value: google_datastore1::api::MutationResult) -> Result<Self, EntailError> {
+        Ok(Self {
+            key: value.key.map(Key::try_from).transpose()?,
             version: value.version.unwrap_or_default(),
             create_time: value.create_time,
             update_time: value.update_time,
-        }
+        })
     }
 }
 
@@ -173,6 +178,38 @@ impl MutationBatch {
         self.add(Mutation::Upsert(e))
     }
 
+    /// Adds an insert of an entity the caller keeps using, see [`MutationBatch::insert`].
+    pub fn insert_ref(self, e: &Entity) -> Self {
+        self.add_api(google_datastore1::api::Mutation {
+            insert: Some(e.to_api()),
+            ..Default::default()
+        })
+    }
+
+    /// Adds an update of an entity the caller keeps using, see [`MutationBatch::update`].
+    pub fn update_ref(self, e: &Entity) -> Self {
+        self.add_api(google_datastore1::api::Mutation {
+            update: Some(e.to_api()),
+            ..Default::default()
+        })
+    }
+
+    /// Adds an upsert of an entity the caller keeps using, see [`MutationBatch::upsert`].
+    ///
+    /// The entity is converted right away, so changing it afterwards doesn't affect the
+    /// batch.
+    pub fn upsert_ref(self, e: &Entity) -> Self {
+        self.add_api(google_datastore1::api::Mutation {
+            upsert: Some(e.to_api()),
+            ..Default::default()
+        })
+    }
+
+    fn add_api(mut self, mutation: google_datastore1::api::Mutation) -> Self {
+        self.mutations.push(mutation);
+        self
+    }
+
     /// Convenience method to add an [`Mutation::Delete`] operation.
     ///
     /// Deletes the entity specified by the [`Key`]. The operation will not fail
@@ -223,6 +260,13 @@ impl MutationBatch {
     }
 }
 
+impl From<Vec<Mutation>> for MutationBatch {
+    /// Creates a batch of the mutations, in order.
+    fn from(mutations: Vec<Mutation>) -> Self {
+        Self::new().add_all(mutations)
+    }
+}
+
 impl<'a> Into<Vec<google_datastore1::api::Mutation>> for MutationBatch {
     fn into(self) -> Vec<google_datastore1::api::Mutation> {
         self.mutations
@@ -0,0 +1,116 @@
+use super::*;
+
+use std::time::Duration;
+
+/// An event of a transaction run by [`Transaction`], reported to a [`TransactionObserver`].
+///
+/// The attempts are numbered from `1`.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum TransactionEvent {
+    /// A new transaction was begun for an attempt.
+    AttemptStarted { attempt: u32 },
+    /// An attempt failed and its transaction was rolled back. The `status` is the status of
+    /// the Datastore API error (e.g. `ABORTED` for a contention), if the failure was one.
+    Aborted {
+        attempt: u32,
+        status: Option<String>,
+    },
+    /// The runner waits `delay` before the next attempt.
+    Backoff { attempt: u32, delay: Duration },
+    /// The body of an attempt committed the transaction successfully.
+    Committed { attempt: u32 },
+    /// The body of an attempt succeeded without committing anything (it only read, or had
+    /// no mutations to commit), and the transaction was rolled back.
+    RolledBack { attempt: u32 },
+}
+
+/// The Kinds and entity groups a transaction is known to touch, passed along with every
+/// [`TransactionEvent`].
+///
+/// They are collected from the keys of [`Transaction::with_lock_order`] and of the mutations
+/// committed (or attempted to be committed) through the [`TransactionShell`]. The keys read by
+/// the body are not known to the runner.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct TransactionTags {
+    /// The Kinds of the touched entities, sorted, without duplicates.
+    pub kinds: Vec<String>,
+    /// The roots of the touched entity groups, see [`entity_groups`].
+    pub entity_groups: Vec<Key>,
+}
+
+impl TransactionTags {
+    pub(crate) fn add_keys(&mut self, keys: &[Key]) {
+        if keys.is_empty() {
+            return;
+        }
+        self.kinds
+            .extend(keys.iter().map(|key| key.kind().to_string()));
+        self.kinds.sort();
+        self.kinds.dedup();
+        self.entity_groups = entity_groups(self.entity_groups.iter().chain(keys));
+    }
+}
+
+/// Receives the events of the transactions run by a [`Transaction`] configured with
+/// [`Transaction::with_observer`], e.g. to feed contention metrics.
+///
+/// The observer is called synchronously by the runner, so it should only record the event.
+pub trait TransactionObserver: Send + Sync {
+    /// Called for every event of a transaction.
+    ///
+    /// ## Parameters
+    /// - `event`: The event.
+    /// - `tags`: The Kinds and entity groups the transaction is known to touch so far.
+    fn on_event(&self, event: &TransactionEvent, tags: &TransactionTags);
+}
+
+impl<F> TransactionObserver for F
+where
+    F: Fn(&TransactionEvent, &TransactionTags) + Send + Sync,
+{
+    fn on_event(&self, event: &TransactionEvent, tags: &TransactionTags) {
+        self(event, tags)
+    }
+}
+
+/// Returns the keys of the entities written or deleted by the mutations.
+pub(crate) fn mutation_keys(mutations: &[google_datastore1::api::Mutation]) -> Vec<Key> {
+    mutations
+        .iter()
+        .filter_map(|mutation| {
+            mutation
+                .insert
+                .as_ref()
+                .or(mutation.update.as_ref())
+                .or(mutation.upsert.as_ref())
+                .and_then(|entity| entity.key.clone())
+                .or_else(|| mutation.delete.clone())
+        })
+        .filter_map(|key| Key::try_from(key).ok())
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_tags() {
+        let parent = Key::new("Account").with_name("a");
+        let batch = MutationBatch::new()
+            .upsert(Entity::new(
+                Key::new("Order").with_id(2).with_parent(parent.clone()),
+            ))
+            .delete(Key::new("Order").with_id(1).with_parent(parent.clone()))
+            .insert(Entity::new(Key::new("Audit").with_id(9)));
+        let mut tags = TransactionTags::default();
+        tags.add_keys(&mutation_keys(&batch.mutations));
+        assert_eq!(tags.kinds, vec!["Audit", "Order"]);
+        tags.add_keys(std::slice::from_ref(&parent));
+        assert_eq!(tags.kinds, vec!["Account", "Audit", "Order"]);
+        assert_eq!(
+            tags.entity_groups,
+            vec![parent, Key::new("Audit").with_id(9)]
+        );
+    }
+}
@@ -0,0 +1,140 @@
+use std::borrow::Cow;
+
+/// Additional query parameters sent with every request of a
+/// [`DatastoreShell`](super::DatastoreShell).
+///
+/// Only query parameters are supported, no HTTP header is ever added to the requests. The
+/// options are passed as
+/// [system parameters](https://cloud.google.com/apis/docs/system-parameters) in the query
+/// string, which is the only extension point the underlying `google-datastore1` client
+/// exposes (its call builders offer no way to add HTTP headers). The quota project is set
+/// with the `userProject` parameter, which Google APIs accept in place of the
+/// `X-Goog-User-Project` header, while the request reason (`X-Goog-Request-Reason`) and
+/// arbitrary headers have no query string form and can't be sent. Options can be set on a
+/// shell with [`DatastoreShell::with_request_options`](super::DatastoreShell::with_request_options),
+/// which returns a cheap copy of the shell, so they can be applied to a single call as well.
+///
+/// The `with_*` methods consume and return `Self`, allowing for method chaining.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct RequestOptions {
+    /// An arbitrary string identifying the user on whose behalf the request is made
+    /// (`quotaUser`), so that per-user quotas are enforced separately for each of them.
+    pub quota_user: Option<Cow<'static, str>>,
+    /// The project billed for the request and charged for its quota (`userProject`, the query
+    /// string form of the `X-Goog-User-Project` header).
+    pub user_project: Option<Cow<'static, str>>,
+    /// Any other query parameters, e.g. `("prettyPrint", "false")`.
+    pub params: Vec<(Cow<'static, str>, Cow<'static, str>)>,
+}
+
+impl RequestOptions {
+    /// Creates an empty set of options.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Sets the `quotaUser` system parameter.
+    pub fn with_quota_user(mut self, quota_user: impl Into<Cow<'static, str>>) -> Self {
+        self.quota_user = Some(quota_user.into());
+        self
+    }
+
+    /// Sets the `userProject` system parameter.
+    pub fn with_user_project(mut self, user_project: impl Into<Cow<'static, str>>) -> Self {
+        self.user_project = Some(user_project.into());
+        self
+    }
+
+    /// Adds an arbitrary query parameter.
+    pub fn with_param(
+        mut self,
+        name: impl Into<Cow<'static, str>>,
+        value: impl Into<Cow<'static, str>>,
+    ) -> Self {
+        self.params.push((name.into(), value.into()));
+        self
+    }
+
+    /// Returns `true` if no parameter is set.
+    pub fn is_empty(&self) -> bool {
+        self.quota_user.is_none() && self.user_project.is_none() && self.params.is_empty()
+    }
+
+    /// Returns every parameter (including `quotaUser` and `userProject`) as name and value
+    /// pairs.
+    pub fn iter(&self) -> impl Iterator<Item = (&str, &str)> {
+        self.quota_user
+            .iter()
+            .map(|quota_user| ("quotaUser", quota_user.as_ref()))
+            .chain(
+                self.user_project
+                    .iter()
+                    .map(|user_project| ("userProject", user_project.as_ref())),
+            )
+            .chain(
+                self.params
+                    .iter()
+                    .map(|(name, value)| (name.as_ref(), value.as_ref())),
+            )
+    }
+}
+
+/// Applies the [`RequestOptions`] to the call builders of the `google-datastore1` client.
+pub(crate) trait WithRequestOptions: Sized {
+    fn param_pair(self, name: &str, value: &str) -> Self;
+
+    fn with_options(self, options: &RequestOptions) -> Self {
+        options
+            .iter()
+            .fold(self, |call, (name, value)| call.param_pair(name, value))
+    }
+}
+
+macro_rules! impl_with_request_options {
+    ($($call:ident),*) => {
+        $(
+            impl<C: google_datastore1::common::Connector> WithRequestOptions
+                for google_datastore1::api::$call<'_, C>
+            {
+                fn param_pair(self, name: &str, value: &str) -> Self {
+                    self.param(name, value)
+                }
+            }
+        )*
+    };
+}
+
+impl_with_request_options!(
+    ProjectAllocateIdCall,
+    ProjectBeginTransactionCall,
+    ProjectCommitCall,
+    ProjectLookupCall,
+    ProjectReserveIdCall,
+    ProjectRollbackCall,
+    ProjectRunAggregationQueryCall,
+    ProjectRunQueryCall
+);
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_request_options() {
+        assert!(RequestOptions::new().is_empty());
+        let options = RequestOptions::new()
+            .with_param("prettyPrint", "false")
+            .with_quota_user("tenant-42")
+            .with_user_project("billing-project");
+        assert!(!options.is_empty());
+        assert_eq!(
+            options.iter().collect::<Vec<_>>(),
+            vec![
+                ("quotaUser", "tenant-42"),
+                ("userProject", "billing-project"),
+                ("prettyPrint", "false")
+            ]
+        );
+        assert!(!RequestOptions::new().with_user_project("p").is_empty());
+    }
+}
@@ -0,0 +1,129 @@
+use std::borrow::Cow;
+use std::fmt;
+
+/// The partition of Datastore the entities of a [`super::Key`] or the results of a
+/// [`super::Query`] belong to: a namespace of a database of a project.
+///
+/// A part that is `None` is the one of the [`super::DatastoreShell`] sending the request (for
+/// the namespace, the default namespace unless the shell has one, see
+/// [`super::DatastoreShell::with_namespace`]). A project or a database other than the ones of
+/// the shell is rejected with an error of the kind
+/// [`crate::EntailErrorKind::PartitionMismatch`] before anything is sent.
+///
+/// The `with_*` methods consume and return `Self`, allowing for method chaining.
+#[derive(Clone, Debug, Default, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub struct PartitionId {
+    /// The ID of the project.
+    pub project_id: Option<Cow<'static, str>>,
+    /// The ID of the database, `None` for the default database.
+    pub database_id: Option<Cow<'static, str>>,
+    /// The namespace, `None` for the default namespace.
+    pub namespace: Option<Cow<'static, str>>,
+}
+
+fn non_empty(value: impl Into<Cow<'static, str>>) -> Option<Cow<'static, str>> {
+    let value = value.into();
+    (!value.is_empty()).then_some(value)
+}
+
+impl PartitionId {
+    /// Creates the partition of the shell sending the request.
+    pub const fn new() -> Self {
+        Self {
+            project_id: None,
+            database_id: None,
+            namespace: None,
+        }
+    }
+
+    /// Sets the project. An empty string is the project of the shell.
+    pub fn with_project_id(mut self, project_id: impl Into<Cow<'static, str>>) -> Self {
+        self.project_id = non_empty(project_id);
+        self
+    }
+
+    /// Sets the database. An empty string is the default database.
+    pub fn with_database_id(mut self, database_id: impl Into<Cow<'static, str>>) -> Self {
+        self.database_id = non_empty(database_id);
+        self
+    }
+
+    /// Sets the namespace. An empty string is the default namespace.
+    pub fn with_namespace(mut self, namespace: impl Into<Cow<'static, str>>) -> Self {
+        self.namespace = non_empty(namespace);
+        self
+    }
+
+    /// Returns `true` if no part is set, i.e. this is the partition of the shell.
+    pub fn is_default(&self) -> bool {
+        self.project_id.is_none() && self.database_id.is_none() && self.namespace.is_none()
+    }
+
+    /// Fills the parts that are not set from another partition.
+    pub(crate) fn or(self, other: &PartitionId) -> PartitionId {
+        PartitionId {
+            project_id: self.project_id.or_else(|| other.project_id.clone()),
+            database_id: self.database_id.or_else(|| other.database_id.clone()),
+            namespace: self.namespace.or_else(|| other.namespace.clone()),
+        }
+    }
+
+    /// Converts the partition into the lower-level API `PartitionId`, or `None` if no part is
+    /// set.
+    pub(crate) fn to_api(&self) -> Option<google_datastore1::api::PartitionId> {
+        (!self.is_default()).then(|| google_datastore1::api::PartitionId {
+            project_id: self.project_id.as_deref().map(str::to_string),
+            database_id: self.database_id.as_deref().map(str::to_string),
+            namespace_id: self.namespace.as_deref().map(str::to_string),
+        })
+    }
+}
+
+impl From<google_datastore1::api::PartitionId> for PartitionId {
+    /// Converts the lower-level API `PartitionId`, where empty strings are the defaults.
+    fn from(value: google_datastore1::api::PartitionId) -> Self {
+        PartitionId {
+            project_id: value.project_id.and_then(non_empty),
+            database_id: value.database_id.and_then(non_empty),
+            namespace: value.namespace_id.and_then(non_empty),
+        }
+    }
+}
+
+impl fmt::Display for PartitionId {
+    /// Formats the partition as `project/database/namespace`, with `-` for the parts of the
+    /// shell and `(default)` for the default database and namespace.
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "{}/{}/{}",
+            self.project_id.as_deref().unwrap_or("-"),
+            self.database_id.as_deref().unwrap_or("(default)"),
+            self.namespace.as_deref().unwrap_or("(default)")
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_partition_id() {
+        assert!(PartitionId::new().with_namespace("").is_default());
+        assert!(PartitionId::new().to_api().is_none());
+        let partition = PartitionId::new()
+            .with_project_id("shop")
+            .with_namespace("tenant-a");
+        let api = partition.to_api().unwrap();
+        assert_eq!(api.project_id.as_deref(), Some("shop"));
+        assert_eq!(api.database_id, None);
+        assert_eq!(PartitionId::from(api), partition);
+        assert_eq!(partition.to_string(), "shop/(default)/tenant-a");
+
+        let filled = PartitionId::new()
+            .with_database_id("archive")
+            .or(&partition);
+        assert_eq!(filled.to_string(), "shop/archive/tenant-a");
+    }
+}
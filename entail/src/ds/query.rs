@@ -1,5 +1,6 @@
 use super::*;
 
+use crate::{EntailError, EntailErrorKind};
 use std::borrow::Cow;
 use strum::{Display, EnumString};
 
@@ -124,19 +125,91 @@ where
     }
 }
 
-impl From<google_datastore1::api::QueryResultBatch> for QueryResult<Entity> {
-    fn from(value: google_datastore1::api::QueryResultBatch) -> Self {
+impl<T> IntoIterator for QueryResult<T> {
+    type Item = T;
+    type IntoIter = std::vec::IntoIter<T>;
+
+    fn into_iter(self) -> Self::IntoIter {
+        self.items.into_iter()
+    }
+}
+
+impl<'a, T> IntoIterator for &'a QueryResult<T> {
+    type Item = &'a T;
+    type IntoIter = std::slice::Iter<'a, T>;
+
+    fn into_iter(self) -> Self::IntoIter {
+        self.items.iter()
+    }
+}
+
+#[cfg(feature = "rayon")]
+impl<T: Send> QueryResult<T> {
+    /// Consumes the `QueryResult<T>` and transforms its items into a `QueryResult<U>` on the
+    /// rayon thread pool, for CPU-heavy post-processing like decompression or decryption.
+    /// The order of the items is kept.
+    ///
+    /// The call blocks until every item is transformed, so in an async context large result
+    /// sets are better processed in `tokio::task::spawn_blocking`.
+    pub fn par_map<U, F>(self, f: F) -> QueryResult<U>
+    where
+        U: Send,
+        F: Fn(T) -> U + Sync + Send,
+    {
+        use rayon::prelude::*;
+
+        let QueryResult { items, end_cursor } = self;
+        QueryResult {
+            items: items.into_par_iter().map(f).collect(),
+            end_cursor,
+        }
+    }
+
+    /// The fallible version of [`QueryResult::par_map`]. If the closure returns an `Err(E)`
+    /// for any item, one of the errors is returned, and the remaining items may not be
+    /// transformed.
+    pub fn try_par_map<U, E, F>(self, f: F) -> Result<QueryResult<U>, E>
+    where
+        U: Send,
+        E: Send,
+        F: Fn(T) -> Result<U, E> + Sync + Send,
+    {
+        use rayon::prelude::*;
+
+        let QueryResult { items, end_cursor } = self;
+        Ok(QueryResult {
+            items: items.into_par_iter().map(f).collect::<Result<_, E>>()?,
+            end_cursor,
+        })
+    }
+}
+
+impl TryFrom<google_datastore1::api::QueryResultBatch> for QueryResult<Entity> {
+    type Error = EntailError;
+
+    fn try_from(value: google_datastore1::api::QueryResultBatch) -> Result<Self, EntailError> {
         let end_cursor = value.end_cursor;
         let items = value
             .entity_results
             .unwrap_or_default()
             .into_iter()
-            .map(|e| e.entity.expect("EntityResult without an entity").into())
-            .collect();
-        Self { items, end_cursor }
+            .map(|e| {
+                e.entity
+                    .ok_or_else(missing_entity)
+                    .and_then(Entity::try_from)
+            })
+            .collect::<Result<_, _>>()?;
+        Ok(Self { items, end_cursor })
     }
 }
 
+pub(crate) fn missing_entity() -> EntailError {
+    EntailError::simple(
+        EntailErrorKind::PropertyMappingError,
+        "EntityResult without an entity",
+    )
+}
+
 /// Represents a filter used in a Datastore query.
 ///
 /// Filters are used to constrain the results returned by a query,
@@ -145,7 +218,8 @@ impl From<google_datastore1::api::QueryResultBatch> for QueryResult<Entity> {
 pub enum Filter {
     /// A composite filter that combines multiple sub-filters using a logical operator.
     ///
-    /// Currently, only the `And` operator is supported.
+    /// Composite filters are usually built with [`Filter::and`], [`Filter::or`] or the `&`
+    /// and `|` operators, which flatten nested composites of the same operator.
     Composite(CompositeFilterOperator, Vec<Filter>),
     /// Represents a filter based on a property's value.
     ///
@@ -164,7 +238,8 @@ impl Filter {
     ///
     /// This is a convenience method for creating a `Composite` filter. It handles
     /// edge cases by returning `None` for an empty vector or unwrapping a single
-    /// filter from a vector of one.
+    /// filter from a vector of one. The operands of nested `AND` filters are merged
+    /// into the result.
     ///
     /// ## Parameters
     /// - `filters`: A `Vec` of `Filter`s to be combined.
@@ -173,16 +248,62 @@ impl Filter {
     /// An `Option<Filter>` containing the combined filter, or `None` if the input vector
     /// was empty.
     pub fn and(filters: Vec<Filter>) -> Option<Filter> {
-        if filters.is_empty() {
-            None
-        } else if filters.len() == 1 {
-            filters.into_iter().next()
+        Self::combine(CompositeFilterOperator::And, filters)
+    }
+
+    /// Combines multiple filters with a logical `OR` operator.
+    ///
+    /// This works the same way as [`Filter::and`], including the flattening of nested
+    /// `OR` filters.
+    ///
+    /// ## Parameters
+    /// - `filters`: A `Vec` of `Filter`s to be combined.
+    ///
+    /// ## Returns
+    /// An `Option<Filter>` containing the combined filter, or `None` if the input vector
+    /// was empty.
+    pub fn or(filters: Vec<Filter>) -> Option<Filter> {
+        Self::combine(CompositeFilterOperator::Or, filters)
+    }
+
+    fn combine(op: CompositeFilterOperator, filters: Vec<Filter>) -> Option<Filter> {
+        let mut operands = Vec::with_capacity(filters.len());
+        for filter in filters {
+            match filter {
+                Filter::Composite(inner_op, nested) if inner_op == op => operands.extend(nested),
+                filter => operands.push(filter),
+            }
+        }
+        if operands.len() > 1 {
+            Some(Filter::Composite(op, operands))
         } else {
-            Some(Filter::Composite(CompositeFilterOperator::And, filters))
+            operands.pop()
         }
     }
 }
 
+/// Combines two filters with a logical `AND`, e.g. `a & b & c`. Nested `AND` filters are
+/// flattened, so the result has a single composite with all three operands.
+impl std::ops::BitAnd for Filter {
+    type Output = Filter;
+
+    fn bitand(self, rhs: Filter) -> Filter {
+        Filter::and(vec![self, rhs])
+            .unwrap_or_else(|| Filter::Composite(CompositeFilterOperator::And, Vec::new()))
+    }
+}
+
+/// Combines two filters with a logical `OR`, e.g. `a | b`. Nested `OR` filters are
+/// flattened.
+impl std::ops::BitOr for Filter {
+    type Output = Filter;
+
+    fn bitor(self, rhs: Filter) -> Filter {
+        Filter::or(vec![self, rhs])
+            .unwrap_or_else(|| Filter::Composite(CompositeFilterOperator::Or, Vec::new()))
+    }
+}
+
 impl FilterOperator {
     /// Creates a new `Filter::Property` variant using this operator.
     ///
@@ -215,6 +336,9 @@ pub enum CompositeFilterOperator {
     /// The logical `AND` operator. All sub-filters must evaluate to true for the composite
     /// filter to be true.
     And,
+    /// The logical `OR` operator. At least one of the sub-filters must evaluate to true for
+    /// the composite filter to be true.
+    Or,
 }
 
 /// The comparison operator used in a `Property` filter.
@@ -309,6 +433,9 @@ impl Into<google_datastore1::api::PropertyOrder> for PropertyOrder {
 ///
 /// A `Query` object defines the criteria for retrieving entities, including the
 /// kind of entity, filters, sorting, and pagination options.
+///
+/// The methods setting the filters, the order, the pagination and the projection consume and
+/// return `Self`, allowing for method chaining.
 #[derive(Clone, Debug)]
 pub struct Query {
     /// The **kind** of entity to query.
@@ -362,6 +489,17 @@ pub struct Query {
     /// entities are still read internally by Datastore, affecting query latency and
     /// billing. It is highly recommended to use `start_cursor` for pagination instead.
     pub offset: i32,
+    /// The **namespace** to query, or `None` for the default namespace.
+    ///
+    /// The namespace of the shell running the query takes precedence, see
+    /// [`DatastoreShell::with_namespace`](crate::ds::DatastoreShell::with_namespace).
+    pub namespace: Option<Cow<'static, str>>,
+    /// The project to query, or `None` for the project of the shell running the query, which
+    /// is the only one accepted.
+    pub project_id: Option<Cow<'static, str>>,
+    /// The database to query, or `None` for the database of the shell running the query,
+    /// which is the only one accepted.
+    pub database_id: Option<Cow<'static, str>>,
 }
 
 impl Default for Query {
@@ -376,6 +514,9 @@ impl Default for Query {
             order: Vec::new(),
             limit: 1000,
             offset: 0,
+            namespace: None,
+            project_id: None,
+            database_id: None,
         }
     }
 }
@@ -419,3 +560,341 @@ impl Into<google_datastore1::api::Query> for Query {
         }
     }
 }
+
+impl Filter {
+    fn write_shape(&self, out: &mut String) {
+        match self {
+            Filter::Composite(op, _) => {
+                // nested filters with the same operator are flattened and the operands are
+                // sorted, so that the order in which the filters were combined doesn't matter
+                let mut operands = Vec::new();
+                self.collect_operands(*op, &mut operands);
+                if operands.len() == 1 {
+                    out.push_str(&operands[0]);
+                    return;
+                }
+                operands.sort();
+                out.push_str(&op.to_string());
+                out.push('(');
+                out.push_str(&operands.join(","));
+                out.push(')');
+            }
+            Filter::Property(name, op, _) => {
+                out.push_str(name);
+                out.push(' ');
+                out.push_str(&op.to_string());
+            }
+        }
+    }
+
+    fn collect_operands(&self, op: CompositeFilterOperator, operands: &mut Vec<String>) {
+        match self {
+            Filter::Composite(inner_op, filters) if *inner_op == op => {
+                for filter in filters {
+                    filter.collect_operands(op, operands);
+                }
+            }
+            _ => {
+                let mut shape = String::new();
+                self.write_shape(&mut shape);
+                operands.push(shape);
+            }
+        }
+    }
+}
+
+impl Query {
+    /// Creates a query of a Kind with the default settings, to be refined with the chainable
+    /// methods, e.g. `Query::new("Task").filter(filter).order_desc("created").limit(20)`.
+    ///
+    /// ## Parameters
+    /// - `kind`: The Kind to query, an empty string for a kindless query.
+    pub fn new(kind: impl Into<Cow<'static, str>>) -> Self {
+        Self {
+            kind: kind.into(),
+            ..Default::default()
+        }
+    }
+
+    /// Sets the filter of the query, replacing the previous one.
+    pub fn filter(mut self, filter: Filter) -> Self {
+        self.filter = Some(filter);
+        self
+    }
+
+    /// Adds a filter to the query, combined with the previous one with a logical `AND` (see
+    /// [`Filter::and`]), or set as the filter if there is none yet.
+    pub fn and_filter(mut self, filter: Filter) -> Self {
+        self.filter = Some(match self.filter.take() {
+            Some(previous) => previous & filter,
+            None => filter,
+        });
+        self
+    }
+
+    /// Adds an ascending order by a property, after the previous orders.
+    pub fn order_asc(mut self, name: impl Into<Cow<'static, str>>) -> Self {
+        self.order
+            .push(PropertyOrder::new(name, OrderDirection::ASCENDING));
+        self
+    }
+
+    /// Adds a descending order by a property, after the previous orders.
+    pub fn order_desc(mut self, name: impl Into<Cow<'static, str>>) -> Self {
+        self.order
+            .push(PropertyOrder::new(name, OrderDirection::DESCENDING));
+        self
+    }
+
+    /// Sets the maximum number of results, `0` for no limit.
+    pub fn limit(mut self, limit: i32) -> Self {
+        self.limit = limit;
+        self
+    }
+
+    /// Sets the number of results to skip, see the caveat of the [`Query::offset`] field.
+    pub fn offset(mut self, offset: i32) -> Self {
+        self.offset = offset;
+        self
+    }
+
+    /// Adds properties to the projection of the query.
+    pub fn project<N: Into<Cow<'static, str>>>(
+        mut self,
+        names: impl IntoIterator<Item = N>,
+    ) -> Self {
+        self.projection.extend(names.into_iter().map(Into::into));
+        self
+    }
+
+    /// Adds properties the results have to be distinct on.
+    pub fn distinct_on<N: Into<Cow<'static, str>>>(
+        mut self,
+        names: impl IntoIterator<Item = N>,
+    ) -> Self {
+        self.distinct_on.extend(names.into_iter().map(Into::into));
+        self
+    }
+
+    /// Starts the query at a cursor, e.g. the [`QueryResult::end_cursor`] of the previous
+    /// page.
+    pub fn start_at(mut self, cursor: impl Into<Vec<u8>>) -> Self {
+        self.start_cursor = Some(cursor.into());
+        self
+    }
+
+    /// Returns the partition the query runs in (unless the shell running it overrides the
+    /// namespace).
+    pub fn partition_id(&self) -> PartitionId {
+        PartitionId {
+            project_id: self.project_id.clone(),
+            database_id: self.database_id.clone(),
+            namespace: self.namespace.clone(),
+        }
+    }
+
+    /// Sets the project, the database and the namespace of the query.
+    pub fn with_partition_id(self, partition: PartitionId) -> Self {
+        Query {
+            project_id: partition.project_id,
+            database_id: partition.database_id,
+            namespace: partition.namespace,
+            ..self
+        }
+    }
+
+    /// Returns a normalized, human-readable description of the **shape** of the query.
+    ///
+    /// The shape consists of the kind, the structure of the filter (property names and
+    /// operators), the orders, the projection and the distinct properties, but **not** the
+    /// values the properties are compared to, the cursors, the limit or the offset. The
+    /// operands of composite filters are flattened and sorted, so equivalent filters
+    /// combined in a different order have the same shape.
+    ///
+    /// This is useful for aggregating query statistics, for example in slow-query logs.
+    pub fn shape(&self) -> String {
+        let mut out = String::new();
+        out.push_str(&self.kind);
+        out.push('|');
+        if let Some(filter) = &self.filter {
+            filter.write_shape(&mut out);
+        }
+        out.push('|');
+        let orders: Vec<String> = self
+            .order
+            .iter()
+            .map(|order| format!("{} {}", order.name, order.direction))
+            .collect();
+        out.push_str(&orders.join(","));
+        out.push('|');
+        out.push_str(&self.projection.join(","));
+        out.push('|');
+        out.push_str(&self.distinct_on.join(","));
+        out
+    }
+
+    /// Returns a stable 64 bit hash of the [shape](Self::shape) of the query.
+    ///
+    /// Two queries that only differ in the values they filter on, their cursors, limit or
+    /// offset have the same fingerprint. The hash is computed with FNV-1a, so unlike
+    /// [`std::hash::DefaultHasher`] it does not change between processes or Rust versions,
+    /// which makes it usable in cache keys and persisted tokens.
+    pub fn fingerprint(&self) -> u64 {
+        let mut hasher = super::hash::StableHasher::new();
+        hasher.write(self.shape().as_bytes());
+        hasher.finish()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_fingerprint() {
+        let query = Query {
+            kind: "User".into(),
+            filter: Filter::and(vec![
+                FilterOperator::Equal.of("status", "active"),
+                FilterOperator::GreaterThan.of("age", 18),
+            ]),
+            order: vec![PropertyOrder::new("age", OrderDirection::DESCENDING)],
+            ..Query::default()
+        };
+        assert_eq!(
+            query.shape(),
+            "User|AND(age GREATER_THAN,status EQUAL)|age DESCENDING||"
+        );
+
+        // values, cursors and limits are not part of the shape
+        let other_values = Query {
+            filter: Filter::and(vec![
+                FilterOperator::GreaterThan.of("age", 21),
+                Filter::and(vec![FilterOperator::Equal.of("status", "banned")]).unwrap(),
+            ]),
+            start_cursor: Some(vec![1, 2, 3]),
+            limit: 10,
+            ..query.clone()
+        };
+        assert_eq!(query.fingerprint(), other_values.fingerprint());
+
+        // nested composite filters are flattened
+        let nested = Query {
+            filter: Some(Filter::Composite(
+                CompositeFilterOperator::And,
+                vec![
+                    FilterOperator::Equal.of("status", "active"),
+                    Filter::Composite(
+                        CompositeFilterOperator::And,
+                        vec![FilterOperator::GreaterThan.of("age", 18)],
+                    ),
+                ],
+            )),
+            ..query.clone()
+        };
+        assert_eq!(query.fingerprint(), nested.fingerprint());
+
+        let different_order = Query {
+            order: vec![PropertyOrder::new("age", OrderDirection::ASCENDING)],
+            ..query.clone()
+        };
+        assert_ne!(query.fingerprint(), different_order.fingerprint());
+    }
+
+    #[test]
+    fn test_query_builder() {
+        let active = FilterOperator::Equal.of("status", "active");
+        let adult = FilterOperator::GreaterThanOrEqual.of("age", 18);
+        let query = Query::new("User")
+            .and_filter(active.clone())
+            .and_filter(adult.clone())
+            .order_desc("age")
+            .order_asc("name")
+            .project(["age", "name"])
+            .distinct_on(["age"])
+            .limit(20)
+            .offset(5)
+            .start_at(vec![1, 2, 3]);
+        let expected = Query {
+            kind: "User".into(),
+            filter: Some(active.clone() & adult.clone()),
+            start_cursor: Some(vec![1, 2, 3]),
+            projection: vec!["age".into(), "name".into()],
+            distinct_on: vec!["age".into()],
+            order: vec![
+                PropertyOrder::new("age", OrderDirection::DESCENDING),
+                PropertyOrder::new("name", OrderDirection::ASCENDING),
+            ],
+            limit: 20,
+            offset: 5,
+            ..Query::default()
+        };
+        assert_eq!(query.shape(), expected.shape());
+        assert_eq!(query.start_cursor, expected.start_cursor);
+        assert_eq!((query.limit, query.offset), (20, 5));
+
+        // filter replaces, and_filter adds
+        let replaced = query.filter(adult.clone());
+        assert!(matches!(replaced.filter, Some(Filter::Property(name, _, _)) if name == "age"));
+        assert_eq!(Query::new("User").limit, Query::default().limit);
+    }
+
+    #[test]
+    fn test_filter_combinators() {
+        let active = FilterOperator::Equal.of("status", "active");
+        let adult = FilterOperator::GreaterThanOrEqual.of("age", 18);
+        let admin = FilterOperator::Equal.of("role", "admin");
+        let filter = active.clone() & adult.clone() & (admin.clone() | active.clone());
+        let Filter::Composite(CompositeFilterOperator::And, operands) = &filter else {
+            panic!("Expected an AND filter, got {:?}", filter);
+        };
+        assert_eq!(operands.len(), 3);
+        assert!(matches!(
+            &operands[2],
+            Filter::Composite(CompositeFilterOperator::Or, or_operands) if or_operands.len() == 2
+        ));
+        let query = Query {
+            kind: "User".into(),
+            filter: Some(filter),
+            ..Query::default()
+        };
+        assert_eq!(
+            query.shape(),
+            "User|AND(OR(role EQUAL,status EQUAL),age GREATER_THAN_OR_EQUAL,status EQUAL)|||"
+        );
+
+        assert!(Filter::or(vec![]).is_none());
+        assert!(matches!(
+            Filter::or(vec![admin.clone()]),
+            Some(Filter::Property(name, _, _)) if name == "role"
+        ));
+        let flattened = Filter::or(vec![admin.clone() | active, adult | admin]).unwrap();
+        assert!(matches!(
+            flattened,
+            Filter::Composite(CompositeFilterOperator::Or, operands) if operands.len() == 4
+        ));
+    }
+
+    #[test]
+    fn test_query_result_iteration() {
+        let result = QueryResult::new(vec![1, 2, 3], Some(vec![9]));
+        let mut sum = 0;
+        for item in &result {
+            sum += item;
+        }
+        assert_eq!(sum, 6);
+        assert_eq!(result.into_iter().collect::<Vec<i32>>(), vec![1, 2, 3]);
+    }
+
+    #[cfg(feature = "rayon")]
+    #[test]
+    fn test_par_map() {
+        let result = QueryResult::new((0..1000).collect(), Some(vec![9]));
+        let doubled = result.par_map(|item: i64| item * 2);
+        assert_eq!(doubled.end_cursor, Some(vec![9]));
+        assert!(doubled.items.iter().enumerate().all(|(i, &item)| item == 2 * i as i64));
+
+        let failed = doubled.try_par_map(|item| if item == 10 { Err(item) } else { Ok(item) });
+        assert_eq!(failed.unwrap_err(), 10);
+    }
+}
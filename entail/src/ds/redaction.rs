@@ -0,0 +1,251 @@
+use super::*;
+
+use std::borrow::Cow;
+use std::fmt;
+
+/// Which property values the [`Entity::display_with`] view masks.
+///
+/// A masked value is shown with its type (and its size for strings and blobs), so the output
+/// can be logged without revealing the data. The names given to [`DisplayOptions::reveal`]
+/// and [`DisplayOptions::mask`] take precedence over the rule the options are created with,
+/// and masking wins if a name is given to both.
+///
+/// The names in the key of the entity (and of its ancestors) are masked like a property
+/// named [`KEY_PROPERTY`] (`__key__`), the Kinds and the IDs are always shown.
+///
+/// [`DisplayOptions::reveal`] and [`DisplayOptions::mask`] consume and return `Self`, so
+/// several names can be chained.
+#[derive(Clone, Debug)]
+pub struct DisplayOptions {
+    rule: MaskRule,
+    revealed: Vec<Cow<'static, str>>,
+    masked: Vec<Cow<'static, str>>,
+}
+
+#[derive(Clone, Copy, Debug)]
+enum MaskRule {
+    All,
+    Matching(fn(&str) -> bool),
+    None,
+}
+
+/// The options of [`Entity::redacted`].
+static MASK_ALL: DisplayOptions = DisplayOptions::mask_all();
+
+impl DisplayOptions {
+    /// Creates options masking every value, except the ones of the revealed properties.
+    pub const fn mask_all() -> Self {
+        Self {
+            rule: MaskRule::All,
+            revealed: Vec::new(),
+            masked: Vec::new(),
+        }
+    }
+
+    /// Creates options masking the values of the properties a function selects, e.g.
+    /// `DisplayOptions::mask_matching(User::is_sensitive)` for the properties of the fields
+    /// marked with `#[entail(sensitive)]`.
+    ///
+    /// ## Parameters
+    /// - `is_sensitive`: Returns `true` for the names of the properties to mask.
+    pub fn mask_matching(is_sensitive: fn(&str) -> bool) -> Self {
+        Self {
+            rule: MaskRule::Matching(is_sensitive),
+            ..Self::mask_all()
+        }
+    }
+
+    /// Creates options showing every value, except the ones of the masked properties.
+    pub const fn reveal_all() -> Self {
+        Self {
+            rule: MaskRule::None,
+            revealed: Vec::new(),
+            masked: Vec::new(),
+        }
+    }
+
+    /// Shows the value of a property.
+    pub fn reveal(mut self, name: impl Into<Cow<'static, str>>) -> Self {
+        self.revealed.push(name.into());
+        self
+    }
+
+    /// Masks the value of a property.
+    pub fn mask(mut self, name: impl Into<Cow<'static, str>>) -> Self {
+        self.masked.push(name.into());
+        self
+    }
+
+    /// Returns whether the value of a property is masked.
+    pub fn is_masked(&self, name: &str) -> bool {
+        if self.masked.iter().any(|masked| masked == name) {
+            true
+        } else if self.revealed.iter().any(|revealed| revealed == name) {
+            false
+        } else {
+            match self.rule {
+                MaskRule::All => true,
+                MaskRule::Matching(is_sensitive) => is_sensitive(name),
+                MaskRule::None => false,
+            }
+        }
+    }
+}
+
+impl Default for DisplayOptions {
+    /// Masks every value, see [`DisplayOptions::mask_all`].
+    fn default() -> Self {
+        Self::mask_all()
+    }
+}
+
+impl Entity {
+    /// Returns a view of the entity for logging, formatted like the entity with every
+    /// property value and the names in the key masked. The types, the sizes and the indexing
+    /// of the values are shown.
+    pub fn redacted(&self) -> RedactedEntity<'_> {
+        self.display_with(&MASK_ALL)
+    }
+
+    /// Returns a view of the entity for logging, formatted like the entity with the values
+    /// selected by the options masked.
+    ///
+    /// ## Parameters
+    /// - `options`: The properties to mask.
+    pub fn display_with<'a>(&'a self, options: &'a DisplayOptions) -> RedactedEntity<'a> {
+        RedactedEntity {
+            entity: self,
+            options,
+        }
+    }
+}
+
+/// An [`Entity`] formatted with some of its property values masked, returned by
+/// [`Entity::redacted`] and [`Entity::display_with`]. Both the `Display` and the `Debug`
+/// output are masked, and the `Display` output of [`Entity`] is the one of this view with
+/// nothing masked.
+#[derive(Clone, Copy)]
+pub struct RedactedEntity<'a> {
+    entity: &'a Entity,
+    options: &'a DisplayOptions,
+}
+
+impl fmt::Display for RedactedEntity<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        self.entity
+            .key()
+            .fmt_with(f, self.options.is_masked(KEY_PROPERTY))?;
+        write!(f, " {{")?;
+        for (name, property) in self.entity.property_iter_sorted() {
+            write!(f, "\n  {}: ", name)?;
+            if self.options.is_masked(name) {
+                fmt_masked(property.value(), f)?;
+            } else {
+                property.value().fmt(f)?;
+            }
+            if property.is_indexed() {
+                write!(f, " (indexed),")?;
+            } else {
+                write!(f, " (unindexed),")?;
+            }
+        }
+        write!(f, "\n}}")
+    }
+}
+
+impl fmt::Debug for RedactedEntity<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        fmt::Display::fmt(self, f)
+    }
+}
+
+/// Formats a value like its `Display` implementation, without its content.
+fn fmt_masked(value: &Value, f: &mut fmt::Formatter) -> fmt::Result {
+    match value {
+        Value::Null => write!(f, "null"),
+        Value::Integer(_) => write!(f, "int({})", REDACTED),
+        Value::Boolean(_) => write!(f, "bool({})", REDACTED),
+        Value::Blob(b) => write!(f, "blob({}, {} bytes)", REDACTED, b.len()),
+        Value::UnicodeString(s) => write!(f, "string({}, {} bytes)", REDACTED, s.len()),
+        Value::FloatingPoint(_) => write!(f, "float({})", REDACTED),
+        Value::Array(values) => {
+            write!(f, "[")?;
+            for value in values {
+                fmt_masked(value, f)?;
+                write!(f, ",")?;
+            }
+            write!(f, "]")
+        }
+        Value::Key(_) => write!(f, "key({})", REDACTED),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_redacted_display() {
+        let entity = Entity::build(Key::new("User").with_id(1))
+            .string("email", "jane@example.com")
+            .int("age", 41)
+            .array("phones", ["555-0100"])
+            .null("nickname")
+            .unindexed_blob("avatar", vec![0u8; 3])
+            .finish();
+        assert_eq!(
+            entity.redacted().to_string(),
+            "User(id:1) {\n  age: int(<redacted>) (indexed),\n  \
+             avatar: blob(<redacted>, 3 bytes) (unindexed),\n  \
+             email: string(<redacted>, 16 bytes) (indexed),\n  \
+             nickname: null (indexed),\n  \
+             phones: [string(<redacted>, 8 bytes),] (indexed),\n}"
+        );
+        assert_eq!(
+            format!("{:?}", entity.redacted()),
+            entity.redacted().to_string()
+        );
+
+        let options = DisplayOptions::mask_all()
+            .reveal("age")
+            .reveal("email")
+            .mask("email");
+        let shown = entity.display_with(&options).to_string();
+        assert!(shown.contains("age: int(41) (indexed)"));
+        assert!(shown.contains("email: string(<redacted>, 16 bytes)"));
+
+        fn is_sensitive(name: &str) -> bool {
+            name == "email" || name == "phones"
+        }
+        let options = DisplayOptions::mask_matching(is_sensitive).reveal("phones");
+        let shown = entity.display_with(&options).to_string();
+        assert!(shown.contains("age: int(41)"));
+        assert!(shown.contains("email: string(<redacted>, 16 bytes)"));
+        assert!(shown.contains("phones: [string(555-0100),]"));
+        assert!(!shown.contains("jane"));
+
+        let parent = Key::new("Account").with_name("acme");
+        let entity = Entity::build(Key::new("User").with_name("jane").with_parent(parent))
+            .string("email", "jane@example.com")
+            .finish();
+        assert_eq!(
+            entity.redacted().to_string(),
+            "Account(name:<redacted>)/User(name:<redacted>) {\n  \
+             email: string(<redacted>, 16 bytes) (indexed),\n}"
+        );
+        let shown = entity.display_with(&options).to_string();
+        assert!(shown.starts_with("Account(name:\"acme\")/User(name:\"jane\") {"));
+        let options = DisplayOptions::reveal_all().mask(KEY_PROPERTY);
+        assert_eq!(
+            entity.display_with(&options).to_string(),
+            "Account(name:<redacted>)/User(name:<redacted>) {\n  \
+             email: string(jane@example.com) (indexed),\n}"
+        );
+        assert_eq!(
+            entity
+                .display_with(&DisplayOptions::reveal_all())
+                .to_string(),
+            entity.to_string()
+        );
+    }
+}
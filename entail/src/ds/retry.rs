@@ -0,0 +1,216 @@
+use super::super::*;
+use super::*;
+
+use std::future::Future;
+use std::time::Duration;
+
+/// Describes how a failed, idempotent Datastore operation (such as a lookup) is retried.
+///
+/// Different callers have different latency budgets: an interactive request handler would
+/// rather fail fast, while a batch job can afford to wait for a transient outage to pass.
+/// A `RetryPolicy` can be passed to the read methods of [`crate::EntityAdapter`] (e.g.
+/// [`crate::EntityAdapter::fetch_single_with_policy`]) on a per-call basis.
+///
+/// The delay between attempts starts at `first_retry` and doubles after every retry (up to
+/// `max_retry_delay`, if set), with a random [`Jitter`] applied to prevent synchronized
+/// retries.
+///
+/// The setters consume and return `Self`, allowing for method chaining.
+#[derive(Clone, Debug)]
+pub struct RetryPolicy {
+    /// The maximum number of retries after the initial attempt.
+    pub retry_count: u32,
+    /// The base duration for the first retry delay.
+    pub first_retry: Duration,
+    /// Decides whether an error is worth retrying.
+    pub classifier: fn(&EntailError) -> bool,
+    /// How the random jitter is applied to the delays. Defaults to [`Jitter::Bounded`].
+    pub jitter: Jitter,
+    /// The upper bound of the delays, which stop growing once they reach it.
+    pub max_retry_delay: Option<Duration>,
+}
+
+impl Default for RetryPolicy {
+    /// Returns the [`RetryPolicy::interactive`] policy.
+    fn default() -> Self {
+        Self::interactive()
+    }
+}
+
+impl RetryPolicy {
+    /// A policy that never retries.
+    pub fn none() -> Self {
+        Self {
+            retry_count: 0,
+            first_retry: Duration::ZERO,
+            classifier: Self::default_classifier,
+            jitter: Jitter::Bounded,
+            max_retry_delay: None,
+        }
+    }
+
+    /// A policy suited for latency sensitive callers: at most 2 retries starting with a
+    /// `25ms` delay.
+    pub fn interactive() -> Self {
+        Self {
+            retry_count: 2,
+            first_retry: Duration::from_millis(25),
+            classifier: Self::default_classifier,
+            jitter: Jitter::Bounded,
+            max_retry_delay: None,
+        }
+    }
+
+    /// A policy suited for batch jobs: at most 8 retries starting with a `100ms` delay.
+    pub fn batch() -> Self {
+        Self {
+            retry_count: 8,
+            first_retry: Duration::from_millis(100),
+            classifier: Self::default_classifier,
+            jitter: Jitter::Bounded,
+            max_retry_delay: None,
+        }
+    }
+
+    /// Sets the maximum number of retries.
+    pub fn with_retry_count(mut self, retry_count: u32) -> Self {
+        self.retry_count = retry_count;
+        self
+    }
+
+    /// Sets the base duration for the first retry delay.
+    pub fn first_retry(mut self, first_retry: Duration) -> Self {
+        self.first_retry = first_retry;
+        self
+    }
+
+    /// Sets how the random jitter is applied to the delays.
+    pub fn with_jitter(mut self, jitter: Jitter) -> Self {
+        self.jitter = jitter;
+        self
+    }
+
+    /// Sets the upper bound of the delays.
+    pub fn with_max_retry_delay(mut self, max_retry_delay: Duration) -> Self {
+        self.max_retry_delay = Some(max_retry_delay);
+        self
+    }
+
+    /// Sets the function deciding whether an error is worth retrying.
+    pub fn with_classifier(mut self, classifier: fn(&EntailError) -> bool) -> Self {
+        self.classifier = classifier;
+        self
+    }
+
+    /// The classifier used by the predefined policies.
+    ///
+    /// An error is considered retryable if it carries a Datastore API error with a status
+    /// the transaction runner would retry as well (`ABORTED`, `DEADLINE_EXCEEDED`,
+    /// `UNAVAILABLE` or `INTERNAL`).
+    pub fn default_classifier(error: &EntailError) -> bool {
+        error.is_retryable()
+    }
+
+    /// Runs the operation, retrying it according to this policy.
+    ///
+    /// ## Parameters
+    /// - `operation`: A closure creating the future of a single attempt. It is called once
+    ///   for every attempt.
+    ///
+    /// ## Returns
+    /// The result of the first successful attempt, the error of the first attempt that is not
+    /// retryable, or an [`EntailErrorKind::RetriesExhausted`] error if all the retries failed.
+    pub async fn run<T, F, Fut>(&self, mut operation: F) -> Result<T, EntailError>
+    where
+        F: FnMut() -> Fut,
+        Fut: Future<Output = Result<T, EntailError>>,
+    {
+        let mut retries_left = self.retry_count;
+        let mut current_delay = capped(self.first_retry, self.max_retry_delay);
+        let mut previous_delay = current_delay;
+        let mut rng = fastrand::Rng::default();
+        loop {
+            let err = match operation().await {
+                Ok(result) => return Ok(result),
+                Err(err) => err,
+            };
+            if !(self.classifier)(&err) {
+                return Err(err);
+            }
+            if retries_left == 0 {
+                return Err(EntailError {
+                    kind: EntailErrorKind::RetriesExhausted,
+                    message: "Retries exhausted".into(),
+                    ds_error: err.ds_error,
+                    mapping: None,
+                });
+            }
+            retries_left -= 1;
+            let delay = self.jitter.delay(
+                &mut rng,
+                current_delay / 2,
+                current_delay,
+                self.first_retry,
+                previous_delay,
+                self.max_retry_delay,
+            );
+            tokio::time::sleep(delay).await;
+            previous_delay = delay;
+            current_delay = capped(
+                current_delay.checked_mul(2).unwrap_or(current_delay),
+                self.max_retry_delay,
+            );
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn always_retry(_: &EntailError) -> bool {
+        true
+    }
+
+    #[tokio::test]
+    async fn test_retry_policy() {
+        let policy = RetryPolicy::none()
+            .with_retry_count(2)
+            .with_classifier(always_retry);
+        let mut attempts = 0;
+        let result = policy
+            .run(|| {
+                attempts += 1;
+                let attempt = attempts;
+                async move {
+                    if attempt < 3 {
+                        Err(EntailError::app("transient"))
+                    } else {
+                        Ok(attempt)
+                    }
+                }
+            })
+            .await;
+        assert_eq!(result.unwrap(), 3);
+
+        attempts = 0;
+        let result: Result<(), _> = policy
+            .run(|| {
+                attempts += 1;
+                async { Err(EntailError::app("permanent")) }
+            })
+            .await;
+        assert_eq!(result.unwrap_err().kind, EntailErrorKind::RetriesExhausted);
+        assert_eq!(attempts, 3);
+
+        attempts = 0;
+        let result: Result<(), _> = RetryPolicy::batch()
+            .run(|| {
+                attempts += 1;
+                async { Err(EntailError::app("not a Datastore error")) }
+            })
+            .await;
+        assert_eq!(result.unwrap_err().kind, EntailErrorKind::ApplicationError);
+        assert_eq!(attempts, 1);
+    }
+}
@@ -0,0 +1,244 @@
+use super::*;
+
+use std::borrow::Cow;
+use std::collections::BTreeMap;
+
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
+
+// The serialized forms are self-describing and don't change between versions:
+//
+// - a key is `{"namespace": "ns", "path": [{"kind": "Account", "name": "a"}, {"kind": "Order",
+//   "id": 2}]}`, where the namespace is omitted for the default namespace and the path starts
+//   at the root (a key with a project or a database, see `PartitionId`, has `"project"` or
+//   `"database"` as well),
+// - a value is tagged with its type, e.g. `{"integer": 3}`, `{"string": "a"}` or `"null"`,
+// - an entity is `{"key": KEY, "properties": {"name": {"value": VALUE, "indexed": true}}}`,
+//   with the properties sorted by their names and the meaning added if there is one.
+
+#[derive(Serialize, Deserialize)]
+#[serde(rename = "Key")]
+struct KeyRepr<'a> {
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    project: Option<Cow<'a, str>>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    database: Option<Cow<'a, str>>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    namespace: Option<Cow<'a, str>>,
+    path: Vec<PathElementRepr<'a>>,
+}
+
+#[derive(Serialize, Deserialize)]
+#[serde(rename = "PathElement")]
+struct PathElementRepr<'a> {
+    kind: Cow<'a, str>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    id: Option<i64>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    name: Option<Cow<'a, str>>,
+}
+
+#[derive(Serialize, Deserialize)]
+#[serde(rename = "Value", rename_all = "camelCase")]
+enum ValueRepr<'a> {
+    Null,
+    Integer(i64),
+    Boolean(bool),
+    Blob(Cow<'a, [u8]>),
+    String(Cow<'a, str>),
+    Double(f64),
+    Key(Cow<'a, Key>),
+    Array(Cow<'a, [Value]>),
+}
+
+#[derive(Serialize, Deserialize)]
+#[serde(rename = "Entity")]
+struct EntityRepr<'a> {
+    key: Cow<'a, Key>,
+    properties: BTreeMap<Cow<'a, str>, PropertyRepr<'a>>,
+}
+
+#[derive(Serialize, Deserialize)]
+#[serde(rename = "Property")]
+struct PropertyRepr<'a> {
+    value: Cow<'a, Value>,
+    indexed: bool,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    meaning: Option<i32>,
+}
+
+impl Serialize for Key {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        let mut path = Vec::new();
+        let mut next = Some(self);
+        while let Some(key) = next {
+            path.push(PathElementRepr {
+                kind: Cow::Borrowed(key.kind()),
+                id: key.id(),
+                name: key.name().map(Cow::Borrowed),
+            });
+            next = key.parent();
+        }
+        path.reverse();
+        let partition = self.partition_id();
+        KeyRepr {
+            project: partition.project_id.as_deref().map(Cow::Borrowed),
+            database: partition.database_id.as_deref().map(Cow::Borrowed),
+            namespace: partition.namespace.as_deref().map(Cow::Borrowed),
+            path,
+        }
+        .serialize(serializer)
+    }
+}
+
+impl<'de> Deserialize<'de> for Key {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        let repr = KeyRepr::deserialize(deserializer)?;
+        let mut key_opt: Option<Key> = None;
+        for element in repr.path {
+            let mut key = Key::new(element.kind.into_owned());
+            if let Some(id) = element.id {
+                key = key.with_id(id);
+            } else if let Some(name) = element.name {
+                key = key.with_name(name.into_owned());
+            }
+            if let Some(parent) = key_opt {
+                key = key.with_parent(parent);
+            }
+            key_opt = Some(key);
+        }
+        let partition = PartitionId {
+            project_id: repr.project.map(|id| Cow::Owned(id.into_owned())),
+            database_id: repr.database.map(|id| Cow::Owned(id.into_owned())),
+            namespace: repr.namespace.map(|ns| Cow::Owned(ns.into_owned())),
+        };
+        key_opt
+            .map(|key| key.with_partition_id(partition))
+            .ok_or_else(|| serde::de::Error::custom("a key must have at least one path element"))
+    }
+}
+
+impl Serialize for Value {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        match self {
+            Value::Null => ValueRepr::Null,
+            Value::Integer(val) => ValueRepr::Integer(*val),
+            Value::Boolean(val) => ValueRepr::Boolean(*val),
+            Value::Blob(val) => ValueRepr::Blob(Cow::Borrowed(val.as_ref())),
+            Value::UnicodeString(val) => ValueRepr::String(Cow::Borrowed(val)),
+            Value::FloatingPoint(val) => ValueRepr::Double(*val),
+            Value::Key(val) => ValueRepr::Key(Cow::Borrowed(val)),
+            Value::Array(vals) => ValueRepr::Array(Cow::Borrowed(vals)),
+        }
+        .serialize(serializer)
+    }
+}
+
+impl<'de> Deserialize<'de> for Value {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        Ok(match ValueRepr::deserialize(deserializer)? {
+            ValueRepr::Null => Value::Null,
+            ValueRepr::Integer(val) => Value::Integer(val),
+            ValueRepr::Boolean(val) => Value::Boolean(val),
+            ValueRepr::Blob(val) => Value::blob(val.into_owned()),
+            ValueRepr::String(val) => Value::UnicodeString(Cow::Owned(val.into_owned())),
+            ValueRepr::Double(val) => Value::FloatingPoint(val),
+            ValueRepr::Key(val) => Value::Key(val.into_owned()),
+            ValueRepr::Array(vals) => Value::Array(vals.into_owned()),
+        })
+    }
+}
+
+impl Serialize for Entity {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        EntityRepr {
+            key: Cow::Borrowed(self.key()),
+            properties: self
+                .property_iter_raw()
+                .map(|(name, property)| {
+                    let repr = PropertyRepr {
+                        value: Cow::Borrowed(property.value()),
+                        indexed: property.is_indexed(),
+                        meaning: property.meaning(),
+                    };
+                    (Cow::Borrowed(name.as_ref()), repr)
+                })
+                .collect(),
+        }
+        .serialize(serializer)
+    }
+}
+
+impl<'de> Deserialize<'de> for Entity {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        let repr = EntityRepr::deserialize(deserializer)?;
+        let mut entity = Entity::new(repr.key.into_owned());
+        for (name, property) in repr.properties {
+            entity.set(
+                name.into_owned(),
+                property.value.into_owned(),
+                property.indexed,
+                property.meaning,
+            );
+        }
+        Ok(entity)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_serialization() {
+        let key = Key::new("Order")
+            .with_id(2)
+            .with_parent(Key::new("Account").with_name("a"))
+            .with_namespace("shop");
+        let json = serde_json::to_value(&key).unwrap();
+        assert_eq!(
+            json,
+            serde_json::json!({
+                "namespace": "shop",
+                "path": [{"kind": "Account", "name": "a"}, {"kind": "Order", "id": 2}]
+            })
+        );
+        assert_eq!(serde_json::from_value::<Key>(json).unwrap(), key);
+        let elsewhere = key
+            .clone()
+            .with_partition_id(key.partition_id().clone().with_database_id("archive"));
+        let json = serde_json::to_value(&elsewhere).unwrap();
+        assert_eq!(json["database"], "archive");
+        assert_eq!(serde_json::from_value::<Key>(json).unwrap(), elsewhere);
+        assert!(serde_json::from_str::<Key>(r#"{"path": []}"#).is_err());
+        assert_eq!(serde_json::to_value(key.id_or_name()).unwrap(), 2);
+        assert_eq!(
+            serde_json::to_value(key.parent().unwrap().id_or_name()).unwrap(),
+            "a"
+        );
+
+        let mut entity = Entity::new(key.clone());
+        entity
+            .set_indexed("total", Value::integer(1050))
+            .set_unindexed("note", Value::unicode_string("Gift"))
+            .set_indexed("related", Value::key(Key::new("Item").with_name("lamp")))
+            .set_indexed(
+                "tags",
+                Value::array(vec![Value::unicode_string("a"), Value::null()]),
+            )
+            .set_unindexed("data", Value::blob(vec![1, 2]))
+            .set("ratio", Value::floating_point(0.5), true, Some(1));
+        let json = serde_json::to_value(&entity).unwrap();
+        assert_eq!(
+            json["properties"]["note"],
+            serde_json::json!({"value": {"string": "Gift"}, "indexed": false})
+        );
+        assert_eq!(
+            json["properties"]["tags"]["value"],
+            serde_json::json!({"array": [{"string": "a"}, "null"]})
+        );
+        assert_eq!(json["properties"]["ratio"]["meaning"], 1);
+        let text = serde_json::to_string(&entity).unwrap();
+        assert!(text.find("\"data\"").unwrap() < text.find("\"total\"").unwrap());
+        assert_eq!(serde_json::from_str::<Entity>(&text).unwrap(), entity);
+    }
+}
@@ -1,8 +1,9 @@
 use super::super::*;
 
 use google_datastore1::api::{
-    AllocateIdsRequest, BeginTransactionRequest, CommitRequest, LookupRequest, ReadOptions,
-    ReadWrite, ReserveIdsRequest, RollbackRequest, RunQueryRequest, TransactionOptions,
+    AllocateIdsRequest, BeginTransactionRequest, CommitRequest, LookupRequest, PropertyMask,
+    ReadOptions, ReadWrite, ReserveIdsRequest, RollbackRequest, RunAggregationQueryRequest,
+    RunQueryRequest, TransactionOptions,
 };
 use google_datastore1::yup_oauth2::{
     ApplicationDefaultCredentialsAuthenticator, ApplicationDefaultCredentialsFlowOpts,
@@ -14,7 +15,10 @@ use hyper_util::client::legacy::{Client, connect::HttpConnector};
 use hyper_util::rt::TokioExecutor;
 use std::borrow::Borrow;
 use std::error::Error;
+use ds::WithRequestOptions;
 use std::sync::Arc;
+use std::sync::atomic::{AtomicBool, Ordering};
+use tokio::sync::broadcast;
 
 /// A shell around google_datastore1's Datastore service that simplifies access to the
 /// Cloud Datastore API.
@@ -32,6 +36,19 @@ pub struct DatastoreShell {
     pub hub: Arc<Datastore<HttpsConnector<HttpConnector>>>,
     pub database_id: Option<String>,
     pub transaction: Option<Vec<u8>>,
+    /// The sizes of the entities observed through this shell, shared with all the
+    /// transactional shells created from it.
+    pub entity_stats: Arc<ds::EntityStats>,
+    /// The parameters added to every request made through this shell.
+    pub request_options: Arc<ds::RequestOptions>,
+    lifecycle: Arc<ds::Lifecycle>,
+    change_feed: Option<broadcast::Sender<ds::ChangeEvent>>,
+    write_mirror: Option<Arc<ds::WriteMirror>>,
+    namespace: Option<Cow<'static, str>>,
+    /// Whether the entities are checked with [`ds::Entity::validate`] before a commit.
+    validate_writes: bool,
+    /// Whether the transaction of the shell is still open, shared by its clones.
+    transaction_active: Option<Arc<AtomicBool>>,
 }
 
 fn simple_error<T>(
@@ -43,9 +60,34 @@ fn simple_error<T>(
         kind,
         message: s.into(),
         ds_error: Some(error),
+        mapping: None,
     })
 }
 
+/// The error of a request made through a transactional shell after its transaction ended.
+fn inactive_transaction() -> EntailError {
+    EntailError::simple(
+        EntailErrorKind::TransactionInactive,
+        "The transaction was already committed or rolled back",
+    )
+}
+
+/// Returns `true` if the Datastore rejected the cursor of a query, e.g. because it expired
+/// or the indexes it was created with changed.
+fn is_invalid_cursor(error: &google_datastore1::Error) -> bool {
+    ds::api_status(error) == Some("INVALID_ARGUMENT")
+        && ds::api_message(error).is_some_and(|message| {
+            message.to_ascii_lowercase().contains("cursor")
+        })
+}
+
+/// The mask of a lookup or a query returning only the given properties.
+fn property_mask(properties: &[Cow<'static, str>]) -> PropertyMask {
+    PropertyMask {
+        paths: Some(properties.iter().map(|p| p.to_string()).collect()),
+    }
+}
+
 impl DatastoreShell {
     /// Initializes a new `DatastoreShell` instance.
     ///
@@ -103,9 +145,221 @@ impl DatastoreShell {
             hub: Arc::new(hub),
             database_id,
             transaction: None,
+            entity_stats: Arc::new(ds::EntityStats::new()),
+            request_options: Arc::new(ds::RequestOptions::default()),
+            lifecycle: Arc::new(ds::Lifecycle::default()),
+            change_feed: None,
+            write_mirror: None,
+            namespace: None,
+            validate_writes: false,
+            transaction_active: None,
+        })
+    }
+
+    /// Returns a copy of the shell that sends the given options with every request.
+    ///
+    /// The copy shares everything else (including the transaction, the entity statistics
+    /// and the shutdown state) with this shell, so it can be used to apply the options to a
+    /// single call, e.g. `ds.with_request_options(options).get_single(key)`. Transactional
+    /// shells created from the copy inherit the options.
+    ///
+    /// ## Parameters
+    /// - `options`: The options replacing the current ones.
+    pub fn with_request_options(&self, options: ds::RequestOptions) -> Self {
+        Self {
+            request_options: Arc::new(options),
+            ..self.clone()
+        }
+    }
+
+    /// Returns a copy of the shell that publishes a [`ds::ChangeEvent`] for every write it
+    /// commits successfully.
+    ///
+    /// The feed is shared with the transactional shells created from the copy, whose events
+    /// are published when the transaction commits. Writes made through this shell (or other
+    /// copies of it) are not published. Events are only kept for the subscribers present at
+    /// the time of the commit, see [`Self::subscribe`].
+    ///
+    /// ## Parameters
+    /// - `capacity`: The number of events retained for a lagging subscriber before the
+    ///   oldest ones are dropped (reported as [`broadcast::error::RecvError::Lagged`]).
+    pub fn with_change_feed(&self, capacity: usize) -> Self {
+        let (sender, _) = broadcast::channel(capacity);
+        Self {
+            change_feed: Some(sender),
+            ..self.clone()
+        }
+    }
+
+    /// Returns a copy of the shell that mirrors every successful commit to a secondary
+    /// target, for migrating to another database or project.
+    ///
+    /// After a commit succeeded, the same mutations are committed to `secondary` (outside of
+    /// any transaction) before `commit` returns, so the writes reach the secondary in the same
+    /// order. The mirroring is best-effort: its failures are reported to `on_failure` and are
+    /// never returned by `commit`. Inserts and updates are mirrored as upserts with the keys
+    /// allocated by the primary, so entities already copied to the secondary (or not yet
+    /// copied) don't make the mirrored commit fail.
+    ///
+    /// The transactional shells created from the copy mirror their commits as well.
+    ///
+    /// ## Parameters
+    /// - `secondary`: The shell of the secondary target.
+    /// - `on_failure`: Called with the keys and the error of every failed mirrored commit.
+    pub fn with_write_mirror(
+        &self,
+        secondary: DatastoreShell,
+        on_failure: impl Fn(ds::MirrorFailure) + Send + Sync + 'static,
+    ) -> Self {
+        Self {
+            write_mirror: Some(Arc::new(ds::WriteMirror::new(secondary, on_failure))),
+            ..self.clone()
+        }
+    }
+
+    /// Returns a copy of the shell that works in the given namespace, e.g. the one of a tenant.
+    ///
+    /// The namespace overrides the one of the keys (of lookups, mutations and ID allocations)
+    /// and the queries sent through the copy, including the namespace set on a model with
+    /// `#[entail(namespace = "...")]`, so the same models can be stored per tenant. Keys
+    /// stored in property values or used in filters are not moved. The transactional shells
+    /// created from the copy work in the namespace as well.
+    ///
+    /// ## Parameters
+    /// - `namespace`: The namespace, an empty string is the default namespace.
+    pub fn with_namespace(&self, namespace: impl Into<Cow<'static, str>>) -> Self {
+        let namespace: Cow<'static, str> = namespace.into();
+        Self {
+            namespace: (!namespace.is_empty()).then_some(namespace),
+            ..self.clone()
+        }
+    }
+
+    /// Returns a copy of the shell that checks every inserted, updated or upserted entity with
+    /// [`ds::Entity::validate`] before committing, so a commit the Datastore would reject
+    /// (e.g. with an indexed string too long to be indexed) fails without a round trip, with
+    /// an error naming the offending property. The transactional shells created from the copy
+    /// validate their commits as well.
+    pub fn with_validation(&self) -> Self {
+        Self {
+            validate_writes: true,
+            ..self.clone()
+        }
+    }
+
+    /// Returns the namespace set with [`Self::with_namespace`], or `None` if the namespaces
+    /// of the keys and the queries are kept.
+    pub fn namespace(&self) -> Option<&str> {
+        self.namespace.as_deref()
+    }
+
+    /// Returns the partition the shell works in: its project, its database and the
+    /// namespace set with [`Self::with_namespace`].
+    pub fn partition_id(&self) -> ds::PartitionId {
+        ds::PartitionId {
+            project_id: Some(Cow::Owned(self.project_id.clone())),
+            database_id: self
+                .database_id
+                .clone()
+                .filter(|id| !id.is_empty())
+                .map(Cow::Owned),
+            namespace: self.namespace.clone(),
+        }
+    }
+
+    /// Fills in the partition of a request: the project and the database of the shell, and
+    /// the namespace of the shell, if it has one, or else the one requested.
+    ///
+    /// ## Returns
+    /// The partition, or an [`EntailError`] of the kind [`EntailErrorKind::PartitionMismatch`]
+    /// if the requested partition is in another project or database.
+    fn outgoing_partition(
+        &self,
+        requested: ds::PartitionId,
+        subject: impl FnOnce() -> String,
+    ) -> Result<ds::PartitionId, EntailError> {
+        let own = self.partition_id();
+        let mismatch = if requested.project_id.is_some() && requested.project_id != own.project_id {
+            Some("project")
+        } else if requested.database_id.is_some() && requested.database_id != own.database_id {
+            Some("database")
+        } else {
+            None
+        };
+        if let Some(part) = mismatch {
+            return Err(EntailError::simple(
+                EntailErrorKind::PartitionMismatch,
+                format!(
+                    "{} is in the partition {}, but the shell works in another {} ({})",
+                    subject(),
+                    requested,
+                    part,
+                    own
+                ),
+            ));
+        }
+        Ok(ds::PartitionId {
+            namespace: own.namespace.clone().or(requested.namespace),
+            ..own
         })
     }
 
+    /// Fills in the partition of a key, see [`Self::outgoing_partition`].
+    fn outgoing_key(
+        &self,
+        mut key: google_datastore1::api::Key,
+    ) -> Result<google_datastore1::api::Key, EntailError> {
+        let requested = ds::PartitionId::from(key.partition_id.take().unwrap_or_default());
+        let partition = self.outgoing_partition(requested, || {
+            let described = ds::Key::try_from(key.clone())
+                .map(|key| key.to_string())
+                .unwrap_or_default();
+            format!("The key {}", described)
+        })?;
+        key.partition_id = partition.to_api();
+        Ok(key)
+    }
+
+    /// Subscribes to the change feed of the shell.
+    ///
+    /// ## Returns
+    /// A receiver of the events of the writes committed after this call, or `None` if the
+    /// shell was not created with [`Self::with_change_feed`].
+    pub fn subscribe(&self) -> Option<broadcast::Receiver<ds::ChangeEvent>> {
+        self.change_feed.as_ref().map(|sender| sender.subscribe())
+    }
+
+    /// Shuts the shell down gracefully, waiting for the in-flight operations to finish.
+    ///
+    /// After calling this method, the shell (and every shell cloned from it, including the
+    /// transactional shells) rejects new operations with
+    /// [`EntailErrorKind::ShuttingDown`]. Operations that belong to an already running
+    /// transaction are still admitted so that the transaction can commit or roll back
+    /// within the grace period. This method is meant to be called when the service is asked
+    /// to stop (e.g. on `SIGTERM` in Cloud Run).
+    ///
+    /// ## Parameters
+    /// - `grace`: The maximum time to wait for the in-flight operations to finish.
+    ///
+    /// ## Returns
+    /// A [`ds::ShutdownReport`] describing whether all the in-flight operations finished
+    /// and how many were abandoned when the grace period elapsed.
+    pub async fn shutdown(&self, grace: std::time::Duration) -> ds::ShutdownReport {
+        self.lifecycle.shutdown(grace).await
+    }
+
+    /// Returns `true` if [`Self::shutdown`] has been called on this shell or on any shell
+    /// sharing its state.
+    pub fn is_shutting_down(&self) -> bool {
+        self.lifecycle.is_shutting_down()
+    }
+
+    /// Returns the number of operations currently in-flight through this shell and all
+    /// the shells sharing its state.
+    pub fn in_flight_operations(&self) -> usize {
+        self.lifecycle.in_flight()
+    }
+
     fn build_read_options(&self) -> ReadOptions {
         ReadOptions {
             read_consistency: if self.transaction.is_none() {
@@ -127,7 +381,9 @@ impl DatastoreShell {
     /// A `Result` containing `Some(Entity)` if found, `None` if not found,
     /// or an `EntailError` if the operation fails.
     pub async fn get_single(&self, key: ds::Key) -> Result<Option<ds::Entity>, EntailError> {
-        let native_key = key.into();
+        let _in_flight = self.lifecycle.enter(self.transaction.is_some())?;
+        self.check_transaction()?;
+        let native_key = self.outgoing_key(key.into())?;
         let lookup = LookupRequest {
             database_id: self.database_id.clone(),
             keys: Some(vec![native_key]),
@@ -138,6 +394,7 @@ impl DatastoreShell {
             .hub
             .projects()
             .lookup(lookup, &self.project_id)
+            .with_options(&self.request_options)
             .doit()
             .await;
         match response {
@@ -145,7 +402,10 @@ impl DatastoreShell {
                 let e: Option<ds::Entity> = result
                     .found
                     .and_then(|e| e.into_iter().next())
-                    .and_then(|er| er.entity.map(|e| e.into()));
+                    .and_then(|er| er.entity)
+                    .map(ds::Entity::try_from)
+                    .transpose()?;
+                self.entity_stats.record(&e);
                 Ok(e)
             }
             Err(err) => simple_error(EntailErrorKind::RequestFailure, "Lookup error", err),
@@ -176,8 +436,47 @@ impl DatastoreShell {
         I: IntoIterator,
         I::Item: Borrow<ds::Key>,
     {
-        let mut native_keys: Vec<google_datastore1::api::Key> =
-            keys.into_iter().map(|key| key.borrow().to_api()).collect();
+        self.lookup_all(keys, None).await
+    }
+
+    /// Fetches multiple entities from Datastore by a list of keys like [`Self::get_all`], but
+    /// only returns the selected properties of the entities.
+    ///
+    /// ## Parameters
+    /// - `keys`: A collection of complete `Key`s to retrieve, see [`Self::get_all`].
+    /// - `properties`: The names of the properties to return. The entities have no other
+    ///   properties, and the ones missing from an entity are omitted.
+    ///
+    /// ## Returns
+    /// A `Result` containing a `Vec<Entity>` of the entities that were found, in no particular
+    /// order.
+    pub async fn get_all_selected<I>(
+        &self,
+        keys: I,
+        properties: &[Cow<'static, str>],
+    ) -> Result<Vec<ds::Entity>, EntailError>
+    where
+        I: IntoIterator,
+        I::Item: Borrow<ds::Key>,
+    {
+        self.lookup_all(keys, Some(property_mask(properties))).await
+    }
+
+    async fn lookup_all<I>(
+        &self,
+        keys: I,
+        property_mask: Option<PropertyMask>,
+    ) -> Result<Vec<ds::Entity>, EntailError>
+    where
+        I: IntoIterator,
+        I::Item: Borrow<ds::Key>,
+    {
+        let _in_flight = self.lifecycle.enter(self.transaction.is_some())?;
+        self.check_transaction()?;
+        let mut native_keys: Vec<google_datastore1::api::Key> = keys
+            .into_iter()
+            .map(|key| self.outgoing_key(key.borrow().to_api()))
+            .collect::<Result<_, _>>()?;
         if native_keys.is_empty() {
             return Ok(Vec::new());
         }
@@ -197,22 +496,24 @@ impl DatastoreShell {
                 database_id: self.database_id.clone(),
                 read_options: Some(self.build_read_options()),
                 keys: Some(native_keys),
-                ..Default::default()
+                property_mask: property_mask.clone(),
             };
             let response = self
                 .hub
                 .projects()
                 .lookup(lookup, &self.project_id)
+                .with_options(&self.request_options)
                 .doit()
                 .await;
             match response {
                 Ok((_, lr)) => {
                     let deferred = lr.deferred.unwrap_or_default();
-                    result.extend(lr
-                        .found
-                        .unwrap_or_default()
-                        .into_iter()
-                        .map(|er| er.entity.unwrap().into()));
+                    let found_from = result.len();
+                    for er in lr.found.unwrap_or_default() {
+                        let entity = er.entity.ok_or_else(ds::missing_entity)?;
+                        result.push(ds::Entity::try_from(entity)?);
+                    }
+                    self.entity_stats.record(&result[found_from..]);
                     if deferred.is_empty() && rest.is_empty() {
                         return Ok(result);
                     } else {
@@ -240,24 +541,129 @@ impl DatastoreShell {
         &self,
         query: ds::Query,
     ) -> Result<ds::QueryResult<ds::Entity>, EntailError> {
+        self.query_with_mask(query, None).await
+    }
+
+    /// Runs a Datastore query like [`Self::run_query`], but only returns the selected
+    /// properties of the entities.
+    ///
+    /// Unlike a projection, this reads the entities and not the indexes, so the properties
+    /// don't have to be indexed, and it can be used in transactions.
+    ///
+    /// ## Parameters
+    /// - `query`: The `Query` object specifying the kind, filters, and projections.
+    /// - `properties`: The names of the properties to return.
+    ///
+    /// ## Returns
+    /// A `Result` containing a `QueryResult<Entity>` which holds the fetched
+    /// entities and cursor information, or an `EntailError` on failure.
+    pub async fn run_query_selected(
+        &self,
+        query: ds::Query,
+        properties: &[Cow<'static, str>],
+    ) -> Result<ds::QueryResult<ds::Entity>, EntailError> {
+        self.query_with_mask(query, Some(property_mask(properties)))
+            .await
+    }
+
+    /// Runs an aggregation query, computing counts, sums and averages over the entities
+    /// matching a query without reading them.
+    ///
+    /// ## Parameters
+    /// - `query`: The [`ds::AggregationQuery`] with the query and the aggregations.
+    ///
+    /// ## Returns
+    /// A `Result` containing the [`ds::AggregationResult`] with the results by their aliases,
+    /// or an `EntailError` on failure.
+    pub async fn run_aggregation(
+        &self,
+        query: ds::AggregationQuery,
+    ) -> Result<ds::AggregationResult, EntailError> {
+        let _in_flight = self.lifecycle.enter(self.transaction.is_some())?;
+        self.check_transaction()?;
+        let partition_id = self
+            .outgoing_partition(query.query.partition_id(), || {
+                format!("The aggregation query of {}", query.query.kind)
+            })?
+            .to_api();
+        let request = RunAggregationQueryRequest {
+            database_id: self.database_id.clone(),
+            partition_id,
+            read_options: Some(self.build_read_options()),
+            aggregation_query: Some(query.into()),
+            ..Default::default()
+        };
+        let response = self
+            .hub
+            .projects()
+            .run_aggregation_query(request, &self.project_id)
+            .with_options(&self.request_options)
+            .doit()
+            .await;
+        match response {
+            Ok((_, result)) => ds::AggregationResult::try_from(result.batch.unwrap_or_default()),
+            Err(err) => simple_error(EntailErrorKind::RequestFailure, "Aggregation error", err),
+        }
+    }
+
+    async fn query_with_mask(
+        &self,
+        query: ds::Query,
+        property_mask: Option<PropertyMask>,
+    ) -> Result<ds::QueryResult<ds::Entity>, EntailError> {
+        let _in_flight = self.lifecycle.enter(self.transaction.is_some())?;
+        self.check_transaction()?;
+        let partition_id = self
+            .outgoing_partition(query.partition_id(), || {
+                format!("The query of {}", query.kind)
+            })?
+            .to_api();
+        let has_cursor = query.start_cursor.is_some() || query.end_cursor.is_some();
         let request = RunQueryRequest {
             database_id: self.database_id.clone(),
+            partition_id,
             read_options: Some(self.build_read_options()),
             query: Some(query.into()),
+            property_mask,
             ..Default::default()
         };
         let response = self
             .hub
             .projects()
             .run_query(request, &self.project_id)
+            .with_options(&self.request_options)
             .doit()
             .await;
         match response {
-            Ok((_, result)) => Ok(result.batch.unwrap_or_default().into()),
+            Ok((_, result)) => {
+                let result = ds::QueryResult::try_from(result.batch.unwrap_or_default())?;
+                self.entity_stats.record(&result.items);
+                Ok(result)
+            }
+            Err(err) if has_cursor && is_invalid_cursor(&err) => simple_error(
+                EntailErrorKind::InvalidCursor,
+                "Query error: the cursor is invalid",
+                err,
+            ),
             Err(err) => simple_error(EntailErrorKind::RequestFailure, "Query error", err),
         }
     }
 
+    /// Suggests a query limit for the given Kind so that a page of results is expected to
+    /// stay within `target_bytes`, based on the sizes of the entities observed by this shell.
+    ///
+    /// See [`ds::EntityStats::suggest_page_size`] for details.
+    ///
+    /// ## Parameters
+    /// - `kind`: The Datastore Kind the query targets.
+    /// - `target_bytes`: The desired upper bound for the size of a single page.
+    ///
+    /// ## Returns
+    /// The suggested limit, or `None` if no entity of the Kind has been observed yet.
+    pub fn suggest_page_size(&self, kind: &str, target_bytes: usize) -> Option<i32> {
+        self.entity_stats.suggest_page_size(kind, target_bytes)
+    }
+
     /// Commits a batch of mutations to the Datastore.
     ///
     /// This method applies a set of inserts, updates, upserts, or deletes.
@@ -268,6 +674,13 @@ impl DatastoreShell {
     /// **Note:** If this `DatastoreShell` instance is tied to a transaction, this
     /// operation will automatically end that transaction.
     ///
+    /// If the shell has a change feed (see [`Self::with_change_feed`]), an event is published
+    /// for every mutation once the commit succeeded.
+    /// If the shell has a write mirror (see [`Self::with_write_mirror`]), the mutations are
+    /// committed to the secondary target as well.
+    /// If the shell validates the writes (see [`Self::with_validation`]), an invalid entity
+    /// fails the commit before it is sent.
+    ///
     /// ## Parameters
     /// - `batch`: A `MutationBatch` containing the mutations to be applied.
     ///
@@ -276,8 +689,45 @@ impl DatastoreShell {
     /// or an `EntailError` on failure.
     pub async fn commit(
         &self,
-        batch: ds::MutationBatch,
+        mut batch: ds::MutationBatch,
     ) -> Result<ds::MutationResponse, EntailError> {
+        let _in_flight = self.lifecycle.enter(self.transaction.is_some())?;
+        self.check_transaction()?;
+        if self.validate_writes {
+            let entities = batch.mutations.iter().filter_map(|mutation| {
+                mutation
+                    .insert
+                    .as_ref()
+                    .or(mutation.update.as_ref())
+                    .or(mutation.upsert.as_ref())
+            });
+            for entity in entities {
+                ds::validate_api_entity(entity)?;
+            }
+        }
+        for mutation in batch.mutations.iter_mut() {
+            let entity = mutation
+                .insert
+                .as_mut()
+                .or(mutation.update.as_mut())
+                .or(mutation.upsert.as_mut());
+            let key = match entity {
+                Some(entity) => entity.key.as_mut(),
+                None => mutation.delete.as_mut(),
+            };
+            if let Some(key) = key {
+                *key = self.outgoing_key(std::mem::take(key))?;
+            }
+        }
+        let pending = self
+            .change_feed
+            .as_ref()
+            .filter(|sender| sender.receiver_count() > 0)
+            .map(|_| ds::PendingChanges::capture(&batch.mutations));
+        let mirrored = self
+            .write_mirror
+            .as_ref()
+            .map(|_| batch.mutations.clone());
         let request = CommitRequest {
             database_id: self.database_id.clone(),
             mode: Some(
@@ -303,14 +753,47 @@ impl DatastoreShell {
             .hub
             .projects()
             .commit(request, &self.project_id)
+            .with_options(&self.request_options)
             .doit()
             .await;
         match response {
-            Ok((_, result)) => Ok(result.into()),
+            Ok((_, result)) => {
+                self.end_transaction();
+                let response = ds::MutationResponse::try_from(result)?;
+                if let (Some(sender), Some(pending)) = (&self.change_feed, pending) {
+                    for event in pending.into_events(&response) {
+                        // only fails if every subscriber is gone
+                        let _ = sender.send(event);
+                    }
+                }
+                if let (Some(mirror), Some(mutations)) = (&self.write_mirror, mirrored) {
+                    mirror.apply(mutations, &response).await;
+                }
+                Ok(response)
+            }
             Err(err) => simple_error(EntailErrorKind::RequestFailure, "Commit error", err),
         }
     }
 
+    /// Fails with an error of the kind [`EntailErrorKind::TransactionInactive`] if the shell
+    /// is tied to a transaction that was already committed or rolled back, e.g. through a
+    /// clone of the shell taken out of a [`ds::TransactionShell`].
+    fn check_transaction(&self) -> Result<(), EntailError> {
+        match (&self.transaction, &self.transaction_active) {
+            (Some(_), Some(active)) if !active.load(Ordering::Relaxed) => {
+                Err(inactive_transaction())
+            }
+            _ => Ok(()),
+        }
+    }
+
+    /// Marks the transaction of the shell (and its clones) as ended.
+    fn end_transaction(&self) {
+        if let (Some(_), Some(active)) = (&self.transaction, &self.transaction_active) {
+            active.store(false, Ordering::Relaxed);
+        }
+    }
+
     /// Begins a new transaction.
     ///
     /// This method creates a new transaction and returns a new `DatastoreShell`
@@ -325,6 +808,7 @@ impl DatastoreShell {
     /// A `Result` containing a new `DatastoreShell` instance for the transaction,
     /// or an `EntailError` if the transaction could not be started.
     pub async fn begin_transaction(&self, previous: &Option<Vec<u8>>) -> Result<Self, EntailError> {
+        let _in_flight = self.lifecycle.enter(self.transaction.is_some())?;
         let request = BeginTransactionRequest {
             database_id: self.database_id.clone(),
             transaction_options: Some(TransactionOptions {
@@ -339,10 +823,15 @@ impl DatastoreShell {
             .hub
             .projects()
             .begin_transaction(request, &self.project_id)
+            .with_options(&self.request_options)
             .doit()
             .await;
         match response {
             Ok((_, result)) => Ok(Self {
+                transaction_active: result
+                    .transaction
+                    .as_ref()
+                    .map(|_| Arc::new(AtomicBool::new(true))),
                 transaction: result.transaction,
                 ..self.clone()
             }),
@@ -361,9 +850,18 @@ impl DatastoreShell {
     ///   rollback. `None` will roll back the current transaction associated with
     ///   the `DatastoreShell` instance.
     ///
+    /// Rolling back the transaction of the shell after it was committed or rolled back (through
+    /// any clone of the shell) does nothing.
+    ///
     /// ## Returns
     /// A `Result` indicating success (`()`) or an `EntailError` on failure.
     pub async fn rollback(&self, transaction: &Option<Vec<u8>>) -> Result<(), EntailError> {
+        // rolling back is always allowed, it only releases resources
+        let _in_flight = self.lifecycle.enter(true)?;
+        let own_transaction = transaction.is_none() || *transaction == self.transaction;
+        if own_transaction && self.check_transaction().is_err() {
+            return Ok(());
+        }
         let request = RollbackRequest {
             database_id: self.database_id.clone(),
             transaction: transaction.clone().or_else(|| self.transaction.clone()),
@@ -376,10 +874,16 @@ impl DatastoreShell {
             .hub
             .projects()
             .rollback(request, &self.project_id)
+            .with_options(&self.request_options)
             .doit()
             .await;
         match response {
-            Ok(_) => Ok(()),
+            Ok(_) => {
+                if own_transaction {
+                    self.end_transaction();
+                }
+                Ok(())
+            }
             Err(err) => simple_error(EntailErrorKind::RequestFailure, "Rollback error", err),
         }
     }
@@ -409,10 +913,11 @@ impl DatastoreShell {
         I: IntoIterator,
         I::Item: Borrow<ds::Key>,
     {
+        let _in_flight = self.lifecycle.enter(self.transaction.is_some())?;
         let keys: Vec<google_datastore1::api::Key> = incomplete_keys
             .into_iter()
-            .map(|key| key.borrow().to_api())
-            .collect();
+            .map(|key| self.outgoing_key(key.borrow().to_api()))
+            .collect::<Result<_, _>>()?;
         if keys.is_empty() {
             return Ok(Vec::new());
         }
@@ -424,15 +929,16 @@ impl DatastoreShell {
             .hub
             .projects()
             .allocate_ids(request, &self.project_id)
+            .with_options(&self.request_options)
             .doit()
             .await;
         match response {
-            Ok((_, result)) => Ok(result
+            Ok((_, result)) => result
                 .keys
                 .unwrap_or_default()
                 .into_iter()
-                .map(ds::Key::from)
-                .collect()),
+                .map(ds::Key::try_from)
+                .collect(),
             Err(err) => simple_error(EntailErrorKind::RequestFailure, "Allocate IDs error", err),
         }
     }
@@ -462,10 +968,11 @@ impl DatastoreShell {
         I: IntoIterator,
         I::Item: Borrow<ds::Key>,
     {
+        let _in_flight = self.lifecycle.enter(self.transaction.is_some())?;
         let keys: Vec<google_datastore1::api::Key> = id_keys
             .into_iter()
-            .map(|key| key.borrow().to_api())
-            .collect();
+            .map(|key| self.outgoing_key(key.borrow().to_api()))
+            .collect::<Result<_, _>>()?;
         if keys.is_empty() {
             return Ok(());
         }
@@ -477,6 +984,7 @@ impl DatastoreShell {
             .hub
             .projects()
             .reserve_ids(request, &self.project_id)
+            .with_options(&self.request_options)
             .doit()
             .await;
         match response {
@@ -485,3 +993,142 @@ impl DatastoreShell {
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn api_error(status: &str, message: &str) -> google_datastore1::Error {
+        google_datastore1::Error::BadRequest(serde_json::json!({
+            "error": { "code": 400, "status": status, "message": message }
+        }))
+    }
+
+    #[test]
+    fn test_is_invalid_cursor() {
+        assert!(is_invalid_cursor(&api_error(
+            "INVALID_ARGUMENT",
+            "The query cursor is invalid."
+        )));
+        assert!(!is_invalid_cursor(&api_error(
+            "INVALID_ARGUMENT",
+            "The limit must not be negative."
+        )));
+        assert!(!is_invalid_cursor(&api_error("UNAVAILABLE", "Invalid cursor")));
+    }
+
+    #[tokio::test]
+    async fn test_validation() {
+        let ds = crate::test_support::offline_shell().await.with_validation();
+        let mut entity = ds::Entity::new(ds::Key::new("Order").with_id(1));
+        entity.set_indexed("summary", ds::Value::unicode_string("x".repeat(2000)));
+        let err = ds
+            .commit(
+                ds::MutationBatch::new()
+                    .delete(ds::Key::new("Order").with_id(2))
+                    .upsert(entity),
+            )
+            .await
+            .unwrap_err();
+        assert_eq!(err.kind, EntailErrorKind::PropertyMappingError);
+        assert_eq!(err.mapping.unwrap().property, "summary");
+    }
+
+    #[tokio::test]
+    async fn test_request_options() {
+        use tokio::io::{AsyncReadExt, AsyncWriteExt};
+
+        // a server answering the first request with an error, returning the request
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let url = format!("http://{}/", listener.local_addr().unwrap());
+        let server = tokio::spawn(async move {
+            let (mut socket, _) = listener.accept().await.unwrap();
+            let mut request = vec![0; 8192];
+            let read = socket.read(&mut request).await.unwrap();
+            let body = r#"{"error":{"code":400,"status":"INVALID_ARGUMENT","message":"no"}}"#;
+            let response = format!(
+                "HTTP/1.1 400 Bad Request\r\ncontent-type: application/json\r\n\
+                 content-length: {}\r\nconnection: close\r\n\r\n{}",
+                body.len(),
+                body
+            );
+            socket.write_all(response.as_bytes()).await.unwrap();
+            String::from_utf8_lossy(&request[..read]).into_owned()
+        });
+
+        let mut ds = crate::test_support::offline_shell().await;
+        let https = HttpsConnectorBuilder::new()
+            .with_native_roots()
+            .unwrap()
+            .https_or_http()
+            .enable_http1()
+            .build();
+        let mut hub = Datastore::new(Client::builder(TokioExecutor::new()).build(https), NoToken);
+        hub.base_url(url.clone());
+        hub.root_url(url);
+        ds.hub = Arc::new(hub);
+        let options = ds::RequestOptions::new()
+            .with_quota_user("tenant-42")
+            .with_user_project("billing")
+            .with_param("prettyPrint", "false");
+        let result = ds
+            .with_request_options(options)
+            .get_single(ds::Key::new("Order").with_id(1))
+            .await;
+        assert!(result.is_err());
+
+        let request = server.await.unwrap();
+        let request_line = request.lines().next().unwrap();
+        assert!(request_line.starts_with("POST /v1/projects/shop:lookup?"));
+        assert!(request_line.contains("quotaUser=tenant-42"));
+        assert!(request_line.contains("userProject=billing"));
+        assert!(request_line.contains("prettyPrint=false"));
+    }
+
+    #[tokio::test]
+    async fn test_outgoing_partition() {
+        crate::test_support::init_ring();
+        let ds = DatastoreShell::new("shop", false, Some("orders".to_string()))
+            .await
+            .unwrap();
+        let key = ds::Key::new("Order").with_id(1).with_namespace("tenant-a");
+        let partition = ds.outgoing_key(key.to_api()).unwrap().partition_id.unwrap();
+        assert_eq!(partition.project_id.as_deref(), Some("shop"));
+        assert_eq!(partition.database_id.as_deref(), Some("orders"));
+        assert_eq!(partition.namespace_id.as_deref(), Some("tenant-a"));
+        let partition = ds
+            .with_namespace("tenant-b")
+            .outgoing_key(key.to_api())
+            .unwrap()
+            .partition_id
+            .unwrap();
+        assert_eq!(partition.namespace_id.as_deref(), Some("tenant-b"));
+
+        let own = ds::PartitionId::new()
+            .with_project_id("shop")
+            .with_database_id("orders");
+        assert!(
+            ds.outgoing_key(key.clone().with_partition_id(own).to_api())
+                .is_ok()
+        );
+        let other = ds::PartitionId::new().with_database_id("archive");
+        let err = ds
+            .outgoing_key(key.with_partition_id(other.clone()).to_api())
+            .unwrap_err();
+        assert_eq!(err.kind, EntailErrorKind::PartitionMismatch);
+        assert_eq!(
+            err.message,
+            "The key Order(id:1) is in the partition -/archive/(default), but the shell works \
+             in another database (shop/orders/(default))"
+        );
+        let query = ds::Query {
+            kind: "Order".into(),
+            ..Default::default()
+        }
+        .with_partition_id(other);
+        assert!(
+            ds.outgoing_partition(query.partition_id(), String::new)
+                .is_err()
+        );
+    }
+}
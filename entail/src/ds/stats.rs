@@ -0,0 +1,123 @@
+use super::*;
+
+use std::collections::HashMap;
+use std::sync::Mutex;
+
+#[derive(Clone, Copy, Default)]
+struct SizeAccumulator {
+    count: u64,
+    total_bytes: u64,
+}
+
+/// Tracks the sizes of the entities observed by a [`DatastoreShell`], grouped by **Kind**.
+///
+/// Every entity returned by a query or a lookup through the shell is measured with
+/// [`Entity::approximate_size`]. The collected averages can be used to choose query limits
+/// so that a single page stays within a memory or latency budget, see
+/// [`EntityStats::suggest_page_size`].
+///
+/// The statistics are shared between a shell and all the transactional shells created
+/// from it.
+#[derive(Default)]
+pub struct EntityStats {
+    sizes: Mutex<HashMap<String, SizeAccumulator>>,
+}
+
+impl EntityStats {
+    /// Creates a new, empty `EntityStats` instance.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Records the sizes of the given entities.
+    pub fn record<'a>(&self, entities: impl IntoIterator<Item = &'a Entity>) {
+        let mut sizes = self.sizes.lock().unwrap_or_else(|e| e.into_inner());
+        for entity in entities {
+            let size = entity.approximate_size() as u64;
+            match sizes.get_mut(entity.kind()) {
+                Some(acc) => {
+                    acc.count += 1;
+                    acc.total_bytes += size;
+                }
+                None => {
+                    sizes.insert(
+                        entity.kind().to_string(),
+                        SizeAccumulator {
+                            count: 1,
+                            total_bytes: size,
+                        },
+                    );
+                }
+            }
+        }
+    }
+
+    /// Returns the number of entities of the given Kind observed so far.
+    pub fn observed_count(&self, kind: &str) -> u64 {
+        self.accumulator(kind).map_or(0, |acc| acc.count)
+    }
+
+    /// Returns the average approximate size in bytes of the entities of the given Kind,
+    /// or `None` if no entity of the Kind has been observed yet.
+    pub fn average_size(&self, kind: &str) -> Option<usize> {
+        self.accumulator(kind)
+            .filter(|acc| acc.count > 0)
+            .map(|acc| (acc.total_bytes / acc.count) as usize)
+    }
+
+    /// Suggests a query limit so that a page of entities of the given Kind is expected to
+    /// stay within `target_bytes`.
+    ///
+    /// ## Parameters
+    /// - `kind`: The Datastore Kind the query targets.
+    /// - `target_bytes`: The desired upper bound for the size of a single page.
+    ///
+    /// ## Returns
+    /// `None` if no entity of the Kind has been observed yet, otherwise the suggested limit,
+    /// which is always between `1` and `1000` (the default limit of [`Query`]).
+    pub fn suggest_page_size(&self, kind: &str, target_bytes: usize) -> Option<i32> {
+        self.average_size(kind)
+            .map(|average| (target_bytes / average.max(1)).clamp(1, 1000) as i32)
+    }
+
+    /// Forgets all the observed sizes.
+    pub fn reset(&self) {
+        self.sizes.lock().unwrap_or_else(|e| e.into_inner()).clear();
+    }
+
+    fn accumulator(&self, kind: &str) -> Option<SizeAccumulator> {
+        self.sizes
+            .lock()
+            .unwrap_or_else(|e| e.into_inner())
+            .get(kind)
+            .copied()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_suggest_page_size() {
+        let stats = EntityStats::new();
+        assert_eq!(stats.suggest_page_size("Foo", 10_000), None);
+        let mut small = Entity::new(Key::new("Foo").with_id(1));
+        small.set_indexed("a", Value::blob(vec![0; 99]));
+        let mut large = Entity::new(Key::new("Foo").with_id(2));
+        large.set_indexed("a", Value::blob(vec![0; 299]));
+        stats.record([&small, &large]);
+        let average = stats.average_size("Foo").unwrap();
+        assert_eq!(
+            average,
+            (small.approximate_size() + large.approximate_size()) / 2
+        );
+        assert_eq!(stats.observed_count("Foo"), 2);
+        assert_eq!(stats.suggest_page_size("Foo", average * 10), Some(10));
+        assert_eq!(stats.suggest_page_size("Foo", 1), Some(1));
+        assert_eq!(stats.suggest_page_size("Foo", usize::MAX), Some(1000));
+        assert_eq!(stats.suggest_page_size("Bar", 10_000), None);
+        stats.reset();
+        assert_eq!(stats.average_size("Foo"), None);
+    }
+}
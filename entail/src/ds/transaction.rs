@@ -1,10 +1,11 @@
 use super::super::*;
 use super::*;
 
+use std::borrow::Borrow;
 use std::future::Future;
 use std::ops::Deref;
 use std::sync::{
-    Arc,
+    Arc, Mutex,
     atomic::{AtomicBool, Ordering},
 };
 use std::time::Duration;
@@ -25,6 +26,14 @@ use std::time::Duration;
 pub struct TransactionShell {
     ds: DatastoreShell,
     active: AtomicBool,
+    prefetched: Vec<Entity>,
+    committed: AtomicBool,
+    /// The keys of the committed mutations, collected only for a [`TransactionObserver`].
+    touched: Option<Mutex<Vec<Key>>>,
+    /// The limit set with [`Transaction::with_max_entity_groups`].
+    max_entity_groups: Option<usize>,
+    /// The entity groups of the lock order, which count towards the limit.
+    locked_groups: Vec<Key>,
 }
 
 impl<'a> Deref for TransactionShell {
@@ -41,20 +50,46 @@ impl TransactionShell {
     ///
     /// If the commit is successful, the internal transaction state is marked as **inactive**
     /// (`active = false`), ensuring the transaction will not be rolled back automatically
-    /// by the transaction runner.
+    /// by the transaction runner. An empty batch has nothing to commit, so the transaction is
+    /// rolled back instead, see [`Self::rollback`], and the response has no mutation results.
     ///
     /// ## Parameters
     /// - `batch`: A [`ds::MutationBatch`] containing the changes to apply.
     ///
     /// ## Returns
-    /// A [`Result`] containing the commit response or an error.
+    /// A [`Result`] containing the commit response or an error, of the kind
+    /// [`EntailErrorKind::TooManyEntityGroups`] if the mutations exceed the limit set with
+    /// [`Transaction::with_max_entity_groups`].
     pub async fn commit(
         &self,
         batch: ds::MutationBatch,
     ) -> Result<ds::MutationResponse, EntailError> {
+        if batch.mutations.is_empty() {
+            self.rollback().await?;
+            return Ok(ds::MutationResponse::default());
+        }
+        if let Some(max_entity_groups) = self.max_entity_groups {
+            let keys = observer::mutation_keys(&batch.mutations);
+            let groups = entity_groups(self.locked_groups.iter().chain(&keys));
+            if groups.len() > max_entity_groups {
+                return Err(EntailError::simple(
+                    EntailErrorKind::TooManyEntityGroups,
+                    format!(
+                        "The transaction touches {} entity groups, more than the limit of {}",
+                        groups.len(),
+                        max_entity_groups
+                    ),
+                ));
+            }
+        }
+        if let Some(touched) = &self.touched {
+            let keys = observer::mutation_keys(&batch.mutations);
+            touched.lock().unwrap().extend(keys);
+        }
         let result = self.ds.commit(batch).await;
         if result.is_ok() {
             self.active.store(false, Ordering::Relaxed);
+            self.committed.store(true, Ordering::Relaxed);
         }
         result
     }
@@ -75,6 +110,29 @@ impl TransactionShell {
         result
     }
 
+    /// Returns an entity read at the start of the transaction, in the lock order set with
+    /// [`Transaction::with_lock_order`].
+    ///
+    /// ## Returns
+    /// The entity, or `None` if it doesn't exist or its key is not part of the lock order.
+    pub fn prefetched(&self, key: &Key) -> Option<&Entity> {
+        self.prefetched.iter().find(|entity| entity.key() == key)
+    }
+
+    /// Reads the entities of the lock order, see [`Transaction::with_lock_order`].
+    ///
+    /// The keys are sorted, so the keys of an entity group are next to each other. The entity
+    /// groups are read one after another, as the keys of a single lookup are not read in a
+    /// specified order.
+    async fn prefetch(&mut self, keys: &[Key]) -> Result<(), EntailError> {
+        self.prefetched.clear();
+        for group in keys.chunk_by(|a, b| a.root() == b.root()) {
+            let entities = self.ds.get_all(group).await?;
+            self.prefetched.extend(entities);
+        }
+        Ok(())
+    }
+
     fn make_inactive(&self) {
         self.active.store(false, Ordering::Relaxed);
     }
@@ -82,6 +140,17 @@ impl TransactionShell {
     fn is_active(&self) -> bool {
         self.active.load(Ordering::Relaxed)
     }
+
+    fn is_committed(&self) -> bool {
+        self.committed.load(Ordering::Relaxed)
+    }
+
+    fn take_touched(&self) -> Vec<Key> {
+        self.touched
+            .as_ref()
+            .map(|touched| std::mem::take(&mut *touched.lock().unwrap()))
+            .unwrap_or_default()
+    }
 }
 
 impl From<DatastoreShell> for TransactionShell {
@@ -95,6 +164,11 @@ impl From<DatastoreShell> for TransactionShell {
         Self {
             ds,
             active: AtomicBool::new(has_txn),
+            prefetched: Vec::new(),
+            committed: AtomicBool::new(false),
+            touched: None,
+            max_entity_groups: None,
+            locked_groups: Vec::new(),
         }
     }
 }
@@ -122,31 +196,41 @@ fn get_obj<'a>(
     }
 }
 
+fn api_error_field<'a>(error: &'a google_datastore1::Error, field: &str) -> Option<&'a str> {
+    let google_datastore1::Error::BadRequest(value) = error else {
+        return None;
+    };
+    match get_obj(value, "error").and_then(|obj| obj.get(field)) {
+        Some(serde_json::Value::String(s)) => Some(s.as_str()),
+        _ => None,
+    }
+}
+
+/// Extracts the gRPC style status (e.g. `ABORTED`) from an API error, if there is one.
+pub(crate) fn api_status(error: &google_datastore1::Error) -> Option<&str> {
+    api_error_field(error, "status")
+}
+
+/// Extracts the human-readable message the API attached to an error, if there is one.
+pub(crate) fn api_message(error: &google_datastore1::Error) -> Option<&str> {
+    api_error_field(error, "message")
+}
+
 impl RetryRule {
     pub(crate) fn based_on_error(error: &google_datastore1::Error) -> Self {
-        if let google_datastore1::Error::BadRequest(value) = error {
-            if let Some(serde_json::Value::String(status)) =
-                get_obj(value, "error").and_then(|obj| obj.get("status"))
+        match api_status(error) {
+            Some("ABORTED") => Self::Normal,
+            Some("DEADLINE_EXCEEDED" | "UNAVAILABLE") => RetryRule::Backoff,
+            Some("INTERNAL") => Self::Once,
+            Some("RESOURCE_EXHAUSTED") =>
+            // "RESOURCE_EXHAUSTED" could be retried if it's a capacity issue
+            // and not a quota issue, but I have no way of figuring that out
+            // This is also a catch-all for anything we haven't seen yet, it
+            // seems best not to retry
             {
-                match status.as_str() {
-                    "ABORTED" => Self::Normal,
-                    "DEADLINE_EXCEEDED" | "UNAVAILABLE" => RetryRule::Backoff,
-                    "INTERNAL" => Self::Once,
-                    "RESOURCE_EXHAUSTED" =>
-                    // "RESOURCE_EXHAUSTED" could be retried if it's a capacity issue
-                    // and not a quota issue, but I have no way of figuring that out
-                    // This is also a catch-all for anything we haven't seen yet, it
-                    // seems best not to retry
-                    {
-                        Self::Never
-                    }
-                    _ => Self::Never,
-                }
-            } else {
                 Self::Never
             }
-        } else {
-            Self::Never
+            _ => Self::Never,
         }
     }
 }
@@ -165,9 +249,42 @@ pub struct Transaction<'a> {
     /// exponentially for subsequent retries, and a random jitter is added
     /// to the delay to prevent stampeding. Defaults to `25ms`.
     pub first_retry: Duration,
+    /// How the random jitter is applied to the retry delays. Defaults to
+    /// [`Jitter::Bounded`].
+    pub jitter: Jitter,
+    /// The upper bound of the retry delays, which stop growing once they reach it.
+    /// Defaults to `None`, i.e. the delays double without a bound.
+    pub max_retry_delay: Option<Duration>,
+    /// The maximum number of entity groups a commit may touch, see
+    /// [`Transaction::with_max_entity_groups`]. Defaults to `None`, i.e. no limit.
+    pub max_entity_groups: Option<usize>,
+    lock_order: Vec<Key>,
+    clock: Arc<dyn Clock>,
+    jitter_source: Box<dyn JitterSource + Send>,
+    observer: Option<Arc<dyn TransactionObserver>>,
     ds: &'a DatastoreShell,
 }
 
+/// Returns the roots of the entity groups of the keys in the canonical order of the keys,
+/// without duplicates.
+///
+/// Transactions that lock the same entity groups in different orders can deadlock, and
+/// Datastore resolves that by aborting one of them. Acquiring the locks in a canonical order,
+/// e.g. with [`Transaction::with_lock_order`], avoids these aborts.
+pub fn entity_groups<I>(keys: I) -> Vec<Key>
+where
+    I: IntoIterator,
+    I::Item: Borrow<Key>,
+{
+    let mut roots: Vec<Key> = keys
+        .into_iter()
+        .map(|key| key.borrow().root().clone())
+        .collect();
+    roots.sort();
+    roots.dedup();
+    roots
+}
+
 impl<'a> Transaction<'a> {
     /// Creates a new `Transaction` runner tied to a [`DatastoreShell`].
     ///
@@ -179,6 +296,13 @@ impl<'a> Transaction<'a> {
         Self {
             retry_count: 16,
             first_retry: Duration::from_millis(25),
+            jitter: Jitter::default(),
+            max_retry_delay: None,
+            max_entity_groups: None,
+            lock_order: Vec::new(),
+            clock: Arc::new(TokioClock),
+            jitter_source: Box::new(fastrand::Rng::new()),
+            observer: None,
             ds,
         }
     }
@@ -206,6 +330,98 @@ impl<'a> Transaction<'a> {
         self
     }
 
+    /// Sets how the random jitter is applied to the retry delays.
+    ///
+    /// ## Parameters
+    /// - `jitter`: The jitter strategy.
+    pub fn with_jitter(mut self, jitter: Jitter) -> Self {
+        self.jitter = jitter;
+        self
+    }
+
+    /// Sets the upper bound of the retry delays, so that a long series of retries doesn't
+    /// overshoot the deadline of the caller.
+    ///
+    /// ## Parameters
+    /// - `max_retry_delay`: The longest delay between two attempts.
+    pub fn with_max_retry_delay(mut self, max_retry_delay: Duration) -> Self {
+        self.max_retry_delay = Some(max_retry_delay);
+        self
+    }
+
+    /// Sets the maximum number of entity groups the mutations committed in the transaction
+    /// (together with the keys of [`Self::with_lock_order`]) may touch.
+    ///
+    /// Datastore limits the number of entity groups of a transaction (25 in the legacy
+    /// Datastore, where exceeding it fails the commit only after the full round trip). With a
+    /// limit, [`TransactionShell::commit`] counts the distinct entity groups before sending
+    /// the mutations, and fails with an error of the kind
+    /// [`EntailErrorKind::TooManyEntityGroups`], which is not retried.
+    ///
+    /// ## Parameters
+    /// - `max_entity_groups`: The maximum number of entity groups.
+    pub fn with_max_entity_groups(mut self, max_entity_groups: usize) -> Self {
+        self.max_entity_groups = Some(max_entity_groups);
+        self
+    }
+
+    /// Sets the clock waiting between the attempts, e.g. to record the delays in tests.
+    ///
+    /// ## Parameters
+    /// - `clock`: The clock to sleep with instead of [`TokioClock`].
+    pub fn with_clock(mut self, clock: impl Clock + 'static) -> Self {
+        self.clock = Arc::new(clock);
+        self
+    }
+
+    /// Sets the source of the random numbers of the jitter, e.g. a seeded
+    /// [`fastrand::Rng`] for deterministic delays in tests.
+    ///
+    /// ## Parameters
+    /// - `source`: The source of the random numbers.
+    pub fn with_jitter_source(mut self, source: impl JitterSource + Send + 'static) -> Self {
+        self.jitter_source = Box::new(source);
+        self
+    }
+
+    /// Sets the observer receiving the events of the transaction (the attempts, the aborts,
+    /// the backoff delays and the commit or rollback), tagged with the Kinds and entity groups it
+    /// touches, e.g. to collect contention metrics.
+    ///
+    /// ## Parameters
+    /// - `observer`: The observer, which can be a closure taking a [`TransactionEvent`] and
+    ///   the [`TransactionTags`].
+    pub fn with_observer(mut self, observer: impl TransactionObserver + 'static) -> Self {
+        self.observer = Some(Arc::new(observer));
+        self
+    }
+
+    /// Sets the entities read at the start of every attempt, before the body runs.
+    ///
+    /// The keys are sorted in the canonical key order, which groups them by entity group, and
+    /// every entity group is read with a lookup of its own, one after another. When concurrent
+    /// transactions touching overlapping entity groups declare the keys they will read or
+    /// write, they acquire the locks of the entity groups in the same order, so they wait for
+    /// each other instead of deadlocking and aborting. This costs a round trip per entity
+    /// group. The entities
+    /// read this way are available to the body through [`TransactionShell::prefetched`],
+    /// without reading them again.
+    ///
+    /// ## Parameters
+    /// - `keys`: The complete keys of the entities the transaction will touch, in any order.
+    ///   Duplicates are ignored.
+    pub fn with_lock_order<I>(mut self, keys: I) -> Self
+    where
+        I: IntoIterator,
+        I::Item: Borrow<Key>,
+    {
+        let mut keys: Vec<Key> = keys.into_iter().map(|key| key.borrow().clone()).collect();
+        keys.sort();
+        keys.dedup();
+        self.lock_order = keys;
+        self
+    }
+
     /// Runs the provided asynchronous code block within a Datastore transaction.
     ///
     /// This is the primary method for executing transactional logic. It will automatically
@@ -283,25 +499,52 @@ impl<'a> Transaction<'a> {
         let mut last_error: Option<google_datastore1::Error> = None;
         let mut last_txn: Option<Vec<u8>> = None;
         let mut current_delay = self.first_retry;
-        let mut rng = fastrand::Rng::default();
+        let mut previous_delay = self.first_retry;
+        let mut jitter_source = self.jitter_source;
+        let mut tags = TransactionTags::default();
+        tags.add_keys(&self.lock_order);
+        let notify = |event: TransactionEvent, tags: &TransactionTags| {
+            if let Some(observer) = &self.observer {
+                observer.on_event(&event, tags);
+            }
+        };
         loop {
             if retries_left == 0 {
                 return Err(EntailError {
                     kind: EntailErrorKind::RetriesExhausted,
                     message: "Retries exhausted".into(),
                     ds_error: last_error,
+                    mapping: None,
                 });
             }
             retries_left -= 1;
-            let this_txn = Arc::new(TransactionShell::from(
-                self.ds.begin_transaction(&last_txn).await?,
-            ));
+            let attempt = self.retry_count - retries_left;
+            let mut this_txn = TransactionShell::from(self.ds.begin_transaction(&last_txn).await?);
             last_txn = this_txn.ds.transaction.clone();
-            let result = body(this_txn.clone()).await;
+            if self.observer.is_some() {
+                this_txn.touched = Some(Mutex::default());
+            }
+            if self.max_entity_groups.is_some() {
+                this_txn.max_entity_groups = self.max_entity_groups;
+                this_txn.locked_groups = entity_groups(&self.lock_order);
+            }
+            notify(TransactionEvent::AttemptStarted { attempt }, &tags);
+            let prefetched = this_txn.prefetch(&self.lock_order).await;
+            let this_txn = Arc::new(this_txn);
+            let result = match prefetched {
+                Ok(()) => body(this_txn.clone()).await,
+                Err(err) => Err(err),
+            };
+            tags.add_keys(&this_txn.take_touched());
             match result {
                 Ok(result) => {
-                    if this_txn.is_active() {
-                        this_txn.rollback().await?;
+                    if this_txn.is_committed() {
+                        notify(TransactionEvent::Committed { attempt }, &tags);
+                    } else {
+                        if this_txn.is_active() {
+                            this_txn.rollback().await?;
+                        }
+                        notify(TransactionEvent::RolledBack { attempt }, &tags);
                     }
                     return Ok(result);
                 }
@@ -314,6 +557,8 @@ impl<'a> Transaction<'a> {
                             });
                         }
                     }
+                    let status = err.status().map(str::to_string);
+                    notify(TransactionEvent::Aborted { attempt, status }, &tags);
                     let retry = if let Some(raw_error) = err.ds_error.as_ref() {
                         RetryRule::based_on_error(raw_error)
                     } else {
@@ -327,15 +572,23 @@ impl<'a> Transaction<'a> {
                             } else {
                                 current_delay
                             };
-                            let min =
-                                (current_delay.as_micros() >> if backoff { 0 } else { 1 }) as u64;
-                            let max = next_delay.as_micros() as u64;
-                            let val = if max > min {
-                                rng.u64(min..max)
+                            let next_delay = capped(next_delay, self.max_retry_delay);
+                            let floor = if backoff {
+                                current_delay
                             } else {
-                                max
+                                current_delay / 2
                             };
-                            tokio::time::sleep(Duration::from_micros(val)).await;
+                            let delay = self.jitter.delay(
+                                jitter_source.as_mut(),
+                                floor,
+                                next_delay,
+                                self.first_retry,
+                                previous_delay,
+                                self.max_retry_delay,
+                            );
+                            notify(TransactionEvent::Backoff { attempt, delay }, &tags);
+                            self.clock.sleep(delay).await;
+                            previous_delay = delay;
                             current_delay = next_delay;
                         }
                         RetryRule::Once => {
@@ -352,4 +605,110 @@ impl<'a> Transaction<'a> {
             }
         }
     }
+
+    /// Runs the provided asynchronous code block within a Datastore transaction, and commits
+    /// the mutations it returns.
+    ///
+    /// This is a variant of [`Self::run`] where the closure only reads and builds the
+    /// mutations: it returns a [`ds::MutationBatch`] (or a `Vec` of [`ds::Mutation`]s) instead
+    /// of committing, and the runner commits them as the final step of every attempt. The
+    /// reads always precede the write this way, and a forgotten commit cannot silently roll
+    /// the transaction back. A conflict reported by the commit is retried like in
+    /// [`Self::run`], by running the closure again. If the closure returns no mutations, the
+    /// transaction is rolled back instead of committed, and the response has no mutation
+    /// results. With [`Self::with_lock_order`], the closure can build the mutations from the
+    /// prefetched entities.
+    ///
+    /// ## Example
+    /// ```
+    /// use entail::{
+    ///     ds::{DatastoreShell, Key, MutationBatch, Transaction, Value},
+    ///     EntailError,
+    /// };
+    ///
+    /// async fn increment_counter(ds: &DatastoreShell, name: &str) -> Result<(), EntailError> {
+    ///     Transaction::new(ds)
+    ///         .run_mutations(|ts| {
+    ///             let key = Key::new("Counter").with_name(name.to_string());
+    ///             async move {
+    ///                 let mut counter = ts.get_single(key).await?.expect("Counter not found");
+    ///                 let count = match counter.get_value("count") {
+    ///                     Some(Value::Integer(count)) => *count,
+    ///                     _ => 0,
+    ///                 };
+    ///                 counter.set_indexed("count", Value::integer(count + 1));
+    ///                 Ok(MutationBatch::new().update(counter))
+    ///             }
+    ///         })
+    ///         .await?;
+    ///     Ok(())
+    /// }
+    /// ```
+    ///
+    /// ## Parameters
+    /// - `body`: An async closure containing the logic to run inside the transaction,
+    ///   returning the mutations to commit.
+    ///
+    /// ## Returns
+    /// The response of the commit, or an [`EntailError`] if the closure failed or all
+    /// retries fail.
+    pub async fn run_mutations<B, F, Fut>(
+        self,
+        mut body: F,
+    ) -> Result<ds::MutationResponse, EntailError>
+    where
+        F: FnMut(Arc<TransactionShell>) -> Fut,
+        Fut: Future<Output = Result<B, EntailError>> + Send,
+        B: Into<ds::MutationBatch>,
+    {
+        self.run(|ts| {
+            let mutations = body(ts.clone());
+            async move {
+                let batch = mutations.await?.into();
+                ts.commit(batch).await
+            }
+        })
+        .await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn test_max_entity_groups() {
+        let ds = crate::test_support::offline_shell().await;
+        let mut ts = TransactionShell::from(ds);
+        ts.max_entity_groups = Some(2);
+        ts.locked_groups = entity_groups([Key::new("Account").with_id(1)]);
+        let order = |account: i64, id: i64| {
+            Entity::new(
+                Key::new("Order")
+                    .with_id(id)
+                    .with_parent(Key::new("Account").with_id(account)),
+            )
+        };
+        let batch = MutationBatch::new()
+            .upsert(order(1, 1))
+            .upsert(order(2, 1))
+            .delete(Key::new("Account").with_id(3));
+        let err = ts.commit(batch).await.unwrap_err();
+        assert_eq!(err.kind, EntailErrorKind::TooManyEntityGroups);
+        assert_eq!(
+            err.message,
+            "The transaction touches 3 entity groups, more than the limit of 2"
+        );
+        assert!(err.is_client_fault());
+    }
+
+    #[tokio::test]
+    async fn test_empty_commit_rolls_back() {
+        let ds = crate::test_support::offline_shell().await;
+        let ts = TransactionShell::from(ds);
+        let response = ts.commit(MutationBatch::new()).await.unwrap();
+        assert!(response.mutation_results.is_empty());
+        assert!(!ts.is_active());
+        assert!(!ts.is_committed());
+    }
 }
@@ -0,0 +1,308 @@
+use super::*;
+
+use crate::{EntailError, EntailErrorKind, MappingContext};
+
+/// The maximum size in bytes of a Kind, a key name or a property name.
+pub const MAX_NAME_BYTES: usize = 1500;
+
+impl Entity {
+    /// Checks the entity against the constraints the Datastore enforces when it is committed,
+    /// so an invalid entity can be rejected before the round trip:
+    ///
+    /// - the Kinds and the names of the key path are not empty, not longer than
+    ///   [`MAX_NAME_BYTES`] and not reserved (Kinds starting with `__`, names matching
+    ///   `__*__`), the IDs are not `0`, and only the last element of the path can be
+    ///   incomplete,
+    /// - the property names are not empty, not longer than [`MAX_NAME_BYTES`] and not
+    ///   reserved,
+    /// - the indexed strings and blobs are not longer than [`MAX_INDEXED_BYTES`],
+    /// - the arrays don't hold arrays.
+    ///
+    /// The checks can be applied to every commit with
+    /// [`DatastoreShell::with_validation`](super::DatastoreShell::with_validation).
+    ///
+    /// ## Returns
+    /// A [`Result`] indicating success, or the first error of [`Entity::validation_errors`].
+    pub fn validate(&self) -> Result<(), EntailError> {
+        match self.validation_errors().into_iter().next() {
+            Some(err) => Err(err),
+            None => Ok(()),
+        }
+    }
+
+    /// Checks the entity like [`Entity::validate`], collecting all the violations.
+    ///
+    /// ## Returns
+    /// The errors, of the kind [`EntailErrorKind::PropertyMappingError`], with the offending
+    /// property (`__key__` for the key) in their `mapping`, the key first, then the properties
+    /// sorted by their names.
+    pub fn validation_errors(&self) -> Vec<EntailError> {
+        let mut errors = Vec::new();
+        if let Some(reason) = key_violation(self.key()) {
+            errors.push(violation(self.key(), KEY_PROPERTY, None, reason));
+        }
+        for (name, property) in self.property_iter_sorted() {
+            let value = property.value();
+            let reason = name_violation(name, "name").or_else(|| {
+                value_violation(
+                    property.is_indexed() && value.exceeds_index_limit(),
+                    matches!(value, Value::Array(values)
+                        if values.iter().any(|v| matches!(v, Value::Array(_)))),
+                )
+            });
+            if let Some(reason) = reason {
+                errors.push(violation(
+                    self.key(),
+                    name,
+                    Some(value.value_type()),
+                    reason,
+                ));
+            }
+        }
+        errors
+    }
+}
+
+/// Checks an entity of a commit like [`Entity::validate`], without converting it.
+///
+/// Only the key is converted, to report a violation. The values without a [`Value`]
+/// counterpart (e.g. embedded entities) are left to the Datastore.
+pub(crate) fn validate_api_entity(
+    entity: &google_datastore1::api::Entity,
+) -> Result<(), EntailError> {
+    let key = match entity.key.clone().map(Key::try_from).transpose()? {
+        Some(key) => key,
+        None => {
+            return Err(EntailError::simple(
+                EntailErrorKind::PropertyMappingError,
+                "Entity without a key",
+            ));
+        }
+    };
+    if let Some(reason) = key_violation(&key) {
+        return Err(violation(&key, KEY_PROPERTY, None, reason));
+    }
+    let mut properties: Vec<_> = entity.properties.iter().flatten().collect();
+    properties.sort_by_key(|(name, _)| name.as_str());
+    for (name, value) in properties {
+        let elements = value
+            .array_value
+            .as_ref()
+            .and_then(|array| array.values.as_deref());
+        // the indexing of an array is set on its elements
+        let exceeds_index_limit = elements
+            .unwrap_or(std::slice::from_ref(value))
+            .iter()
+            .any(api_exceeds_index_limit);
+        let nested_array =
+            elements.is_some_and(|values| values.iter().any(|v| v.array_value.is_some()));
+        let reason = name_violation(name, "name")
+            .or_else(|| value_violation(exceeds_index_limit, nested_array));
+        if let Some(reason) = reason {
+            return Err(violation(&key, name, Some(api_value_type(value)), reason));
+        }
+    }
+    Ok(())
+}
+
+/// Returns the type of the [`Value`] an API value is converted to.
+fn api_value_type(value: &google_datastore1::api::Value) -> ValueType {
+    if value.integer_value.is_some() {
+        ValueType::Integer
+    } else if value.boolean_value.is_some() {
+        ValueType::Boolean
+    } else if value.blob_value.is_some() {
+        ValueType::Blob
+    } else if value.string_value.is_some() {
+        ValueType::UnicodeString
+    } else if value.double_value.is_some() {
+        ValueType::FloatingPoint
+    } else if value.array_value.is_some() {
+        ValueType::Array
+    } else if value.key_value.is_some() {
+        ValueType::Key
+    } else {
+        ValueType::Null
+    }
+}
+
+/// Checks if an API value is an indexed string or blob longer than [`MAX_INDEXED_BYTES`].
+fn api_exceeds_index_limit(value: &google_datastore1::api::Value) -> bool {
+    let len = value
+        .string_value
+        .as_ref()
+        .map(String::len)
+        .or_else(|| value.blob_value.as_ref().map(Vec::len));
+    !value.exclude_from_indexes.unwrap_or(false) && len.is_some_and(|len| len > MAX_INDEXED_BYTES)
+}
+
+/// Returns the reason a name (a key name or a property name) is not accepted.
+fn name_violation(name: &str, what: &str) -> Option<String> {
+    if name.is_empty() {
+        Some(format!("has an empty {}", what))
+    } else if name.len() > MAX_NAME_BYTES {
+        Some(format!(
+            "has a {} longer than {} bytes",
+            what, MAX_NAME_BYTES
+        ))
+    } else if name.len() >= 4 && name.starts_with("__") && name.ends_with("__") {
+        Some(format!("has a reserved {} ({})", what, name))
+    } else {
+        None
+    }
+}
+
+/// Returns the reason a Kind is not accepted. The Kinds starting with `__` are reserved, the
+/// same rule `#[derive(Entail)]` applies to the Kinds of the models.
+fn kind_violation(kind: &str) -> Option<String> {
+    if kind.starts_with("__") {
+        Some(format!("has a reserved Kind ({})", kind))
+    } else {
+        name_violation(kind, "Kind")
+    }
+}
+
+/// Returns the reason a property value is not accepted.
+fn value_violation(exceeds_index_limit: bool, nested_array: bool) -> Option<String> {
+    if exceeds_index_limit {
+        Some(format!(
+            "is indexed, but longer than {} bytes",
+            MAX_INDEXED_BYTES
+        ))
+    } else if nested_array {
+        Some("is an array holding an array".to_string())
+    } else {
+        None
+    }
+}
+
+fn key_violation(key: &Key) -> Option<String> {
+    let last = key.depth() - 1;
+    key.path_elements()
+        .enumerate()
+        .find_map(|(index, (kind, variant))| {
+            kind_violation(kind).or_else(|| match variant {
+                KeyVariant::Name(name) => name_violation(name, "key name"),
+                KeyVariant::Id(0) => Some("has the ID 0".to_string()),
+                KeyVariant::Incomplete if index < last => {
+                    Some("has an incomplete parent".to_string())
+                }
+                _ => None,
+            })
+        })
+}
+
+fn violation(key: &Key, property: &str, actual: Option<ValueType>, reason: String) -> EntailError {
+    let subject = if property == KEY_PROPERTY {
+        format!("Key {} {}", key, reason)
+    } else {
+        format!("Property {} of {} {}", property, key, reason)
+    };
+    EntailError {
+        mapping: Some(Box::new(MappingContext {
+            kind: key.kind().to_string().into(),
+            property: property.to_string().into(),
+            expected_type: "a value accepted by Datastore".into(),
+            actual,
+        })),
+        ..EntailError::simple(EntailErrorKind::PropertyMappingError, subject)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_validation() {
+        let mut entity = Entity::new(Key::new("Order").with_id(1));
+        entity
+            .set_indexed("title", Value::unicode_string("Lamp"))
+            .set_unindexed("body", Value::unicode_string("x".repeat(2000)));
+        assert!(entity.validate().is_ok());
+
+        entity
+            .set_indexed("summary", Value::unicode_string("x".repeat(2000)))
+            .set_indexed("__hidden__", Value::integer(1))
+            .set_indexed(
+                "nested",
+                Value::array(vec![Value::array(vec![Value::integer(1)])]),
+            );
+        let errors = entity.validation_errors();
+        let properties: Vec<&str> = errors
+            .iter()
+            .map(|err| err.mapping.as_ref().unwrap().property.as_ref())
+            .collect();
+        assert_eq!(properties, vec!["__hidden__", "nested", "summary"]);
+        assert!(
+            errors
+                .iter()
+                .all(|err| err.kind == EntailErrorKind::PropertyMappingError)
+        );
+        assert_eq!(
+            errors[2].message,
+            "Property summary of Order(id:1) is indexed, but longer than 1500 bytes"
+        );
+        assert_eq!(
+            errors[2].mapping.as_ref().unwrap().actual,
+            Some(ValueType::UnicodeString)
+        );
+        assert_eq!(entity.validate().unwrap_err().message, errors[0].message);
+
+        for key in [
+            Key::new("Order").with_name("x".repeat(1501)),
+            Key::new("__Order__").with_id(1),
+            Key::new("__Order").with_id(1),
+            Key::new("Order").with_id(0),
+            Key::new("Order")
+                .with_id(1)
+                .with_parent(Key::new("Account")),
+        ] {
+            let err = Entity::new(key).validate().unwrap_err();
+            assert_eq!(err.mapping.unwrap().property, KEY_PROPERTY);
+        }
+        assert_eq!(
+            Entity::new(Key::new("Order").with_id(0))
+                .validate()
+                .unwrap_err()
+                .message,
+            "Key Order(id:0) has the ID 0"
+        );
+        // an incomplete key is completed by the commit
+        assert!(Entity::new(Key::new("Order")).validate().is_ok());
+        // only Kinds are reserved by their prefix
+        assert!(
+            Entity::new(Key::new("Order").with_name("__draft"))
+                .validate()
+                .is_ok()
+        );
+    }
+
+    #[test]
+    fn test_api_validation() {
+        let valid = Entity::new(Key::new("Order").with_id(1))
+            .set_indexed("tags", Value::array(vec![Value::unicode_string("a")]))
+            .set_unindexed("body", Value::blob(vec![0; 2000]))
+            .clone();
+        assert!(validate_api_entity(&valid.into()).is_ok());
+
+        let mut invalid = vec![
+            Entity::new(Key::new("__Order").with_id(1)),
+            Entity::new(Key::new("Order").with_id(1)),
+            Entity::new(Key::new("Order").with_id(1)),
+            Entity::new(Key::new("Order").with_id(1)),
+        ];
+        invalid[1].set_indexed("__hidden__", Value::integer(1));
+        invalid[2].set_indexed("tags", Value::array(vec![Value::blob(vec![0; 2000])]));
+        invalid[3].set_indexed(
+            "nested",
+            Value::array(vec![Value::array(vec![Value::integer(1)])]),
+        );
+        for entity in invalid {
+            let expected = entity.validate().unwrap_err();
+            let err = validate_api_entity(&entity.into()).unwrap_err();
+            assert_eq!(err.message, expected.message);
+            assert_eq!(err.mapping, expected.mapping);
+        }
+    }
+}
@@ -0,0 +1,321 @@
+use super::*;
+
+use crate::{EntailError, EntailErrorKind};
+
+// The legacy App Engine key encoding is the URL safe base64 form (without padding) of the
+// `Reference` protocol buffer message:
+//
+//   message Reference {
+//     required string app = 13;
+//     optional string name_space = 20;
+//     required Path path = 14;
+//     optional string database_id = 23;
+//   }
+//   message Path {
+//     repeated group Element = 1 {
+//       required string type = 2;
+//       optional int64 id = 3;
+//       optional string name = 4;
+//     }
+//   }
+
+const APP: u64 = 13;
+const PATH: u64 = 14;
+const NAME_SPACE: u64 = 20;
+const DATABASE_ID: u64 = 23;
+const ELEMENT: u64 = 1;
+const TYPE: u64 = 2;
+const ID: u64 = 3;
+const NAME: u64 = 4;
+
+const VARINT: u64 = 0;
+const FIXED64: u64 = 1;
+const LENGTH_DELIMITED: u64 = 2;
+const START_GROUP: u64 = 3;
+const END_GROUP: u64 = 4;
+const FIXED32: u64 = 5;
+
+const BASE64_ALPHABET: &[u8; 64] =
+    b"ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789-_";
+
+impl Key {
+    /// Encodes the key in the web safe form of the legacy App Engine APIs (`urlsafe()` of
+    /// NDB, `KeyFactory.keyToString` of Java), which can be embedded in URLs and cookies as
+    /// it is, and is understood by the App Engine applications of the same project.
+    ///
+    /// The encoding includes the application, which is the project of the key if it has one
+    /// (see [`Key::with_partition_id`]), or else `project_id`. The App Engine applications
+    /// prefix the project with their region (`s~` for most), which is added to a project
+    /// without one.
+    pub fn to_websafe_string(&self, project_id: &str) -> String {
+        let partition = self.partition_id();
+        let project = partition.project_id.as_deref().unwrap_or(project_id);
+        let app = if project.contains('~') {
+            project.to_string()
+        } else {
+            format!("s~{}", project)
+        };
+        let mut path = Vec::new();
+        write_elements(self, &mut path);
+        let mut reference = Vec::new();
+        write_bytes(&mut reference, APP, app.as_bytes());
+        write_bytes(&mut reference, PATH, &path);
+        if let Some(namespace) = &partition.namespace {
+            write_bytes(&mut reference, NAME_SPACE, namespace.as_bytes());
+        }
+        if let Some(database_id) = &partition.database_id {
+            write_bytes(&mut reference, DATABASE_ID, database_id.as_bytes());
+        }
+        encode_base64(&reference)
+    }
+
+    /// Decodes a key encoded by [`Key::to_websafe_string`] or by an App Engine application.
+    ///
+    /// The partition of the key holds the project (without the region prefix of the
+    /// application), so the shell rejects the keys of another project, and the database
+    /// and the namespace, if the string has them.
+    ///
+    /// ## Returns
+    /// A [`Result`] containing the key, or an [`EntailError`] of the kind
+    /// [`EntailErrorKind::MalformedKey`] if the string is not a valid encoded key.
+    pub fn from_websafe_string(encoded: &str) -> Result<Key, EntailError> {
+        let bytes = decode_base64(encoded).ok_or_else(|| malformed("it is not base64"))?;
+        let mut reader = Reader(&bytes);
+        let mut partition = PartitionId::new();
+        let mut key_opt: Option<Key> = None;
+        while let Some((field, wire_type)) = reader.tag()? {
+            match (field, wire_type) {
+                (APP, LENGTH_DELIMITED) => {
+                    let app = reader.string()?;
+                    let project = app.split_once('~').map_or(app.as_str(), |(_, p)| p);
+                    partition = partition.with_project_id(project.to_string());
+                }
+                (NAME_SPACE, LENGTH_DELIMITED) => {
+                    partition = partition.with_namespace(reader.string()?);
+                }
+                (DATABASE_ID, LENGTH_DELIMITED) => {
+                    partition = partition.with_database_id(reader.string()?);
+                }
+                (PATH, LENGTH_DELIMITED) => {
+                    key_opt = read_path(&mut Reader(reader.bytes()?))?;
+                }
+                _ => reader.skip(field, wire_type)?,
+            }
+        }
+        key_opt
+            .map(|key| key.with_partition_id(partition))
+            .ok_or_else(|| malformed("it has no path"))
+    }
+}
+
+fn malformed(reason: &str) -> EntailError {
+    EntailError::simple(
+        EntailErrorKind::MalformedKey,
+        format!("Malformed web safe key: {}", reason),
+    )
+}
+
+fn write_varint(out: &mut Vec<u8>, mut value: u64) {
+    while value >= 0x80 {
+        out.push((value as u8) | 0x80);
+        value >>= 7;
+    }
+    out.push(value as u8);
+}
+
+fn write_bytes(out: &mut Vec<u8>, field: u64, bytes: &[u8]) {
+    write_varint(out, field << 3 | LENGTH_DELIMITED);
+    write_varint(out, bytes.len() as u64);
+    out.extend_from_slice(bytes);
+}
+
+/// Writes the path elements of a key, starting from the root.
+fn write_elements(key: &Key, out: &mut Vec<u8>) {
+    if let Some(parent) = key.parent() {
+        write_elements(parent, out);
+    }
+    write_varint(out, ELEMENT << 3 | START_GROUP);
+    write_bytes(out, TYPE, key.kind().as_bytes());
+    if let Some(id) = key.id() {
+        write_varint(out, ID << 3 | VARINT);
+        write_varint(out, id as u64);
+    } else if let Some(name) = key.name() {
+        write_bytes(out, NAME, name.as_bytes());
+    }
+    write_varint(out, ELEMENT << 3 | END_GROUP);
+}
+
+fn read_path(reader: &mut Reader) -> Result<Option<Key>, EntailError> {
+    let mut key_opt: Option<Key> = None;
+    while let Some((field, wire_type)) = reader.tag()? {
+        if (field, wire_type) != (ELEMENT, START_GROUP) {
+            reader.skip(field, wire_type)?;
+            continue;
+        }
+        let (mut kind, mut id, mut name) = (None, None, None);
+        loop {
+            match reader
+                .tag()?
+                .ok_or_else(|| malformed("a path element is not closed"))?
+            {
+                (ELEMENT, END_GROUP) => break,
+                (TYPE, LENGTH_DELIMITED) => kind = Some(reader.string()?),
+                (ID, VARINT) => id = Some(reader.varint()? as i64),
+                (NAME, LENGTH_DELIMITED) => name = Some(reader.string()?),
+                (field, wire_type) => reader.skip(field, wire_type)?,
+            }
+        }
+        let mut key = Key::new(kind.ok_or_else(|| malformed("a path element has no Kind"))?);
+        if let Some(id) = id {
+            key = key.with_id(id);
+        } else if let Some(name) = name {
+            key = key.with_name(name);
+        }
+        if let Some(parent) = key_opt {
+            key = key.with_parent(parent);
+        }
+        key_opt = Some(key);
+    }
+    Ok(key_opt)
+}
+
+/// Reads the fields of a protocol buffer message.
+struct Reader<'a>(&'a [u8]);
+
+impl<'a> Reader<'a> {
+    fn varint(&mut self) -> Result<u64, EntailError> {
+        let mut value = 0u64;
+        for shift in (0..64).step_by(7) {
+            let (&byte, rest) = self
+                .0
+                .split_first()
+                .ok_or_else(|| malformed("it is truncated"))?;
+            self.0 = rest;
+            value |= ((byte & 0x7f) as u64) << shift;
+            if byte & 0x80 == 0 {
+                return Ok(value);
+            }
+        }
+        Err(malformed("a number is too long"))
+    }
+
+    /// Reads the next field number and wire type, or `None` at the end of the message.
+    fn tag(&mut self) -> Result<Option<(u64, u64)>, EntailError> {
+        if self.0.is_empty() {
+            return Ok(None);
+        }
+        let tag = self.varint()?;
+        Ok(Some((tag >> 3, tag & 7)))
+    }
+
+    fn take(&mut self, len: usize) -> Result<&'a [u8], EntailError> {
+        if len > self.0.len() {
+            return Err(malformed("it is truncated"));
+        }
+        let (taken, rest) = self.0.split_at(len);
+        self.0 = rest;
+        Ok(taken)
+    }
+
+    fn bytes(&mut self) -> Result<&'a [u8], EntailError> {
+        let len = self.varint()?;
+        self.take(usize::try_from(len).map_err(|_| malformed("it is truncated"))?)
+    }
+
+    fn string(&mut self) -> Result<String, EntailError> {
+        String::from_utf8(self.bytes()?.to_vec()).map_err(|_| malformed("a string is not UTF-8"))
+    }
+
+    /// Skips a field of an unknown number.
+    fn skip(&mut self, field: u64, wire_type: u64) -> Result<(), EntailError> {
+        match wire_type {
+            VARINT => self.varint().map(|_| ()),
+            FIXED64 => self.take(8).map(|_| ()),
+            LENGTH_DELIMITED => self.bytes().map(|_| ()),
+            FIXED32 => self.take(4).map(|_| ()),
+            START_GROUP => loop {
+                match self
+                    .tag()?
+                    .ok_or_else(|| malformed("a group is not closed"))?
+                {
+                    (end, END_GROUP) if end == field => return Ok(()),
+                    (inner, wire_type) => self.skip(inner, wire_type)?,
+                }
+            },
+            _ => Err(malformed("it has an unknown wire type")),
+        }
+    }
+}
+
+fn encode_base64(bytes: &[u8]) -> String {
+    let mut out = String::with_capacity(bytes.len().div_ceil(3) * 4);
+    for chunk in bytes.chunks(3) {
+        let n = chunk
+            .iter()
+            .enumerate()
+            .fold(0u32, |n, (i, &byte)| n | (byte as u32) << (16 - 8 * i));
+        for i in 0..=chunk.len() {
+            out.push(BASE64_ALPHABET[(n >> (18 - 6 * i) & 63) as usize] as char);
+        }
+    }
+    out
+}
+
+/// Decodes URL safe base64, also accepting the standard alphabet and padding.
+fn decode_base64(encoded: &str) -> Option<Vec<u8>> {
+    let mut out = Vec::with_capacity(encoded.len() * 3 / 4);
+    let (mut n, mut bits) = (0u32, 0);
+    for byte in encoded.trim_end_matches('=').bytes() {
+        let sextet = match byte {
+            b'A'..=b'Z' => byte - b'A',
+            b'a'..=b'z' => byte - b'a' + 26,
+            b'0'..=b'9' => byte - b'0' + 52,
+            b'-' | b'+' => 62,
+            b'_' | b'/' => 63,
+            _ => return None,
+        };
+        n = n << 6 | sextet as u32;
+        bits += 6;
+        if bits >= 8 {
+            bits -= 8;
+            out.push((n >> bits) as u8);
+        }
+    }
+    Some(out)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_websafe_key() {
+        // the key of a User entity of the App Engine application s~my-project
+        let key = Key::from_websafe_string("agxzfm15LXByb2plY3RyEQsSBFVzZXIYgICAgICAgAoM").unwrap();
+        assert_eq!(key.kind(), "User");
+        assert_eq!(key.id(), Some(5629499534213120));
+        assert_eq!(key.partition_id().project_id.as_deref(), Some("my-project"));
+        assert_eq!(
+            key.to_websafe_string("other"),
+            "agxzfm15LXByb2plY3RyEQsSBFVzZXIYgICAgICAgAoM"
+        );
+
+        let key = Key::new("Order")
+            .with_name("o-1")
+            .with_parent(Key::new("Account").with_id(-3))
+            .with_namespace("tenant-a");
+        let encoded = key.to_websafe_string("shop");
+        assert!(encoded.bytes().all(|b| BASE64_ALPHABET.contains(&b)));
+        let decoded = Key::from_websafe_string(&encoded).unwrap();
+        assert_eq!(
+            decoded.partition_id().to_string(),
+            "shop/(default)/tenant-a"
+        );
+        assert_eq!(decoded.with_partition_id(key.partition_id().clone()), key);
+
+        let err = Key::from_websafe_string("not a key!").unwrap_err();
+        assert_eq!(err.kind, EntailErrorKind::MalformedKey);
+        assert!(Key::from_websafe_string(&encoded[..encoded.len() - 4]).is_err());
+        assert!(Key::from_websafe_string("").is_err());
+    }
+}
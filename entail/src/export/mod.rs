@@ -0,0 +1,228 @@
+use tokio::io::{AsyncWrite, AsyncWriteExt};
+
+use crate::ds;
+use crate::{EntailError, EntailErrorKind};
+
+/// The configuration of [`stream_to_writer`].
+///
+/// The setters consume and return `Self`, allowing for method chaining.
+#[derive(Clone, Debug)]
+pub struct ExportOptions {
+    /// The maximum number of entities fetched (and held in memory) at a time.
+    /// Defaults to `500`.
+    pub page_size: i32,
+    /// If `true`, the values of the sensitive properties (`#[entail(sensitive)]`) of the
+    /// Kinds registered with [`crate::registry::register`] are masked. Defaults to `true`.
+    pub redact_sensitive: bool,
+    /// If `true`, the output is compressed with gzip. Defaults to `false`.
+    #[cfg(feature = "gzip")]
+    pub gzip: bool,
+}
+
+impl Default for ExportOptions {
+    fn default() -> Self {
+        Self {
+            page_size: 500,
+            redact_sensitive: true,
+            #[cfg(feature = "gzip")]
+            gzip: false,
+        }
+    }
+}
+
+impl ExportOptions {
+    /// Creates the default options.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Sets the maximum number of entities fetched at a time.
+    pub fn with_page_size(mut self, page_size: i32) -> Self {
+        self.page_size = page_size.max(1);
+        self
+    }
+
+    /// Exports the values of the sensitive properties as they are stored, e.g. for backups.
+    pub fn include_sensitive(mut self) -> Self {
+        self.redact_sensitive = false;
+        self
+    }
+
+    /// Compresses the output with gzip.
+    #[cfg(feature = "gzip")]
+    pub fn gzip(mut self) -> Self {
+        self.gzip = true;
+        self
+    }
+}
+
+/// The outcome of [`stream_to_writer`].
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct ExportReport {
+    /// The number of exported entities.
+    pub entities: u64,
+    /// The number of bytes of the rows, before compression.
+    pub bytes: u64,
+    /// The number of query pages fetched.
+    pub pages: u64,
+}
+
+/// Streams the results of a query to a writer as newline delimited JSON.
+///
+/// Every row is an entity in the JSON form of the Datastore REST API (the `Entity` object,
+/// with the `key` and the `properties`), so the export can be read back by any tool that
+/// understands that format. The query is fetched page by page following the cursors, and
+/// each page is written before the next one is requested, so only a single page is held in
+/// memory and a slow writer slows the export down instead of filling up the memory.
+///
+/// The limit of the query, if any, caps the total number of exported entities.
+/// The sensitive properties of the Kinds registered with [`crate::registry::register`] are
+/// masked unless [`ExportOptions::include_sensitive`] is set.
+///
+/// ## Parameters
+/// - `ds`: A reference to the [`ds::DatastoreShell`] to be used for Datastore access.
+/// - `query`: The query of the exported entities.
+/// - `writer`: The destination, which is flushed (or, with gzip, shut down to write the
+///   trailer) at the end.
+/// - `options`: The page size and the compression.
+///
+/// ## Returns
+/// An [`ExportReport`] with the number of exported entities, or an [`EntailError`] if a query
+/// failed, or of the kind [`EntailErrorKind::IoFailure`] if writing failed.
+pub async fn stream_to_writer<W>(
+    ds: &ds::DatastoreShell,
+    query: ds::Query,
+    writer: W,
+    options: &ExportOptions,
+) -> Result<ExportReport, EntailError>
+where
+    W: AsyncWrite + Unpin,
+{
+    #[cfg(feature = "gzip")]
+    if options.gzip {
+        let mut encoder = async_compression::tokio::write::GzipEncoder::new(writer);
+        let report = write_rows(ds, query, &mut encoder, options).await?;
+        encoder.shutdown().await.map_err(io_error)?;
+        return Ok(report);
+    }
+    let mut writer = writer;
+    let report = write_rows(ds, query, &mut writer, options).await?;
+    writer.flush().await.map_err(io_error)?;
+    Ok(report)
+}
+
+async fn write_rows<W>(
+    ds: &ds::DatastoreShell,
+    mut query: ds::Query,
+    writer: &mut W,
+    options: &ExportOptions,
+) -> Result<ExportReport, EntailError>
+where
+    W: AsyncWrite + Unpin,
+{
+    let total_limit = (query.limit > 0).then_some(query.limit as u64);
+    let mut report = ExportReport::default();
+    loop {
+        let remaining = total_limit.map_or(u64::MAX, |limit| limit - report.entities);
+        if remaining == 0 {
+            break;
+        }
+        query.limit = options.page_size.min(i32::try_from(remaining).unwrap_or(i32::MAX));
+        let page = ds.run_query(query.clone()).await?;
+        report.pages += 1;
+        if page.items.is_empty() {
+            break;
+        }
+        for mut entity in page.items {
+            if options.redact_sensitive {
+                redact(&mut entity);
+            }
+            let row = to_row(entity)?;
+            writer.write_all(&row).await.map_err(io_error)?;
+            report.entities += 1;
+            report.bytes += row.len() as u64;
+        }
+        match page.end_cursor {
+            Some(cursor) => query.start_cursor = Some(cursor),
+            None => break,
+        }
+    }
+    Ok(report)
+}
+
+/// Masks the sensitive properties of the model registered for the Kind of the entity, if any.
+fn redact(entity: &mut ds::Entity) {
+    if let Some(adapter) = crate::registry::adapter_for(entity.kind()) {
+        mask_values(entity, |name| adapter.is_sensitive(name));
+    }
+}
+
+/// Replaces the values of the sensitive properties with the [`ds::REDACTED`] string, so the
+/// row stays a valid entity. Null values are kept, they reveal nothing.
+fn mask_values(entity: &mut ds::Entity, is_sensitive: impl Fn(&str) -> bool) {
+    let sensitive: Vec<_> = entity
+        .property_iter()
+        .filter(|(name, value)| !value.is_null() && is_sensitive(name))
+        .map(|(name, _)| name.clone())
+        .collect();
+    for name in sensitive {
+        entity.set_unindexed(name, ds::Value::unicode_string(ds::REDACTED));
+    }
+}
+
+/// Serializes an entity into a JSON line.
+fn to_row(entity: ds::Entity) -> Result<Vec<u8>, EntailError> {
+    let entity: google_datastore1::api::Entity = entity.into();
+    let mut row = serde_json::to_vec(&entity).map_err(|err| {
+        EntailError::simple(
+            EntailErrorKind::PropertyMappingError,
+            format!("Cannot serialize the entity: {}", err),
+        )
+    })?;
+    row.push(b'\n');
+    Ok(row)
+}
+
+fn io_error(err: std::io::Error) -> EntailError {
+    EntailError::simple(
+        EntailErrorKind::IoFailure,
+        format!("Cannot write the export: {}", err),
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_to_row() {
+        let mut entity = ds::Entity::new(ds::Key::new("Item").with_name("a"));
+        entity.set_indexed("count", ds::Value::integer(3));
+        let row = to_row(entity).unwrap();
+        assert_eq!(row.last(), Some(&b'\n'));
+        let json: serde_json::Value = serde_json::from_slice(&row).unwrap();
+        assert_eq!(json["key"]["path"][0]["kind"], "Item");
+        assert_eq!(json["key"]["path"][0]["name"], "a");
+        assert_eq!(json["properties"]["count"]["integerValue"], "3");
+    }
+
+    #[test]
+    fn test_mask_values() {
+        let mut entity = ds::Entity::new(ds::Key::new("User").with_name("u"));
+        entity
+            .set_indexed("email", ds::Value::unicode_string("a@example.com"))
+            .set_indexed("phone", ds::Value::null())
+            .set_indexed("name", ds::Value::unicode_string("A"));
+        mask_values(&mut entity, |name| name == "email" || name == "phone");
+        assert_eq!(
+            entity.get_value("email"),
+            Some(&ds::Value::unicode_string(ds::REDACTED))
+        );
+        assert!(!entity.is_indexed("email"));
+        assert_eq!(entity.get_value("phone"), Some(&ds::Value::null()));
+        assert_eq!(
+            entity.get_value("name"),
+            Some(&ds::Value::unicode_string("A"))
+        );
+    }
+}
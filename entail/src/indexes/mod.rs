@@ -0,0 +1,208 @@
+use std::borrow::Cow;
+
+use crate::ds;
+use crate::{EntailError, EntailErrorKind};
+
+/// A composite index that the application expects to exist, the same way it would be
+/// declared in `index.yaml`.
+///
+/// The fields are added in order with [`IndexDefinition::asc`] and [`IndexDefinition::desc`],
+/// which consume and return `Self` like [`IndexDefinition::with_ancestor`].
+#[derive(Clone, Debug)]
+pub struct IndexDefinition {
+    /// The **Kind** the index belongs to.
+    pub kind: Cow<'static, str>,
+    /// `true` if the index supports ancestor queries (`ancestor: yes` in `index.yaml`).
+    pub ancestor: bool,
+    /// The indexed properties, in the order they appear in the index.
+    pub properties: Vec<ds::PropertyOrder>,
+}
+
+impl IndexDefinition {
+    /// Creates a new index definition without properties.
+    ///
+    /// ## Parameters
+    /// - `kind`: The Datastore Kind the index belongs to.
+    pub fn new(kind: impl Into<Cow<'static, str>>) -> Self {
+        Self {
+            kind: kind.into(),
+            ancestor: false,
+            properties: Vec::new(),
+        }
+    }
+
+    /// Marks the index as an ancestor index.
+    pub fn with_ancestor(mut self) -> Self {
+        self.ancestor = true;
+        self
+    }
+
+    /// Appends an ascending property to the index.
+    pub fn asc(mut self, name: impl Into<Cow<'static, str>>) -> Self {
+        self.properties
+            .push(ds::PropertyOrder::new(name, ds::OrderDirection::ASCENDING));
+        self
+    }
+
+    /// Appends a descending property to the index.
+    pub fn desc(mut self, name: impl Into<Cow<'static, str>>) -> Self {
+        self.properties
+            .push(ds::PropertyOrder::new(name, ds::OrderDirection::DESCENDING));
+        self
+    }
+
+    /// Builds the cheapest query that can only be served by this index: a keys-only query
+    /// limited to a single result, ordered by all the properties of the index and (for
+    /// ancestor indexes) filtered by a placeholder ancestor.
+    pub fn probe_query(&self) -> ds::Query {
+        let filter = if self.ancestor {
+            let ancestor = ds::Key::new(self.kind.clone()).with_name("__entail_index_probe__");
+            Some(ds::FilterOperator::HasAncestor.of("__key__", ancestor))
+        } else {
+            None
+        };
+        ds::Query {
+            kind: self.kind.clone(),
+            filter,
+            projection: vec!["__key__".into()],
+            order: self.properties.clone(),
+            limit: 1,
+            ..Default::default()
+        }
+    }
+}
+
+/// The state of a single index as observed by [`verify`].
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum IndexStatus {
+    /// The probe query succeeded, so the index is serving.
+    Ready,
+    /// Datastore reported that there is no index matching the probe query.
+    Missing,
+    /// The index exists but Datastore reported that it is not ready to serve yet.
+    Building,
+}
+
+/// The outcome of [`verify`], with the status of every index of the manifest in the
+/// order they were declared.
+#[derive(Clone, Debug)]
+pub struct IndexReport {
+    pub indexes: Vec<(IndexDefinition, IndexStatus)>,
+}
+
+impl IndexReport {
+    /// Returns `true` if every index of the manifest is serving.
+    pub fn is_ready(&self) -> bool {
+        self.indexes
+            .iter()
+            .all(|(_, status)| *status == IndexStatus::Ready)
+    }
+
+    /// Returns the indexes that Datastore does not know about.
+    pub fn missing(&self) -> impl Iterator<Item = &IndexDefinition> {
+        self.with_status(IndexStatus::Missing)
+    }
+
+    /// Returns the indexes that exist but are still being built.
+    pub fn building(&self) -> impl Iterator<Item = &IndexDefinition> {
+        self.with_status(IndexStatus::Building)
+    }
+
+    fn with_status(&self, status: IndexStatus) -> impl Iterator<Item = &IndexDefinition> {
+        self.indexes
+            .iter()
+            .filter(move |(_, s)| *s == status)
+            .map(|(index, _)| index)
+    }
+}
+
+/// Checks the composite indexes declared in `manifest` by running a cheap probe query
+/// (see [`IndexDefinition::probe_query`]) for each of them.
+///
+/// This is meant to be called on startup, so that a deployment can fail fast instead of
+/// serving requests that would run into missing index errors. Note that queries that can be
+/// served by the built-in single property indexes are always reported as ready.
+///
+/// ## Parameters
+/// - `ds`: A reference to the [`ds::DatastoreShell`] to be used for Datastore access.
+/// - `manifest`: The indexes the application relies on.
+///
+/// ## Returns
+/// An [`IndexReport`] with the status of every index, or the first error that does not
+/// indicate a missing or building index.
+pub async fn verify(
+    ds: &ds::DatastoreShell,
+    manifest: &[IndexDefinition],
+) -> Result<IndexReport, EntailError> {
+    let mut indexes = Vec::with_capacity(manifest.len());
+    for index in manifest {
+        let status = match ds.run_query(index.probe_query()).await {
+            Ok(_) => IndexStatus::Ready,
+            Err(err) => match classify(&err) {
+                Some(status) => status,
+                None => return Err(err),
+            },
+        };
+        indexes.push((index.clone(), status));
+    }
+    Ok(IndexReport { indexes })
+}
+
+fn classify(err: &EntailError) -> Option<IndexStatus> {
+    if err.kind != EntailErrorKind::RequestFailure || err.status() != Some("FAILED_PRECONDITION") {
+        return None;
+    }
+    let message = err.ds_error.as_ref().and_then(ds::api_message)?;
+    let message = message.to_ascii_lowercase();
+    if message.contains("not ready to serve") {
+        Some(IndexStatus::Building)
+    } else if message.contains("no matching index") {
+        Some(IndexStatus::Missing)
+    } else {
+        None
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn api_error(status: &str, message: &str) -> EntailError {
+        EntailError {
+            kind: EntailErrorKind::RequestFailure,
+            message: "Query error".into(),
+            ds_error: Some(google_datastore1::Error::BadRequest(serde_json::json!({
+                "error": { "code": 400, "status": status, "message": message }
+            }))),
+            mapping: None,
+        }
+    }
+
+    #[test]
+    fn test_probe_and_classify() {
+        let index = IndexDefinition::new("Task")
+            .with_ancestor()
+            .asc("done")
+            .desc("priority");
+        let query = index.probe_query();
+        assert_eq!(query.limit, 1);
+        assert_eq!(query.projection, vec![Cow::from("__key__")]);
+        assert_eq!(
+            query.shape(),
+            "Task|__key__ HAS_ANCESTOR|done ASCENDING,priority DESCENDING|__key__|"
+        );
+
+        let missing = api_error(
+            "FAILED_PRECONDITION",
+            "no matching index found. recommended index is: ...",
+        );
+        assert_eq!(classify(&missing), Some(IndexStatus::Missing));
+        let building = api_error(
+            "FAILED_PRECONDITION",
+            "The index for this query is not ready to serve. See the Datastore Indexes page.",
+        );
+        assert_eq!(classify(&building), Some(IndexStatus::Building));
+        let other = api_error("PERMISSION_DENIED", "no matching index found");
+        assert_eq!(classify(&other), None);
+    }
+}
@@ -21,9 +21,30 @@ an ongoing operation.
 * **Identity Management**: Methods like `allocate_ids` (to obtain IDs for incomplete keys)
   and `reserve_ids` (to prevent auto-allocation of specific IDs).
 * **Query Execution**: Run complex queries via `run_query`, returning results as a
-  `QueryResult` with built-in pagination support.
+  `QueryResult` with built-in pagination support. Queries are built with chainable methods,
+  e.g. `ds::Query::new("Task").filter(filter).order_desc("created").limit(20)`, and their
+  public fields remain available for advanced use. With the `rayon` feature, `par_map` and
+  `try_par_map` transform the items in parallel for CPU-heavy post-processing.
+* **Aggregations**: `run_aggregation` counts the entities matching a query, or sums and
+  averages a property of them, without reading the entities, e.g.
+  `ds.run_aggregation(ds::AggregationQuery::new(query).count("n")).await?.count("n")`.
 * **Transaction Factory**: Acts as the basis for the `Transaction` runner, allowing you to
   execute code within an atomic unit.
+* **Partitions**: `ds::PartitionId` names the project, the database and the namespace of a
+  `Key` (`Key::with_partition_id`) or a `Query`. The shell fills in its own project and
+  database on every outgoing key and query, and rejects a key or a query of another project
+  or database with a `PartitionMismatch` error before sending anything.
+* **Web Safe Keys**: `Key::to_websafe_string` and `Key::from_websafe_string` convert keys to and
+  from the URL safe encoding of the legacy App Engine APIs, so they can be put in URLs and
+  cookies, and exchanged with App Engine applications.
+* **Validation**: `Entity::validate` checks the constraints the Datastore enforces at commit
+  time (reserved or too long names, indexed strings and blobs over 1500 bytes, nested arrays)
+  and names the offending property in the error. `with_validation` returns a copy of the shell
+  that validates every written entity before committing.
+* **Serialization**: With the `serde` feature, `Entity`, `Key` and `Value` implement
+  `Serialize` and `Deserialize` with a stable, self-describing format (the properties sorted
+  by name, every value tagged with its type), e.g. to cache entities or to pass them between
+  services.
 
 ### Atomic Transactions
 
@@ -34,8 +55,27 @@ atomically. It handles the complexities of Datastore transactions, including:
   an automatic `rollback` occurs.
 * **Retry Logic**: Implements sophisticated retry rules (including exponential backoff
   and jitter) for concurrency conflicts (ABORTED) or transient network issues.
+  `with_jitter` selects the jitter strategy (`Jitter::Bounded`, `Full` or `Decorrelated`),
+  `with_max_retry_delay` caps the growing delays, and `with_clock` and `with_jitter_source`
+  make the delays deterministic in tests. `RetryPolicy` takes the same jitter and cap.
 * **TransactionShell**: Provides a specific shell instance (`TransactionShell`) inside the
   closure that dereferences to `DatastoreShell` for familiar API access.
+  A clone of the shell used after the transaction ended fails with `TransactionInactive`
+  instead of sending a request with a stale transaction.
+* **Commit-Time Mutations**: With `run_mutations`, the closure only reads and returns the
+  mutations (a `MutationBatch` or a `Vec<Mutation>`), and the runner commits them at the end
+  of every attempt, so the commit cannot be forgotten.
+* **Lock Ordering**: `with_lock_order` reads the entities the transaction will touch at the
+  start of every attempt, one entity group after another in the order of `entity_groups`, so
+  concurrent transactions acquire their locks in the same order instead of aborting each other. The
+  body gets the entities with `TransactionShell::prefetched`.
+* **Transaction Events**: `with_observer` reports the attempts, the aborts with their status,
+  the chosen backoff delays and the commit (or the rollback of an attempt that had nothing
+  to commit) to a `TransactionObserver` (or a closure), tagged with the Kinds and entity
+  groups the transaction touches, e.g. for contention metrics.
+* **Entity Group Limit**: `with_max_entity_groups` counts the distinct entity groups of the
+  mutations (and the lock order) before the commit is sent, and fails with
+  `TooManyEntityGroups` instead of a round trip the server would reject.
 
 ### The `EntityModel` Trait
 
@@ -47,6 +87,138 @@ implementation of the `EntityModel` trait:
 * `adapter`: Returns a static `EntityAdapter` providing model-specific utilities for
   key creation and query building.
 
+`#[derive(Entail)]` supports structs with named fields and enums (see
+[Polymorphic Models](#polymorphic-models)). Tuple structs, unit structs and unions are
+rejected with a compile error pointing at the offending item. A tuple struct wrapping a
+single value can use `#[entail(transparent)]` instead:
+
+```compile_fail
+# use entail::Entail;
+#[derive(Entail)]
+struct Point(i64, i64); // error: Entail cannot be derived for tuple structs
+```
+
+The macro also generates a constant with the property name of every modeled field, named
+after the field in `SCREAMING_SNAKE_CASE` (e.g. `Task::DUE_DATE` holding `"dueDate"` with
+`rename_all = "camelCase"`), so filters and orders don't have to repeat the property names.
+The constant of the key field holds `"__key__"`. Fields whose constant would shadow a constant
+of `EntityModel` (such as `KIND`, `VERSION_PROPERTY` or `KEY_STRATEGY`) don't get one.
+
+For models without generic parameters, a `{Model}Fields` struct is generated as well, with
+an `entail::TypedProperty` constant for every field except the parent and the decimals, and
+`Model::query()` returns an `entail::TypedQuery` that only accepts these properties, with
+values of the field type (the element type for arrays):
+
+```rust,ignore
+let query = Task::query()
+    .filter(TaskFields::DONE, ds::FilterOperator::Equal, false)?
+    .order_by_desc(TaskFields::DUE_DATE)
+    .limit(20);
+let page = query.fetch(&ds).await?;
+```
+
+These models also get a `{Model}Patch` struct with an `Option` of every property, implementing
+`entail::ModelPatch`. Its `apply_to` method only writes the fields that are `Some` to an
+entity, once their `validate` functions accepted them, and `ModeledUpdate::apply_patch` applies it while keeping the checksum and the version
+of the model consistent:
+
+```rust,ignore
+let mut update = ModeledUpdate::<Task>::new(entity)?;
+update.apply_patch(&TaskPatch {
+    done: Some(true),
+    ..Default::default()
+})?;
+ds.commit(ds::MutationBatch::new().update(update.update_into_entity()?)).await?;
+```
+
+#### Projections
+
+Projection queries only return the projected properties (and the key), so their results
+usually cannot be mapped to the full model. A smaller struct marked with
+`#[entail(projection_of = "Model")]` implements `entail::ModelProjection` instead: its fields
+are read from the properties of the model fields with the same names, using the property
+constants of the model, so the renaming of the model applies and a field the model doesn't
+have fails to compile. `ModelProjection::query()` returns a query of the Kind of the model
+with the projection filled in, and `EntityAdapter::fetch_projection` maps the results:
+
+```rust,ignore
+#[derive(Entail)]
+#[entail(projection_of = "Task")]
+struct TaskSummary {
+    #[entail]
+    key: i64,
+    #[entail]
+    title: String,
+    #[entail]
+    due_date: Option<i64>,
+}
+
+let query = TaskSummary::query().limit(50);
+let page = Task::adapter().fetch_projection::<TaskSummary>(&ds, query).await?;
+```
+
+The projected properties have to be indexed, so JSON and checksum fields are rejected, and so
+are arrays, because a projection query returns a separate result for every element of an
+array.
+
+The key is never a property of the projected entities: a `__key__` value in the results
+becomes the key of the entity (`ds::KEY_PROPERTY` names it), `ds::Entity::value_or_key` reads
+a projected name whether it is the key or a property, and `ds::Entity::is_key_only` tells the
+results of a keys-only projection apart.
+
+#### Field Selection
+
+When the client of an endpoint chooses the returned fields, `entail::FetchOptions::select`
+lists the selected properties, and the adapter fetches only those as entities (without the
+other properties of the model): `EntityAdapter::fetch_selected` looks up keys with a property
+mask, and `EntityAdapter::query_selected` runs a projection query (or, in a transaction, a
+query with a property mask). The selection is validated against the properties generated by
+`#[derive(Entail)]` (`EntityModel::has_property`) before anything is read:
+
+```rust,ignore
+let options = entail::FetchOptions::select(&[Task::TITLE, Task::DUE_DATE]);
+let page = Task::adapter().query_selected(&ds, Task::adapter().query(), &options).await?;
+```
+
+#### Active Record Methods
+
+With the `active-record` feature, the models also get inherent async methods delegating to
+their adapter, for code preferring the active record style over adapter calls:
+`Model::get(&ds, key)` fetches a model (`EntityAdapter::fetch_single`), `model.save(&ds)`
+writes it, completing an empty key (`EntityAdapter::upsert_model`), and `model.delete(&ds)`
+deletes its entity (`EntityAdapter::delete_model`). A model defining methods with the same
+names cannot enable the feature.
+
+```rust,ignore
+let mut task = Task::get(&ds, key).await?;
+task.done = true;
+task.save(&ds).await?;
+```
+
+#### Repositories
+
+`entail::Repository<T>` is a trait with `get`, `save`, `delete` and `query` for the models of a
+Kind, for services that take their storage as a dependency. `DatastoreRepository` implements it
+with the adapter, and `InMemoryRepository` keeps the entities in memory, evaluating the filters,
+the orders and the limits of the queries, so the services can be unit tested without an
+emulator:
+
+```rust,ignore
+async fn close_all(repo: &dyn entail::Repository<Task>, owner: &str) -> Result<(), EntailError> {
+    let query = Task::query()
+        .filter(TaskFields::OWNER, ds::FilterOperator::Equal, owner)?
+        .build();
+    for mut task in repo.query(query).await?.items {
+        task.done = true;
+        repo.save(&mut task).await?;
+    }
+    Ok(())
+}
+
+close_all(&entail::DatastoreRepository::new(ds), "alice").await?;
+close_all(&entail::InMemoryRepository::new(), "alice").await?;
+```
+
 ### The `#[entail]` Attribute
 
 The `#[entail]` attribute is used to customize the behavior of the `#[derive(Entail)]` macro.
@@ -59,22 +231,68 @@ It can be applied at both the **struct** level and the **field** level.
 These attributes are placed on the struct definition to configure global behavior.
 
 * `#[entail(rename_all = "camelCase")]`
-    This option specifies a naming convention for all fields within the struct, this `camelCase`
-    being the default, an empty string will leave the field names alone by default.
+    This option specifies a naming convention for all fields within the struct, without it
+    (or with an empty string) the field names are used as-is.
     The generated Datastore property names will follow this convention. Supported
-    values are `"camelCase"`, `"snake_case"`, `"PascalCase"`, and the empty string for leaving
-    it as-is.
+    values are `"camelCase"`, `"snake_case"`, `"PascalCase"`, `"kebab-case"`,
+    `"SCREAMING_SNAKE_CASE"`, and the empty string for leaving it as-is. Any other value is
+    a compile error.
 
 * `#[entail(name = "KindName")]`
     This attribute overrides the default Datastore **Kind** name, which is inferred from the
-    struct's name.
+    struct's name. Kind names are checked at compile time: they cannot be empty, longer than
+    1500 bytes, have leading or trailing whitespace or start with `__`.
+
+* `#[entail(discriminator = "__type")]`
+    Only for enums, see below. Overrides the name of the property that stores the variant
+    of the entity, which is `__type` by default.
+
+* `#[entail(transparent)]`
+    Only for structs with a single field, like `struct Email(String)`. Instead of a model,
+    the struct becomes a value type: `IntoValue` and `FromValue` are implemented by delegating
+    to the field, so it can be used as a field of other models (including inside `Option` and
+    `Vec`) and is stored the same way as its field.
+
+* `#[entail(auto_unindex)]`
+    Applies `#[entail(auto_unindex)]` (see below) to every field of the struct.
+
+* `#[entail(projection_of = "Model")]`
+    Declares the struct as a projection of a model instead of a model of its own, see
+    [Projections](#projections).
+
+* `#[entail(description = "...")]`
+    A human-readable description of the Kind, returned by `EntityModel::description()` and
+    listed by `entail::registry::manifest()` for admin UIs and schema reports. Fields can
+    have a description as well (see below).
+
+* `#[entail(namespace = "tenant-a")]`
+    Stores the Kind in a Datastore namespace: the keys created by `to_ds_entity()` (including
+    their parents) and by the adapter (`create_key()` and friends) are in the namespace, and
+    so are the queries of the adapter. A shell created with
+    `DatastoreShell::with_namespace("tenant-b")` overrides it at runtime, e.g. to store the
+    same models per tenant.
+
+* `#[entail(default_order = "-created, title")]`
+    The order of the queries of the adapter (`fetch_query` and friends) that have no orders of
+    their own, as a comma separated list of indexed fields, descending with a leading `-`. The
+    key field orders by `__key__`.
+
+* `#[entail(serde)]`
+    Reuses the `#[serde(...)]` attributes of the struct as the source of truth for mapping:
+    every field is persisted (with or without `#[entail]`) unless it has `#[serde(skip)]`, the
+    property names come from `#[serde(rename = "...")]`, and `#[serde(rename_all = "...")]` on
+    the struct applies like `#[entail(rename_all)]`. The `#[entail]` attributes take
+    precedence, e.g. `#[entail(name)]` over `#[serde(rename)]`. Renames that differ between
+    serialization and deserialization, and `#[serde(flatten)]` fields are rejected, as a
+    property has a single name.
 
 ---
 
 ### Field-Level Attributes
 
-By default, fields are **not** persisted to Datastore unless they have a `#[entail]` attribute.
-You can mark a field for mapping by simply adding `#[entail]` to it.
+By default, fields are **not** persisted to Datastore unless they have a `#[entail]` attribute
+(or the struct has `#[entail(serde)]`). You can mark a field for mapping by simply adding
+`#[entail]` to it.
 
 Here are the available options for fields:
 
@@ -82,7 +300,9 @@ Here are the available options for fields:
     Marks the field as the **primary key** for the entity. A struct must have exactly one primary
     key field. This field's value will be used to populate the `name` or `id` component of the
     `entail::ds::Key`. If a field is named `key` and has the `#[entail]` attribute, it's automatically
-    treated as the primary key unless overridden.
+    treated as the primary key unless overridden. The key field can be a `String`,
+    `Cow<'static, str>`, `i64`, `NonZeroI64`, `uuid::Uuid` (stored as the hyphenated name) or
+    `entail::ds::Key`, optionally wrapped in an `Option` for keys that are allocated on insert.
 
 * `#[entail(field)]`
     Forces a field to be treated as a regular Datastore property, even if its name or other
@@ -97,9 +317,18 @@ Here are the available options for fields:
     values up to 1 MiB. However, this flag explicitly marks the field for correct decoding as a
     Text type in older environments. **Text properties are always unindexed.**
 
+* `#[entail(meaning = 7)]`
+    Sets the `meaning` of the (non-null) values of the field, for interoperating with the legacy
+    App Engine meanings, e.g. `GD_WHEN` (`entail::ds::MEANING_GD_WHEN`) or `BLOBKEY`
+    (`entail::ds::MEANING_BLOBKEY`). The meaning doesn't change the indexing, and it is ignored
+    when reading. It cannot be combined with `#[entail(text)]`, which sets the meaning itself.
+
 * `#[entail(name = "custom_name")]`
     Overrides the Datastore property name for a specific field. By default, the property name is
     the same as the Rust field name, potentially modified by the `rename_all` struct attribute.
+    Property names (and aliases) are checked at compile time like Kind names, except that only
+    the names matching `__.*__` are reserved. Dots are rejected as well, Datastore reads them as
+    the paths of the properties of embedded entities.
 
 * `#[entail(indexed)]`
     Ensures the field is always indexed in Datastore. This is the **default behavior** for any
@@ -115,6 +344,108 @@ Here are the available options for fields:
     its value is `Some(T)`. If the value is `None`, the property is still created with a `Null`
     value but will not be indexed.
 
+* `#[entail(default)]` and `#[entail(default = "path::to::function")]`
+    Provides a value for the field when the property is **absent** from the stored entity,
+    instead of failing with a `PropertyMappingError`. The bare form uses `Default::default()`,
+    the second form calls the named function (which takes no arguments). This makes it possible
+    to load entities written before the field was introduced. A property that is present but
+    holds a value of the wrong type is still an error.
+
+* `#[entail(coerce)]`
+    Makes reading the field lenient: a stored value of a different type is accepted if it can
+    be converted unambiguously, for example the string `"42"` for an integer field, `"true"` for
+    a boolean field or an integer for a string field. This is useful for datasets written by
+    importers that didn't keep the types consistent. Writing the entity always uses the type
+    of the field, so the data is gradually normalized as the entities are saved.
+
+* `#[entail(checksum)]`
+    Stores a checksum of all the other modeled properties in this field (which should be an
+    `i64` or `Option<i64>`), as an unindexed integer. The value of the field is ignored when
+    writing, the checksum is always recomputed. `EntityAdapter::verify_checksum` uses it to
+    detect modifications made without going through the model, and
+    `EntityAdapter::upsert_if_changed` to skip writes that wouldn't change anything. At most
+    one field can be the checksum.
+
+* `#[entail(parent)]`
+    Maps the field to the parent of the entity key instead of a property, which is how
+    hierarchical data (entity groups) is modeled in Datastore. The field must be an
+    `entail::ds::Key` (e.g. the key of another model) or an `Option<entail::ds::Key>`. When
+    the field is not optional, reading an entity whose key has no parent is an error.
+
+* `#[entail(version)]`
+    Marks an `i64` field as an optimistic concurrency counter. `to_ds_entity` writes the
+    version of the model incremented by one (as an unindexed integer, not included in the
+    checksum), and an absent property is read as `0`. `EntityAdapter::update_versioned` writes
+    the model only if the stored entity still has the version the model was read with, and
+    fails with `EntailErrorKind::VersionConflict` otherwise. It runs its own transaction, so
+    in an existing transaction `EntityAdapter::add_versioned_update` is used instead, adding
+    the write to the batch the transaction commits.
+
+* `#[entail(json)]`
+    Stores the field serialized with `serde_json` as an unindexed string, so any type
+    implementing `serde::Serialize` and `serde::Deserialize` (e.g. nested configuration) can be
+    stored without implementing `IntoValue` and `FromValue`. The whole field is serialized, so
+    `None` is stored as the string `null`. When reading, blobs holding UTF-8 JSON are accepted as
+    well, and an absent property is read as the JSON `null`. JSON fields cannot be the key, the
+    parent, a checksum or a version, and cannot be marked `indexed` or `coerce`.
+
+* `#[entail(auto_unindex)]`
+    Stores the value of an indexed field unindexed if it is too large to be indexed, i.e. it
+    is a string or a blob longer than 1500 bytes (or an array holding one), instead of failing
+    at commit time. Queries on the property don't return the entities whose value was stored
+    unindexed. It can also be applied to the struct to enable it for every field.
+
+* `#[entail(alias = "old_name")]`
+    A former property name of the field, which is read if the entity doesn't have the current
+    property, so entities written before a rename still load. Writing the model only sets the
+    current property. The attribute can be repeated for several former names, which are tried
+    in order. The key and the parent cannot have aliases.
+
+* `#[entail(key_strategy = "uuid")]` or `#[entail(key_strategy = "allocate")]`
+    Only for the key field, completes an empty key (`None`, an empty string or a zero ID).
+    With `"uuid"` (for `String` and `Cow<'static, str>` keys, or `Option<uuid::Uuid>`),
+    `to_ds_entity` names the entity with a random UUID. With `"allocate"` (for `i64` keys, or
+    `Option<i64>` and `Option<NonZeroI64>`), the key is left incomplete, and
+    `EntityAdapter::prepare_entity` and `EntityAdapter::insert_model` allocate an ID before
+    writing. Both methods write the completed key back into the model.
+
+* `#[entail(description = "...")]`
+    A human-readable description of the property, returned by
+    `EntityModel::property_descriptions()` with the property name (`__key__` for the key)
+    and listed by `entail::registry::manifest()`. The parent cannot have a description.
+
+* `#[entail(validate = "path::to::fn")]`
+    Checks the field before it is written: `to_ds_entity()` calls the function with a reference
+    to the field, and fails with an error of the kind `EntailErrorKind::ValidationFailed` (with
+    the field in the message and the property in the `mapping`) if it returns an `Err`. The
+    error type of the function only needs to implement `Display`, e.g.
+    `fn not_blank(value: &str) -> Result<(), &'static str>`.
+
+* `#[entail(validate_on_read)]`
+    Runs the validation of the field after reading an entity in `from_ds_entity()` (and in
+    projections) as well, so that invalid data written by other tools is rejected too.
+
+* `#[entail(sensitive)]`
+    Marks the property as personal or secret data. `EntityModel::redacted_debug()` formats the
+    entity of the model for logging with the values of the sensitive properties masked, the
+    same way `entity.display_with(&ds::DisplayOptions::mask_matching(Model::is_sensitive))`
+    does, and `entail::export::stream_to_writer` replaces them with `"<redacted>"` for the
+    Kinds registered with `entail::registry::register` (unless
+    `ExportOptions::include_sensitive()` is set). `entity.redacted()` masks every value and
+    the names in the key. The key and the parent cannot be sensitive, but
+    `DisplayOptions::mask("__key__")` masks the names in the key.
+
+* `#[entail(decimal_scale = 2)]`
+    Stores a `rust_decimal::Decimal` (or an `Option` or a `Vec` of them) as an integer holding
+    the value multiplied by `10^scale`, e.g. the cents of an amount, instead of a string. Writing
+    a value with more decimal places than the scale is a mapping error rather than a rounding.
+    Requires the `decimal` feature. The typed properties of the model skip these fields.
+
+* `#[entail(soft_delete)]`
+    Marks an indexed `bool` field as the deleted flag of the entity: the queries of the adapter
+    (`fetch_query` and friends, and the typed queries run through them) only return the
+    entities where it is `false`. Lookups by key still return the deleted entities.
+
 ---
 
 ### Type Mapping
@@ -124,18 +455,101 @@ The `entail` library handles the conversion between common Rust types and `entai
 | Rust Type | `entail::ds::Value` | Notes |
 | :--- | :--- | :--- |
 | `String`, `Cow<'static, str>` | `UnicodeString` | |
-| `i32`, `i64`, `u32` | `Integer` | 32 bit types are mapped to `i64`. |
+| `i32`, `i64`, `u32` | `Integer` | 32 bit types are mapped to `i64`, reading a value out of their range is a mapping error. |
+| `i8`, `i16`, `u8`, `u16` | `Integer` | Reading a value out of the range of the type is a mapping error. `Vec<u8>` is a `Blob`, not an array of integers. |
+| `u64` | `Integer` | Values greater than `i64::MAX` cannot be stored, `to_ds_entity` fails with a mapping error instead of truncating them. |
+| `NonZeroI64`, `NonZeroU32` | `Integer` | Reading a stored `0` is a mapping error. |
+| `std::time::Duration` | `Integer` | Stored in microseconds, the sub-microsecond part is truncated. Reading a negative value is a mapping error. |
 | `f32`, `f64` | `FloatingPoint` | |
 | `bool` | `Boolean` | |
-| `Vec<u8>` | `Blob` | |
+| `Vec<u8>`, `Cow<'static, [u8]>`, `bytes::Bytes` | `Blob` | `Bytes` shares the bytes with the entity instead of copying them. |
+| `[u8; N]` | `Blob` | Reading a `Blob` of a different length is a mapping error. |
 | `entail::ds::Key` | `Key` | |
-| `Vec<T>` | `Array` | The elements of the vector are mapped to `Value`s. |
+| `uuid::Uuid` | `UnicodeString` | Requires the `uuid` feature. Stored in the hyphenated form, 16 byte `Blob`s are accepted when reading. |
+| `rust_decimal::Decimal` | `UnicodeString` | Requires the `decimal` feature. Stored as a string keeping the scale (`"1.50"`), so values round-trip exactly. Integers are accepted when reading. The strings don't compare by value, so the typed properties of the model skip these fields. See `#[entail(decimal_scale)]` for integer storage. |
+| `Box<T>`, `Arc<T>`, `Box<str>`, `Arc<str>` | Same as `T` (`UnicodeString` for `str`) | |
+| `Vec<T>` | `Array` | The elements of the vector are mapped to `Value`s, this includes `Vec<entail::ds::Key>`. The indexing attributes apply to every element. A single non-array value is read as a vector of one. |
+| `HashSet<T>`, `BTreeSet<T>` | `Array` | Mapped like `Vec<T>`. A `BTreeSet` is stored in sorted order, duplicates are dropped when reading. |
 | `Option<T>` | `T` or `Null` | A value of `Some(T)` is converted to the corresponding `Value`, while `None` becomes `Value::Null`. On deserialization, `Option<T>` can be populated from `Null`, a single `Value`, or an array of one `Value`. An empty array becomes `None`, and an array with more than one element will result in an error. |
+| `Option<Vec<T>>` | `Array` or `Null` | `None` is stored as `Value::Null` and `Some` as an array. An empty vector is stored as `Value::Null` as well, so it is read back as `None`. An absent or `Null` property is read as `None`, a single non-array value as a vector of one. |
+| Any type implementing `entail::IntoValue` and `entail::FromValue` | Any | Custom types, like newtypes around primitives, can be used anywhere the types above can, including inside `Option` and `Vec`. An absent property is passed to `FromValue::from_value` as `Value::Null`. |
+
+---
+
+### Polymorphic Models
+
+`#[derive(Entail)]` can also be applied to an enum whose variants each hold a single model,
+so that one Kind can store several kinds of records. The variant is stored in a discriminator
+property (`__type` by default, an indexed string) and `from_ds_entity` dispatches on it. The
+value stored is the name of the variant unless it is overridden by `#[entail(name = "...")]`
+on the variant. The models held by the variants must use the same Kind as the enum.
+
+```
+# use entail::Entail;
+#[derive(Entail, Default)]
+#[entail(name = "Shape")]
+struct Circle {
+    #[entail]
+    key: String,
+    #[entail]
+    radius: f64,
+}
+
+#[derive(Entail, Default)]
+#[entail(name = "Shape")]
+struct Rectangle {
+    #[entail]
+    key: String,
+    #[entail]
+    width: f64,
+    #[entail]
+    height: f64,
+}
+
+#[derive(Entail)]
+enum Shape {
+    Circle(Circle),
+    #[entail(name = "rect")]
+    Rectangle(Rectangle),
+}
+```
+
+### Kind Registry
+
+Generic tools and code handling several Kinds at once can look up models by their Kind at
+runtime. The models are registered explicitly at startup with
+`entail::registry::register::<Task>()`, after which `entail::registry::adapter_for(kind)`
+returns an object-safe adapter, and `entail::registry::deserialize(&entity)` maps an entity to
+the model registered for its Kind, e.g. for the results of a lookup with keys of several Kinds:
+
+```rust,ignore
+entail::registry::register::<Task>();
+entail::registry::register::<Project>();
+
+for entity in ds.get_all(&keys).await? {
+    let model = entail::registry::deserialize(&entity)?;
+    if let Some(task) = model.downcast_ref::<Task>() {
+        // ...
+    }
+}
+```
 */
+pub mod admin;
 pub mod ds;
+pub mod export;
+pub mod indexes;
+pub mod registry;
+pub mod scan;
+pub mod shadow;
 pub use entail_derive::Entail;
 use strum::Display;
 mod adapter;
+mod cache;
+mod convert;
+mod repository;
+mod scope;
+#[cfg(test)]
+mod test_support;
 
 use std::{borrow::Cow, fmt};
 
@@ -151,6 +565,39 @@ pub trait EntityModel: Sized {
     /// `#[entail(name = "...")]` attribute.
     const KIND: &'static str;
 
+    /// The name of the property the checksum of the modeled properties is stored in, if the
+    /// model has a field marked with `#[entail(checksum)]`.
+    ///
+    /// See [`ds::Entity::checksum`] and [`EntityAdapter::verify_checksum`].
+    const CHECKSUM_PROPERTY: Option<&'static str> = None;
+
+    /// The name of the property the version counter is stored in, if the model has a field
+    /// marked with `#[entail(version)]`.
+    ///
+    /// [`to_ds_entity`](Self::to_ds_entity) writes the version of the model incremented by
+    /// one, see [`EntityAdapter::update_versioned`].
+    const VERSION_PROPERTY: Option<&'static str> = None;
+
+    /// How the key is completed when the key field of the model is empty, see
+    /// [`KeyStrategy`].
+    const KEY_STRATEGY: KeyStrategy = KeyStrategy::Manual;
+
+    /// The names of the properties of the model, the key and the parent excluded.
+    ///
+    /// It is populated by `#[derive(Entail)]` for structs, and empty for polymorphic models.
+    /// See [`ds::EntityDecoder::for_model`].
+    const PROPERTY_NAMES: &'static [&'static str] = &[];
+
+    /// The name of the property marking the entity as deleted, if the model has a field
+    /// marked with `#[entail(soft_delete)]`.
+    ///
+    /// The queries of [`EntityAdapter`] only return the entities where it is `false`.
+    const SOFT_DELETE_PROPERTY: Option<&'static str> = None;
+
+    /// The order of the queries of [`EntityAdapter`] without orders of their own, set with
+    /// `#[entail(default_order = "...")]`.
+    const DEFAULT_ORDER: &'static [(&'static str, ds::OrderDirection)] = &[];
+
     /// Converts the Rust struct instance into an `entail::Entity` (aliased as `ds::Entity`).
     ///
     /// This method maps the struct's fields to Datastore properties, applying any
@@ -174,12 +621,108 @@ pub trait EntityModel: Sized {
     /// if the conversion fails (e.g., a required field is missing or a type mismatch occurs).
     fn from_ds_entity(e: &ds::Entity) -> Result<Self, EntailError>;
 
+    /// Compares two models by their Datastore representation.
+    ///
+    /// Two models are equal if [`to_ds_entity`](Self::to_ds_entity) produces the same key
+    /// and the same properties (including their indexing and meaning) for both, so fields
+    /// that are not mapped to properties are ignored. This is useful in tests and to decide
+    /// whether a model needs to be saved, without requiring `PartialEq` on every field type.
+    /// Models that cannot be converted are never equal.
+    fn entail_eq(&self, other: &Self) -> bool {
+        match (self.to_ds_entity(), other.to_ds_entity()) {
+            (Ok(a), Ok(b)) => a == b,
+            _ => false,
+        }
+    }
+
+    /// Sets the key field of the model (and the parent field, if any) from a key, e.g. one
+    /// allocated by Datastore for a model that was saved with an empty key.
+    ///
+    /// The default implementation fails, it is implemented by `#[derive(Entail)]` for structs.
+    ///
+    /// ## Returns
+    /// A [`Result`] indicating success, or an [`EntailError`] if the key cannot be stored in
+    /// the key field (e.g. a key with a name for an `i64` key field).
+    fn assign_key(&mut self, key: &ds::Key) -> Result<(), EntailError> {
+        Err(EntailError::simple(
+            EntailErrorKind::PropertyMappingError,
+            format!("Cannot assign the key {} to {}", key, Self::KIND),
+        ))
+    }
+
+    /// Returns the description of the Kind, set with `#[entail(description = "...")]` on the
+    /// struct, for admin UIs and schema reports.
+    fn description() -> Option<&'static str> {
+        None
+    }
+
+    /// Returns the property names and descriptions of the fields that have an
+    /// `#[entail(description = "...")]`, in the order of the fields. The key is described as
+    /// `__key__`.
+    fn property_descriptions() -> &'static [(&'static str, &'static str)] {
+        &[]
+    }
+
+    /// Returns the namespace of the Kind, set with `#[entail(namespace = "...")]` on the
+    /// struct, or `None` for the default namespace.
+    ///
+    /// The keys of the model and the queries of its adapter are created in the namespace.
+    /// A shell with a namespace overrides it, see [`ds::DatastoreShell::with_namespace`].
+    fn namespace() -> Option<&'static str> {
+        None
+    }
+
+    /// Returns `true` if the model has a property with the given name, or the name is
+    /// `__key__`. For enums, these are the properties of the variants and the discriminator.
+    ///
+    /// The default implementation accepts every name, it is implemented by
+    /// `#[derive(Entail)]` to validate the selected properties of [`FetchOptions`].
+    fn has_property(_name: &str) -> bool {
+        true
+    }
+
+    /// Returns `true` if the property with the given name (or alias) belongs to a field marked
+    /// with `#[entail(sensitive)]`. For enums, these are the sensitive properties of the
+    /// variants.
+    fn is_sensitive(_name: &str) -> bool {
+        false
+    }
+
+    /// Returns the entity of the model formatted with the values of the sensitive properties
+    /// masked, for logging, see [`ds::Entity::display_with`]. If the model cannot be converted
+    /// to an entity, the error is shown instead of the properties.
+    fn redacted_debug(&self) -> String {
+        match self.to_ds_entity() {
+            Ok(e) => {
+                let options = ds::DisplayOptions::mask_matching(Self::is_sensitive);
+                e.display_with(&options).to_string()
+            }
+            Err(err) => format!("{} {{ <{}> }}", Self::KIND, err.message),
+        }
+    }
+
     /// Returns a static reference to the EntityAdapter for type T.
     ///
     /// This adapter provides utility methods (like key creation) tied to the model.
     fn adapter() -> &'static EntityAdapter<Self>;
 }
 
+/// How the key of a model is completed when its key field is empty, set with
+/// `#[entail(key_strategy = "...")]` on the key field.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum KeyStrategy {
+    /// The key is left to the application, an empty key field results in an incomplete key
+    /// that Datastore completes when the entity is inserted.
+    #[default]
+    Manual,
+    /// [`EntityModel::to_ds_entity`] names an entity with an empty key with a random UUID,
+    /// see [`ds::Key::with_uuid_name`].
+    Uuid,
+    /// An empty key is left incomplete by [`EntityModel::to_ds_entity`], and the save methods
+    /// of the adapter (like [`EntityAdapter::insert_model`]) allocate an ID before writing.
+    Allocate,
+}
+
 /// Represents the high-level category of error that occurred.
 #[derive(Debug, PartialEq, Eq, PartialOrd, Ord, Display)]
 pub enum EntailErrorKind {
@@ -205,6 +748,40 @@ pub enum EntailErrorKind {
     /// An error occurred during the conversion process between an entity's properties and the Rust struct's fields,
     /// such as a **type mismatch** or a **missing required property**.
     PropertyMappingError,
+    /// The operation was rejected because [`ds::DatastoreShell::shutdown`] has been called.
+    ShuttingDown,
+    /// A conditional update failed because the stored entity has a different version than
+    /// the model, i.e. it was modified since the model was read.
+    /// See [`EntityAdapter::update_versioned`].
+    VersionConflict,
+    /// The operation must not overwrite an entity, but one already exists with the target key.
+    /// See [`admin::move_entity`].
+    EntityAlreadyExists,
+    /// Reading from or writing to a stream other than the Datastore failed.
+    /// See [`export::stream_to_writer`].
+    IoFailure,
+    /// A field of a model was rejected by the function of its `#[entail(validate = "...")]`
+    /// attribute. The failing field is reported in the `mapping` of the error.
+    ValidationFailed,
+    /// The start or end cursor of a query was rejected by the Datastore, e.g. because it
+    /// expired or the indexes changed since it was returned. The query has to be restarted
+    /// without the cursor. See [`scan::ShardedScan::with_restart_on_invalid_cursor`].
+    InvalidCursor,
+    /// A request was made through a transactional [`ds::DatastoreShell`] (e.g. a clone taken
+    /// out of a [`ds::TransactionShell`]) after its transaction was committed or rolled back.
+    TransactionInactive,
+    /// A key or a query targets a project or a database other than the ones of the
+    /// [`ds::DatastoreShell`] it was sent through, see [`ds::PartitionId`].
+    PartitionMismatch,
+    /// A key string could not be decoded, see [`ds::Key::from_websafe_string`] and the
+    /// [`std::str::FromStr`] implementation of [`ds::Key`].
+    MalformedKey,
+    /// The mutations committed in a transaction touch more entity groups than the limit set
+    /// with [`ds::Transaction::with_max_entity_groups`], so they were not sent.
+    TooManyEntityGroups,
+    /// The operation runs its own transaction, so it was rejected for a transactional
+    /// [`ds::DatastoreShell`]. See [`EntityAdapter::update_versioned`].
+    TransactionNotAllowed,
 }
 
 impl Default for EntailErrorKind {
@@ -227,6 +804,27 @@ pub struct EntailError {
     /// client library, providing detailed context for API failures (e.g., networking,
     /// authorization, or transactional conflicts).
     pub ds_error: Option<google_datastore1::Error>,
+    /// Details about the property that failed to map (or to validate), if this is an error of
+    /// the kind [`EntailErrorKind::PropertyMappingError`] or
+    /// [`EntailErrorKind::ValidationFailed`] reported by a model generated by
+    /// `#[derive(Entail)]`.
+    pub mapping: Option<Box<MappingContext>>,
+}
+
+/// The structured details of a property that could not be mapped to a model field.
+///
+/// This makes it possible to aggregate mapping failures (e.g. in logs or metrics) by kind and
+/// property without parsing the error message.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct MappingContext {
+    /// The Datastore **Kind** of the entity being mapped.
+    pub kind: Cow<'static, str>,
+    /// The name of the property that failed to map. For key related errors this is `__key__`.
+    pub property: Cow<'static, str>,
+    /// The Rust type of the field, as written in the model (e.g. `Option<i64>`).
+    pub expected_type: Cow<'static, str>,
+    /// The type of the value found in the entity, or `None` if the property was absent.
+    pub actual: Option<ds::ValueType>,
 }
 
 impl EntailError {
@@ -242,6 +840,7 @@ impl EntailError {
             kind,
             message: message.into(),
             ds_error: None,
+            mapping: None,
         }
     }
 
@@ -255,6 +854,67 @@ impl EntailError {
     pub fn app(message: impl Into<std::borrow::Cow<'static, str>>) -> Self {
         Self::simple(EntailErrorKind::ApplicationError, message)
     }
+
+    /// Returns the status of the underlying Datastore API error (e.g. `"ABORTED"` or
+    /// `"INVALID_ARGUMENT"`), if the error carries one.
+    pub fn status(&self) -> Option<&str> {
+        self.ds_error.as_ref().and_then(ds::api_status)
+    }
+
+    /// Returns `true` if repeating the same operation later may succeed.
+    ///
+    /// This is the case for Datastore API errors with a status the [`ds::Transaction`]
+    /// runner would retry as well (`ABORTED`, `DEADLINE_EXCEEDED`, `UNAVAILABLE` or
+    /// `INTERNAL`). Errors without an attached Datastore API error are never retryable.
+    pub fn is_retryable(&self) -> bool {
+        self.ds_error
+            .as_ref()
+            .map(|e| ds::RetryRule::based_on_error(e) != ds::RetryRule::Never)
+            .unwrap_or(false)
+    }
+
+    /// Returns `true` if the error was caused by the request of the caller, so that
+    /// repeating it unchanged would fail again (i.e. it maps to a 4xx response).
+    ///
+    /// This covers [`EntailErrorKind::RequiredEntityNotFound`],
+    /// [`EntailErrorKind::InvalidCursor`], [`EntailErrorKind::PartitionMismatch`],
+    /// [`EntailErrorKind::MalformedKey`], [`EntailErrorKind::TooManyEntityGroups`] and
+    /// Datastore API errors
+    /// with the statuses `INVALID_ARGUMENT`, `FAILED_PRECONDITION`, `OUT_OF_RANGE`,
+    /// `NOT_FOUND` and `ALREADY_EXISTS`.
+    pub fn is_client_fault(&self) -> bool {
+        match self.kind {
+            EntailErrorKind::RequiredEntityNotFound
+            | EntailErrorKind::InvalidCursor
+            | EntailErrorKind::PartitionMismatch
+            | EntailErrorKind::MalformedKey
+            | EntailErrorKind::TooManyEntityGroups => true,
+            EntailErrorKind::RequestFailure => matches!(
+                self.status(),
+                Some(
+                    "INVALID_ARGUMENT"
+                        | "FAILED_PRECONDITION"
+                        | "OUT_OF_RANGE"
+                        | "NOT_FOUND"
+                        | "ALREADY_EXISTS"
+                )
+            ),
+            _ => false,
+        }
+    }
+
+    /// Returns `true` if the stored data could not be mapped to the model, i.e. the
+    /// error is of the kind [`EntailErrorKind::PropertyMappingError`] or
+    /// [`EntailErrorKind::EntityKindMismatch`].
+    ///
+    /// These errors are neither retryable nor caused by the caller, they usually indicate
+    /// a schema change that the stored entities have not caught up with.
+    pub fn is_data_fault(&self) -> bool {
+        matches!(
+            self.kind,
+            EntailErrorKind::PropertyMappingError | EntailErrorKind::EntityKindMismatch
+        )
+    }
 }
 
 impl std::fmt::Display for EntailError {
@@ -268,3 +928,46 @@ impl std::fmt::Display for EntailError {
 impl std::error::Error for EntailError {}
 
 pub use adapter::*;
+pub use cache::*;
+pub use convert::*;
+pub use repository::*;
+pub use scope::*;
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn api_error(status: &str) -> EntailError {
+        EntailError {
+            kind: EntailErrorKind::RequestFailure,
+            message: "Commit error".into(),
+            ds_error: Some(google_datastore1::Error::BadRequest(serde_json::json!({
+                "error": { "code": 400, "status": status }
+            }))),
+            mapping: None,
+        }
+    }
+
+    #[test]
+    fn test_error_categories() {
+        let aborted = api_error("ABORTED");
+        assert_eq!(aborted.status(), Some("ABORTED"));
+        assert!(aborted.is_retryable());
+        assert!(!aborted.is_client_fault());
+
+        let invalid = api_error("INVALID_ARGUMENT");
+        assert!(!invalid.is_retryable());
+        assert!(invalid.is_client_fault());
+        assert!(!invalid.is_data_fault());
+
+        let mapping = EntailError::simple(EntailErrorKind::PropertyMappingError, "Bad value");
+        assert_eq!(mapping.status(), None);
+        assert!(!mapping.is_retryable());
+        assert!(!mapping.is_client_fault());
+        assert!(mapping.is_data_fault());
+
+        let not_found = EntailError::simple(EntailErrorKind::RequiredEntityNotFound, "Missing");
+        assert!(not_found.is_client_fault());
+        assert!(!EntailError::app("Failure").is_client_fault());
+    }
+}
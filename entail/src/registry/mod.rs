@@ -0,0 +1,226 @@
+use std::any::Any;
+use std::future::Future;
+use std::pin::Pin;
+use std::sync::RwLock;
+
+use crate::{EntailError, EntailErrorKind, EntityAdapter, EntityModel, ds};
+
+/// The future returned by the asynchronous methods of [`DynEntityAdapter`].
+pub type BoxFuture<'a, T> = Pin<Box<dyn Future<Output = T> + Send + 'a>>;
+
+/// An object-safe view of an [`EntityAdapter`], so that generic tools (admin UIs, exporters)
+/// can work with the models of the application without knowing their types.
+///
+/// Every method works with raw [`ds::Entity`] values, but checks that they can be mapped to
+/// the model the adapter belongs to.
+pub trait DynEntityAdapter: Send + Sync {
+    /// Returns the Datastore **Kind** of the model.
+    fn kind(&self) -> &'static str;
+
+    /// Returns the description of the Kind, see [`EntityModel::description`].
+    fn description(&self) -> Option<&'static str>;
+
+    /// Returns the descriptions of the properties, see [`EntityModel::property_descriptions`].
+    fn property_descriptions(&self) -> &'static [(&'static str, &'static str)];
+
+    /// Returns `true` if the property is sensitive, see [`EntityModel::is_sensitive`].
+    fn is_sensitive(&self, property: &str) -> bool;
+
+    /// Maps the entity to the model and back, returning the entity the model would write.
+    ///
+    /// ## Returns
+    /// A [`Result`] containing the normalized entity, or an [`EntailError`] if the entity
+    /// cannot be mapped to the model. Unmodeled properties are dropped.
+    fn serialize(&self, entity: &ds::Entity) -> Result<ds::Entity, EntailError>;
+
+    /// Maps the entity to the model, returning it boxed, so that it can be downcast to the
+    /// type of the model.
+    ///
+    /// ## Returns
+    /// A [`Result`] containing the model, or an [`EntailError`] if the entity cannot be mapped
+    /// to the model.
+    fn deserialize(&self, entity: &ds::Entity) -> Result<Box<dyn Any + Send>, EntailError>;
+
+    /// Looks up a single entity of the model.
+    ///
+    /// ## Returns
+    /// A [`Result`] containing the entity as it is stored or `None` if it doesn't exist, or an
+    /// [`EntailError`] if the lookup failed or the entity cannot be mapped to the model.
+    fn fetch<'a>(
+        &'a self,
+        ds: &'a ds::DatastoreShell,
+        key: ds::Key,
+    ) -> BoxFuture<'a, Result<Option<ds::Entity>, EntailError>>;
+
+    /// Fetches a page of the entities of the model, in key order.
+    ///
+    /// ## Parameters
+    /// - `ds`: A reference to the [`ds::DatastoreShell`] to be used for Datastore access.
+    /// - `start_cursor`: The end cursor of the previous page, or `None` for the first page.
+    /// - `limit`: The maximum number of entities in the page.
+    ///
+    /// ## Returns
+    /// A [`Result`] containing the entities as they are stored, or an [`EntailError`] if the
+    /// query failed or any of the entities cannot be mapped to the model.
+    fn fetch_page<'a>(
+        &'a self,
+        ds: &'a ds::DatastoreShell,
+        start_cursor: Option<Vec<u8>>,
+        limit: i32,
+    ) -> BoxFuture<'a, Result<ds::QueryResult<ds::Entity>, EntailError>>;
+}
+
+impl<T> DynEntityAdapter for EntityAdapter<T>
+where
+    T: EntityModel + Send + Sync + 'static,
+{
+    fn kind(&self) -> &'static str {
+        EntityAdapter::kind(self)
+    }
+
+    fn description(&self) -> Option<&'static str> {
+        T::description()
+    }
+
+    fn property_descriptions(&self) -> &'static [(&'static str, &'static str)] {
+        T::property_descriptions()
+    }
+
+    fn is_sensitive(&self, property: &str) -> bool {
+        T::is_sensitive(property)
+    }
+
+    fn serialize(&self, entity: &ds::Entity) -> Result<ds::Entity, EntailError> {
+        T::from_ds_entity(entity)?.to_ds_entity()
+    }
+
+    fn deserialize(&self, entity: &ds::Entity) -> Result<Box<dyn Any + Send>, EntailError> {
+        Ok(Box::new(T::from_ds_entity(entity)?))
+    }
+
+    fn fetch<'a>(
+        &'a self,
+        ds: &'a ds::DatastoreShell,
+        key: ds::Key,
+    ) -> BoxFuture<'a, Result<Option<ds::Entity>, EntailError>> {
+        Box::pin(async move {
+            let entity = ds.get_single(key).await?;
+            if let Some(entity) = &entity {
+                T::from_ds_entity(entity)?;
+            }
+            Ok(entity)
+        })
+    }
+
+    fn fetch_page<'a>(
+        &'a self,
+        ds: &'a ds::DatastoreShell,
+        start_cursor: Option<Vec<u8>>,
+        limit: i32,
+    ) -> BoxFuture<'a, Result<ds::QueryResult<ds::Entity>, EntailError>> {
+        Box::pin(async move {
+            let query = ds::Query {
+                start_cursor,
+                limit,
+                ..self.query()
+            };
+            let result = ds.run_query(query).await?;
+            for entity in &result.items {
+                T::from_ds_entity(entity)?;
+            }
+            Ok(result)
+        })
+    }
+}
+
+static ADAPTERS: RwLock<Vec<&'static dyn DynEntityAdapter>> = RwLock::new(Vec::new());
+
+/// Registers the adapter of a model, so that it can be found by its Kind with [`adapter_for`].
+///
+/// Registering a Kind again replaces the adapter registered for it before, so if several
+/// models use the same Kind (e.g. the models of a polymorphic enum), the one registered last
+/// is returned by [`adapter_for`].
+pub fn register<T>()
+where
+    T: EntityModel + Send + Sync + 'static,
+{
+    let adapter: &'static dyn DynEntityAdapter = T::adapter();
+    let mut adapters = ADAPTERS.write().unwrap();
+    adapters.retain(|registered| registered.kind() != adapter.kind());
+    adapters.push(adapter);
+}
+
+/// Returns the adapter registered for a Kind with [`register`], if any.
+pub fn adapter_for(kind: &str) -> Option<&'static dyn DynEntityAdapter> {
+    ADAPTERS
+        .read()
+        .unwrap()
+        .iter()
+        .find(|adapter| adapter.kind() == kind)
+        .copied()
+}
+
+/// Maps an entity to the model registered for its Kind, e.g. to handle the results of a
+/// lookup or a query spanning several Kinds. The result can be downcast to the model:
+///
+/// ```rust,ignore
+/// for entity in ds.get_all(&keys).await? {
+///     let model = entail::registry::deserialize(&entity)?;
+///     if let Some(task) = model.downcast_ref::<Task>() {
+///         // ...
+///     }
+/// }
+/// ```
+///
+/// ## Returns
+/// A [`Result`] containing the boxed model, or an [`EntailError`] of the kind
+/// [`EntailErrorKind::EntityKindMismatch`] if no model is registered for the Kind, or if the
+/// entity cannot be mapped to the model.
+pub fn deserialize(entity: &ds::Entity) -> Result<Box<dyn Any + Send>, EntailError> {
+    let kind = entity.kind();
+    adapter_for(kind)
+        .ok_or_else(|| {
+            EntailError::simple(
+                EntailErrorKind::EntityKindMismatch,
+                format!("No model is registered for the Kind {:?}", kind),
+            )
+        })?
+        .deserialize(entity)
+}
+
+/// Returns the Kinds of all registered adapters, in the order they were registered.
+pub fn registered_kinds() -> Vec<&'static str> {
+    ADAPTERS
+        .read()
+        .unwrap()
+        .iter()
+        .map(|adapter| adapter.kind())
+        .collect()
+}
+
+/// The documentation of a registered Kind, see [`manifest`].
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct KindManifest {
+    /// The Datastore **Kind**.
+    pub kind: &'static str,
+    /// The description of the Kind, if the model has one.
+    pub description: Option<&'static str>,
+    /// The property names and descriptions of the described fields.
+    pub properties: &'static [(&'static str, &'static str)],
+}
+
+/// Returns the documentation of all registered Kinds, in the order they were registered, so
+/// that admin UIs and schema reports can show the descriptions set with
+/// `#[entail(description = "...")]`.
+pub fn manifest() -> Vec<KindManifest> {
+    ADAPTERS
+        .read()
+        .unwrap()
+        .iter()
+        .map(|adapter| KindManifest {
+            kind: adapter.kind(),
+            description: adapter.description(),
+            properties: adapter.property_descriptions(),
+        })
+        .collect()
+}
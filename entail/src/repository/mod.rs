@@ -0,0 +1,338 @@
+use std::cmp::Ordering;
+use std::collections::BTreeMap;
+use std::future::Future;
+use std::marker::PhantomData;
+use std::pin::Pin;
+use std::sync::Mutex;
+
+use crate::ds;
+use crate::{EntailError, EntailErrorKind, EntityModel};
+
+/// The future returned by the methods of a [`Repository`].
+pub type RepositoryFuture<'a, R> =
+    Pin<Box<dyn Future<Output = Result<R, EntailError>> + Send + 'a>>;
+
+/// The storage of the models of a Kind, as seen by the application services.
+///
+/// Services taking a `&dyn Repository<T>` (or an `Arc<dyn Repository<T>>`) instead of a
+/// [`ds::DatastoreShell`] can be wired to Datastore with a [`DatastoreRepository`] in
+/// production, and unit tested against an [`InMemoryRepository`], without an emulator.
+///
+/// The methods return boxed futures, so the trait can be used as a trait object.
+pub trait Repository<T: EntityModel + Send>: Send + Sync {
+    /// Gets the model of a key.
+    ///
+    /// ## Returns
+    /// A [`Result`] containing the model, or `None` if it doesn't exist, or an
+    /// [`EntailError`] if the lookup or the mapping failed.
+    fn get<'a>(&'a self, key: &'a ds::Key) -> RepositoryFuture<'a, Option<T>>;
+
+    /// Writes the model, inserting or overwriting its entity. An empty key is completed
+    /// first, and the completed key is written back into the model, like
+    /// [`crate::EntityAdapter::upsert_model`] does.
+    ///
+    /// ## Returns
+    /// A [`Result`] containing the key of the written entity, or an [`EntailError`].
+    fn save<'a>(&'a self, model: &'a mut T) -> RepositoryFuture<'a, ds::Key>;
+
+    /// Deletes the model of a key. Deleting a missing model succeeds.
+    fn delete<'a>(&'a self, key: &'a ds::Key) -> RepositoryFuture<'a, ()>;
+
+    /// Runs a query, prepared with [`crate::EntityAdapter::prepare_query`], and maps the
+    /// results to models.
+    ///
+    /// ## Returns
+    /// A [`Result`] containing the page of models, or an [`EntailError`] if the query or the
+    /// mapping failed.
+    fn query<'a>(&'a self, query: ds::Query) -> RepositoryFuture<'a, ds::QueryResult<T>>;
+}
+
+/// A [`Repository`] backed by Datastore, running everything through an
+/// [`crate::EntityAdapter`].
+pub struct DatastoreRepository<T: EntityModel> {
+    ds: ds::DatastoreShell,
+    _marker: PhantomData<fn() -> T>,
+}
+
+impl<T: EntityModel> DatastoreRepository<T> {
+    /// Creates a repository using a shell, which may be part of a transaction.
+    ///
+    /// ## Parameters
+    /// - `ds`: The [`ds::DatastoreShell`] to be used for Datastore access.
+    pub fn new(ds: ds::DatastoreShell) -> Self {
+        Self {
+            ds,
+            _marker: PhantomData,
+        }
+    }
+
+    /// Returns the shell the repository uses.
+    pub fn shell(&self) -> &ds::DatastoreShell {
+        &self.ds
+    }
+}
+
+impl<T: EntityModel + Send + Sync + 'static> Repository<T> for DatastoreRepository<T> {
+    fn get<'a>(&'a self, key: &'a ds::Key) -> RepositoryFuture<'a, Option<T>> {
+        Box::pin(async move {
+            match self.ds.get_single(key.clone()).await? {
+                Some(entity) => T::from_ds_entity(&entity).map(Some),
+                None => Ok(None),
+            }
+        })
+    }
+
+    fn save<'a>(&'a self, model: &'a mut T) -> RepositoryFuture<'a, ds::Key> {
+        Box::pin(T::adapter().upsert_model(&self.ds, model))
+    }
+
+    fn delete<'a>(&'a self, key: &'a ds::Key) -> RepositoryFuture<'a, ()> {
+        Box::pin(async move {
+            self.ds
+                .commit(ds::MutationBatch::new().delete(key.clone()))
+                .await?;
+            Ok(())
+        })
+    }
+
+    fn query<'a>(&'a self, query: ds::Query) -> RepositoryFuture<'a, ds::QueryResult<T>> {
+        Box::pin(T::adapter().fetch_query(&self.ds, query))
+    }
+}
+
+/// A [`Repository`] keeping the entities in memory, for unit tests.
+///
+/// The models are stored as entities, converted with [`EntityModel::to_ds_entity`], so the
+/// mapping is exercised like with Datastore. Empty keys are completed with sequential IDs
+/// starting from `1`.
+///
+/// The queries are evaluated the way Datastore does: the filters only match the indexed
+/// properties (a filter on an array property matches if any of its elements does, and `!=`
+/// and `NOT_IN` only if none of them is excluded), and the entities lacking a property of the
+/// order are left out. Projections, `distinct_on` and cursors are not supported.
+pub struct InMemoryRepository<T: EntityModel> {
+    state: Mutex<State>,
+    _marker: PhantomData<fn() -> T>,
+}
+
+#[derive(Default)]
+struct State {
+    entities: BTreeMap<ds::Key, ds::Entity>,
+    last_id: i64,
+}
+
+impl<T: EntityModel> Default for InMemoryRepository<T> {
+    fn default() -> Self {
+        Self {
+            state: Mutex::default(),
+            _marker: PhantomData,
+        }
+    }
+}
+
+impl<T: EntityModel + 'static> InMemoryRepository<T> {
+    /// Creates an empty repository.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Stores an entity as it is, e.g. to set up a test with data that doesn't come from a
+    /// model.
+    pub fn put_entity(&self, entity: ds::Entity) {
+        let mut state = self.state.lock().unwrap();
+        state.entities.insert(entity.key().clone(), entity);
+    }
+
+    /// Returns the stored entity of a key, e.g. to check what a service saved.
+    pub fn entity(&self, key: &ds::Key) -> Option<ds::Entity> {
+        self.state.lock().unwrap().entities.get(key).cloned()
+    }
+
+    /// Returns the number of the stored entities.
+    pub fn len(&self) -> usize {
+        self.state.lock().unwrap().entities.len()
+    }
+
+    /// Returns `true` if no entity is stored.
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    fn save_now(&self, model: &mut T) -> Result<ds::Key, EntailError> {
+        let mut entity = model.to_ds_entity()?;
+        let mut state = self.state.lock().unwrap();
+        if !entity.key().is_complete() {
+            // skipping the IDs of the entities stored with put_entity
+            let key = loop {
+                state.last_id += 1;
+                let key = entity.key().clone().with_id(state.last_id);
+                if !state.entities.contains_key(&key) {
+                    break key;
+                }
+            };
+            model.assign_key(&key)?;
+            entity = model.to_ds_entity()?;
+        }
+        let key = entity.key().clone();
+        state.entities.insert(key.clone(), entity);
+        Ok(key)
+    }
+
+    fn query_now(&self, query: ds::Query) -> Result<ds::QueryResult<T>, EntailError> {
+        let query = T::adapter().prepare_query(query)?;
+        if !query.projection.is_empty()
+            || !query.distinct_on.is_empty()
+            || query.start_cursor.is_some()
+            || query.end_cursor.is_some()
+        {
+            return Err(EntailError::simple(
+                EntailErrorKind::RequestFailure,
+                "InMemoryRepository doesn't support projections, distinct_on and cursors",
+            ));
+        }
+        let state = self.state.lock().unwrap();
+        let mut found: Vec<&ds::Entity> = state
+            .entities
+            .values()
+            .filter(|entity| {
+                (query.kind.is_empty() || entity.kind() == query.kind)
+                    && entity.namespace() == query.namespace.as_deref()
+                    && query
+                        .filter
+                        .as_ref()
+                        .is_none_or(|f| filter_matches(f, entity))
+                    && query
+                        .order
+                        .iter()
+                        .all(|order| indexed_value(entity, &order.name).is_some())
+            })
+            .collect();
+        // the entities are already sorted by their keys, which break the ties
+        found.sort_by(|a, b| {
+            query
+                .order
+                .iter()
+                .map(|order| compare_for_order(a, b, order))
+                .find(|ordering| ordering.is_ne())
+                .unwrap_or(Ordering::Equal)
+        });
+        let limit = if query.limit > 0 {
+            query.limit as usize
+        } else {
+            usize::MAX
+        };
+        let items = found
+            .into_iter()
+            .skip(query.offset.max(0) as usize)
+            .take(limit)
+            .map(T::from_ds_entity)
+            .collect::<Result<_, _>>()?;
+        Ok(ds::QueryResult::new(items, None))
+    }
+}
+
+impl<T: EntityModel + Send + Sync + 'static> Repository<T> for InMemoryRepository<T> {
+    // the work is done when the futures are polled, like with the Datastore repository
+    fn get<'a>(&'a self, key: &'a ds::Key) -> RepositoryFuture<'a, Option<T>> {
+        Box::pin(async move {
+            self.entity(key)
+                .map(|entity| T::from_ds_entity(&entity))
+                .transpose()
+        })
+    }
+
+    fn save<'a>(&'a self, model: &'a mut T) -> RepositoryFuture<'a, ds::Key> {
+        Box::pin(async move { self.save_now(model) })
+    }
+
+    fn delete<'a>(&'a self, key: &'a ds::Key) -> RepositoryFuture<'a, ()> {
+        Box::pin(async move {
+            self.state.lock().unwrap().entities.remove(key);
+            Ok(())
+        })
+    }
+
+    fn query<'a>(&'a self, query: ds::Query) -> RepositoryFuture<'a, ds::QueryResult<T>> {
+        Box::pin(async move { self.query_now(query) })
+    }
+}
+
+/// Gets a value filters and orders can see: the key, or an indexed property.
+fn indexed_value<'e>(
+    entity: &'e ds::Entity,
+    name: &str,
+) -> Option<std::borrow::Cow<'e, ds::Value>> {
+    if name != ds::KEY_PROPERTY && !entity.is_indexed(name) {
+        return None;
+    }
+    entity.value_or_key(name)
+}
+
+fn filter_matches(filter: &ds::Filter, entity: &ds::Entity) -> bool {
+    match filter {
+        ds::Filter::Composite(ds::CompositeFilterOperator::And, filters) => {
+            filters.iter().all(|filter| filter_matches(filter, entity))
+        }
+        ds::Filter::Composite(ds::CompositeFilterOperator::Or, filters) => {
+            filters.iter().any(|filter| filter_matches(filter, entity))
+        }
+        ds::Filter::Property(_, ds::FilterOperator::HasAncestor, ds::Value::Key(ancestor)) => {
+            entity.key() == ancestor || entity.key().has_ancestor(ancestor)
+        }
+        ds::Filter::Property(name, op, operand) => {
+            let Some(value) = indexed_value(entity, name) else {
+                return false;
+            };
+            let values = match value.as_ref() {
+                ds::Value::Array(values) => values.as_slice(),
+                value => std::slice::from_ref(value),
+            };
+            let operands = match operand {
+                ds::Value::Array(operands) => operands.as_slice(),
+                operand => std::slice::from_ref(operand),
+            };
+            match op {
+                ds::FilterOperator::NotEqual | ds::FilterOperator::NotIn => {
+                    !values.is_empty() && values.iter().all(|value| !operands.contains(value))
+                }
+                ds::FilterOperator::In => values.iter().any(|value| operands.contains(value)),
+                _ => values.iter().any(|value| compares(value, *op, operand)),
+            }
+        }
+    }
+}
+
+/// Compares a value to the operand of a filter, values of different types never match.
+fn compares(value: &ds::Value, op: ds::FilterOperator, operand: &ds::Value) -> bool {
+    if value.value_type() != operand.value_type() {
+        return false;
+    }
+    let ordering = value.cmp(operand);
+    match op {
+        ds::FilterOperator::LessThan => ordering.is_lt(),
+        ds::FilterOperator::LessThanOrEqual => ordering.is_le(),
+        ds::FilterOperator::GreaterThan => ordering.is_gt(),
+        ds::FilterOperator::GreaterThanOrEqual => ordering.is_ge(),
+        ds::FilterOperator::Equal => ordering.is_eq(),
+        _ => false,
+    }
+}
+
+/// Compares two entities by a property of the order. An array property sorts by its smallest
+/// element ascending, and by its largest one descending.
+fn compare_for_order(a: &ds::Entity, b: &ds::Entity, order: &ds::PropertyOrder) -> Ordering {
+    let descending = order.direction == ds::OrderDirection::DESCENDING;
+    let sort_value = |entity: &ds::Entity| {
+        indexed_value(entity, &order.name).map(|value| match value.as_ref() {
+            ds::Value::Array(values) if descending => values.iter().max().cloned(),
+            ds::Value::Array(values) => values.iter().min().cloned(),
+            value => Some(value.clone()),
+        })
+    };
+    let ordering = sort_value(a).cmp(&sort_value(b));
+    if descending {
+        ordering.reverse()
+    } else {
+        ordering
+    }
+}
@@ -0,0 +1,248 @@
+mod split;
+
+use std::borrow::Cow;
+
+use tokio::sync::mpsc;
+
+use crate::ds;
+use crate::{EntailError, EntailErrorKind};
+
+pub use split::*;
+
+/// A runner that scans every entity of a single **Kind** using several concurrent shards.
+///
+/// A single cursor walk over a large Kind is bounded by the latency of sequential page
+/// fetches. `ShardedScan` first samples the key space with [`sample_split_points`], splits
+/// it into approximately even `__key__` ranges, and then walks each range with its own
+/// cursor in a separate task. The entities of all shards are streamed through a
+/// single bounded channel, so a slow consumer applies backpressure to all shards.
+///
+/// The order in which entities are received is **not** defined, only the order within a
+/// single shard follows the `__key__` order.
+///
+/// The `with_*` methods consume and return `Self`, allowing for method chaining.
+pub struct ShardedScan<'a> {
+    /// The number of key ranges to scan concurrently. Defaults to `8`.
+    pub shards: usize,
+    /// The number of sample keys requested per shard when computing the split points.
+    /// Higher values result in more even shards at the cost of a larger sampling query.
+    /// Defaults to `32`.
+    pub oversampling: usize,
+    /// The maximum number of entities fetched by a single query page. Defaults to `500`.
+    pub page_size: i32,
+    /// The capacity of the channel the entities are streamed through. Defaults to `1000`.
+    pub buffer: usize,
+    /// If `true`, a shard whose cursor is rejected (see [`EntailErrorKind::InvalidCursor`])
+    /// restarts its query without a cursor, right after the last key it has sent. Otherwise
+    /// the error is sent through the channel and the shard stops. Defaults to `false`.
+    pub restart_on_invalid_cursor: bool,
+    ds: &'a ds::DatastoreShell,
+    kind: Cow<'static, str>,
+}
+
+impl<'a> ShardedScan<'a> {
+    /// Creates a new `ShardedScan` for the given Kind with the default configuration.
+    ///
+    /// ## Parameters
+    /// - `ds`: A reference to the [`ds::DatastoreShell`] to be used for Datastore access.
+    /// - `kind`: The Datastore Kind to scan.
+    pub fn new(ds: &'a ds::DatastoreShell, kind: impl Into<Cow<'static, str>>) -> Self {
+        Self {
+            shards: 8,
+            oversampling: 32,
+            page_size: 500,
+            buffer: 1000,
+            restart_on_invalid_cursor: false,
+            ds,
+            kind: kind.into(),
+        }
+    }
+
+    /// Sets the number of concurrently scanned key ranges.
+    pub fn with_shards(mut self, shards: usize) -> Self {
+        self.shards = shards;
+        self
+    }
+
+    /// Sets the number of sample keys requested per shard.
+    pub fn with_oversampling(mut self, oversampling: usize) -> Self {
+        self.oversampling = oversampling;
+        self
+    }
+
+    /// Sets the maximum number of entities fetched by a single query page.
+    pub fn with_page_size(mut self, page_size: i32) -> Self {
+        self.page_size = page_size;
+        self
+    }
+
+    /// Sets the capacity of the channel the entities are streamed through.
+    pub fn with_buffer(mut self, buffer: usize) -> Self {
+        self.buffer = buffer;
+        self
+    }
+
+    /// Restarts the query of a shard after the last key it has sent when its cursor expires
+    /// or becomes invalid (e.g. because the indexes changed during a long scan), instead of
+    /// failing. The shards are walked in `__key__` order, so no entity is sent twice.
+    pub fn with_restart_on_invalid_cursor(mut self) -> Self {
+        self.restart_on_invalid_cursor = true;
+        self
+    }
+
+    /// Samples the key space and starts scanning all shards concurrently.
+    ///
+    /// Every shard runs in its own Tokio task. The tasks stop as soon as their key range is
+    /// exhausted, an error occurs (the error is sent through the channel), or the receiver
+    /// is dropped.
+    ///
+    /// ## Returns
+    /// A [`Result`] containing the receiving half of the channel the entities are streamed
+    /// through, or an [`EntailError`] if sampling the key space fails.
+    pub async fn run(self) -> Result<mpsc::Receiver<Result<ds::Entity, EntailError>>, EntailError> {
+        let split_points =
+            sample_split_points(self.ds, self.kind.clone(), self.shards, self.oversampling)
+                .await?;
+        let (tx, rx) = mpsc::channel(self.buffer.max(1));
+        for range in KeyRange::from_split_points(split_points) {
+            let query = self.shard_query(&range);
+            let shell = self.ds.clone();
+            let tx = tx.clone();
+            let restart = self.restart_on_invalid_cursor.then_some(range);
+            tokio::spawn(async move { scan_shard(shell, query, restart, tx).await });
+        }
+        Ok(rx)
+    }
+
+    fn shard_query(&self, range: &KeyRange) -> ds::Query {
+        ds::Query {
+            kind: self.kind.clone(),
+            filter: range.filter(),
+            order: vec![ds::PropertyOrder::new(
+                "__key__",
+                ds::OrderDirection::ASCENDING,
+            )],
+            limit: self.page_size,
+            ..ds::Query::default()
+        }
+    }
+}
+
+/// Walks the key range of a shard. If `restart` holds the range, an invalid cursor restarts
+/// the query after the last sent key.
+async fn scan_shard(
+    ds: ds::DatastoreShell,
+    mut query: ds::Query,
+    restart: Option<KeyRange>,
+    tx: mpsc::Sender<Result<ds::Entity, EntailError>>,
+) {
+    let mut last_key: Option<ds::Key> = None;
+    loop {
+        let page = match ds.run_query(query.clone()).await {
+            Ok(page) => page,
+            Err(err) => match (&restart, &err.kind) {
+                (Some(range), EntailErrorKind::InvalidCursor) if query.start_cursor.is_some() => {
+                    query.start_cursor = None;
+                    query.filter = resumed_filter(range, last_key.as_ref());
+                    continue;
+                }
+                _ => {
+                    _ = tx.send(Err(err)).await;
+                    return;
+                }
+            },
+        };
+        if page.items.is_empty() {
+            return;
+        }
+        for entity in page.items {
+            last_key = Some(entity.key().clone());
+            if tx.send(Ok(entity)).await.is_err() {
+                // the receiver was dropped, nobody is interested in the rest
+                return;
+            }
+        }
+        match page.end_cursor {
+            Some(cursor) => query.start_cursor = Some(cursor),
+            None => return,
+        }
+    }
+}
+
+/// Returns the filter of the rest of a key range, after `last_key` (if any was sent).
+fn resumed_filter(range: &KeyRange, last_key: Option<&ds::Key>) -> Option<ds::Filter> {
+    let Some(last_key) = last_key else {
+        return range.filter();
+    };
+    let filters = std::iter::once(ds::FilterOperator::GreaterThan.of("__key__", last_key.clone()))
+        .chain(
+            range
+                .end
+                .clone()
+                .map(|key| ds::FilterOperator::LessThan.of("__key__", key)),
+        )
+        .collect();
+    ds::Filter::and(filters)
+}
+
+/// Scans all entities of a Kind using `shards` concurrently walked key ranges.
+///
+/// This is a convenience wrapper around [`ShardedScan`] using its default configuration
+/// apart from the number of shards.
+///
+/// ## Parameters
+/// - `ds`: A reference to the [`ds::DatastoreShell`] to be used for Datastore access.
+/// - `kind`: The Datastore Kind to scan.
+/// - `shards`: The number of key ranges to scan concurrently.
+///
+/// ## Returns
+/// A [`Result`] containing the receiving half of the channel the entities are streamed
+/// through, or an [`EntailError`] if sampling the key space fails.
+pub async fn sharded_scan(
+    ds: &ds::DatastoreShell,
+    kind: impl Into<Cow<'static, str>>,
+    shards: usize,
+) -> Result<mpsc::Receiver<Result<ds::Entity, EntailError>>, EntailError> {
+    ShardedScan::new(ds, kind).with_shards(shards).run().await
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_resumed_filter() {
+        let key = |id| ds::Key::new("Item").with_id(id);
+        let range = KeyRange {
+            start: Some(key(1)),
+            end: Some(key(10)),
+        };
+        let Some(ds::Filter::Composite(_, filters)) = resumed_filter(&range, Some(&key(4))) else {
+            panic!("expected a composite filter");
+        };
+        let bounds: Vec<_> = filters
+            .into_iter()
+            .map(|filter| match filter {
+                ds::Filter::Property(name, op, ds::Value::Key(key)) => (name, op, key),
+                other => panic!("unexpected filter {:?}", other),
+            })
+            .collect();
+        assert!(matches!(
+            bounds.as_slice(),
+            [
+                (_, ds::FilterOperator::GreaterThan, after),
+                (_, ds::FilterOperator::LessThan, end),
+            ] if *after == key(4) && *end == key(10)
+        ));
+        // nothing was sent yet, the whole range is scanned again
+        assert!(matches!(
+            resumed_filter(&range, None),
+            Some(ds::Filter::Composite(_, filters)) if filters.len() == 2
+        ));
+        let open = KeyRange::default();
+        assert!(matches!(
+            resumed_filter(&open, Some(&key(4))),
+            Some(ds::Filter::Property(_, ds::FilterOperator::GreaterThan, _))
+        ));
+    }
+}
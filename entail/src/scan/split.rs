@@ -0,0 +1,183 @@
+use std::borrow::Cow;
+
+use crate::EntailError;
+use crate::ds;
+
+/// A range of keys, from `start` (inclusive) to `end` (exclusive). A missing bound is
+/// unbounded.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct KeyRange {
+    pub start: Option<ds::Key>,
+    pub end: Option<ds::Key>,
+}
+
+impl KeyRange {
+    /// Creates the ranges between consecutive split points, covering the whole key space:
+    /// `n` split points result in `n + 1` ranges.
+    ///
+    /// ## Parameters
+    /// - `split_points`: The split points in ascending order, see [`sample_split_points`].
+    pub fn from_split_points(split_points: Vec<ds::Key>) -> Vec<KeyRange> {
+        let mut start: Option<ds::Key> = None;
+        split_points
+            .into_iter()
+            .map(Some)
+            .chain(std::iter::once(None))
+            .map(|end| KeyRange {
+                start: std::mem::replace(&mut start, end.clone()),
+                end,
+            })
+            .collect()
+    }
+
+    /// Returns the filter restricting a query to the range, or `None` if it is unbounded.
+    pub fn filter(&self) -> Option<ds::Filter> {
+        let filters = self
+            .start
+            .clone()
+            .map(|key| ds::FilterOperator::GreaterThanOrEqual.of("__key__", key))
+            .into_iter()
+            .chain(
+                self.end
+                    .clone()
+                    .map(|key| ds::FilterOperator::LessThan.of("__key__", key)),
+            )
+            .collect();
+        ds::Filter::and(filters)
+    }
+
+    /// Returns `true` if the key falls within the range.
+    pub fn contains(&self, key: &ds::Key) -> bool {
+        self.start.as_ref().is_none_or(|start| start <= key)
+            && self.end.as_ref().is_none_or(|end| key < end)
+    }
+}
+
+/// Computes approximately even split points of the key space of a **Kind**, to process it in
+/// `ranges` parts concurrently (scans, deletes, exports).
+///
+/// The keys are sampled with a keys-only query ordered by the special `__scatter__` property,
+/// which is set on a random subset of the entities, so the sample is spread over the whole key
+/// space without reading it. If the sample has fewer keys than the number of ranges (the Kind
+/// is small, or the Datastore emulator doesn't maintain `__scatter__`), all the keys are read
+/// with keys-only queries in `__key__` order instead, which is only cheap for small Kinds.
+///
+/// ## Parameters
+/// - `ds`: A reference to the [`ds::DatastoreShell`] to be used for Datastore access.
+/// - `kind`: The Datastore Kind to split.
+/// - `ranges`: The number of ranges the split points should separate.
+/// - `oversampling`: The number of sample keys requested per range. Higher values result in
+///   more even ranges at the cost of a larger sampling query.
+///
+/// ## Returns
+/// A [`Result`] containing at most `ranges - 1` distinct split points in ascending order (fewer
+/// if the Kind has fewer keys), see [`KeyRange::from_split_points`], or an [`EntailError`] if a
+/// query fails.
+pub async fn sample_split_points(
+    ds: &ds::DatastoreShell,
+    kind: impl Into<Cow<'static, str>>,
+    ranges: usize,
+    oversampling: usize,
+) -> Result<Vec<ds::Key>, EntailError> {
+    let ranges = ranges.max(1);
+    if ranges == 1 {
+        return Ok(Vec::new());
+    }
+    let kind = kind.into();
+    let query = ds::Query {
+        kind: kind.clone(),
+        projection: vec!["__key__".into()],
+        order: vec![ds::PropertyOrder::new(
+            "__scatter__",
+            ds::OrderDirection::ASCENDING,
+        )],
+        limit: i32::try_from(ranges * oversampling.max(1)).unwrap_or(i32::MAX),
+        ..ds::Query::default()
+    };
+    let mut sample: Vec<ds::Key> = ds
+        .run_query(query)
+        .await?
+        .items
+        .into_iter()
+        .map(ds::Entity::just_key)
+        .collect();
+    if sample.len() < ranges {
+        sample = all_keys(ds, kind).await?;
+    }
+    Ok(even_split_points(sample, ranges))
+}
+
+async fn all_keys(
+    ds: &ds::DatastoreShell,
+    kind: Cow<'static, str>,
+) -> Result<Vec<ds::Key>, EntailError> {
+    let mut query = ds::Query {
+        kind,
+        projection: vec!["__key__".into()],
+        ..ds::Query::default()
+    };
+    let mut keys = Vec::new();
+    loop {
+        let page = ds.run_query(query.clone()).await?;
+        if page.items.is_empty() {
+            break;
+        }
+        keys.extend(page.items.into_iter().map(ds::Entity::just_key));
+        match page.end_cursor {
+            Some(cursor) => query.start_cursor = Some(cursor),
+            None => break,
+        }
+    }
+    Ok(keys)
+}
+
+/// Picks the keys at the boundaries of `ranges` equally sized parts of the sample.
+fn even_split_points(mut sample: Vec<ds::Key>, ranges: usize) -> Vec<ds::Key> {
+    sample.sort();
+    let mut split_points: Vec<ds::Key> = (1..ranges)
+        .filter_map(|range| sample.get(range * sample.len() / ranges).cloned())
+        .collect();
+    split_points.dedup();
+    split_points
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn key(id: i64) -> ds::Key {
+        ds::Key::new("Item").with_id(id)
+    }
+
+    #[test]
+    fn test_split_points_and_ranges() {
+        let sample: Vec<ds::Key> = (1..=12).rev().map(key).collect();
+        let split_points = even_split_points(sample, 4);
+        assert_eq!(split_points, vec![key(4), key(7), key(10)]);
+        // fewer distinct keys than ranges
+        assert_eq!(even_split_points(vec![key(1), key(1)], 4), vec![key(1)]);
+        assert!(even_split_points(Vec::new(), 4).is_empty());
+
+        let ranges = KeyRange::from_split_points(split_points);
+        assert_eq!(ranges.len(), 4);
+        assert_eq!(
+            ranges[0],
+            KeyRange {
+                start: None,
+                end: Some(key(4)),
+            }
+        );
+        assert_eq!(
+            ranges[3],
+            KeyRange {
+                start: Some(key(10)),
+                end: None,
+            }
+        );
+        assert!(ranges[1].contains(&key(4)) && !ranges[1].contains(&key(7)));
+        assert!(ranges[3].contains(&key(100)));
+        assert!(ranges[1].filter().is_some());
+        assert!(KeyRange::default().filter().is_none());
+        assert_eq!(KeyRange::from_split_points(Vec::new()).len(), 1);
+    }
+}
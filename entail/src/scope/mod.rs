@@ -0,0 +1,192 @@
+use std::borrow::Borrow;
+use std::collections::HashMap;
+use std::sync::Mutex;
+
+use crate::ds;
+use crate::{EntailError, EntailErrorKind, EntityModel};
+
+/// Memoizes entity lookups for the lifetime of a single unit of work, typically an HTTP
+/// request.
+///
+/// Resolver-style code often looks up the same entity several times while handling one
+/// request (e.g. the author of every comment on a page). A `RequestScope` remembers the
+/// result of every lookup made through it, including the keys that were **not found**, so
+/// each key is fetched from Datastore at most once. There is no expiry or invalidation
+/// logic beyond [`RequestScope::invalidate`]: create a new scope for every request and drop
+/// it afterwards.
+///
+/// The entities are cached in their raw form and mapped to the model on every access, so
+/// the models don't have to implement `Clone`.
+pub struct RequestScope<'a> {
+    ds: &'a ds::DatastoreShell,
+    entities: Mutex<HashMap<ds::Key, Option<ds::Entity>>>,
+}
+
+impl<'a> RequestScope<'a> {
+    /// Creates a new, empty scope.
+    ///
+    /// ## Parameters
+    /// - `ds`: A reference to the [`ds::DatastoreShell`] used for the lookups.
+    pub fn new(ds: &'a ds::DatastoreShell) -> Self {
+        Self {
+            ds,
+            entities: Mutex::new(HashMap::new()),
+        }
+    }
+
+    /// Returns the shell the scope uses for the lookups.
+    pub fn shell(&self) -> &'a ds::DatastoreShell {
+        self.ds
+    }
+
+    /// Looks up a single entity, or returns it from the scope if it was looked up before.
+    ///
+    /// ## Parameters
+    /// - `key`: The complete [`ds::Key`] of the entity.
+    ///
+    /// ## Returns
+    /// A [`Result`] containing the entity or `None` if it doesn't exist, or an
+    /// [`EntailError`] if the lookup failed. Failed lookups are not remembered.
+    pub async fn get_single(&self, key: ds::Key) -> Result<Option<ds::Entity>, EntailError> {
+        if let Some(cached) = self.entities.lock().unwrap().get(&key) {
+            return Ok(cached.clone());
+        }
+        let entity = self.ds.get_single(key.clone()).await?;
+        self.entities.lock().unwrap().insert(key, entity.clone());
+        Ok(entity)
+    }
+
+    /// Looks up a batch of entities, only fetching the keys that were not looked up
+    /// through this scope before.
+    ///
+    /// ## Parameters
+    /// - `keys`: A collection of complete [`ds::Key`]s or references to them.
+    ///
+    /// ## Returns
+    /// A [`Result`] containing the found entities (in no particular order, missing entities
+    /// are omitted), or an [`EntailError`] if the lookup failed.
+    pub async fn get_all<I>(&self, keys: I) -> Result<Vec<ds::Entity>, EntailError>
+    where
+        I: IntoIterator,
+        I::Item: Borrow<ds::Key>,
+    {
+        let keys: Vec<ds::Key> = keys.into_iter().map(|key| key.borrow().clone()).collect();
+        let missing: Vec<&ds::Key> = {
+            let entities = self.entities.lock().unwrap();
+            keys.iter()
+                .filter(|key| !entities.contains_key(key))
+                .collect()
+        };
+        if !missing.is_empty() {
+            let mut found: HashMap<ds::Key, ds::Entity> = self
+                .ds
+                .get_all(missing.iter().copied())
+                .await?
+                .into_iter()
+                .map(|entity| (entity.key().clone(), entity))
+                .collect();
+            let mut entities = self.entities.lock().unwrap();
+            for key in missing {
+                entities.insert(key.clone(), found.remove(key));
+            }
+        }
+        let entities = self.entities.lock().unwrap();
+        let mut result = Vec::with_capacity(keys.len());
+        for key in &keys {
+            if let Some(Some(entity)) = entities.get(key) {
+                result.push(entity.clone());
+            }
+        }
+        Ok(result)
+    }
+
+    /// Fetches a single model like [`crate::EntityAdapter::fetch_single`], using the scope.
+    ///
+    /// ## Parameters
+    /// - `key`: The complete [`ds::Key`] of the entity.
+    ///
+    /// ## Returns
+    /// A [`Result`] containing the model, or an [`EntailError`] if the entity is not found,
+    /// the lookup failed or the entity cannot be mapped to the model.
+    pub async fn fetch_single<T: EntityModel>(&self, key: ds::Key) -> Result<T, EntailError> {
+        let key_string = key.to_string();
+        match self.get_single(key).await? {
+            Some(entity) => T::from_ds_entity(&entity),
+            None => Err(EntailError::simple(
+                EntailErrorKind::RequiredEntityNotFound,
+                format!("Required {} not found", key_string),
+            )),
+        }
+    }
+
+    /// Fetches a batch of models like [`crate::EntityAdapter::fetch_all`], using the scope.
+    ///
+    /// ## Parameters
+    /// - `keys`: A collection of complete [`ds::Key`]s or references to them.
+    ///
+    /// ## Returns
+    /// A [`Result`] containing the found models by their keys, or an [`EntailError`] if the
+    /// lookup failed or any found entity cannot be mapped to the model.
+    pub async fn fetch_all<T, I>(&self, keys: I) -> Result<HashMap<ds::Key, T>, EntailError>
+    where
+        T: EntityModel,
+        I: IntoIterator,
+        I::Item: Borrow<ds::Key>,
+    {
+        let entities = self.get_all(keys).await?;
+        let mut map = HashMap::with_capacity(entities.len());
+        for entity in entities {
+            let model = T::from_ds_entity(&entity)?;
+            map.insert(entity.just_key(), model);
+        }
+        Ok(map)
+    }
+
+    /// Stores an entity in the scope, e.g. one that has just been written, so that later
+    /// lookups of its key don't hit Datastore.
+    pub fn prime(&self, entity: ds::Entity) {
+        let key = entity.key().clone();
+        self.entities.lock().unwrap().insert(key, Some(entity));
+    }
+
+    /// Forgets the cached result for a key, so the next lookup fetches it again.
+    pub fn invalidate(&self, key: &ds::Key) {
+        self.entities.lock().unwrap().remove(key);
+    }
+
+    /// Returns the number of keys (found or not) remembered by the scope.
+    pub fn len(&self) -> usize {
+        self.entities.lock().unwrap().len()
+    }
+
+    /// Returns `true` if nothing has been looked up through the scope yet.
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn test_primed_lookups() {
+        let ds = crate::test_support::offline_shell().await;
+        let scope = RequestScope::new(&ds);
+        assert!(scope.is_empty());
+        let key = ds::Key::new("User").with_name("alice");
+        let mut entity = ds::Entity::new(key.clone());
+        entity.set_indexed("name", ds::Value::unicode_string("Alice"));
+        scope.prime(entity);
+
+        // served from the scope, no request is made
+        let found = scope.get_single(key.clone()).await.unwrap().unwrap();
+        assert_eq!(found.get_value("name"), Some(&"Alice".into()));
+        let all = scope.get_all([&key, &key]).await.unwrap();
+        assert_eq!(all.len(), 2);
+        assert_eq!(scope.len(), 1);
+
+        scope.invalidate(&key);
+        assert!(scope.is_empty());
+    }
+}
@@ -0,0 +1,262 @@
+use std::borrow::Borrow;
+use std::collections::{BTreeMap, BTreeSet};
+use std::sync::Arc;
+
+use crate::EntailError;
+use crate::ds;
+
+/// The read that was mirrored to the secondary database.
+#[derive(Clone, Debug)]
+pub enum ShadowRead {
+    /// A lookup by one or more keys.
+    Lookup(Vec<ds::Key>),
+    /// A query, the first page of the results is compared.
+    Query(Box<ds::Query>),
+}
+
+/// An entity that differs between the two databases. A missing entity is `None`.
+#[derive(Clone, Debug, PartialEq)]
+pub struct EntityDiff {
+    pub key: ds::Key,
+    pub primary: Option<ds::Entity>,
+    pub secondary: Option<ds::Entity>,
+}
+
+/// The report passed to the callback of a [`ShadowShell`] when the secondary database
+/// returned something different than the primary one.
+#[derive(Debug)]
+pub struct ShadowDivergence {
+    /// The read that diverged.
+    pub read: ShadowRead,
+    /// The number of distinct keys returned by either database.
+    pub compared: usize,
+    /// The number of keys whose entities differ (including the ones missing from one side).
+    pub mismatched: usize,
+    /// The first few differing entities, in key order. The number of samples is limited by
+    /// [`ShadowShell::with_sample_limit`].
+    pub samples: Vec<EntityDiff>,
+    /// The error of the secondary read, in which case nothing was compared.
+    pub secondary_error: Option<EntailError>,
+}
+
+type DivergenceCallback = Arc<dyn Fn(ShadowDivergence) + Send + Sync>;
+
+/// A wrapper around two shells that serves reads from the primary database and mirrors them
+/// to a secondary one, reporting any divergence through a callback.
+///
+/// This is meant for live migrations between databases (or projects): while the data is
+/// being copied and the writes are applied to both, the shadow reads show whether the
+/// secondary database is ready to take over. The secondary read is done in a background task
+/// after the primary one returned, so it doesn't add to the latency of the reads, and its
+/// failures are only reported, never returned.
+///
+/// Only reads are mirrored, writes have to be applied to both databases by the application.
+///
+/// [`ShadowShell::with_sample_limit`] consumes and returns `Self`, allowing for method chaining.
+#[derive(Clone)]
+pub struct ShadowShell {
+    primary: ds::DatastoreShell,
+    secondary: ds::DatastoreShell,
+    on_divergence: DivergenceCallback,
+    sample_limit: usize,
+}
+
+impl ShadowShell {
+    /// Creates a new wrapper.
+    ///
+    /// ## Parameters
+    /// - `primary`: The shell the results are returned from.
+    /// - `secondary`: The shell the reads are mirrored to.
+    /// - `on_divergence`: Called from a background task with the report of every read whose
+    ///   results differ, or whose secondary read failed.
+    pub fn new(
+        primary: ds::DatastoreShell,
+        secondary: ds::DatastoreShell,
+        on_divergence: impl Fn(ShadowDivergence) + Send + Sync + 'static,
+    ) -> Self {
+        Self {
+            primary,
+            secondary,
+            on_divergence: Arc::new(on_divergence),
+            sample_limit: 5,
+        }
+    }
+
+    /// Sets the maximum number of differing entities included in a report. Defaults to `5`.
+    pub fn with_sample_limit(mut self, sample_limit: usize) -> Self {
+        self.sample_limit = sample_limit;
+        self
+    }
+
+    /// Returns the shell the results are served from, e.g. for writes.
+    pub fn primary(&self) -> &ds::DatastoreShell {
+        &self.primary
+    }
+
+    /// Returns the shell the reads are mirrored to.
+    pub fn secondary(&self) -> &ds::DatastoreShell {
+        &self.secondary
+    }
+
+    /// Looks up a single entity in the primary database like
+    /// [`ds::DatastoreShell::get_single`], and mirrors the lookup to the secondary one.
+    pub async fn get_single(&self, key: ds::Key) -> Result<Option<ds::Entity>, EntailError> {
+        let entity = self.primary.get_single(key.clone()).await?;
+        let secondary = self.secondary.clone();
+        let lookup = key.clone();
+        self.spawn_comparison(
+            ShadowRead::Lookup(vec![key]),
+            entity.iter().cloned().collect(),
+            async move {
+                secondary
+                    .get_single(lookup)
+                    .await
+                    .map(|entity| entity.into_iter().collect())
+            },
+        );
+        Ok(entity)
+    }
+
+    /// Looks up a batch of entities in the primary database like
+    /// [`ds::DatastoreShell::get_all`], and mirrors the lookup to the secondary one.
+    pub async fn get_all<I>(&self, keys: I) -> Result<Vec<ds::Entity>, EntailError>
+    where
+        I: IntoIterator,
+        I::Item: Borrow<ds::Key>,
+    {
+        let keys: Vec<ds::Key> = keys.into_iter().map(|key| key.borrow().clone()).collect();
+        let entities = self.primary.get_all(&keys).await?;
+        let secondary = self.secondary.clone();
+        let lookup = keys.clone();
+        self.spawn_comparison(ShadowRead::Lookup(keys), entities.clone(), async move {
+            secondary.get_all(lookup).await
+        });
+        Ok(entities)
+    }
+
+    /// Runs a query against the primary database like [`ds::DatastoreShell::run_query`], and
+    /// mirrors it to the secondary one. Only the first page is compared, the cursors of the
+    /// two databases are not interchangeable, so a query with a start cursor is not mirrored.
+    pub async fn run_query(
+        &self,
+        query: ds::Query,
+    ) -> Result<ds::QueryResult<ds::Entity>, EntailError> {
+        let result = self.primary.run_query(query.clone()).await?;
+        if query.start_cursor.is_none() {
+            let secondary = self.secondary.clone();
+            let mirrored = query.clone();
+            self.spawn_comparison(
+                ShadowRead::Query(Box::new(query)),
+                result.items.clone(),
+                async move {
+                    secondary
+                        .run_query(mirrored)
+                        .await
+                        .map(|result| result.items)
+                },
+            );
+        }
+        Ok(result)
+    }
+
+    fn spawn_comparison<F>(&self, read: ShadowRead, primary: Vec<ds::Entity>, secondary: F)
+    where
+        F: Future<Output = Result<Vec<ds::Entity>, EntailError>> + Send + 'static,
+    {
+        let on_divergence = self.on_divergence.clone();
+        let sample_limit = self.sample_limit;
+        tokio::spawn(async move {
+            let divergence = match secondary.await {
+                Ok(secondary) => compare(read, &primary, &secondary, sample_limit),
+                Err(err) => Some(ShadowDivergence {
+                    read,
+                    compared: 0,
+                    mismatched: 0,
+                    samples: Vec::new(),
+                    secondary_error: Some(err),
+                }),
+            };
+            if let Some(divergence) = divergence {
+                on_divergence(divergence);
+            }
+        });
+    }
+}
+
+/// Compares the entities returned by the two databases by their keys, returning `None` if
+/// they are the same.
+fn compare(
+    read: ShadowRead,
+    primary: &[ds::Entity],
+    secondary: &[ds::Entity],
+    sample_limit: usize,
+) -> Option<ShadowDivergence> {
+    let primary: BTreeMap<&ds::Key, &ds::Entity> = primary.iter().map(|e| (e.key(), e)).collect();
+    let secondary: BTreeMap<&ds::Key, &ds::Entity> =
+        secondary.iter().map(|e| (e.key(), e)).collect();
+    let keys: BTreeSet<&ds::Key> = primary.keys().chain(secondary.keys()).copied().collect();
+    let mut mismatched = 0;
+    let mut samples = Vec::new();
+    for key in &keys {
+        let (primary, secondary) = (primary.get(key), secondary.get(key));
+        if primary == secondary {
+            continue;
+        }
+        mismatched += 1;
+        if samples.len() < sample_limit {
+            samples.push(EntityDiff {
+                key: (*key).clone(),
+                primary: primary.map(|e| (*e).clone()),
+                secondary: secondary.map(|e| (*e).clone()),
+            });
+        }
+    }
+    if mismatched == 0 {
+        return None;
+    }
+    Some(ShadowDivergence {
+        read,
+        compared: keys.len(),
+        mismatched,
+        samples,
+        secondary_error: None,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn entity(name: &str, value: i64) -> ds::Entity {
+        let mut e = ds::Entity::new(ds::Key::new("Item").with_name(name.to_string()));
+        e.set_indexed("value", ds::Value::integer(value));
+        e
+    }
+
+    #[test]
+    fn test_compare() {
+        let read = ShadowRead::Lookup(Vec::new());
+        let same = [entity("a", 1), entity("b", 2)];
+        assert!(compare(read.clone(), &same, &same, 5).is_none());
+
+        let primary = [entity("a", 1), entity("b", 2), entity("c", 3)];
+        let secondary = [entity("d", 4), entity("b", 20), entity("a", 1)];
+        let divergence = compare(read.clone(), &primary, &secondary, 5).unwrap();
+        assert_eq!(divergence.compared, 4);
+        assert_eq!(divergence.mismatched, 3);
+        assert_eq!(
+            divergence.samples[0],
+            EntityDiff {
+                key: ds::Key::new("Item").with_name("b"),
+                primary: Some(entity("b", 2)),
+                secondary: Some(entity("b", 20)),
+            }
+        );
+        assert_eq!(divergence.samples[1].secondary, None);
+        assert_eq!(divergence.samples[2].primary, None);
+
+        let divergence = compare(read, &primary, &secondary, 1).unwrap();
+        assert_eq!(divergence.mismatched, 3);
+        assert_eq!(divergence.samples.len(), 1);
+    }
+}
@@ -0,0 +1,20 @@
+use std::sync::Once;
+
+use crate::ds;
+
+static RING_INIT: Once = Once::new();
+
+pub fn init_ring() {
+    RING_INIT.call_once(|| {
+        rustls::crypto::ring::default_provider()
+            .install_default()
+            .unwrap();
+    });
+}
+
+/// Creates a shell for the tests that don't reach the Datastore, e.g. the ones checking the
+/// requests before they are sent.
+pub async fn offline_shell() -> ds::DatastoreShell {
+    init_ring();
+    ds::DatastoreShell::new("shop", false, None).await.unwrap()
+}
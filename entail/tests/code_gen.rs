@@ -1,4 +1,5 @@
-use entail::{Entail, EntailErrorKind, EntityModel, ds};
+use entail::{Entail, EntailError, EntailErrorKind, EntityModel, FromValue, IntoValue, ds};
+use std::borrow::Cow;
 use std::collections::HashSet;
 
 #[derive(Entail, Debug, Default)]
@@ -155,6 +156,47 @@ fn code_gen() {
     println!("{:?}", new_model);
 }
 
+#[test]
+fn code_gen_property_constants() {
+    assert_eq!(Model::NAME, "__key__");
+    assert_eq!(Model::SOME_FIELD, "someField");
+    assert_eq!(Model::KEY, "key");
+    assert_eq!(MinimalModel::TEXT_FIELD, "textField");
+    assert_eq!(KebabModel::EXPLICIT_NAME, "as_is");
+    let filter = ds::FilterOperator::Equal.of(Model::SOME_BOOL, true);
+    assert!(matches!(filter, ds::Filter::Property(name, _, _) if name == "someBool"));
+}
+
+#[test]
+fn code_gen_typed_query() {
+    assert_eq!(ModelFields::NAME.name(), "__key__");
+    assert_eq!(ModelFields::KEY.name(), "key");
+    let query = Model::query()
+        .filter(ModelFields::SOME_BOOL, ds::FilterOperator::Equal, true)
+        .unwrap()
+        .filter(ModelFields::LOOKUP, ds::FilterOperator::Equal, "tag")
+        .unwrap()
+        .filter(ModelFields::KEY, ds::FilterOperator::GreaterThan, 3)
+        .unwrap()
+        .order_by_asc(ModelFields::KEY)
+        .order_by_desc(ModelFields::SOME_FIELD)
+        .limit(10)
+        .build();
+    assert_eq!(query.kind, "Model");
+    assert_eq!(query.limit, 10);
+    assert_eq!(
+        query.shape(),
+        "Model|AND(key GREATER_THAN,lookup EQUAL,someBool EQUAL)|key ASCENDING,someField DESCENDING||"
+    );
+    let filter = query.filter.unwrap();
+    let ds::Filter::Composite(_, filters) = filter else {
+        panic!("Expected a composite filter, got {:?}", filter);
+    };
+    assert!(
+        matches!(&filters[2], ds::Filter::Property(_, _, value) if *value == ds::Value::integer(3))
+    );
+}
+
 #[test]
 fn code_gen_minimal_model() {
     let min_mod = MinimalModel {
@@ -171,6 +213,164 @@ fn code_gen_minimal_model() {
     println!("Expected error message: {}", result.message);
 }
 
+#[derive(Entail, Debug, Default)]
+#[entail(rename_all = "kebab-case")]
+struct KebabModel {
+    #[entail]
+    key: i64,
+    #[entail]
+    display_name: String,
+    #[entail(name = "as_is")]
+    explicit_name: i64,
+}
+
+#[derive(Entail, Debug, Default)]
+#[entail(rename_all = "SCREAMING_SNAKE_CASE")]
+struct ScreamingModel {
+    #[entail]
+    key: i64,
+    #[entail]
+    display_name: String,
+}
+
+#[test]
+fn code_gen_rename_all() {
+    let model = KebabModel {
+        key: 1,
+        display_name: "name".into(),
+        explicit_name: 2,
+    };
+    let e = model.to_ds_entity().unwrap();
+    assert!(e.has("display-name"));
+    assert!(e.has("as_is"));
+
+    let model = ScreamingModel {
+        key: 1,
+        display_name: "name".into(),
+    };
+    let e = model.to_ds_entity().unwrap();
+    assert!(e.has("DISPLAY_NAME"));
+    let model = ScreamingModel::from_ds_entity(&e).unwrap();
+    assert_eq!(model.display_name, "name");
+}
+
+#[test]
+fn code_gen_entail_eq() {
+    let model = KebabModel {
+        key: 1,
+        display_name: "name".into(),
+        explicit_name: 2,
+    };
+    let same = KebabModel {
+        key: 1,
+        display_name: "name".into(),
+        explicit_name: 2,
+    };
+    assert!(model.entail_eq(&same));
+    let different = KebabModel {
+        explicit_name: 3,
+        ..same
+    };
+    assert!(!model.entail_eq(&different));
+    let other_key = KebabModel {
+        key: 2,
+        display_name: "name".into(),
+        explicit_name: 2,
+    };
+    assert!(!model.entail_eq(&other_key));
+}
+
+#[derive(Entail, Debug)]
+struct Comment {
+    #[entail]
+    key: i64,
+    #[entail(parent)]
+    post: ds::Key,
+    #[entail]
+    text: String,
+}
+
+#[derive(Entail, Debug, Default)]
+struct Attachment {
+    #[entail]
+    key: String,
+    #[entail(parent)]
+    owner: Option<ds::Key>,
+}
+
+#[test]
+fn code_gen_parent() {
+    let post = ds::Key::new("Post").with_name("hello");
+    let comment = Comment {
+        key: 3,
+        post: post.clone(),
+        text: "first".into(),
+    };
+    let e = comment.to_ds_entity().unwrap();
+    assert_eq!(
+        e.key(),
+        &ds::Key::new("Comment").with_id(3).with_parent(post.clone())
+    );
+    assert!(!e.has("post"));
+    let comment = Comment::from_ds_entity(&e).unwrap();
+    assert_eq!(comment.post, post);
+    assert_eq!(comment.key, 3);
+
+    let orphan = ds::Entity::new(ds::Key::new("Comment").with_id(4));
+    let err = Comment::from_ds_entity(&orphan).expect_err("Missing parent");
+    assert_eq!(err.kind, EntailErrorKind::PropertyMappingError);
+
+    let attachment = Attachment {
+        key: "a".into(),
+        owner: None,
+    };
+    let e = attachment.to_ds_entity().unwrap();
+    assert_eq!(e.key().parent(), None);
+    assert_eq!(Attachment::from_ds_entity(&e).unwrap().owner, None);
+    let key = Attachment::adapter().create_named_key("b");
+    let e = ds::Entity::new(key.with_parent(post.clone()));
+    assert_eq!(Attachment::from_ds_entity(&e).unwrap().owner, Some(post));
+}
+
+#[derive(Entail, Debug, Default)]
+struct Versioned {
+    #[entail]
+    key: String,
+    #[entail]
+    value: i64,
+    #[entail(version)]
+    version: i64,
+    #[entail(checksum)]
+    checksum: Option<i64>,
+}
+
+#[test]
+fn code_gen_version() {
+    assert_eq!(Versioned::VERSION_PROPERTY, Some("version"));
+    let model = Versioned {
+        key: "v".into(),
+        value: 1,
+        version: 4,
+        checksum: None,
+    };
+    let e = model.to_ds_entity().unwrap();
+    assert_eq!(e.get_value("version"), Some(&ds::Value::integer(5)));
+    assert!(!e.get("version").unwrap().is_indexed());
+    let read = Versioned::from_ds_entity(&e).unwrap();
+    assert_eq!(read.version, 5);
+    // the version doesn't affect the checksum
+    assert!(read.checksum.is_some());
+    assert_eq!(
+        read.to_ds_entity().unwrap().get_value("checksum"),
+        e.get_value("checksum")
+    );
+
+    // entities written before the version was introduced are at version 0
+    let mut e = ds::Entity::new(Versioned::adapter().create_named_key("old"));
+    e.set_indexed("value", ds::Value::integer(1));
+    assert_eq!(Versioned::from_ds_entity(&e).unwrap().version, 0);
+}
+
 #[test]
 fn code_gen_auto_id() {
     let auto_id = AutoId::default();
@@ -196,3 +396,1718 @@ fn code_gen_auto_id() {
     ManualId::from_ds_entity(&e)
         .expect_err("Should have returned an error since the key is incomplete");
 }
+
+#[derive(Entail, Debug, Default)]
+struct KeyArrays {
+    #[entail]
+    key: i64,
+    #[entail]
+    refs: Vec<ds::Key>,
+    #[entail(unindexed)]
+    hidden_refs: Vec<entail::ds::Key>,
+}
+
+#[test]
+fn code_gen_key_arrays() {
+    let refs = vec![
+        ds::Key::new("Ref").with_id(1),
+        ds::Key::new("Ref").with_name("two"),
+    ];
+    let model = KeyArrays {
+        key: 7,
+        refs: refs.clone(),
+        hidden_refs: vec![ds::Key::new("Hidden").with_id(3)],
+    };
+    let mut e = model.to_ds_entity().unwrap();
+    assert!(e.is_indexed("refs"));
+    assert!(!e.is_indexed("hidden_refs"));
+    assert_eq!(
+        e.get_value("refs"),
+        Some(ds::Value::array(
+            refs.iter().cloned().map(ds::Value::key).collect()
+        ))
+        .as_ref()
+    );
+    let read_back = KeyArrays::from_ds_entity(&e).unwrap();
+    assert_eq!(read_back.refs, refs);
+    assert_eq!(read_back.hidden_refs, model.hidden_refs);
+
+    // a single key value is read as an array of one
+    e.set_indexed("refs", ds::Value::key(refs[0].clone()));
+    e.set_indexed("hidden_refs", ds::Value::null());
+    let read_back = KeyArrays::from_ds_entity(&e).unwrap();
+    assert_eq!(read_back.refs, vec![refs[0].clone()]);
+    assert!(read_back.hidden_refs.is_empty());
+
+    e.set_indexed("refs", ds::Value::array(vec![ds::Value::integer(1)]));
+    let err = KeyArrays::from_ds_entity(&e).expect_err("Integers are not keys");
+    assert_eq!(err.kind, EntailErrorKind::PropertyMappingError);
+}
+
+#[derive(Entail, Debug, Default)]
+#[entail(rename_all = "camelCase")]
+struct OptionalArrays {
+    #[entail]
+    key: String,
+    #[entail]
+    tags: Option<Vec<String>>,
+    #[entail(unindexed_nulls)]
+    scores: Option<Vec<i64>>,
+    #[entail]
+    payload: Option<Vec<u8>>,
+}
+
+#[test]
+fn code_gen_optional_arrays() {
+    let model = OptionalArrays {
+        key: "opt".into(),
+        tags: Some(vec!["a".into(), "b".into()]),
+        scores: None,
+        payload: Some(vec![1, 2]),
+    };
+    let mut e = model.to_ds_entity().unwrap();
+    assert_eq!(
+        e.get_value("tags"),
+        Some(ds::Value::array(vec![
+            ds::Value::unicode_string("a"),
+            ds::Value::unicode_string("b"),
+        ]))
+        .as_ref()
+    );
+    assert!(e.is_indexed("tags"));
+    assert_eq!(e.get_value("scores"), Some(&ds::Value::Null));
+    assert!(!e.is_indexed("scores"));
+    assert_eq!(e.get_value("payload"), Some(&ds::Value::blob(vec![1, 2])));
+
+    let read_back = OptionalArrays::from_ds_entity(&e).unwrap();
+    assert_eq!(read_back.tags, model.tags);
+    assert_eq!(read_back.scores, None);
+    assert_eq!(read_back.payload, model.payload);
+
+    // absent properties and single values
+    e.remove("tags");
+    e.set_indexed("scores", ds::Value::integer(5));
+    let read_back = OptionalArrays::from_ds_entity(&e).unwrap();
+    assert_eq!(read_back.tags, None);
+    assert_eq!(read_back.scores, Some(vec![5]));
+
+    // an empty array is stored as null, so it is read back as `None`
+    let e = OptionalArrays {
+        tags: Some(vec![]),
+        ..OptionalArrays::default()
+    }
+    .to_ds_entity()
+    .unwrap();
+    assert_eq!(e.get_value("tags"), Some(&ds::Value::Null));
+    assert_eq!(OptionalArrays::from_ds_entity(&e).unwrap().tags, None);
+}
+
+fn default_retries() -> i32 {
+    3
+}
+
+#[derive(Entail, Debug, Default)]
+struct WithDefaults {
+    #[entail]
+    key: String,
+    #[entail]
+    name: String,
+    #[entail(default)]
+    added_later: String,
+    #[entail(default = "default_retries")]
+    retries: i32,
+}
+
+#[test]
+fn code_gen_defaults() {
+    let mut e = ds::Entity::new(WithDefaults::adapter().create_named_key("old"));
+    e.set_indexed("name", ds::Value::unicode_string("legacy"));
+    let model = WithDefaults::from_ds_entity(&e).unwrap();
+    assert_eq!(model.name, "legacy");
+    assert_eq!(model.added_later, "");
+    assert_eq!(model.retries, 3);
+
+    e.set_indexed("added_later", ds::Value::unicode_string("present"));
+    e.set_indexed("retries", ds::Value::integer(7));
+    let model = WithDefaults::from_ds_entity(&e).unwrap();
+    assert_eq!(model.added_later, "present");
+    assert_eq!(model.retries, 7);
+
+    // a present property of the wrong type is still an error
+    e.set_indexed("retries", ds::Value::null());
+    WithDefaults::from_ds_entity(&e).expect_err("Null is not an integer");
+
+    // fields without a default are still required
+    e.remove("name");
+    WithDefaults::from_ds_entity(&e).expect_err("The name is required");
+}
+
+#[derive(Debug, Default, PartialEq)]
+struct Cents(i64);
+
+impl IntoValue for Cents {
+    fn to_value(&self) -> ds::Value {
+        self.0.to_value()
+    }
+}
+
+impl FromValue for Cents {
+    fn from_value(value: &ds::Value) -> Result<Self, EntailError> {
+        i64::from_value(value).map(Cents)
+    }
+}
+
+#[derive(Entail, Debug, Default)]
+struct CustomValues {
+    #[entail]
+    key: String,
+    #[entail]
+    price: Cents,
+    #[entail]
+    discount: Option<Cents>,
+    #[entail]
+    history: Vec<Cents>,
+}
+
+#[test]
+fn code_gen_custom_values() {
+    let model = CustomValues {
+        key: "item".into(),
+        price: Cents(1250),
+        discount: None,
+        history: vec![Cents(1000), Cents(1100)],
+    };
+    let e = model.to_ds_entity().unwrap();
+    assert_eq!(e.get_value("price"), Some(&ds::Value::integer(1250)));
+    assert_eq!(e.get_value("discount"), Some(&ds::Value::Null));
+    let read_back = CustomValues::from_ds_entity(&e).unwrap();
+    assert_eq!(read_back.price, model.price);
+    assert_eq!(read_back.discount, None);
+    assert_eq!(read_back.history, model.history);
+
+    let mut e = e;
+    e.set_indexed("price", ds::Value::unicode_string("free"));
+    let err = CustomValues::from_ds_entity(&e).unwrap_err();
+    assert_eq!(err.kind, EntailErrorKind::PropertyMappingError);
+    assert_eq!(
+        err.message,
+        "Expected a value of Integer in CustomValues.price"
+    );
+}
+
+#[derive(Entail, Debug, Default)]
+struct Imported {
+    #[entail]
+    key: String,
+    #[entail(coerce)]
+    quantity: i64,
+    #[entail(coerce)]
+    active: Option<bool>,
+    #[entail(coerce)]
+    sku: String,
+    #[entail]
+    strict: i64,
+}
+
+#[test]
+fn code_gen_coerce() {
+    let mut e = ds::Entity::new(Imported::adapter().create_named_key("row-1"));
+    e.set_indexed("quantity", ds::Value::unicode_string("42"));
+    e.set_indexed("active", ds::Value::unicode_string("true"));
+    e.set_indexed("sku", ds::Value::integer(1234));
+    e.set_indexed("strict", ds::Value::integer(1));
+    let model = Imported::from_ds_entity(&e).unwrap();
+    assert_eq!(model.quantity, 42);
+    assert_eq!(model.active, Some(true));
+    assert_eq!(model.sku, "1234");
+
+    // writing uses the types of the fields
+    let written = model.to_ds_entity().unwrap();
+    assert_eq!(written.get_value("quantity"), Some(&ds::Value::integer(42)));
+    assert_eq!(
+        written.get_value("sku"),
+        Some(&ds::Value::unicode_string("1234"))
+    );
+
+    e.set_indexed("quantity", ds::Value::unicode_string("many"));
+    Imported::from_ds_entity(&e).expect_err("Not a number");
+
+    e.set_indexed("quantity", ds::Value::integer(1));
+    e.set_indexed("strict", ds::Value::unicode_string("1"));
+    Imported::from_ds_entity(&e).expect_err("Coercion is opt-in");
+}
+
+#[test]
+fn code_gen_mapping_context() {
+    let mut e = ds::Entity::new(Imported::adapter().create_named_key("row-1"));
+    let err = Imported::from_ds_entity(&e).expect_err("Missing property");
+    let mapping = err.mapping.expect("Expected a mapping context");
+    assert_eq!(mapping.property, "quantity");
+    assert_eq!(mapping.expected_type, "i64");
+    assert_eq!(mapping.actual, None);
+
+    e.set_indexed("quantity", ds::Value::integer(1));
+    e.set_indexed("active", ds::Value::array(vec![]));
+    let err = Imported::from_ds_entity(&e).expect_err("Unexpected array");
+    assert_eq!(
+        err.mapping.as_deref(),
+        Some(&entail::MappingContext {
+            kind: "Imported".into(),
+            property: "active".into(),
+            expected_type: "Option<bool>".into(),
+            actual: Some(ds::ValueType::Array),
+        })
+    );
+    assert!(err.message.ends_with(" in Imported.active"));
+
+    let e = ds::Entity::new(Imported::adapter().create_id_key(7));
+    let err = Imported::from_ds_entity(&e).expect_err("Missing key name");
+    assert_eq!(
+        err.mapping.expect("Expected a mapping context").property,
+        "__key__"
+    );
+}
+
+#[derive(Entail, Debug, Default)]
+struct Checksummed {
+    #[entail]
+    key: String,
+    #[entail]
+    name: String,
+    #[entail(unindexed)]
+    count: i64,
+    #[entail(checksum, name = "_checksum")]
+    checksum: Option<i64>,
+}
+
+#[test]
+fn code_gen_checksum() {
+    assert_eq!(Checksummed::CHECKSUM_PROPERTY, Some("_checksum"));
+    let model = Checksummed {
+        key: "item".into(),
+        name: "name".into(),
+        count: 3,
+        checksum: None,
+    };
+    let mut e = model.to_ds_entity().unwrap();
+    let checksum = e.checksum("_checksum");
+    assert_eq!(
+        e.get_value("_checksum"),
+        Some(&ds::Value::integer(checksum))
+    );
+    assert!(!e.is_indexed("_checksum"));
+    let read_back = Checksummed::from_ds_entity(&e).unwrap();
+    assert_eq!(read_back.checksum, Some(checksum));
+    // the field value is ignored when writing, the checksum is always recomputed
+    assert_eq!(
+        read_back.to_ds_entity().unwrap().checksum("_checksum"),
+        checksum
+    );
+
+    let a = Checksummed::adapter();
+    assert_eq!(a.verify_checksum(&e).unwrap(), Some(true));
+    // unmodeled properties are not covered
+    e.set_indexed("unmodeled", ds::Value::boolean(true));
+    assert_eq!(a.verify_checksum(&e).unwrap(), Some(true));
+    e.set_indexed("count", ds::Value::integer(4));
+    assert_eq!(a.verify_checksum(&e).unwrap(), Some(false));
+    e.remove("_checksum");
+    assert_eq!(a.verify_checksum(&e).unwrap(), None);
+    assert_eq!(Model::adapter().verify_checksum(&e).unwrap(), None);
+}
+
+#[derive(Entail, Debug, Default, PartialEq)]
+#[entail(name = "Shape")]
+struct Circle {
+    #[entail]
+    key: String,
+    #[entail]
+    radius: f64,
+}
+
+#[derive(Entail, Debug, Default, PartialEq)]
+#[entail(name = "Shape")]
+struct Rectangle {
+    #[entail]
+    key: String,
+    #[entail]
+    width: f64,
+    #[entail]
+    height: f64,
+}
+
+#[derive(Entail, Debug, PartialEq)]
+enum Shape {
+    Circle(Circle),
+    #[entail(name = "rect")]
+    Rectangle(Rectangle),
+}
+
+#[derive(Entail, Debug, PartialEq)]
+#[entail(name = "Shape", discriminator = "shapeType")]
+enum RoundShape {
+    Circle(Circle),
+}
+
+#[test]
+fn code_gen_enum() {
+    let circle = Shape::Circle(Circle {
+        key: "c".into(),
+        radius: 1.5,
+    });
+    let e = circle.to_ds_entity().unwrap();
+    assert_eq!(e.kind(), "Shape");
+    assert_eq!(e.key().name(), Some("c"));
+    assert_eq!(
+        e.get_value("__type"),
+        Some(&ds::Value::unicode_string("Circle"))
+    );
+    assert!(e.is_indexed("__type"));
+    assert_eq!(Shape::from_ds_entity(&e).unwrap(), circle);
+
+    let rect = Shape::Rectangle(Rectangle {
+        key: "r".into(),
+        width: 2.0,
+        height: 3.0,
+    });
+    let e = rect.to_ds_entity().unwrap();
+    assert_eq!(
+        e.get_value("__type"),
+        Some(&ds::Value::unicode_string("rect"))
+    );
+    assert_eq!(Shape::from_ds_entity(&e).unwrap(), rect);
+    assert_eq!(Shape::adapter().kind(), "Shape");
+
+    let round = RoundShape::Circle(Circle {
+        key: "c".into(),
+        radius: 1.5,
+    });
+    let e = round.to_ds_entity().unwrap();
+    assert_eq!(
+        e.get_value("shapeType"),
+        Some(&ds::Value::unicode_string("Circle"))
+    );
+    assert_eq!(RoundShape::from_ds_entity(&e).unwrap(), round);
+
+    let mut e = ds::Entity::new(ds::Key::new("Shape").with_name("t"));
+    let err = Shape::from_ds_entity(&e).unwrap_err();
+    assert_eq!(err.kind, EntailErrorKind::PropertyMappingError);
+    e.set_indexed("__type", ds::Value::unicode_string("Triangle"));
+    let err = Shape::from_ds_entity(&e).unwrap_err();
+    assert_eq!(
+        err.message,
+        "Unknown Shape variant Some(\"Triangle\") in Shape.__type"
+    );
+}
+
+#[derive(serde::Serialize, serde::Deserialize, Debug, Default, PartialEq)]
+struct Settings {
+    theme: String,
+    limits: Vec<u32>,
+}
+
+#[derive(Entail, Debug, Default)]
+struct Configured {
+    #[entail]
+    key: String,
+    #[entail(json)]
+    settings: Settings,
+    #[entail(json)]
+    overrides: Option<std::collections::BTreeMap<String, i64>>,
+}
+
+#[test]
+fn code_gen_json() {
+    let model = Configured {
+        key: "app".into(),
+        settings: Settings {
+            theme: "dark".into(),
+            limits: vec![10, 20],
+        },
+        overrides: None,
+    };
+    let e = model.to_ds_entity().unwrap();
+    assert_eq!(
+        e.get_value("settings"),
+        Some(&ds::Value::unicode_string(
+            r#"{"theme":"dark","limits":[10,20]}"#
+        ))
+    );
+    assert!(!e.is_indexed("settings"));
+    assert_eq!(
+        e.get_value("overrides"),
+        Some(&ds::Value::unicode_string("null"))
+    );
+    let read = Configured::from_ds_entity(&e).unwrap();
+    assert_eq!(read.settings, model.settings);
+    assert_eq!(read.overrides, None);
+
+    let mut e = ds::Entity::new(Configured::adapter().create_named_key("app"));
+    e.set_unindexed(
+        "settings",
+        ds::Value::blob(br#"{"theme":"light","limits":[]}"#.to_vec()),
+    );
+    let read = Configured::from_ds_entity(&e).unwrap();
+    assert_eq!(read.settings.theme, "light");
+    assert_eq!(read.overrides, None);
+
+    e.set_unindexed("settings", ds::Value::unicode_string("{"));
+    let err = Configured::from_ds_entity(&e).unwrap_err();
+    assert_eq!(err.kind, EntailErrorKind::PropertyMappingError);
+    assert_eq!(err.mapping.unwrap().property, "settings");
+}
+
+#[derive(Entail, Debug)]
+struct NonZeroModel {
+    #[entail]
+    key: std::num::NonZeroI64,
+    #[entail]
+    count: std::num::NonZeroU32,
+    #[entail]
+    previous: Option<std::num::NonZeroI64>,
+}
+
+#[derive(Entail, Debug, Default)]
+#[entail(name = "NonZeroModel")]
+struct OptionalNonZeroKey {
+    #[entail]
+    key: Option<std::num::NonZeroI64>,
+}
+
+#[test]
+fn code_gen_non_zero() {
+    let model = NonZeroModel {
+        key: std::num::NonZeroI64::new(12).unwrap(),
+        count: std::num::NonZeroU32::new(3).unwrap(),
+        previous: None,
+    };
+    let mut e = model.to_ds_entity().unwrap();
+    assert_eq!(e.key().id(), Some(12));
+    assert_eq!(e.get_value("count"), Some(&ds::Value::integer(3)));
+    let read = NonZeroModel::from_ds_entity(&e).unwrap();
+    assert_eq!(read.key, model.key);
+    assert_eq!(read.count, model.count);
+    assert_eq!(read.previous, None);
+
+    e.set_indexed("count", ds::Value::integer(0));
+    let err = NonZeroModel::from_ds_entity(&e).unwrap_err();
+    assert_eq!(err.kind, EntailErrorKind::PropertyMappingError);
+    assert_eq!(err.mapping.unwrap().property, "count");
+
+    let named = ds::Entity::new(NonZeroModel::adapter().create_named_key("zero"));
+    let err = NonZeroModel::from_ds_entity(&named).unwrap_err();
+    assert_eq!(err.mapping.unwrap().property, "__key__");
+    assert_eq!(
+        OptionalNonZeroKey::from_ds_entity(&named).unwrap().key,
+        None
+    );
+    let incomplete = OptionalNonZeroKey::default().to_ds_entity().unwrap();
+    assert!(!incomplete.key().is_complete());
+}
+
+#[derive(Entail, Debug, Default)]
+struct SmallInts {
+    #[entail]
+    key: i64,
+    #[entail]
+    tiny: i8,
+    #[entail]
+    short: i16,
+    #[entail]
+    byte: u8,
+    #[entail]
+    maybe_byte: Option<u8>,
+    #[entail]
+    ports: Vec<u16>,
+    #[entail]
+    big: u64,
+    #[entail(unindexed)]
+    bytes: Vec<u8>,
+}
+
+#[test]
+fn code_gen_small_integers() {
+    let model = SmallInts {
+        key: 1,
+        tiny: -8,
+        short: 300,
+        byte: 255,
+        maybe_byte: Some(7),
+        ports: vec![80, 443],
+        big: i64::MAX as u64,
+        bytes: vec![1, 2],
+    };
+    let mut e = model.to_ds_entity().unwrap();
+    assert_eq!(e.get_value("byte"), Some(&ds::Value::integer(255)));
+    assert_eq!(e.get_value("bytes"), Some(&ds::Value::blob(vec![1u8, 2])));
+    let read = SmallInts::from_ds_entity(&e).unwrap();
+    assert_eq!(
+        (read.tiny, read.short, read.byte, read.maybe_byte),
+        (-8, 300, 255, Some(7))
+    );
+    assert_eq!(read.ports, vec![80, 443]);
+    assert_eq!(read.big, i64::MAX as u64);
+
+    e.set_indexed("byte", ds::Value::integer(256));
+    let err = SmallInts::from_ds_entity(&e).unwrap_err();
+    assert_eq!(err.kind, EntailErrorKind::PropertyMappingError);
+    assert_eq!(err.mapping.unwrap().property, "byte");
+
+    let too_big = SmallInts {
+        big: u64::MAX,
+        ..Default::default()
+    };
+    let err = too_big.to_ds_entity().unwrap_err();
+    assert_eq!(err.kind, EntailErrorKind::PropertyMappingError);
+
+    let query = SmallInts::query()
+        .filter(SmallIntsFields::BYTE, ds::FilterOperator::Equal, 3u8)
+        .unwrap()
+        .build();
+    assert!(matches!(
+        query.filter,
+        Some(ds::Filter::Property(_, _, value)) if value == ds::Value::integer(3)
+    ));
+    // a u64 filter value out of the range of Datastore integers is an error, not a panic
+    let err = SmallInts::query()
+        .filter(SmallIntsFields::BIG, ds::FilterOperator::Equal, u64::MAX)
+        .unwrap_err();
+    assert_eq!(err.kind, EntailErrorKind::PropertyMappingError);
+}
+
+#[derive(Entail, Debug, Default)]
+struct Timer {
+    #[entail]
+    key: i64,
+    #[entail]
+    timeout: std::time::Duration,
+}
+
+#[test]
+fn code_gen_duration() {
+    let timer = Timer {
+        key: 1,
+        timeout: std::time::Duration::from_millis(1500),
+    };
+    let e = timer.to_ds_entity().unwrap();
+    assert_eq!(e.get_value("timeout"), Some(&ds::Value::integer(1_500_000)));
+    assert_eq!(Timer::from_ds_entity(&e).unwrap().timeout, timer.timeout);
+
+    // a duration overflowing the microseconds is an error, not a panic
+    let too_long = Timer {
+        timeout: std::time::Duration::MAX,
+        ..Default::default()
+    };
+    let err = too_long.to_ds_entity().unwrap_err();
+    assert_eq!(err.kind, EntailErrorKind::PropertyMappingError);
+    let err = Timer::query()
+        .filter(
+            TimerFields::TIMEOUT,
+            ds::FilterOperator::LessThan,
+            std::time::Duration::MAX,
+        )
+        .unwrap_err();
+    assert_eq!(err.kind, EntailErrorKind::PropertyMappingError);
+}
+
+#[cfg(feature = "uuid")]
+mod uuid_models {
+    use super::*;
+
+    #[derive(Entail, Debug, Default)]
+    pub struct Account {
+        #[entail]
+        pub key: uuid::Uuid,
+        #[entail]
+        pub owner: Option<uuid::Uuid>,
+        #[entail]
+        pub members: Vec<uuid::Uuid>,
+    }
+
+    #[derive(Entail, Debug, Default)]
+    #[entail(name = "Account")]
+    pub struct NewAccount {
+        #[entail]
+        pub key: Option<uuid::Uuid>,
+    }
+
+    #[test]
+    fn code_gen_uuid() {
+        let id = uuid::Uuid::from_u128(0x67e55044_10b1_426f_9247_bb680e5fe0c8);
+        let member = uuid::Uuid::from_u128(7);
+        let model = Account {
+            key: id,
+            owner: None,
+            members: vec![member],
+        };
+        let mut e = model.to_ds_entity().unwrap();
+        assert_eq!(e.key().name(), Some("67e55044-10b1-426f-9247-bb680e5fe0c8"));
+        assert_eq!(
+            e.get_value("members"),
+            Some(&ds::Value::array(vec![ds::Value::unicode_string(
+                "00000000-0000-0000-0000-000000000007"
+            )]))
+        );
+        let read = Account::from_ds_entity(&e).unwrap();
+        assert_eq!(read.key, id);
+        assert_eq!(read.owner, None);
+        assert_eq!(read.members, vec![member]);
+
+        e.set_indexed("owner", ds::Value::blob(member.as_bytes().to_vec()));
+        assert_eq!(Account::from_ds_entity(&e).unwrap().owner, Some(member));
+
+        let named = ds::Entity::new(Account::adapter().create_named_key("nope"));
+        let err = Account::from_ds_entity(&named).unwrap_err();
+        assert_eq!(err.mapping.unwrap().property, "__key__");
+        assert!(NewAccount::from_ds_entity(&named).is_err());
+        let incomplete = NewAccount::default().to_ds_entity().unwrap();
+        assert!(!incomplete.key().is_complete());
+        assert_eq!(NewAccount::from_ds_entity(&incomplete).unwrap().key, None);
+    }
+}
+
+#[test]
+fn code_gen_registry() {
+    entail::registry::register::<MinimalModel>();
+    entail::registry::register::<KebabModel>();
+    assert!(entail::registry::registered_kinds().contains(&"KebabModel"));
+    assert!(entail::registry::adapter_for("Unregistered").is_none());
+
+    let adapter = entail::registry::adapter_for("MM").unwrap();
+    assert_eq!(adapter.kind(), "MM");
+    let mut e = ds::Entity::new(ds::Key::new("MM").with_name("wibz"));
+    e.set_indexed("textField", ds::Value::unicode_string("foo"));
+    e.set_indexed("unmodeled", ds::Value::integer(1));
+    let normalized = adapter.serialize(&e).unwrap();
+    assert_eq!(normalized.get_value("textField"), e.get_value("textField"));
+    assert!(!normalized.has("unmodeled"));
+
+    let other = ds::Entity::new(ds::Key::new("KebabModel").with_id(1));
+    let err = adapter.serialize(&other).unwrap_err();
+    assert_eq!(err.kind, EntailErrorKind::EntityKindMismatch);
+
+    let model = entail::registry::deserialize(&e).unwrap();
+    let model = model.downcast::<MinimalModel>().unwrap();
+    assert_eq!(model.text_field, "foo");
+    let kebab = KebabModel {
+        key: 1,
+        ..Default::default()
+    };
+    let model = entail::registry::deserialize(&kebab.to_ds_entity().unwrap()).unwrap();
+    assert!(model.is::<KebabModel>());
+    let unregistered = ds::Entity::new(ds::Key::new("Unregistered").with_id(1));
+    let err = entail::registry::deserialize(&unregistered).unwrap_err();
+    assert_eq!(err.kind, EntailErrorKind::EntityKindMismatch);
+}
+
+#[derive(Entail, Debug, Clone, PartialEq)]
+#[entail(transparent)]
+struct Email(String);
+
+#[derive(Entail, Debug, Clone, Copy, PartialEq)]
+#[entail(transparent)]
+struct Score {
+    points: u64,
+}
+
+#[derive(Entail, Debug)]
+struct Wrapped {
+    #[entail]
+    key: String,
+    // large models box their big fields
+    #[allow(clippy::box_collection)]
+    #[entail(text)]
+    body: Box<String>,
+    #[entail]
+    title: std::sync::Arc<str>,
+    #[entail]
+    email: Email,
+    #[entail]
+    cc: Vec<Email>,
+    #[entail]
+    score: Option<Score>,
+}
+
+#[test]
+fn code_gen_wrappers() {
+    let model = Wrapped {
+        key: "w".into(),
+        body: Box::new("long".into()),
+        title: "Title".into(),
+        email: Email("a@example.com".into()),
+        cc: vec![Email("b@example.com".into())],
+        score: Some(Score { points: 7 }),
+    };
+    let mut e = model.to_ds_entity().unwrap();
+    assert_eq!(e.get_value("title"), Some(&"Title".into()));
+    assert_eq!(e.get_value("email"), Some(&"a@example.com".into()));
+    assert_eq!(e.get_value("score"), Some(&ds::Value::integer(7)));
+    let read = Wrapped::from_ds_entity(&e).unwrap();
+    assert_eq!(*read.body, "long");
+    assert_eq!(&*read.title, "Title");
+    assert_eq!(read.email, model.email);
+    assert_eq!(read.cc, model.cc);
+    assert_eq!(read.score, model.score);
+
+    e.set_indexed("score", ds::Value::integer(-1));
+    let err = Wrapped::from_ds_entity(&e).unwrap_err();
+    assert_eq!(err.mapping.unwrap().property, "score");
+}
+
+#[derive(Entail, Debug, Default)]
+struct Tagged {
+    #[entail]
+    key: String,
+    #[entail]
+    tags: std::collections::BTreeSet<String>,
+    #[entail(unindexed)]
+    ids: HashSet<i64>,
+    #[entail]
+    labels: Option<std::collections::BTreeSet<String>>,
+}
+
+#[test]
+fn code_gen_sets() {
+    let model = Tagged {
+        key: "t".into(),
+        tags: ["b".to_string(), "c".to_string(), "a".to_string()].into(),
+        ids: [3].into(),
+        labels: None,
+    };
+    let mut e = model.to_ds_entity().unwrap();
+    assert!(e.is_indexed("tags"));
+    assert!(!e.is_indexed("ids"));
+    assert_eq!(
+        e.get_value("tags"),
+        Some(&ds::Value::array(vec!["a".into(), "b".into(), "c".into()]))
+    );
+    assert_eq!(e.get_value("ids"), Some(&ds::Value::array(vec![3.into()])));
+    assert_eq!(e.get_value("labels"), Some(&ds::Value::Null));
+
+    e.set_indexed("ids", ds::Value::array(vec![1.into(), 2.into(), 1.into()]));
+    e.set_indexed("labels", "single".into());
+    let read = Tagged::from_ds_entity(&e).unwrap();
+    assert_eq!(read.tags, model.tags);
+    assert_eq!(read.ids, [1, 2].into());
+    assert_eq!(read.labels, Some(["single".to_string()].into()));
+    // filters on a set compare to its elements
+    let query = Tagged::query()
+        .filter(TaggedFields::TAGS, ds::FilterOperator::Equal, "a")
+        .unwrap()
+        .build();
+    assert!(query.filter.is_some());
+}
+
+#[derive(Entail, Debug, Default)]
+struct Article {
+    #[entail]
+    key: String,
+    #[entail(auto_unindex)]
+    title: String,
+    #[entail(auto_unindex)]
+    tags: Vec<String>,
+    #[entail]
+    summary: String,
+}
+
+#[derive(Entail, Debug, Default)]
+#[entail(auto_unindex)]
+struct Snippet {
+    #[entail]
+    key: String,
+    #[entail]
+    body: Option<String>,
+    #[entail(unindexed)]
+    note: String,
+}
+
+#[test]
+fn code_gen_auto_unindex() {
+    let long = "x".repeat(ds::MAX_INDEXED_BYTES + 1);
+    let article = Article {
+        key: "a".into(),
+        title: "short".into(),
+        tags: vec!["short".into(), long.clone()],
+        summary: long.clone(),
+    };
+    let e = article.to_ds_entity().unwrap();
+    assert!(e.is_indexed("title"));
+    assert!(!e.is_indexed("tags"));
+    // not opted in, the commit would fail
+    assert!(e.is_indexed("summary"));
+
+    let e = Article {
+        title: long.clone(),
+        ..Article::default()
+    }
+    .to_ds_entity()
+    .unwrap();
+    assert!(!e.is_indexed("title"));
+    assert!(e.is_indexed("tags"));
+
+    let snippet = Snippet {
+        key: "s".into(),
+        body: Some(long),
+        note: "short".into(),
+    };
+    let e = snippet.to_ds_entity().unwrap();
+    assert!(!e.is_indexed("body"));
+    assert!(!e.is_indexed("note"));
+    let e = Snippet::default().to_ds_entity().unwrap();
+    assert!(e.is_indexed("body"));
+}
+
+#[derive(Entail, Debug, Default)]
+struct Profile {
+    #[entail]
+    key: String,
+    #[entail]
+    name: String,
+    #[entail(unindexed)]
+    bio: Option<String>,
+    #[entail]
+    age: u8,
+    #[entail(checksum)]
+    checksum: Option<i64>,
+}
+
+#[test]
+fn code_gen_patch() {
+    use entail::{ModelPatch, ModeledUpdate};
+
+    let profile = Profile {
+        key: "p".into(),
+        name: "Alice".into(),
+        bio: Some("Hello".into()),
+        age: 30,
+        checksum: None,
+    };
+    let mut e = profile.to_ds_entity().unwrap();
+    e.set_indexed("unmodeled", ds::Value::boolean(true));
+    assert!(ProfilePatch::default().is_empty());
+
+    let patch = ProfilePatch {
+        name: Some("Bob".into()),
+        bio: Some(None),
+        ..Default::default()
+    };
+    assert!(!patch.is_empty());
+    let mut patched = e.clone();
+    patch.apply_to(&mut patched).unwrap();
+    assert_eq!(patched.get_value("name"), Some(&"Bob".into()));
+    assert_eq!(patched.get_value("bio"), Some(&ds::Value::Null));
+    assert!(!patched.is_indexed("bio"));
+    assert_eq!(patched.get_value("age"), Some(&ds::Value::integer(30)));
+    assert_eq!(
+        patched.get_value("unmodeled"),
+        Some(&ds::Value::boolean(true))
+    );
+    // the raw entity keeps the old checksum
+    let a = Profile::adapter();
+    assert_eq!(a.verify_checksum(&patched).unwrap(), Some(false));
+
+    let mut update = ModeledUpdate::<Profile>::new(e).unwrap();
+    update
+        .apply_patch(&ProfilePatch {
+            age: Some(31),
+            ..Default::default()
+        })
+        .unwrap();
+    assert_eq!(update.model.age, 31);
+    assert!(update.model.checksum.is_some());
+    let updated = update.update_into_entity().unwrap();
+    assert_eq!(updated.get_value("age"), Some(&ds::Value::integer(31)));
+    assert_eq!(
+        updated.get_value("unmodeled"),
+        Some(&ds::Value::boolean(true))
+    );
+    assert_eq!(a.verify_checksum(&updated).unwrap(), Some(true));
+}
+
+#[derive(Entail, Debug, Default)]
+#[entail(name = "Task", rename_all = "camelCase")]
+struct TaskModel {
+    #[entail]
+    key: i64,
+    #[entail]
+    title: String,
+    #[entail]
+    due_date: Option<i64>,
+    #[entail(unindexed)]
+    description: String,
+}
+
+#[derive(Entail, Debug, PartialEq)]
+#[entail(projection_of = "TaskModel")]
+struct TaskSummary {
+    #[entail]
+    key: i64,
+    #[entail]
+    due_date: Option<i64>,
+    #[entail(default)]
+    title: String,
+}
+
+#[test]
+fn code_gen_projection() {
+    use entail::ModelProjection;
+
+    assert_eq!(TaskSummary::PROPERTIES, &["dueDate", "title"]);
+    let query = TaskSummary::query();
+    assert_eq!(query.kind, "Task");
+    assert_eq!(query.projection, vec!["dueDate", "title"]);
+
+    // a projection query only returns the projected properties
+    let mut e = ds::Entity::new(ds::Key::new("Task").with_id(7));
+    e.set_indexed("dueDate", ds::Value::integer(100));
+    assert!(TaskModel::from_ds_entity(&e).is_err());
+    assert_eq!(
+        TaskSummary::from_projected(&e).unwrap(),
+        TaskSummary {
+            key: 7,
+            due_date: Some(100),
+            title: String::new(),
+        }
+    );
+
+    e.set_indexed("title", ds::Value::integer(1));
+    let err = TaskSummary::from_projected(&e).unwrap_err();
+    let mapping = err.mapping.unwrap();
+    assert_eq!(mapping.kind, "Task");
+    assert_eq!(mapping.property, "title");
+
+    let other = ds::Entity::new(ds::Key::new("Other").with_id(7));
+    assert_eq!(
+        TaskSummary::from_projected(&other).unwrap_err().kind,
+        EntailErrorKind::EntityKindMismatch
+    );
+}
+
+#[derive(Entail, Debug, Default)]
+#[entail(rename_all = "camelCase")]
+struct Renamed {
+    #[entail]
+    key: String,
+    #[entail(alias = "title", alias = "heading")]
+    display_name: String,
+    #[entail(default, alias = "cnt")]
+    count: i64,
+}
+
+#[test]
+fn code_gen_alias() {
+    let mut e = ds::Entity::new(ds::Key::new("Renamed").with_name("r"));
+    e.set_indexed("heading", ds::Value::unicode_string("Old"));
+    let r = Renamed::from_ds_entity(&e).unwrap();
+    assert_eq!(r.display_name, "Old");
+    assert_eq!(r.count, 0);
+
+    // the current name wins over the aliases, which are tried in order
+    e.set_indexed("title", ds::Value::unicode_string("Older"));
+    assert_eq!(Renamed::from_ds_entity(&e).unwrap().display_name, "Older");
+    e.set_indexed("displayName", ds::Value::unicode_string("New"));
+    e.set_indexed("cnt", ds::Value::integer(3));
+    let r = Renamed::from_ds_entity(&e).unwrap();
+    assert_eq!(r.display_name, "New");
+    assert_eq!(r.count, 3);
+
+    let written = r.to_ds_entity().unwrap();
+    assert_eq!(written.get_value("displayName"), Some(&"New".into()));
+    assert_eq!(written.get_value("count"), Some(&ds::Value::integer(3)));
+    assert!(!written.has("title") && !written.has("heading") && !written.has("cnt"));
+
+    let mut e = ds::Entity::new(ds::Key::new("Renamed").with_name("r"));
+    e.set_indexed("title", ds::Value::unicode_string("Old"));
+    e.set_indexed("cnt", ds::Value::boolean(true));
+    let err = Renamed::from_ds_entity(&e).unwrap_err();
+    assert_eq!(err.mapping.unwrap().property, "count");
+}
+
+#[derive(Entail, Debug, Default)]
+struct Session {
+    #[entail(key_strategy = "uuid")]
+    key: String,
+    #[entail]
+    user: String,
+}
+
+#[derive(Entail, Debug, Default)]
+struct Invoice {
+    #[entail(key_strategy = "allocate")]
+    key: i64,
+    #[entail(parent)]
+    account: Option<ds::Key>,
+}
+
+#[test]
+fn code_gen_key_strategy() {
+    assert_eq!(Session::KEY_STRATEGY, entail::KeyStrategy::Uuid);
+    assert_eq!(Invoice::KEY_STRATEGY, entail::KeyStrategy::Allocate);
+    assert_eq!(Model::KEY_STRATEGY, entail::KeyStrategy::Manual);
+
+    let mut session = Session::default();
+    let e = session.to_ds_entity().unwrap();
+    let name = e.key().name().unwrap().to_string();
+    assert_eq!(name.len(), 36);
+    session.assign_key(e.key()).unwrap();
+    assert_eq!(session.key, name);
+    // a set key is kept
+    assert_eq!(session.to_ds_entity().unwrap().key(), e.key());
+
+    let account = ds::Key::new("Account").with_name("a");
+    let mut invoice = Invoice {
+        key: 0,
+        account: Some(account.clone()),
+    };
+    let e = invoice.to_ds_entity().unwrap();
+    assert!(!e.key().is_complete());
+    assert_eq!(e.key().parent(), Some(&account));
+    let allocated = ds::Key::new("Invoice").with_id(42).with_parent(account);
+    invoice.assign_key(&allocated).unwrap();
+    assert_eq!(invoice.key, 42);
+    assert_eq!(invoice.to_ds_entity().unwrap().key(), &allocated);
+
+    let err = invoice
+        .assign_key(&ds::Key::new("Invoice").with_name("x"))
+        .unwrap_err();
+    assert_eq!(err.kind, EntailErrorKind::PropertyMappingError);
+}
+
+#[derive(Entail, Debug, Default)]
+#[entail(rename_all = "camelCase", description = "A customer of the shop")]
+struct Customer {
+    #[entail(description = "The email address")]
+    key: String,
+    #[entail(description = "Shown on the invoices")]
+    display_name: String,
+    #[entail]
+    notes: String,
+}
+
+#[test]
+fn code_gen_descriptions() {
+    assert_eq!(Customer::description(), Some("A customer of the shop"));
+    assert_eq!(
+        Customer::property_descriptions(),
+        &[
+            ("__key__", "The email address"),
+            ("displayName", "Shown on the invoices")
+        ]
+    );
+    assert_eq!(MinimalModel::description(), None);
+    assert!(MinimalModel::property_descriptions().is_empty());
+
+    entail::registry::register::<Customer>();
+    let manifest = entail::registry::manifest();
+    let customer = manifest.iter().find(|m| m.kind == "Customer").unwrap();
+    assert_eq!(customer.description, Some("A customer of the shop"));
+    assert_eq!(customer.properties, Customer::property_descriptions());
+    let adapter = entail::registry::adapter_for("Customer").unwrap();
+    assert_eq!(adapter.description(), Some("A customer of the shop"));
+}
+
+#[derive(Entail, Debug, Default)]
+#[entail(namespace = "tenant-a")]
+struct TenantSetting {
+    #[entail]
+    key: String,
+    #[entail(parent)]
+    owner: Option<ds::Key>,
+    #[entail]
+    value: String,
+}
+
+#[test]
+fn code_gen_namespace() {
+    assert_eq!(TenantSetting::namespace(), Some("tenant-a"));
+    assert_eq!(MinimalModel::namespace(), None);
+    let owner = ds::Key::new("User").with_name("alice");
+    let setting = TenantSetting {
+        key: "theme".into(),
+        owner: Some(owner.clone()),
+        value: "dark".into(),
+    };
+    let e = setting.to_ds_entity().unwrap();
+    assert_eq!(e.key().namespace(), Some("tenant-a"));
+    assert_eq!(
+        e.key().parent().and_then(ds::Key::namespace),
+        Some("tenant-a")
+    );
+    let read = TenantSetting::from_ds_entity(&e).unwrap();
+    assert_eq!(read.owner, Some(owner.with_namespace("tenant-a")));
+
+    let adapter = TenantSetting::adapter();
+    assert_eq!(
+        adapter.create_named_key("theme").namespace(),
+        Some("tenant-a")
+    );
+    assert_eq!(adapter.query().namespace.as_deref(), Some("tenant-a"));
+    assert_eq!(MinimalModel::adapter().query().namespace, None);
+
+    let query = adapter.prepare_query(ds::Query::default()).unwrap();
+    assert_eq!(query.kind, "TenantSetting");
+    assert_eq!(query.namespace.as_deref(), Some("tenant-a"));
+    let query = adapter
+        .prepare_query(ds::Query {
+            namespace: Some("tenant-b".into()),
+            ..ds::Query::default()
+        })
+        .unwrap();
+    assert_eq!(query.namespace.as_deref(), Some("tenant-b"));
+}
+
+#[derive(Entail, Debug, Default)]
+#[entail(default_order = "-pinned, key")]
+struct Note {
+    #[entail(key)]
+    key: Option<i64>,
+    #[entail]
+    pinned: bool,
+    #[entail(soft_delete)]
+    deleted: bool,
+}
+
+#[tokio::test]
+async fn code_gen_query_policies() {
+    use entail::Repository;
+
+    assert_eq!(Note::SOFT_DELETE_PROPERTY, Some("deleted"));
+    assert_eq!(
+        Note::DEFAULT_ORDER,
+        &[
+            ("pinned", ds::OrderDirection::DESCENDING),
+            ("__key__", ds::OrderDirection::ASCENDING)
+        ]
+    );
+    assert_eq!(MinimalModel::SOFT_DELETE_PROPERTY, None);
+    assert!(MinimalModel::DEFAULT_ORDER.is_empty());
+
+    let adapter = Note::adapter();
+    let query = adapter.prepare_query(ds::Query::default()).unwrap();
+    assert_eq!(query.kind, "Note");
+    let orders: Vec<_> = query
+        .order
+        .iter()
+        .map(|order| (order.name.as_ref(), order.direction))
+        .collect();
+    assert_eq!(orders, Note::DEFAULT_ORDER);
+    assert!(matches!(
+        query.filter,
+        Some(ds::Filter::Property(name, ds::FilterOperator::Equal, ds::Value::Boolean(false)))
+            if name == "deleted"
+    ));
+    // the orders of the query replace the default order
+    let query = adapter
+        .prepare_query(adapter.query().order_asc("pinned"))
+        .unwrap();
+    assert_eq!(query.order.len(), 1);
+    assert_eq!(query.order[0].direction, ds::OrderDirection::ASCENDING);
+    let err = adapter
+        .prepare_query(ds::Query::new("Customer"))
+        .unwrap_err();
+    assert_eq!(err.kind, EntailErrorKind::EntityKindMismatch);
+
+    let repo = entail::InMemoryRepository::<Note>::new();
+    for (pinned, deleted) in [(false, false), (true, true), (false, true), (true, false)] {
+        let mut note = Note {
+            key: None,
+            pinned,
+            deleted,
+        };
+        repo.save(&mut note).await.unwrap();
+    }
+    let found = repo.query(adapter.query()).await.unwrap().items;
+    assert_eq!(
+        found
+            .iter()
+            .map(|note| note.key.unwrap())
+            .collect::<Vec<_>>(),
+        vec![4, 1]
+    );
+}
+
+#[derive(Entail, Debug, Default)]
+struct Glossary {
+    #[entail(key)]
+    key: String,
+    #[entail]
+    property_names: Vec<String>,
+}
+
+#[test]
+fn code_gen_constant_names() {
+    // the field doesn't shadow the constant of EntityModel
+    assert_eq!(Glossary::PROPERTY_NAMES, &["property_names"]);
+    assert!(Glossary::has_property("property_names"));
+    assert!(Glossary::has_property("__key__"));
+    assert!(!Glossary::has_property("key"));
+}
+
+fn not_blank(value: &str) -> Result<(), &'static str> {
+    if value.trim().is_empty() {
+        Err("must not be blank")
+    } else {
+        Ok(())
+    }
+}
+
+fn percentage(value: &i64) -> Result<(), String> {
+    if (0..=100).contains(value) {
+        Ok(())
+    } else {
+        Err(format!("{} is not a percentage", value))
+    }
+}
+
+#[derive(Entail, Debug, Default)]
+struct Discount {
+    #[entail(validate = "not_blank")]
+    key: String,
+    #[entail(validate = "percentage", validate_on_read)]
+    percent: i64,
+    #[entail(validate = "not_blank")]
+    label: String,
+}
+
+#[test]
+fn code_gen_validate() {
+    use entail::ModelPatch;
+
+    let mut discount = Discount {
+        key: "spring".into(),
+        percent: 20,
+        label: "Spring sale".into(),
+    };
+    let mut e = discount.to_ds_entity().unwrap();
+
+    discount.percent = 120;
+    let err = discount.to_ds_entity().unwrap_err();
+    assert_eq!(err.kind, EntailErrorKind::ValidationFailed);
+    assert_eq!(err.message, "Invalid Discount.percent: 120 is not a percentage");
+    assert_eq!(err.mapping.unwrap().property, "percent");
+
+    discount.key = " ".into();
+    let err = discount.to_ds_entity().unwrap_err();
+    assert_eq!(err.mapping.unwrap().property, "__key__");
+
+    // only the fields marked with validate_on_read are validated when reading
+    e.set_indexed("label", ds::Value::unicode_string(""));
+    assert!(Discount::from_ds_entity(&e).is_ok());
+    e.set_indexed("percent", ds::Value::integer(-1));
+    let err = Discount::from_ds_entity(&e).unwrap_err();
+    assert_eq!(err.kind, EntailErrorKind::ValidationFailed);
+    assert_eq!(err.mapping.unwrap().property, "percent");
+
+    // a patch is validated before anything is written
+    let patch = DiscountPatch {
+        percent: Some(50),
+        label: Some(" ".into()),
+    };
+    let mut patched = e.clone();
+    let err = patch.apply_to(&mut patched).unwrap_err();
+    assert_eq!(err.kind, EntailErrorKind::ValidationFailed);
+    assert_eq!(err.message, "Invalid Discount.label: must not be blank");
+    assert_eq!(err.mapping.unwrap().property, "label");
+    assert_eq!(patched, e);
+    let patch = DiscountPatch {
+        percent: Some(50),
+        ..Default::default()
+    };
+    patch.apply_to(&mut patched).unwrap();
+    assert_eq!(patched.get_value("percent"), Some(&ds::Value::integer(50)));
+}
+
+#[derive(Entail, Debug, Default)]
+struct LegacyUpload {
+    #[entail]
+    key: String,
+    #[entail(meaning = 7)]
+    uploaded_at: Option<i64>,
+    #[entail(meaning = 17, unindexed)]
+    blob_key: String,
+}
+
+#[test]
+fn code_gen_meaning() {
+    let upload = LegacyUpload {
+        key: "report".into(),
+        uploaded_at: None,
+        blob_key: "AMIfv94".into(),
+    };
+    let e = upload.to_ds_entity().unwrap();
+    let blob_key = e.get("blob_key").unwrap();
+    assert_eq!(blob_key.meaning(), Some(ds::MEANING_BLOBKEY));
+    assert!(!blob_key.is_indexed());
+    // null values have no meaning
+    assert_eq!(e.get("uploaded_at").unwrap().meaning(), None);
+
+    let upload = LegacyUpload {
+        uploaded_at: Some(1_700_000_000_000_000),
+        ..upload
+    };
+    let e = upload.to_ds_entity().unwrap();
+    let uploaded_at = e.get("uploaded_at").unwrap();
+    assert_eq!(uploaded_at.meaning(), Some(ds::MEANING_GD_WHEN));
+    assert!(uploaded_at.is_indexed());
+    let read = LegacyUpload::from_ds_entity(&e).unwrap();
+    assert_eq!(read.uploaded_at, upload.uploaded_at);
+}
+
+#[test]
+fn code_gen_has_property() {
+    assert!(Customer::has_property("displayName"));
+    assert!(Customer::has_property("__key__"));
+    assert!(!Customer::has_property("display_name"));
+    assert!(!Customer::has_property("key"));
+    assert!(Shape::has_property("__type"));
+    assert!(Shape::has_property("radius") && Shape::has_property("height"));
+    assert!(!Shape::has_property("depth"));
+
+    assert!(
+        entail::FetchOptions::select(&["displayName", "notes"])
+            .validate::<Customer>()
+            .is_ok()
+    );
+    let err = entail::FetchOptions::select(&["displayName", "email"])
+        .validate::<Customer>()
+        .unwrap_err();
+    assert_eq!(err.kind, EntailErrorKind::PropertyMappingError);
+    assert!(err.message.contains("\"email\""));
+}
+
+#[derive(Entail, serde::Serialize, serde::Deserialize, Debug, Default, PartialEq)]
+#[entail(serde)]
+#[serde(rename_all = "camelCase")]
+struct ApiToken {
+    key: String,
+    display_name: String,
+    #[serde(rename = "scope")]
+    scopes: Vec<String>,
+    #[serde(skip)]
+    cached_hash: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    #[entail(name = "expires")]
+    expires_at: Option<i64>,
+}
+
+#[test]
+fn code_gen_serde() {
+    let token = ApiToken {
+        key: "t1".into(),
+        display_name: "CI".into(),
+        scopes: vec!["read".into()],
+        cached_hash: Some("abc".into()),
+        expires_at: Some(100),
+    };
+    assert_eq!(ApiToken::DISPLAY_NAME, "displayName");
+    assert_eq!(ApiToken::SCOPES, "scope");
+    assert_eq!(ApiToken::EXPIRES_AT, "expires");
+    assert!(!ApiToken::has_property("cachedHash"));
+    let e = token.to_ds_entity().unwrap();
+    assert_eq!(e.key(), &ds::Key::new("ApiToken").with_name("t1"));
+    assert_eq!(
+        e.get_value("displayName"),
+        Some(&ds::Value::unicode_string("CI"))
+    );
+    assert!(e.get_value("scope").is_some());
+    assert!(e.get_value("cachedHash").is_none() && e.get_value("cached_hash").is_none());
+    let read = ApiToken::from_ds_entity(&e).unwrap();
+    assert_eq!(read.cached_hash, None);
+    assert_eq!(read.scopes, vec!["read".to_string()]);
+    assert_eq!(read.expires_at, Some(100));
+}
+
+const NONCE_LEN: usize = 12;
+
+#[derive(Entail, Debug, Default)]
+struct SealedSecret {
+    #[entail]
+    key: String,
+    #[entail]
+    digest: [u8; 32],
+    #[entail]
+    salt: [u8; NONCE_LEN],
+    #[entail]
+    nonce: Option<[u8; NONCE_LEN]>,
+    #[entail(unindexed)]
+    ciphertext: Cow<'static, [u8]>,
+    #[entail]
+    tag: Option<Cow<'static, [u8]>>,
+}
+
+#[test]
+fn code_gen_byte_arrays() {
+    let secret = SealedSecret {
+        key: "s".into(),
+        digest: [7; 32],
+        salt: [2; NONCE_LEN],
+        nonce: Some([1; NONCE_LEN]),
+        ciphertext: Cow::Borrowed(b"sealed"),
+        tag: None,
+    };
+    let mut e = secret.to_ds_entity().unwrap();
+    assert_eq!(e.get_value("digest"), Some(&ds::Value::blob(vec![7u8; 32])));
+    assert_eq!(e.get_value("nonce"), Some(&ds::Value::blob(vec![1u8; 12])));
+    assert_eq!(
+        e.get_value("ciphertext"),
+        Some(&ds::Value::blob(b"sealed".to_vec()))
+    );
+    let read = SealedSecret::from_ds_entity(&e).unwrap();
+    assert_eq!(read.digest, [7; 32]);
+    assert_eq!(read.nonce, Some([1; NONCE_LEN]));
+    assert_eq!(read.ciphertext.as_ref(), b"sealed");
+    assert_eq!(read.tag, None);
+
+    e.set_indexed("digest", ds::Value::blob(vec![7u8; 31]));
+    let err = SealedSecret::from_ds_entity(&e).unwrap_err();
+    assert_eq!(err.kind, EntailErrorKind::PropertyMappingError);
+    assert_eq!(err.mapping.unwrap().property, "digest");
+}
+
+#[derive(Entail, Debug, Default)]
+struct FileBlob {
+    #[entail]
+    key: String,
+    #[entail(unindexed)]
+    content: bytes::Bytes,
+}
+
+#[test]
+fn code_gen_shared_bytes() {
+    let file = FileBlob {
+        key: "a".into(),
+        content: bytes::Bytes::from(vec![1u8; 1024]),
+    };
+    let e = file.to_ds_entity().unwrap();
+    let blob = e
+        .get_value("content")
+        .and_then(ds::Value::blob_value)
+        .unwrap();
+    // the entity shares the bytes of the model
+    assert_eq!(blob.as_ptr(), file.content.as_ptr());
+    let read = FileBlob::from_ds_entity(&e).unwrap();
+    assert_eq!(read.content.as_ptr(), file.content.as_ptr());
+}
+
+#[derive(Entail, Debug, Default)]
+struct Patient {
+    #[entail]
+    key: i64,
+    #[entail]
+    ward: String,
+    #[entail(sensitive)]
+    full_name: String,
+    #[entail(sensitive, alias = "ssn")]
+    national_id: Option<String>,
+}
+
+#[test]
+fn code_gen_sensitive() {
+    assert!(Patient::is_sensitive("full_name"));
+    assert!(Patient::is_sensitive("ssn"));
+    assert!(!Patient::is_sensitive("ward"));
+    assert!(!MinimalModel::is_sensitive("textField"));
+    let patient = Patient {
+        key: 7,
+        ward: "B".into(),
+        full_name: "Jane Roe".into(),
+        national_id: Some("123-45-6789".into()),
+    };
+    let debug = patient.redacted_debug();
+    assert!(debug.contains(ds::REDACTED));
+    assert!(debug.contains("ward: string(B) (indexed)"));
+    assert!(!debug.contains("Jane") && !debug.contains("6789"));
+
+    entail::registry::register::<Patient>();
+    let adapter = entail::registry::adapter_for("Patient").unwrap();
+    assert!(adapter.is_sensitive("national_id"));
+}
+
+#[test]
+fn code_gen_decoder() {
+    assert_eq!(MinimalModel::PROPERTY_NAMES, &["textField"]);
+    let entities: Vec<google_datastore1::api::Entity> = ["a", "b"]
+        .into_iter()
+        .map(|name| {
+            MinimalModel {
+                key: MinimalModel::adapter().create_key().with_name(name),
+                text_field: name.to_uppercase(),
+            }
+            .to_ds_entity()
+            .unwrap()
+            .into()
+        })
+        .collect();
+    let mut decoder = ds::EntityDecoder::for_model::<MinimalModel>();
+    let models: Vec<MinimalModel> = decoder.decode_models(entities.clone()).unwrap();
+    assert_eq!(models[1].text_field, "B");
+    assert!(decoder.decode_models::<AutoId>(entities).is_err());
+}
+
+#[cfg(feature = "decimal")]
+mod decimal_models {
+    use super::*;
+    use rust_decimal::Decimal;
+
+    #[derive(Entail, Debug, Default)]
+    pub struct Invoice {
+        #[entail]
+        pub key: i64,
+        #[entail]
+        pub total: Decimal,
+        #[entail(decimal_scale = 2)]
+        pub paid: Decimal,
+        #[entail(decimal_scale = 4)]
+        pub tax_rate: Option<Decimal>,
+        #[entail(decimal_scale = 2)]
+        pub installments: Vec<Decimal>,
+    }
+
+    #[test]
+    fn code_gen_decimal() {
+        let invoice = Invoice {
+            key: 1,
+            total: Decimal::new(10010, 2),
+            paid: Decimal::new(505, 1),
+            tax_rate: Some(Decimal::new(2, 2)),
+            installments: vec![Decimal::new(5005, 2), Decimal::new(50, 0)],
+        };
+        let mut e = invoice.to_ds_entity().unwrap();
+        assert_eq!(
+            e.get_value("total"),
+            Some(&ds::Value::unicode_string("100.10"))
+        );
+        assert_eq!(e.get_value("paid"), Some(&ds::Value::integer(5050)));
+        assert_eq!(e.get_value("tax_rate"), Some(&ds::Value::integer(200)));
+        assert_eq!(
+            e.get_value("installments"),
+            Some(&ds::Value::array(vec![
+                ds::Value::integer(5005),
+                ds::Value::integer(5000)
+            ]))
+        );
+        let read = Invoice::from_ds_entity(&e).unwrap();
+        assert_eq!(read.total.to_string(), "100.10");
+        assert_eq!(read.paid.to_string(), "50.50");
+        assert_eq!(read.tax_rate, Some(Decimal::new(2, 2)));
+        assert_eq!(read.installments, invoice.installments);
+
+        let rounded = Invoice {
+            paid: Decimal::new(5055, 3),
+            ..Default::default()
+        };
+        let err = rounded.to_ds_entity().unwrap_err();
+        assert_eq!(err.kind, EntailErrorKind::PropertyMappingError);
+
+        e.set_indexed("paid", ds::Value::unicode_string("50.50"));
+        let err = Invoice::from_ds_entity(&e).unwrap_err();
+        assert_eq!(err.mapping.unwrap().property, "paid");
+    }
+}
+
+#[derive(Entail, Debug, Default, PartialEq)]
+struct Task {
+    #[entail(key)]
+    id: Option<i64>,
+    #[entail]
+    owner: String,
+    #[entail]
+    priority: i64,
+    #[entail]
+    labels: Vec<String>,
+    #[entail(unindexed)]
+    note: String,
+}
+
+/// A service written against the repository trait.
+async fn reassign_tasks(
+    repo: &dyn entail::Repository<Task>,
+    from: &str,
+    to: &str,
+) -> Result<usize, EntailError> {
+    let query = Task::query()
+        .filter(
+            TaskFields::OWNER,
+            ds::FilterOperator::Equal,
+            from.to_string(),
+        )?
+        .build();
+    let tasks = repo.query(query).await?.items;
+    let count = tasks.len();
+    for mut task in tasks {
+        task.owner = to.to_string();
+        repo.save(&mut task).await?;
+    }
+    Ok(count)
+}
+
+#[tokio::test]
+async fn code_gen_in_memory_repository() {
+    use entail::Repository;
+
+    let repo = entail::InMemoryRepository::<Task>::new();
+    for (owner, priority, labels) in [
+        ("alice", 2, vec!["urgent"]),
+        ("alice", 5, vec![]),
+        ("bob", 3, vec!["urgent", "bug"]),
+    ] {
+        let mut task = Task {
+            owner: owner.to_string(),
+            priority,
+            labels: labels.into_iter().map(str::to_string).collect(),
+            note: owner.to_string(),
+            ..Default::default()
+        };
+        repo.save(&mut task).await.unwrap();
+        assert!(task.id.is_some());
+    }
+    assert_eq!(repo.len(), 3);
+
+    let moved = reassign_tasks(&repo, "alice", "carol").await.unwrap();
+    assert_eq!(moved, 2);
+    let key = Task::adapter().create_id_key(1);
+    let task = repo.get(&key).await.unwrap().unwrap();
+    assert_eq!((task.owner.as_str(), task.priority), ("carol", 2));
+
+    let by_priority = Task::query()
+        .filter(TaskFields::PRIORITY, ds::FilterOperator::GreaterThan, 2)
+        .unwrap()
+        .order_by_desc(TaskFields::PRIORITY)
+        .build();
+    let found = repo.query(by_priority).await.unwrap().items;
+    assert_eq!(
+        found.iter().map(|task| task.priority).collect::<Vec<_>>(),
+        vec![5, 3]
+    );
+    // an array matches if any of its elements does
+    let urgent = Task::query()
+        .filter(TaskFields::LABELS, ds::FilterOperator::Equal, "urgent")
+        .unwrap()
+        .build();
+    assert_eq!(repo.query(urgent).await.unwrap().items.len(), 2);
+    // unindexed properties never match
+    let by_note = Task::query()
+        .filter(TaskFields::NOTE, ds::FilterOperator::Equal, "bob")
+        .unwrap()
+        .build();
+    assert!(repo.query(by_note).await.unwrap().items.is_empty());
+
+    repo.delete(&key).await.unwrap();
+    assert!(repo.get(&key).await.unwrap().is_none());
+    assert_eq!(repo.len(), 2);
+
+    // nothing happens until the futures are awaited
+    let mut task = Task::default();
+    drop(repo.save(&mut task));
+    drop(repo.delete(&Task::adapter().create_id_key(2)));
+    assert_eq!(repo.len(), 2);
+    // the IDs of the entities stored as they are are skipped
+    let stored = Task {
+        id: Some(4),
+        ..Default::default()
+    };
+    repo.put_entity(stored.to_ds_entity().unwrap());
+    repo.save(&mut task).await.unwrap();
+    assert_eq!(task.id, Some(5));
+    assert_eq!(repo.len(), 4);
+}
@@ -1,11 +1,18 @@
 mod common;
 
 use common::{check_server, init_ring};
+use std::future::Future;
+use std::ops::Range;
+use std::pin::Pin;
+use std::time::Duration;
 use std::{collections::HashSet, sync::Arc};
 
 use entail::{
-    Entail, EntailError, EntityModel,
-    ds::{DatastoreShell, Entity, Key, Mutation, MutationBatch, Transaction, Value},
+    Entail, EntailError, EntailErrorKind, EntityModel,
+    ds::{
+        ChangeOperation, Clock, DatastoreShell, Entity, Jitter, JitterSource, Key, Mutation,
+        MutationBatch, Transaction, TransactionEvent, TransactionTags, Value,
+    },
 };
 
 #[tokio::test]
@@ -215,3 +222,902 @@ pub async fn test_retrieve_thousands() -> Result<(), EntailError> {
 
     Ok(())
 }
+
+#[tokio::test]
+pub async fn test_sharded_scan() -> Result<(), EntailError> {
+    init_ring();
+    check_server();
+
+    let ds = DatastoreShell::new(
+        "test-project",
+        false,
+        Some(format!("db{}", fastrand::i64(..))),
+    )
+    .await
+    .map_err(|_| Default::default())?;
+
+    ds.commit(
+        MutationBatch::new()
+            .upsert_all((1..=250).map(|n| Entity::new(Key::new("Scanned").with_id(n)))),
+    )
+    .await?;
+
+    let mut rx = entail::scan::sharded_scan(&ds, "Scanned", 4).await?;
+    let mut ids = HashSet::new();
+    while let Some(entity) = rx.recv().await {
+        assert!(ids.insert(entity?.key().id().unwrap()));
+    }
+    assert_eq!(ids.len(), 250);
+
+    Ok(())
+}
+
+#[derive(Entail, Default, Debug)]
+struct Checksummed {
+    #[entail]
+    key: String,
+    #[entail]
+    value: i32,
+    #[entail(checksum)]
+    checksum: Option<i64>,
+}
+
+#[tokio::test]
+pub async fn test_upsert_if_changed() -> Result<(), EntailError> {
+    init_ring();
+    check_server();
+
+    let ds = DatastoreShell::new(
+        "test-project",
+        false,
+        Some(format!("db{}", fastrand::i64(..))),
+    )
+    .await
+    .map_err(|_| Default::default())?;
+
+    let a = Checksummed::adapter();
+    let mut model = Checksummed {
+        key: "test".into(),
+        value: 1,
+        checksum: None,
+    };
+    assert!(a.upsert_if_changed(&ds, &model).await?);
+    assert!(!a.upsert_if_changed(&ds, &model).await?);
+    model.value = 2;
+    assert!(a.upsert_if_changed(&ds, &model).await?);
+    let stored = a.fetch_single(&ds, a.create_named_key("test")).await?;
+    assert_eq!(stored.value, 2);
+    assert!(stored.checksum.is_some());
+
+    Ok(())
+}
+
+#[derive(Entail, Default, Debug)]
+struct Versioned {
+    #[entail]
+    key: String,
+    #[entail]
+    value: i32,
+    #[entail(version)]
+    version: i64,
+}
+
+#[tokio::test]
+pub async fn test_update_versioned() -> Result<(), EntailError> {
+    init_ring();
+    check_server();
+
+    let ds = DatastoreShell::new(
+        "test-project",
+        false,
+        Some(format!("db{}", fastrand::i64(..))),
+    )
+    .await
+    .map_err(|_| Default::default())?;
+
+    let a = Versioned::adapter();
+    let mut model = Versioned {
+        key: "test".into(),
+        value: 1,
+        version: 0,
+    };
+    model.version = a.update_versioned(&ds, &model).await?;
+    assert_eq!(model.version, 1);
+    let stale = a.fetch_single(&ds, a.create_named_key("test")).await?;
+    model.value = 2;
+    model.version = a.update_versioned(&ds, &model).await?;
+    assert_eq!(model.version, 2);
+
+    let err = a
+        .update_versioned(&ds, &stale)
+        .await
+        .expect_err("Expected a conflict");
+    assert_eq!(err.kind, EntailErrorKind::VersionConflict);
+    let stored = a.fetch_single(&ds, a.create_named_key("test")).await?;
+    assert_eq!(stored.value, 2);
+    assert_eq!(stored.version, 2);
+
+    // a transaction commits the versioned update itself
+    let ts = ds.begin_transaction(&None).await?;
+    let err = a
+        .update_versioned(&ts, &model)
+        .await
+        .expect_err("Expected a rejected transactional shell");
+    assert_eq!(err.kind, EntailErrorKind::TransactionNotAllowed);
+    ts.rollback(&None).await?;
+    model.value = 3;
+    let model = &model;
+    Transaction::new(&ds)
+        .run_mutations(|ts| async move {
+            let (version, batch) = a
+                .add_versioned_update(&ts, model, MutationBatch::new())
+                .await?;
+            assert_eq!(version, 3);
+            Ok(batch)
+        })
+        .await?;
+    let stored = a.fetch_single(&ds, a.create_named_key("test")).await?;
+    assert_eq!((stored.value, stored.version), (3, 3));
+
+    Ok(())
+}
+
+#[tokio::test]
+pub async fn test_delete_all_models() -> Result<(), EntailError> {
+    init_ring();
+    check_server();
+
+    let ds = DatastoreShell::new(
+        "test-project",
+        false,
+        Some(format!("db{}", fastrand::i64(..))),
+    )
+    .await
+    .map_err(|_| Default::default())?;
+
+    let models: Vec<Sample> = (0..1200)
+        .map(|n| Sample {
+            key: format!("sample{}", n),
+            value: n,
+        })
+        .collect();
+    for chunk in models.chunks(500) {
+        let mut batch = MutationBatch::new();
+        for model in chunk {
+            batch = batch.upsert(model.to_ds_entity()?);
+        }
+        ds.commit(batch).await?;
+    }
+
+    let a = Sample::adapter();
+    let chunks = a.delete_all_models(&ds, &models).await?;
+    assert_eq!(
+        chunks.iter().map(|c| c.keys.len()).collect::<Vec<_>>(),
+        vec![500, 500, 200]
+    );
+    assert!(chunks.iter().all(|c| c.is_ok()));
+    let remaining = a
+        .fetch_all(
+            &ds,
+            models.iter().map(|m| a.create_named_key(m.key.clone())),
+        )
+        .await?;
+    assert!(remaining.is_empty());
+
+    Ok(())
+}
+
+#[tokio::test]
+pub async fn test_move_entity() -> Result<(), EntailError> {
+    init_ring();
+    check_server();
+
+    let ds = DatastoreShell::new(
+        "test-project",
+        false,
+        Some(format!("db{}", fastrand::i64(..))),
+    )
+    .await
+    .map_err(|_| Default::default())?;
+
+    let old_key = Key::new("User").with_name("alice");
+    let new_key = Key::new("Member").with_name("alice");
+    let mut user = Entity::new(old_key.clone());
+    user.set_indexed("name", Value::unicode_string("Alice"));
+    let mut batch = MutationBatch::new().upsert(user);
+    for id in 1..=3 {
+        let mut post = Entity::new(Key::new("Post").with_id(id));
+        post.set_indexed("author", Value::key(old_key.clone()));
+        batch = batch.upsert(post);
+    }
+    ds.commit(batch).await?;
+
+    let options = entail::admin::MoveOptions::new()
+        .with_reference("Post", "author")
+        .with_chunk_size(2);
+    let report = entail::admin::move_entity(
+        &ds,
+        old_key.clone(),
+        new_key.clone(),
+        &options.clone().dry_run(),
+    )
+    .await?;
+    assert_eq!(report.updated_references.len(), 3);
+    assert_eq!(report.transactions, 0);
+    assert!(ds.get_single(new_key.clone()).await?.is_none());
+
+    let report =
+        entail::admin::move_entity(&ds, old_key.clone(), new_key.clone(), &options).await?;
+    // copy, two chunks of references, delete
+    assert_eq!(report.transactions, 4);
+    assert!(ds.get_single(old_key.clone()).await?.is_none());
+    let moved = ds.get_single(new_key.clone()).await?.expect("Moved entity");
+    assert_eq!(
+        moved.get_value("name"),
+        Some(&Value::unicode_string("Alice"))
+    );
+    let post = ds
+        .get_single(Key::new("Post").with_id(2))
+        .await?
+        .expect("Post");
+    assert_eq!(post.get_value("author"), Some(&Value::key(new_key.clone())));
+
+    let err = entail::admin::move_entity(&ds, old_key, new_key, &options)
+        .await
+        .expect_err("Expected the original to be gone");
+    assert_eq!(err.kind, EntailErrorKind::RequiredEntityNotFound);
+
+    Ok(())
+}
+
+#[tokio::test]
+pub async fn test_change_feed() -> Result<(), EntailError> {
+    init_ring();
+    check_server();
+
+    let ds = DatastoreShell::new(
+        "test-project",
+        false,
+        Some(format!("db{}", fastrand::i64(..))),
+    )
+    .await
+    .map_err(|_| Default::default())?
+    .with_change_feed(16);
+    let mut changes = ds.subscribe().expect("Change feed");
+
+    let response = ds
+        .commit(
+            MutationBatch::new()
+                .insert(Entity::new(Key::new("Note")))
+                .delete(Key::new("Note").with_name("gone")),
+        )
+        .await?;
+    let allocated = response.mutation_results[0]
+        .key
+        .clone()
+        .expect("Allocated key");
+    let event = changes.try_recv().expect("Insert event");
+    assert_eq!(event.kind, "Note");
+    assert_eq!(event.key, allocated);
+    assert_eq!(event.operation, ChangeOperation::Insert);
+    let event = changes.try_recv().expect("Delete event");
+    assert_eq!(event.key, Key::new("Note").with_name("gone"));
+    assert_eq!(event.operation, ChangeOperation::Delete);
+
+    let ts = ds.begin_transaction(&None).await?;
+    ts.commit(MutationBatch::new().upsert(Entity::new(allocated.clone())))
+        .await?;
+    let event = changes.try_recv().expect("Transactional event");
+    assert_eq!(event.operation, ChangeOperation::Upsert);
+    assert!(changes.try_recv().is_err());
+
+    Ok(())
+}
+
+#[tokio::test]
+pub async fn test_write_mirror() -> Result<(), EntailError> {
+    init_ring();
+    check_server();
+
+    let primary = DatastoreShell::new(
+        "test-project",
+        false,
+        Some(format!("db{}", fastrand::i64(..))),
+    )
+    .await
+    .map_err(|_| Default::default())?;
+    let secondary = DatastoreShell::new(
+        "test-project",
+        false,
+        Some(format!("db{}", fastrand::i64(..))),
+    )
+    .await
+    .map_err(|_| Default::default())?;
+    let failures = Arc::new(std::sync::Mutex::new(Vec::new()));
+    let reported = failures.clone();
+    let ds = primary.with_write_mirror(secondary.clone(), move |failure| {
+        reported.lock().unwrap().push(failure.keys);
+    });
+
+    let response = ds
+        .commit(MutationBatch::new().insert(Entity::new(Key::new("Note"))))
+        .await?;
+    let allocated = response.mutation_results[0]
+        .key
+        .clone()
+        .expect("Allocated key");
+    // the insert is mirrored with the allocated key
+    assert!(secondary.get_single(allocated.clone()).await?.is_some());
+
+    // an update of an entity missing from the secondary is mirrored as an upsert
+    let key = Key::new("Note").with_name("existing");
+    primary
+        .commit(MutationBatch::new().upsert(Entity::new(key.clone())))
+        .await?;
+    let mut entity = Entity::new(key.clone());
+    entity.set_indexed("text", Value::unicode_string("updated"));
+    ds.commit(MutationBatch::new().update(entity)).await?;
+    let mirrored = secondary.get_single(key.clone()).await?.expect("Mirrored");
+    assert_eq!(
+        mirrored.get_value("text"),
+        Some(&Value::unicode_string("updated"))
+    );
+
+    ds.commit(MutationBatch::new().delete(allocated.clone()))
+        .await?;
+    assert!(secondary.get_single(allocated).await?.is_none());
+    assert!(failures.lock().unwrap().is_empty());
+
+    Ok(())
+}
+
+#[tokio::test]
+pub async fn test_export_stream() -> Result<(), EntailError> {
+    init_ring();
+    check_server();
+
+    let ds = DatastoreShell::new(
+        "test-project",
+        false,
+        Some(format!("db{}", fastrand::i64(..))),
+    )
+    .await
+    .map_err(|_| Default::default())?;
+    let mut batch = MutationBatch::new();
+    for id in 1..=25 {
+        let mut entity = Entity::new(Key::new("Row").with_id(id));
+        entity.set_indexed("n", Value::integer(id));
+        batch = batch.upsert(entity);
+    }
+    ds.commit(batch).await?;
+
+    let query = entail::ds::Query {
+        kind: "Row".into(),
+        ..Default::default()
+    };
+    let options = entail::export::ExportOptions::new().with_page_size(10);
+    let mut out = Vec::new();
+    let report = entail::export::stream_to_writer(&ds, query.clone(), &mut out, &options).await?;
+    assert_eq!(report.entities, 25);
+    assert_eq!(report.bytes, out.len() as u64);
+    let rows: Vec<serde_json::Value> = out
+        .split(|b| *b == b'\n')
+        .filter(|line| !line.is_empty())
+        .map(|line| serde_json::from_slice(line).unwrap())
+        .collect();
+    assert_eq!(rows.len(), 25);
+    assert_eq!(rows[0]["key"]["path"][0]["kind"], "Row");
+
+    // the limit of the query caps the export
+    let mut out = Vec::new();
+    let report = entail::export::stream_to_writer(
+        &ds,
+        entail::ds::Query { limit: 15, ..query },
+        &mut out,
+        &options,
+    )
+    .await?;
+    assert_eq!(report.entities, 15);
+    assert_eq!(report.pages, 2);
+
+    Ok(())
+}
+
+#[derive(Entail, Default, Debug)]
+struct Ticket {
+    #[entail(key_strategy = "allocate")]
+    key: Option<i64>,
+    #[entail]
+    title: String,
+}
+
+#[derive(Entail, Default, Debug)]
+struct Token {
+    #[entail(key_strategy = "uuid")]
+    key: String,
+}
+
+#[tokio::test]
+pub async fn test_insert_model() -> Result<(), EntailError> {
+    init_ring();
+    check_server();
+
+    let ds = DatastoreShell::new(
+        "test-project",
+        false,
+        Some(format!("db{}", fastrand::i64(..))),
+    )
+    .await
+    .map_err(|_| Default::default())?;
+
+    let mut ticket = Ticket {
+        key: None,
+        title: "Broken".into(),
+    };
+    let key = Ticket::adapter().insert_model(&ds, &mut ticket).await?;
+    assert_eq!(ticket.key, key.id());
+    assert!(ticket.key.is_some());
+    let stored: Ticket = Ticket::adapter().fetch_single(&ds, key).await?;
+    assert_eq!(stored.title, "Broken");
+
+    let mut token = Token::default();
+    let key = Token::adapter().insert_model(&ds, &mut token).await?;
+    assert_eq!(Some(token.key.as_str()), key.name());
+    // inserting the same key again fails
+    assert!(Token::adapter().insert_model(&ds, &mut token).await.is_err());
+
+    Ok(())
+}
+
+#[cfg(feature = "active-record")]
+#[tokio::test]
+pub async fn test_active_record() -> Result<(), EntailError> {
+    init_ring();
+    check_server();
+
+    let ds = DatastoreShell::new(
+        "test-project",
+        false,
+        Some(format!("db{}", fastrand::i64(..))),
+    )
+    .await
+    .map_err(|_| Default::default())?;
+
+    let mut ticket = Ticket {
+        key: None,
+        title: "Broken".into(),
+    };
+    let key = ticket.save(&ds).await?;
+    assert_eq!(ticket.key, key.id());
+    ticket.title = "Fixed".into();
+    // saving again overwrites the entity
+    assert_eq!(ticket.save(&ds).await?, key);
+    assert_eq!(Ticket::get(&ds, key.clone()).await?.title, "Fixed");
+
+    ticket.delete(&ds).await?;
+    assert!(Ticket::get(&ds, key).await.is_err());
+
+    Ok(())
+}
+
+#[tokio::test]
+pub async fn test_namespace() -> Result<(), EntailError> {
+    init_ring();
+    check_server();
+
+    let ds = DatastoreShell::new(
+        "test-project",
+        false,
+        Some(format!("db{}", fastrand::i64(..))),
+    )
+    .await
+    .map_err(|_| Default::default())?;
+    let tenant = ds.with_namespace("tenant-a");
+
+    let key = Key::new("Setting").with_name("theme");
+    tenant
+        .commit(MutationBatch::new().insert(Entity::new(key.clone())))
+        .await?;
+    assert!(ds.get_single(key.clone()).await?.is_none());
+    let stored = ds
+        .get_single(key.clone().with_namespace("tenant-a"))
+        .await?
+        .unwrap();
+    assert_eq!(stored.key().namespace(), Some("tenant-a"));
+    assert!(tenant.get_single(key).await?.is_some());
+
+    let query = entail::ds::Query {
+        kind: "Setting".into(),
+        ..Default::default()
+    };
+    assert!(ds.run_query(query.clone()).await?.items.is_empty());
+    assert_eq!(tenant.run_query(query.clone()).await?.items.len(), 1);
+    let query = entail::ds::Query {
+        namespace: Some("tenant-a".into()),
+        ..query
+    };
+    assert_eq!(ds.run_query(query).await?.items.len(), 1);
+
+    Ok(())
+}
+
+#[tokio::test]
+pub async fn test_run_mutations() -> Result<(), EntailError> {
+    init_ring();
+    check_server();
+
+    let ds = DatastoreShell::new(
+        "test-project",
+        false,
+        Some(format!("db{}", fastrand::i64(..))),
+    )
+    .await
+    .map_err(|_| Default::default())?;
+    let key = Key::new("Counter").with_name("visits");
+    ds.commit(MutationBatch::new().insert({
+        let mut e = Entity::new(key.clone());
+        e.set_indexed("count", Value::integer(0));
+        e
+    }))
+    .await?;
+
+    let increment = || {
+        Transaction::new(&ds).run_mutations(|ts| {
+            let key = key.clone();
+            async move {
+                let mut counter = ts.get_single(key).await?.unwrap();
+                let count = match counter.get_value("count") {
+                    Some(Value::Integer(count)) => *count,
+                    _ => 0,
+                };
+                counter.set_indexed("count", Value::integer(count + 1));
+                Ok(vec![Mutation::Update(counter)])
+            }
+        })
+    };
+    // the concurrent increments conflict, and the retried attempts read the new count
+    let (a, b) = tokio::join!(increment(), increment());
+    assert_eq!(a?.mutation_results.len(), 1);
+    assert_eq!(b?.mutation_results.len(), 1);
+    let counter = ds.get_single(key.clone()).await?.unwrap();
+    assert_eq!(counter.get_value("count"), Some(&Value::integer(2)));
+
+    // nothing to commit, the transaction is rolled back
+    let events = Arc::new(std::sync::Mutex::new(Vec::new()));
+    let recorded = events.clone();
+    let response = Transaction::new(&ds)
+        .with_observer(move |event: &TransactionEvent, _: &TransactionTags| {
+            recorded.lock().unwrap().push(event.clone());
+        })
+        .run_mutations(|_| async { Ok(MutationBatch::new()) })
+        .await?;
+    assert!(response.mutation_results.is_empty());
+    assert_eq!(
+        *events.lock().unwrap(),
+        vec![
+            TransactionEvent::AttemptStarted { attempt: 1 },
+            TransactionEvent::RolledBack { attempt: 1 },
+        ]
+    );
+
+    Ok(())
+}
+
+#[tokio::test]
+pub async fn test_lock_order() -> Result<(), EntailError> {
+    init_ring();
+    check_server();
+
+    let ds = DatastoreShell::new(
+        "test-project",
+        false,
+        Some(format!("db{}", fastrand::i64(..))),
+    )
+    .await
+    .map_err(|_| Default::default())?;
+    let from = Key::new("Balance").with_name("alice");
+    let to = Key::new("Balance").with_name("bob");
+    let mut batch = MutationBatch::new();
+    for key in [&from, &to] {
+        let mut e = Entity::new(key.clone());
+        e.set_indexed("amount", Value::integer(100));
+        batch = batch.insert(e);
+    }
+    ds.commit(batch).await?;
+
+    let transfer = |from: Key, to: Key| {
+        Transaction::new(&ds)
+            .with_lock_order([&from, &to])
+            .run_mutations(move |ts| {
+                let (from, to) = (from.clone(), to.clone());
+                async move {
+                    let mut batch = MutationBatch::new();
+                    for (key, delta) in [(from, -10), (to, 10)] {
+                        let mut e = ts.prefetched(&key).unwrap().clone();
+                        let amount = match e.get_value("amount") {
+                            Some(Value::Integer(amount)) => *amount,
+                            _ => 0,
+                        };
+                        e.set_indexed("amount", Value::integer(amount + delta));
+                        batch = batch.update(e);
+                    }
+                    Ok(batch)
+                }
+            })
+    };
+    // the transfers touch the same entities in opposite orders
+    let (a, b) = tokio::join!(
+        transfer(from.clone(), to.clone()),
+        transfer(to.clone(), from.clone())
+    );
+    a?;
+    b?;
+    for key in [from, to] {
+        let e = ds.get_single(key).await?.unwrap();
+        assert_eq!(e.get_value("amount"), Some(&Value::integer(100)));
+    }
+
+    Ok(())
+}
+
+#[derive(Entail, Default, Debug)]
+struct Profile {
+    #[entail]
+    key: String,
+    #[entail]
+    name: String,
+    #[entail]
+    email: String,
+    #[entail(unindexed)]
+    bio: String,
+}
+
+#[tokio::test]
+pub async fn test_fetch_selected() -> Result<(), EntailError> {
+    init_ring();
+    check_server();
+
+    let ds = DatastoreShell::new(
+        "test-project",
+        false,
+        Some(format!("db{}", fastrand::i64(..))),
+    )
+    .await
+    .map_err(|_| Default::default())?;
+    let profile = Profile {
+        key: "ann".into(),
+        name: "Ann".into(),
+        email: "ann@example.com".into(),
+        bio: "Likes databases".into(),
+    };
+    ds.commit(MutationBatch::new().insert(profile.to_ds_entity()?))
+        .await?;
+
+    let adapter = Profile::adapter();
+    let options = entail::FetchOptions::select(&["name", "bio"]);
+    let found = adapter
+        .fetch_selected(&ds, [adapter.create_named_key("ann")], &options)
+        .await?;
+    assert_eq!(found.len(), 1);
+    assert_eq!(
+        found[0].get_value("bio"),
+        Some(&Value::unicode_string("Likes databases"))
+    );
+    assert_eq!(found[0].get_value("email"), None);
+
+    let options = entail::FetchOptions::select(&["email"]);
+    let listed = adapter
+        .query_selected(&ds, adapter.query(), &options)
+        .await?;
+    assert_eq!(listed.items.len(), 1);
+    assert_eq!(listed.items[0].key(), &adapter.create_named_key("ann"));
+    assert_eq!(listed.items[0].get_value("name"), None);
+
+    let unknown = entail::FetchOptions::select(&["mail"]);
+    assert!(
+        adapter
+            .query_selected(&ds, adapter.query(), &unknown)
+            .await
+            .is_err()
+    );
+
+    Ok(())
+}
+
+#[tokio::test]
+pub async fn test_transaction_observer() -> Result<(), EntailError> {
+    init_ring();
+    check_server();
+
+    let ds = DatastoreShell::new(
+        "test-project",
+        false,
+        Some(format!("db{}", fastrand::i64(..))),
+    )
+    .await
+    .map_err(|_| Default::default())?;
+    let events = Arc::new(std::sync::Mutex::new(Vec::new()));
+    let recorded = events.clone();
+    let key = Key::new("Counter").with_name("visits");
+    Transaction::new(&ds)
+        .with_observer(move |event: &TransactionEvent, tags: &TransactionTags| {
+            recorded.lock().unwrap().push((event.clone(), tags.clone()));
+        })
+        .run_mutations(|_| {
+            let key = key.clone();
+            async move { Ok(MutationBatch::new().upsert(Entity::new(key))) }
+        })
+        .await?;
+
+    let events = events.lock().unwrap();
+    assert_eq!(events.len(), 2);
+    assert_eq!(events[0].0, TransactionEvent::AttemptStarted { attempt: 1 });
+    assert!(events[0].1.kinds.is_empty());
+    assert_eq!(events[1].0, TransactionEvent::Committed { attempt: 1 });
+    assert_eq!(events[1].1.kinds, vec!["Counter"]);
+    assert_eq!(events[1].1.entity_groups, vec![key]);
+
+    Ok(())
+}
+
+/// Records the delays instead of sleeping.
+struct RecordingClock(Arc<std::sync::Mutex<Vec<Duration>>>);
+
+impl Clock for RecordingClock {
+    fn sleep(&self, duration: Duration) -> Pin<Box<dyn Future<Output = ()> + Send>> {
+        self.0.lock().unwrap().push(duration);
+        Box::pin(std::future::ready(()))
+    }
+}
+
+/// Always picks the upper end of the range.
+struct Highest;
+
+impl JitterSource for Highest {
+    fn next_in(&mut self, range: Range<u64>) -> u64 {
+        range.end - 1
+    }
+}
+
+#[tokio::test]
+pub async fn test_retry_delays() -> Result<(), EntailError> {
+    init_ring();
+    check_server();
+
+    let ds = DatastoreShell::new(
+        "test-project",
+        false,
+        Some(format!("db{}", fastrand::i64(..))),
+    )
+    .await
+    .map_err(|_| Default::default())?;
+    let delays = Arc::new(std::sync::Mutex::new(Vec::new()));
+    let mut attempts = 0;
+    let result = Transaction::new(&ds)
+        .first_retry(Duration::from_millis(25))
+        .with_jitter(Jitter::Decorrelated)
+        .with_max_retry_delay(Duration::from_millis(100))
+        .with_clock(RecordingClock(delays.clone()))
+        .with_jitter_source(Highest)
+        .run(|_| {
+            attempts += 1;
+            let attempt = attempts;
+            async move {
+                if attempt <= 3 {
+                    let aborted = serde_json::json!({ "error": { "status": "ABORTED" } });
+                    return Err(EntailError {
+                        kind: EntailErrorKind::RequestFailure,
+                        message: "Conflict".into(),
+                        ds_error: Some(google_datastore1::Error::BadRequest(aborted)),
+                        mapping: None,
+                    });
+                }
+                Ok(attempt)
+            }
+        })
+        .await?;
+
+    assert_eq!(result, 4);
+    // between 25ms and three times the previous delay, capped by the maximum delay
+    assert_eq!(
+        *delays.lock().unwrap(),
+        vec![
+            Duration::from_micros(74_999),
+            Duration::from_millis(100),
+            Duration::from_millis(100)
+        ]
+    );
+
+    Ok(())
+}
+
+#[tokio::test]
+pub async fn test_transaction_inactive() -> Result<(), EntailError> {
+    init_ring();
+    check_server();
+
+    let ds = DatastoreShell::new(
+        "test-project",
+        false,
+        Some(format!("db{}", fastrand::i64(..))),
+    )
+    .await
+    .map_err(|_| Default::default())?;
+    let key = Key::new("Counter").with_name("escaped");
+    let escaped = Transaction::new(&ds)
+        .run(|ts| {
+            let key = key.clone();
+            async move {
+                // a clone of the inner shell outliving the transaction
+                let escaped: DatastoreShell = (*ts).clone();
+                ts.commit(MutationBatch::new().upsert(Entity::new(key)))
+                    .await?;
+                Ok(escaped)
+            }
+        })
+        .await?;
+
+    let err = escaped.get_single(key.clone()).await.unwrap_err();
+    assert_eq!(err.kind, EntailErrorKind::TransactionInactive);
+    let err = escaped
+        .commit(MutationBatch::new().delete(key.clone()))
+        .await
+        .unwrap_err();
+    assert_eq!(err.kind, EntailErrorKind::TransactionInactive);
+    // rolling back an ended transaction does nothing
+    escaped.rollback(&None).await?;
+    assert!(ds.get_single(key).await?.is_some());
+
+    Ok(())
+}
+
+#[tokio::test]
+pub async fn test_aggregation() -> Result<(), EntailError> {
+    init_ring();
+    check_server();
+
+    let ds = DatastoreShell::new(
+        "test-project",
+        false,
+        Some(format!("db{}", fastrand::i64(..))),
+    )
+    .await
+    .map_err(|_| Default::default())?;
+    let entities = (1..=4).map(|id| {
+        Entity::build(Key::new("Invoice").with_id(id))
+            .int("amount", id * 10)
+            .finish()
+    });
+    ds.commit(MutationBatch::new().upsert_all(entities)).await?;
+
+    let query = entail::ds::Query {
+        kind: "Invoice".into(),
+        ..Default::default()
+    };
+    let result = ds
+        .run_aggregation(
+            entail::ds::AggregationQuery::new(query.clone())
+                .count("invoices")
+                .count_up_to("some", 2)
+                .sum("total", "amount")
+                .avg("average", "amount"),
+        )
+        .await?;
+    assert_eq!(result.count("invoices"), Some(4));
+    assert_eq!(result.count("some"), Some(2));
+    assert_eq!(result.sum("total"), Some(100.0));
+    assert_eq!(result.sum_integer("total"), Some(100));
+    assert_eq!(result.avg("average"), Some(25.0));
+
+    // an explicit limit caps the aggregated entities
+    let capped = ds
+        .run_aggregation(
+            entail::ds::AggregationQuery::new(query)
+                .with_limit(3)
+                .count("n"),
+        )
+        .await?;
+    assert_eq!(capped.count("n"), Some(3));
+
+    Ok(())
+}
@@ -1,7 +1,9 @@
 use convert_case::{Case, Casing};
-use darling::{FromDeriveInput, FromField};
+use darling::util::Override;
+use darling::{FromDeriveInput, FromField, FromMeta, FromVariant};
 use proc_macro::TokenStream;
-use quote::{format_ident, quote};
+use quote::{format_ident, quote, quote_spanned};
+use std::borrow::Cow;
 use syn::spanned::Spanned;
 use syn::{
     DeriveInput, Field, Fields, GenericArgument, Ident, PathArguments, Type, parse_macro_input,
@@ -79,6 +81,18 @@ fn is_key_type(path: &syn::Path) -> bool {
     is_custom_type(path, KEY_TYPE_PATH)
 }
 
+const UUID_TYPE_PATH: &[&str] = &["uuid", "Uuid"];
+
+fn is_uuid_type(path: &syn::Path) -> bool {
+    is_custom_type(path, UUID_TYPE_PATH)
+}
+
+const NON_ZERO_I64_TYPE_PATH: &[&str] = &["std", "num", "NonZeroI64"];
+
+fn is_non_zero_i64_type(path: &syn::Path) -> bool {
+    is_custom_type(path, NON_ZERO_I64_TYPE_PATH)
+}
+
 /// Checks if the given `syn::Path` represents a `Cow<'static, str>` type.
 /// This function handles both simple "Cow" and fully qualified "std::borrow::Cow",
 /// and specifically verifies the generic arguments for `'static` lifetime and `str` type.
@@ -208,11 +222,44 @@ fn is_vec_type(path: &syn::Path) -> bool {
     false
 }
 
+/// Checks if the given `syn::Path` represents a `HashSet` or a `BTreeSet` type.
+/// This function handles both the simple names and the fully qualified
+/// "std::collections::HashSet" and "std::collections::BTreeSet".
+///
+/// # Arguments
+///
+/// * `path` - A reference to a `syn::Path` to check.
+///
+/// # Returns
+///
+/// `true` if the path represents a set type, `false` otherwise.
+fn is_set_type(path: &syn::Path) -> bool {
+    let segments = &path.segments;
+    if let Some(last_segment) = segments.last() {
+        if last_segment.ident != "HashSet" && last_segment.ident != "BTreeSet" {
+            return false;
+        }
+
+        if segments.len() == 1 {
+            return true;
+        }
+
+        if segments.len() == 3 && segments[0].ident == "std" && segments[1].ident == "collections"
+        {
+            return true;
+        }
+    }
+    false
+}
+
+fn is_u8_type(ty: &syn::Type) -> bool {
+    matches!(ty, syn::Type::Path(ty_path) if ty_path.qself.is_none() && ty_path.path.is_ident("u8"))
+}
+
 fn get_inner_type<'a>(type_path: &'a syn::Path) -> Option<&'a syn::Path> {
-    let last_segment = &type_path.segments.last().unwrap();
+    let last_segment = type_path.segments.last()?;
     if let syn::PathArguments::AngleBracketed(args) = &last_segment.arguments {
-        let ty = &args.args.first().unwrap();
-        if let syn::GenericArgument::Type(syn::Type::Path(embedded_ty)) = ty {
+        if let Some(syn::GenericArgument::Type(syn::Type::Path(embedded_ty))) = args.args.first() {
             Some(&embedded_ty.path)
         } else {
             None
@@ -222,14 +269,83 @@ fn get_inner_type<'a>(type_path: &'a syn::Path) -> Option<&'a syn::Path> {
     }
 }
 
-fn is_integer_type(path: &syn::Path) -> bool {
-    path.is_ident("i32") || path.is_ident("u32") || path.is_ident("i64")
-}
-
 fn has_attribute(field: &Field, name: &str) -> bool {
     field.attrs.iter().any(|attr| attr.path().is_ident(name))
 }
 
+// The naming and skipping options of the #[serde(...)] attributes, read with #[entail(serde)].
+// The other options of serde are ignored.
+#[derive(Debug, Default)]
+struct SerdeAttributes {
+    rename: Option<String>,
+    rename_all: Option<String>,
+    skip: bool,
+    flatten: bool,
+}
+
+impl SerdeAttributes {
+    fn parse(attrs: &[syn::Attribute]) -> syn::Result<Self> {
+        let mut serde = SerdeAttributes::default();
+        for attr in attrs.iter().filter(|attr| attr.path().is_ident("serde")) {
+            attr.parse_nested_meta(|meta| {
+                if meta.path.is_ident("rename") {
+                    serde.rename = Some(parse_serde_name(&meta)?);
+                } else if meta.path.is_ident("rename_all") {
+                    serde.rename_all = Some(parse_serde_name(&meta)?);
+                } else if meta.path.is_ident("skip") {
+                    serde.skip = true;
+                } else if meta.path.is_ident("flatten") {
+                    serde.flatten = true;
+                } else {
+                    skip_serde_meta(&meta)?;
+                }
+                Ok(())
+            })?;
+        }
+        Ok(serde)
+    }
+}
+
+// Reads `name = "..."`, or `name(serialize = "...", deserialize = "...")` with the same name
+// for both directions, as a property has a single name
+fn parse_serde_name(meta: &syn::meta::ParseNestedMeta) -> syn::Result<String> {
+    if meta.input.peek(syn::Token![=]) {
+        let value: syn::LitStr = meta.value()?.parse()?;
+        return Ok(value.value());
+    }
+    let mut serialize = None;
+    let mut deserialize = None;
+    meta.parse_nested_meta(|inner| {
+        let value: syn::LitStr = inner.value()?.parse()?;
+        if inner.path.is_ident("serialize") {
+            serialize = Some(value.value());
+        } else if inner.path.is_ident("deserialize") {
+            deserialize = Some(value.value());
+        } else {
+            return Err(inner.error("Expected `serialize` or `deserialize`"));
+        }
+        Ok(())
+    })?;
+    match (serialize, deserialize) {
+        (Some(serialize), Some(deserialize)) if serialize == deserialize => Ok(serialize),
+        _ => Err(meta.error(
+            "#[entail(serde)] requires the same name for serialization and deserialization, \
+             use #[entail(name = \"...\")] or #[entail(rename_all = \"...\")] to set the \
+             property names",
+        )),
+    }
+}
+
+// Consumes a serde option that Entail doesn't use, with or without a value
+fn skip_serde_meta(meta: &syn::meta::ParseNestedMeta) -> syn::Result<()> {
+    if meta.input.peek(syn::Token![=]) {
+        meta.value()?.parse::<syn::Expr>()?;
+    } else if meta.input.peek(syn::token::Paren) {
+        meta.parse_nested_meta(|inner| skip_serde_meta(&inner))?;
+    }
+    Ok(())
+}
+
 // Represents the parsed #[entail(...)] attribute for a field
 #[derive(Debug, Default, FromField)]
 #[darling(attributes(entail))]
@@ -244,6 +360,10 @@ struct EntailFieldAttribute {
     /// sets the meaning to `entail::ds::MEANING_TEXT`)
     #[darling(default)]
     pub text: bool,
+    /// #[entail(meaning = 7)] - Sets the meaning of the values, for legacy App Engine meanings
+    /// (e.g. 7 for GD_WHEN)
+    #[darling(default)]
+    pub meaning: Option<i32>,
     /// #[entail(name = "custom_name")] - Overrides the Datastore property name
     #[darling(default)]
     pub name: Option<String>,
@@ -256,6 +376,58 @@ struct EntailFieldAttribute {
     /// #[entail(unindexed_nulls)] - Indexes Option<T> only if not None
     #[darling(default)]
     pub unindexed_nulls: bool,
+    /// #[entail(default)] or #[entail(default = "path::to::fn")] - The value used when the
+    /// property is absent from the entity
+    #[darling(default)]
+    pub default: Option<Override<syn::Path>>,
+    /// #[entail(coerce)] - Accepts values of a different type that can be converted (e.g.
+    /// "42" for an integer field) when reading the entity
+    #[darling(default)]
+    pub coerce: bool,
+    /// #[entail(checksum)] - Stores the checksum of the other modeled properties in this field
+    #[darling(default)]
+    pub checksum: bool,
+    /// #[entail(parent)] - Maps this field to the parent of the entity key
+    #[darling(default)]
+    pub parent: bool,
+    /// #[entail(version)] - An optimistic concurrency counter incremented on every write
+    #[darling(default)]
+    pub version: bool,
+    /// #[entail(json)] - Stores the field serialized with serde_json in an unindexed string
+    #[darling(default)]
+    pub json: bool,
+    /// #[entail(auto_unindex)] - Stores the value unindexed if it is too large to be indexed
+    #[darling(default)]
+    pub auto_unindex: bool,
+    /// #[entail(alias = "old_name")] - A former property name the field is read from if the
+    /// property is absent, can be repeated
+    #[darling(default, multiple)]
+    pub alias: Vec<String>,
+    /// #[entail(key_strategy = "uuid")] - How an empty key is completed, only for the key
+    #[darling(default)]
+    pub key_strategy: Option<KeyStrategy>,
+    /// #[entail(description = "...")] - A human-readable description of the property
+    #[darling(default)]
+    pub description: Option<String>,
+    /// #[entail(validate = "path::to::fn")] - A function checking the field before it is
+    /// written, called with a reference to the field and returning a `Result<(), E>` where `E`
+    /// implements `Display`
+    #[darling(default)]
+    pub validate: Option<syn::Path>,
+    /// #[entail(validate_on_read)] - Runs the validation after reading the entity as well
+    #[darling(default)]
+    pub validate_on_read: bool,
+    /// #[entail(sensitive)] - Masks the property in `redacted_debug()` and in exports
+    #[darling(default)]
+    pub sensitive: bool,
+    /// #[entail(decimal_scale = 2)] - Stores a decimal as an integer multiplied by 10^scale
+    /// instead of a string
+    #[darling(default)]
+    pub decimal_scale: Option<u32>,
+    /// #[entail(soft_delete)] - A bool marking deleted entities, which the adapter queries
+    /// leave out
+    #[darling(default)]
+    pub soft_delete: bool,
 }
 
 // Represents the parsed #[entail(...)] attribute for the container (struct)
@@ -264,16 +436,243 @@ struct EntailFieldAttribute {
 struct EntailContainerAttribute {
     /// #[entail(rename_all = "camelCase")] - Global renaming policy
     #[darling(default)]
-    pub rename_all: Option<String>,
+    pub rename_all: Option<RenameRule>,
     /// #[entail(name = "KindName")] - Overrides the Datastore Kind name
     #[darling(default)]
     pub name: Option<String>,
+    /// #[entail(discriminator = "__type")] - The property storing the variant of an enum
+    #[darling(default)]
+    pub discriminator: Option<String>,
+    /// #[entail(transparent)] - Converts a single field struct like its field instead of
+    /// mapping it to an entity
+    #[darling(default)]
+    pub transparent: bool,
+    /// #[entail(auto_unindex)] - Applies #[entail(auto_unindex)] to every field
+    #[darling(default)]
+    pub auto_unindex: bool,
+    /// #[entail(projection_of = "Model")] - Reads a subset of the properties of the model
+    /// from projection queries instead of mapping an entity of its own
+    #[darling(default)]
+    pub projection_of: Option<syn::Path>,
+    /// #[entail(description = "...")] - A human-readable description of the Kind
+    #[darling(default)]
+    pub description: Option<String>,
+    /// #[entail(namespace = "tenant-a")] - The namespace of the keys and the queries of the Kind
+    #[darling(default)]
+    pub namespace: Option<String>,
+    /// #[entail(default_order = "-created, title")] - The order of the adapter queries without
+    /// orders, by field names, descending with a leading `-`
+    #[darling(default)]
+    pub default_order: Option<String>,
+    /// #[entail(serde)] - Maps every field not skipped by serde, taking the property names
+    /// from the #[serde(rename)] and #[serde(rename_all)] attributes
+    #[darling(default)]
+    pub serde: bool,
+}
+
+// The supported values of #[entail(rename_all = "...")], unknown values are rejected
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum RenameRule {
+    None,
+    Camel,
+    Pascal,
+    Snake,
+    Kebab,
+    ScreamingSnake,
+}
+
+impl RenameRule {
+    const VALUES: &'static [(&'static str, RenameRule)] = &[
+        ("", RenameRule::None),
+        ("camelCase", RenameRule::Camel),
+        ("PascalCase", RenameRule::Pascal),
+        ("snake_case", RenameRule::Snake),
+        ("kebab-case", RenameRule::Kebab),
+        ("SCREAMING_SNAKE_CASE", RenameRule::ScreamingSnake),
+    ];
+
+    fn apply(self, name: &str) -> String {
+        match self {
+            RenameRule::None => name.to_string(),
+            RenameRule::Camel => name.to_case(Case::Camel),
+            RenameRule::Pascal => name.to_case(Case::Pascal),
+            RenameRule::Snake => name.to_case(Case::Snake),
+            RenameRule::Kebab => name.to_case(Case::Kebab),
+            RenameRule::ScreamingSnake => name.to_case(Case::Constant),
+        }
+    }
+}
+
+impl FromMeta for RenameRule {
+    fn from_string(value: &str) -> darling::Result<Self> {
+        Self::VALUES
+            .iter()
+            .find(|(name, _)| *name == value)
+            .map(|(_, rule)| *rule)
+            .ok_or_else(|| {
+                let supported: Vec<String> = Self::VALUES
+                    .iter()
+                    .map(|(name, _)| format!("{:?}", name))
+                    .collect();
+                darling::Error::custom(format!(
+                    "Unknown rename_all value {:?}, supported values are {}",
+                    value,
+                    supported.join(", ")
+                ))
+            })
+    }
+}
+
+// The supported values of #[entail(key_strategy = "...")], mirroring entail::KeyStrategy
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum KeyStrategy {
+    Uuid,
+    Allocate,
+}
+
+impl FromMeta for KeyStrategy {
+    fn from_string(value: &str) -> darling::Result<Self> {
+        match value {
+            "uuid" => Ok(KeyStrategy::Uuid),
+            "allocate" => Ok(KeyStrategy::Allocate),
+            _ => Err(darling::Error::custom(format!(
+                "Unknown key_strategy value {:?}, supported values are \"uuid\" and \"allocate\"",
+                value
+            ))),
+        }
+    }
+}
+
+// Represents the parsed #[entail(...)] attribute for an enum variant
+#[derive(Debug, Default, FromVariant)]
+#[darling(attributes(entail))]
+struct EntailVariantAttribute {
+    /// #[entail(name = "circle")] - Overrides the value stored in the discriminator
+    #[darling(default)]
+    pub name: Option<String>,
+}
+
+const DEFAULT_DISCRIMINATOR: &str = "__type";
+
+/// The associated constants of `EntityModel`, no property name constant is generated with
+/// these names.
+const ENTITY_MODEL_CONSTANTS: &[&str] = &[
+    "KIND",
+    "CHECKSUM_PROPERTY",
+    "VERSION_PROPERTY",
+    "KEY_STRATEGY",
+    "PROPERTY_NAMES",
+    "SOFT_DELETE_PROPERTY",
+    "DEFAULT_ORDER",
+];
+
+/// The maximum length of a Kind or a property name, in bytes of UTF-8.
+const MAX_NAME_BYTES: usize = 1500;
+
+/// The maximum scale of a `rust_decimal::Decimal`.
+const MAX_DECIMAL_SCALE: u32 = 28;
+
+/// Checks the rules common to Kind and property names.
+fn check_name(what: &str, name: &str) -> Result<(), String> {
+    if name.is_empty() {
+        return Err(format!("The {} name cannot be empty", what));
+    }
+    if name.len() > MAX_NAME_BYTES {
+        return Err(format!(
+            "The {} name {:?} is {} bytes long in UTF-8, Datastore allows at most {} bytes",
+            what,
+            name,
+            name.len(),
+            MAX_NAME_BYTES
+        ));
+    }
+    if name.trim() != name {
+        return Err(format!(
+            "The {} name {:?} has leading or trailing whitespace",
+            what, name
+        ));
+    }
+    Ok(())
+}
+
+/// Checks a Kind name against the rules of Datastore, the ones `ds::Entity::validate` applies
+/// to the Kinds of a key path.
+fn check_kind_name(kind: &str) -> Result<(), String> {
+    check_name("Kind", kind)?;
+    if kind.starts_with("__") {
+        return Err(format!(
+            "The Kind name {:?} starts with `__`, which is reserved for the special Kinds of \
+             Datastore",
+            kind
+        ));
+    }
+    Ok(())
+}
+
+/// Resolves the fields of `#[entail(default_order = "...")]` to the orders of their
+/// properties, the key field being ordered by `__key__`.
+fn parse_default_order(
+    order: &str,
+    key_field: &ParsedField,
+    fields: &[&ParsedField],
+) -> Result<Vec<proc_macro2::TokenStream>, String> {
+    order
+        .split(',')
+        .map(|item| {
+            let item = item.trim();
+            let (field_name, direction) = match item.strip_prefix('-') {
+                Some(field_name) => (field_name, quote! { DESCENDING }),
+                None => (item, quote! { ASCENDING }),
+            };
+            let field = fields
+                .iter()
+                .find(|f| f.name == field_name)
+                .ok_or_else(|| {
+                    format!(
+                        "The default order refers to {:?}, which is not a field of the model",
+                        field_name
+                    )
+                })?;
+            let property_name = if std::ptr::eq(key_field, *field) {
+                "__key__"
+            } else if field.is_indexed() {
+                field.property_name.as_str()
+            } else {
+                return Err(format!(
+                    "The default order refers to {:?}, which is not indexed",
+                    field_name
+                ));
+            };
+            Ok(quote! { (#property_name, entail::ds::OrderDirection::#direction) })
+        })
+        .collect()
+}
+
+/// Checks a property name against the rules of Datastore, and rejects dots, which are read as
+/// the path of a property of an embedded entity by indexes, filters and projections.
+fn check_property_name(name: &str) -> Result<(), String> {
+    check_name("property", name)?;
+    if name.len() >= 4 && name.starts_with("__") && name.ends_with("__") {
+        return Err(format!(
+            "The property name {:?} matches `__.*__`, which is reserved for the special \
+             properties of Datastore",
+            name
+        ));
+    }
+    if name.contains('.') {
+        return Err(format!(
+            "The property name {:?} contains a dot, which Datastore reads as the path of a \
+             property of an embedded entity in indexes, filters and projections",
+            name
+        ));
+    }
+    Ok(())
 }
 
 #[derive(Debug)]
 struct ParsedField<'a> {
     name: &'a proc_macro2::Ident,
-    ty_path: &'a syn::Path,
+    ty_path: Cow<'a, syn::Path>,
     attrs: EntailFieldAttribute,
     property_name: String,
 }
@@ -284,45 +683,89 @@ struct ParsedFieldPair<'a> {
 }
 
 impl<'a> ParsedField<'a> {
-    fn build(f: &'a Field, c: &'a EntailContainerAttribute) -> Option<Self> {
+    fn build(f: &'a Field, c: &'a EntailContainerAttribute) -> syn::Result<Option<Self>> {
         // Check if the field is named
-        let name = f.ident.as_ref()?;
+        let Some(name) = f.ident.as_ref() else {
+            return Ok(None);
+        };
 
-        // Check if the type is a Path and extract it
-        let ty_path = if let syn::Type::Path(ty_path) = &f.ty {
-            &ty_path.path
+        let serde = if c.serde {
+            SerdeAttributes::parse(&f.attrs)?
         } else {
-            return None;
+            SerdeAttributes::default()
         };
-
-        if !has_attribute(f, "entail") {
-            return None;
+        if !has_attribute(f, "entail") && (!c.serde || serde.skip) {
+            return Ok(None);
         }
-        let attrs = match EntailFieldAttribute::from_field(f) {
-            Ok(attrs) => attrs,
-            Err(e) => {
-                e.write_errors();
-                return None;
+        if serde.flatten {
+            return Err(syn::Error::new_spanned(
+                name,
+                "#[serde(flatten)] fields cannot be mapped to properties, use \
+                 #[entail(json)] or #[serde(skip)] on the field",
+            ));
+        }
+
+        // Check if the type is a Path and extract it, byte arrays are handled through an alias
+        let ty_path = match &f.ty {
+            syn::Type::Path(ty_path) => Cow::Borrowed(&ty_path.path),
+            syn::Type::Array(array) if is_u8_type(&array.elem) => {
+                // a const generic argument is only an expression in braces
+                let len = match &array.len {
+                    len @ syn::Expr::Lit(_) => quote! { #len },
+                    len => quote! { { #len } },
+                };
+                Cow::Owned(syn::parse_quote_spanned! {f.ty.span()=> entail::ByteArray<#len> })
+            }
+            _ => {
+                return Err(syn::Error::new_spanned(
+                    &f.ty,
+                    "Unsupported field type, Entail fields must have a type implementing \
+                     entail::IntoValue and entail::FromValue",
+                ));
             }
         };
 
-        let property_name = if let Some(s) = &attrs.name {
+        let attrs = EntailFieldAttribute::from_field(f)?;
+
+        let property_name = if let Some(s) = attrs.name.as_ref().or(serde.rename.as_ref()) {
             s.clone()
         } else {
-            match c.rename_all.as_ref().map(String::as_str).unwrap_or("") {
-                "camelCase" => name.to_string().to_case(Case::Camel),
-                "PascalCase" => name.to_string().to_case(Case::Pascal),
-                "snake_case" => name.to_string().to_case(Case::Snake),
-                _ => name.to_string(),
-            }
+            c.rename_all
+                .unwrap_or(RenameRule::None)
+                .apply(&name.to_string())
         };
+        if attrs.text && attrs.meaning.is_some() {
+            return Err(syn::Error::new_spanned(
+                name,
+                "#[entail(text)] sets the meaning of the values, it cannot have another meaning",
+            ));
+        }
+        if attrs.meaning.is_some() && (attrs.key || attrs.parent) {
+            return Err(syn::Error::new_spanned(
+                name,
+                "The key and the parent are not properties, they cannot have a meaning",
+            ));
+        }
+        if attrs.validate_on_read && attrs.validate.is_none() {
+            return Err(syn::Error::new_spanned(
+                name,
+                "#[entail(validate_on_read)] requires #[entail(validate = \"...\")]",
+            ));
+        }
+        // the key and the parent are stored in the key of the entity, not in properties
+        if !attrs.key && !attrs.parent {
+            std::iter::once(&property_name)
+                .chain(&attrs.alias)
+                .try_for_each(|property_name| check_property_name(property_name))
+                .map_err(|message| syn::Error::new_spanned(name, message))?;
+        }
 
-        Some(ParsedField {
+        Ok(Some(ParsedField {
             name,
             ty_path,
             attrs,
             property_name,
-        })
+        }))
     }
 
     fn is_nullable(&self) -> bool {
@@ -330,27 +773,26 @@ impl<'a> ParsedField<'a> {
     }
 
     fn is_array(&self) -> bool {
-        is_vec_type(&self.ty_path)
-            || get_inner_type(self.ty_path)
-                .map(is_vec_type)
+        let is_collection = |path: &syn::Path| is_vec_type(path) || is_set_type(path);
+        is_collection(&self.ty_path)
+            || get_inner_type(&self.ty_path)
+                .map(is_collection)
                 .unwrap_or(false)
     }
 
-    fn type_path(&self) -> &'a syn::Path {
+    fn type_path(&self) -> syn::Result<&syn::Path> {
         if !self.is_nullable() && !self.is_array() {
-            &self.ty_path
+            Ok(&self.ty_path)
         } else {
-            let last_segment = &self.ty_path.segments.last().unwrap();
-            match get_inner_type(self.ty_path).and_then(|e| {
-                if self.is_nullable() && self.is_array() {
-                    get_inner_type(e)
-                } else {
-                    Some(e)
-                }
-            }) {
-                Some(path) => path,
-                None => panic!("Unrecognized argument in {:?}", &last_segment.span()),
-            }
+            get_inner_type(&self.ty_path)
+                .and_then(|e| {
+                    if self.is_nullable() && self.is_array() {
+                        get_inner_type(e)
+                    } else {
+                        Some(e)
+                    }
+                })
+                .ok_or_else(|| syn::Error::new_spanned(&self.ty_path, "Unrecognized type argument"))
         }
     }
 
@@ -358,79 +800,738 @@ impl<'a> ParsedField<'a> {
         syn::LitStr::new(&self.property_name, self.name.span())
     }
 
+    /// Whether the field holds `rust_decimal::Decimal` values, matched by the name of the type
+    /// as the macro doesn't resolve types.
+    fn is_decimal(&self) -> bool {
+        self.type_path()
+            .is_ok_and(|ty| ty.segments.last().is_some_and(|s| s.ident == "Decimal"))
+    }
+
+    /// Whether the values of the field are indexed, so they can be filtered and ordered on.
+    fn is_indexed(&self) -> bool {
+        !self.attrs.parent
+            && !self.attrs.checksum
+            && !self.attrs.version
+            && !self.attrs.json
+            && !self.attrs.text
+            && (!self.attrs.unindexed || self.attrs.indexed)
+    }
+
+    /// The type of the values a filter on this field compares to: the element type for
+    /// arrays (except for blobs, which are `Vec<u8>`), the field type otherwise.
+    fn filter_value_type(&self) -> syn::Result<proc_macro2::TokenStream> {
+        if self.is_array() {
+            let element = self.type_path()?;
+            if !element.is_ident("u8") {
+                return Ok(quote! { #element });
+            }
+        }
+        Ok(self.value_type())
+    }
+
+    /// `u8` and `Option<u8>` fields are integers, but `u8` has no `IntoValue` implementation
+    /// (`Vec<u8>` is a blob), so they are converted through `entail::ByteValue`.
+    fn is_byte(&self) -> bool {
+        !self.is_array() && self.type_path().is_ok_and(|ty| ty.is_ident("u8"))
+    }
+
+    /// The type implementing `IntoValue` and `FromValue` for the field.
+    fn value_type(&self) -> proc_macro2::TokenStream {
+        let ty = &self.ty_path;
+        if self.is_byte() {
+            quote_spanned! {ty.span()=> entail::ByteValue<#ty> }
+        } else {
+            quote! { #ty }
+        }
+    }
+
+    /// Generates the statement setting the property of the field on the entity `e`.
+    ///
+    /// ## Parameters
+    /// - `value`: An expression of type `&T`, where `T` is the type of the field.
+    /// - `auto_unindex`: Whether the container has `#[entail(auto_unindex)]`.
+    fn set_property(
+        &self,
+        value: proc_macro2::TokenStream,
+        auto_unindex: bool,
+    ) -> proc_macro2::TokenStream {
+        let property_name_lit: syn::LitStr = self.create_property_name_lit();
+        let meaning = self.meaning_as_string();
+
+        // JSON strings are never indexed, they can easily exceed the 1500 byte limit
+        let index_values = !self.attrs.text
+            && !self.attrs.json
+            && (!self.attrs.unindexed || self.attrs.indexed);
+        let index_nulls =
+            !self.attrs.unindexed_nulls && !self.attrs.unindexed && !self.attrs.json;
+
+        // errors about missing trait implementations should point at the field type
+        let ty = &self.ty_path;
+        let to_value = if self.attrs.json {
+            quote_spanned! {ty.span()=>
+                entail::to_json_value::<#ty>(#value)?
+            }
+        } else if let Some(scale) = self.attrs.decimal_scale {
+            quote_spanned! {ty.span()=>
+                entail::to_scaled_value::<#ty>(#value, #scale)?
+            }
+        } else if self.is_byte() {
+            quote_spanned! {ty.span()=>
+                <entail::ByteValue<#ty> as entail::IntoValue>::try_to_value(
+                    &entail::ByteValue(*#value))?
+            }
+        } else {
+            quote_spanned! {ty.span()=>
+                <#ty as entail::IntoValue>::try_to_value(#value)?
+            }
+        };
+        if index_values && (self.attrs.auto_unindex || auto_unindex) {
+            // oversize values would make the commit fail if they were indexed
+            return quote! {
+                let value = #to_value;
+                let index_values = !value.exceeds_index_limit();
+                e.set_advanced(#property_name_lit, value,
+                    index_values, #index_nulls, #meaning);
+            };
+        }
+        quote! {
+            e.set_advanced(#property_name_lit, #to_value,
+                #index_values, #index_nulls, #meaning);
+        }
+    }
+
+    /// Generates the call of the validation function of the field, returning the error of a
+    /// failed validation, or `None` if the field has no validation.
+    ///
+    /// ## Parameters
+    /// - `value`: An expression of a reference to the value of the field.
+    /// - `property_name`: An expression of the property name for the mapping context.
+    /// - `kind`: An expression of the Kind.
+    fn validate(
+        &self,
+        value: proc_macro2::TokenStream,
+        property_name: impl quote::ToTokens,
+        kind: impl quote::ToTokens,
+    ) -> Option<proc_macro2::TokenStream> {
+        let validate = self.attrs.validate.as_ref()?;
+        let field_name = self.name;
+        let template = format!("Invalid {{}}.{}: {{}}", field_name);
+        let mapping = create_mapping_context(
+            &kind,
+            property_name,
+            &type_name(&self.ty_path),
+            quote! { None },
+        );
+        Some(quote_spanned! {validate.span()=>
+            if let Err(err) = #validate(#value) {
+                return Err(entail::EntailError {
+                    kind: entail::EntailErrorKind::ValidationFailed,
+                    message: format!(#template, #kind, err).into(),
+                    mapping: #mapping,
+                    ..entail::EntailError::default()
+                });
+            }
+        })
+    }
+
+    /// Generates the expression reading the field from the property of the entity `e`, with
+    /// the defaults applied to absent properties.
+    ///
+    /// ## Parameters
+    /// - `property_name`: An expression of the property name.
+    /// - `kind`: An expression of the Kind for the mapping context of the errors.
+    /// - `context`: Appended to the messages of the errors.
+    fn read_property(
+        &self,
+        property_name: proc_macro2::TokenStream,
+        kind: proc_macro2::TokenStream,
+        context: &str,
+    ) -> proc_macro2::TokenStream {
+        let ty = &self.ty_path;
+        let value_type = self.value_type();
+        let from_value = if self.attrs.json {
+            quote_spanned! {ty.span()=> entail::from_json_value::<#ty> }
+        } else if let Some(scale) = self.attrs.decimal_scale {
+            quote_spanned! {ty.span()=>
+                (|value| entail::from_scaled_value::<#ty>(value, #scale))
+            }
+        } else if self.attrs.coerce {
+            quote_spanned! {ty.span()=> <#value_type as entail::FromValue>::from_value_lenient }
+        } else {
+            quote_spanned! {ty.span()=> <#value_type as entail::FromValue>::from_value }
+        };
+        // unwraps the entail::ByteValue of u8 fields
+        let unwrap_value = if self.is_byte() && !self.attrs.json {
+            quote! { .map(|val| val.0) }
+        } else {
+            quote! {}
+        };
+        // entities written before a rename still have the property under one of its aliases
+        let aliases = &self.attrs.alias;
+        let found = quote! {
+            e.get_value(#property_name)#(.or_else(|| e.get_value(#aliases)))*
+        };
+        let mapping = create_mapping_context(
+            kind,
+            &property_name,
+            &type_name(ty),
+            quote! { #found.map(entail::ds::Value::value_type) },
+        );
+        let initializer = quote! {
+            match #from_value(#found.unwrap_or(&null_value))#unwrap_value {
+                Ok(val) => val,
+                Err(err) => return Err(entail::EntailError {
+                    message: format!("{}{}", err.message, #context).into(),
+                    mapping: #mapping,
+                    ..err
+                }),
+            }
+        };
+
+        match (&self.attrs.default, self.attrs.version) {
+            (None, false) => initializer,
+            // entities written before the version field was introduced are at version 0
+            (default, _) => {
+                let default_value = match default {
+                    None | Some(Override::Inherit) => {
+                        quote! { ::std::default::Default::default() }
+                    }
+                    Some(Override::Explicit(path)) => quote! { #path() },
+                };
+                quote! {
+                    if #found.is_some() {
+                        #initializer
+                    } else {
+                        #default_value
+                    }
+                }
+            }
+        }
+    }
+
     fn meaning_as_string(&self) -> proc_macro2::TokenStream {
         if self.attrs.text {
             quote! { Some(entail::ds::MEANING_TEXT) }
+        } else if let Some(meaning) = self.attrs.meaning {
+            quote! { Some(#meaning) }
         } else {
             quote! { None }
         }
     }
 }
 
-fn create_raw_err(text: &str, span: proc_macro2::Span) -> proc_macro2::TokenStream {
-    let err_str = syn::LitStr::new(text, span);
+/// Renders a type the way it is usually written, e.g. `Option<Cow<'static, str>>`.
+fn type_name(ty: &syn::Path) -> String {
+    let tokens = quote! { #ty }.to_string();
+    tokens
+        .split_whitespace()
+        .collect::<String>()
+        .replace(',', ", ")
+}
+
+fn create_mapping_context(
+    kind: impl quote::ToTokens,
+    property: impl quote::ToTokens,
+    expected_type: &str,
+    actual: proc_macro2::TokenStream,
+) -> proc_macro2::TokenStream {
+    quote! {
+        Some(::std::boxed::Box::new(entail::MappingContext {
+            kind: #kind.into(),
+            property: #property.into(),
+            expected_type: #expected_type.into(),
+            actual: #actual,
+        }))
+    }
+}
+
+/// Generates the inherent `get`, `save` and `delete` methods of a model, delegating to its
+/// adapter, when the `active-record` feature is enabled.
+fn active_record_methods(input: &DeriveInput) -> proc_macro2::TokenStream {
+    if !cfg!(feature = "active-record") {
+        return quote! {};
+    }
+    let name = &input.ident;
+    let vis = &input.vis;
+    let (impl_generics, type_generics, where_clause) = input.generics.split_for_impl();
+    quote! {
+        impl #impl_generics #name #type_generics #where_clause {
+            /// Fetches the model by key, see [`entail::EntityAdapter::fetch_single`].
+            #vis async fn get(
+                ds: &entail::ds::DatastoreShell,
+                key: entail::ds::Key,
+            ) -> Result<Self, entail::EntailError> {
+                <Self as entail::EntityModel>::adapter().fetch_single(ds, key).await
+            }
+
+            /// Writes the model, completing an empty key, see
+            /// [`entail::EntityAdapter::upsert_model`].
+            #vis async fn save(
+                &mut self,
+                ds: &entail::ds::DatastoreShell,
+            ) -> Result<entail::ds::Key, entail::EntailError> {
+                <Self as entail::EntityModel>::adapter().upsert_model(ds, self).await
+            }
+
+            /// Deletes the entity of the model, see [`entail::EntityAdapter::delete_model`].
+            #vis async fn delete(
+                &self,
+                ds: &entail::ds::DatastoreShell,
+            ) -> Result<(), entail::EntailError> {
+                <Self as entail::EntityModel>::adapter().delete_model(ds, self).await
+            }
+        }
+    }
+}
+
+fn create_key_err(
+    text: &str,
+    kind: impl quote::ToTokens,
+    key_type_path: &syn::Path,
+) -> proc_macro2::TokenStream {
+    let err_str = syn::LitStr::new(text, key_type_path.span());
+    let mapping =
+        create_mapping_context(kind, "__key__", &type_name(key_type_path), quote! { None });
     quote! { entail::EntailError {
         kind: entail::EntailErrorKind::PropertyMappingError,
         message: #err_str.into(),
+        mapping: #mapping,
         ..entail::EntailError::default()
     } }
 }
 
-fn create_err(text: &str, span: proc_macro2::Span) -> proc_macro2::TokenStream {
-    let inside = create_raw_err(text, span);
-    quote! { Err(#inside) }
+fn invalid_key_type(key_field: &ParsedField) -> syn::Error {
+    syn::Error::new_spanned(
+        &key_field.ty_path,
+        "Invalid key type, expected String, Cow<'static, str>, i64, NonZeroI64, uuid::Uuid \
+         or entail::ds::Key, optionally wrapped in an Option",
+    )
+}
+
+/// Generates the `description` and `property_descriptions` methods of `EntityModel`, the
+/// defaults are kept if nothing is described.
+fn describe_model(
+    description: Option<&str>,
+    property_descriptions: &[(syn::LitStr, &str)],
+) -> proc_macro2::TokenStream {
+    let description = description.map(|description| {
+        quote! {
+            fn description() -> Option<&'static str> {
+                Some(#description)
+            }
+        }
+    });
+    let property_descriptions = (!property_descriptions.is_empty()).then(|| {
+        let (names, descriptions): (Vec<_>, Vec<_>) = property_descriptions.iter().cloned().unzip();
+        quote! {
+            fn property_descriptions() -> &'static [(&'static str, &'static str)] {
+                &[#((#names, #descriptions)),*]
+            }
+        }
+    });
+    quote! {
+        #description
+        #property_descriptions
+    }
+}
+
+/// Generates the expression reading the key field from the key of the entity `e`.
+///
+/// ## Parameters
+/// - `kind`: The Kind named in the error messages.
+/// - `kind_expr`: An expression of the Kind for the mapping context of the errors.
+fn read_key(
+    key_field: &ParsedField,
+    kind: &str,
+    kind_expr: proc_macro2::TokenStream,
+) -> syn::Result<proc_macro2::TokenStream> {
+    let key_type_path = key_field.type_path()?;
+    let key_err = |text: &str| {
+        create_key_err(
+            format!("{} for entity {}", text, kind).as_str(),
+            &kind_expr,
+            key_type_path,
+        )
+    };
+    let key_value = if is_cow_static_str_type(key_type_path) || is_string_type(key_type_path) {
+        let incorrect_key = key_err("Key has no name");
+        if key_field.is_nullable() {
+            quote! { e.key().name().map(|name| String::from(name).into()) }
+        } else {
+            quote! { String::from(e.key().name().ok_or_else(|| #incorrect_key)?).into() }
+        }
+    } else if key_type_path.is_ident("i64") {
+        let incorrect_key = key_err("Key has no id");
+        if key_field.is_nullable() {
+            quote! { e.key().id() }
+        } else {
+            quote! { e.key().id().ok_or_else(|| #incorrect_key)? }
+        }
+    } else if is_uuid_type(key_type_path) {
+        let incorrect_key = key_err("Key has no UUID name");
+        let parse = quote! {
+            <#key_type_path as ::std::str::FromStr>::from_str(name).map_err(|_| #incorrect_key)?
+        };
+        if key_field.is_nullable() {
+            quote! {
+                match e.key().name() {
+                    None => None,
+                    Some(name) => Some(#parse),
+                }
+            }
+        } else {
+            quote! {
+                match e.key().name() {
+                    None => return Err(#incorrect_key),
+                    Some(name) => #parse,
+                }
+            }
+        }
+    } else if is_non_zero_i64_type(key_type_path) {
+        let incorrect_key = key_err("Key has no non-zero id");
+        let id = quote! { e.key().id().and_then(::std::num::NonZeroI64::new) };
+        if key_field.is_nullable() {
+            id
+        } else {
+            quote! { #id.ok_or_else(|| #incorrect_key)? }
+        }
+    } else if is_key_type(key_type_path) {
+        if key_field.is_nullable() {
+            quote! { Some(e.key().clone()) }
+        } else {
+            quote! { e.key().clone() }
+        }
+    } else {
+        return Err(invalid_key_type(key_field));
+    };
+    Ok(key_value)
+}
+
+/// Generates the expression reading the parent field from the key of the entity `e`.
+fn read_parent(
+    parent_field: &ParsedField,
+    kind: &str,
+    kind_expr: proc_macro2::TokenStream,
+) -> proc_macro2::TokenStream {
+    if parent_field.is_nullable() {
+        quote! { e.key().parent().cloned() }
+    } else {
+        let no_parent = create_key_err(
+            format!("Key has no parent for entity {}", kind).as_str(),
+            &kind_expr,
+            &parent_field.ty_path,
+        );
+        quote! { e.key().parent().cloned().ok_or_else(|| #no_parent)? }
+    }
 }
 
 #[proc_macro_derive(Entail, attributes(entail))]
 pub fn derive_entail(input: TokenStream) -> TokenStream {
     let input = parse_macro_input!(input as DeriveInput);
-    let entail_input = match EntailContainerAttribute::from_derive_input(&input) {
-        Ok(input) => input,
-        Err(e) => return e.write_errors().into(),
-    };
+    expand_entail(&input)
+        .unwrap_or_else(syn::Error::into_compile_error)
+        .into()
+}
+
+fn expand_entail(input: &DeriveInput) -> syn::Result<proc_macro2::TokenStream> {
+    let mut entail_input = EntailContainerAttribute::from_derive_input(input)?;
+
+    if entail_input.serde {
+        if let syn::Data::Enum(_) = input.data {
+            return Err(syn::Error::new_spanned(
+                &input.ident,
+                "#[entail(serde)] requires a struct, the renaming of serde applies to the \
+                 variants of an enum, not to properties",
+            ));
+        }
+        let serde = SerdeAttributes::parse(&input.attrs)?;
+        if let (None, Some(rename_all)) = (entail_input.rename_all, serde.rename_all) {
+            entail_input.rename_all = Some(
+                RenameRule::from_string(&rename_all)
+                    .map_err(|err| syn::Error::new_spanned(&input.ident, err))?,
+            );
+        }
+    }
+
+    if entail_input.transparent {
+        return derive_transparent(input);
+    }
 
     let name = &input.ident;
     let raw_name = name.to_string();
     let kind = entail_input.name.as_ref().unwrap_or(&raw_name).as_str();
     let kind_str = syn::LitStr::new(kind, name.span());
+    // a projection has the Kind of its model
+    if entail_input.projection_of.is_none() {
+        check_kind_name(kind).map_err(|message| syn::Error::new_spanned(name, message))?;
+    }
     let fields = match &input.data {
         syn::Data::Struct(syn::DataStruct {
             fields: Fields::Named(fields),
             ..
         }) => &fields.named,
-        _ => panic!("Entail can only be derived for structs with named fields"),
+        syn::Data::Enum(_) if entail_input.projection_of.is_some() => {
+            return Err(syn::Error::new_spanned(
+                name,
+                "#[entail(projection_of)] requires a struct with named fields",
+            ));
+        }
+        syn::Data::Enum(_) if entail_input.namespace.is_some() => {
+            return Err(syn::Error::new_spanned(
+                name,
+                "#[entail(namespace)] requires a struct, the keys of an enum are created by the \
+                 structs of its variants",
+            ));
+        }
+        syn::Data::Enum(_) if entail_input.default_order.is_some() => {
+            return Err(syn::Error::new_spanned(
+                name,
+                "#[entail(default_order)] requires a struct, the fields of an enum are the ones \
+                 of its variants",
+            ));
+        }
+        syn::Data::Enum(data) => return derive_entail_enum(input, data, &entail_input, kind),
+        syn::Data::Struct(syn::DataStruct {
+            fields: Fields::Unnamed(fields),
+            ..
+        }) => {
+            return Err(syn::Error::new_spanned(
+                fields,
+                "Entail cannot be derived for tuple structs, the fields of a model need names \
+                 to be mapped to properties. Use named fields, or #[entail(transparent)] for a \
+                 newtype wrapping a single value",
+            ));
+        }
+        syn::Data::Struct(_) => {
+            return Err(syn::Error::new_spanned(
+                name,
+                "Entail cannot be derived for unit structs, a model needs at least a key field",
+            ));
+        }
+        syn::Data::Union(data) => {
+            return Err(syn::Error::new_spanned(
+                data.union_token,
+                "Entail cannot be derived for unions, use a struct or an enum",
+            ));
+        }
     };
 
     let all_fields: Vec<ParsedFieldPair> = fields
         .iter()
-        .map(|field| ParsedFieldPair {
-            field,
-            parsed_field: ParsedField::build(&field, &entail_input),
+        .map(|field| {
+            Ok(ParsedFieldPair {
+                field,
+                parsed_field: ParsedField::build(field, &entail_input)?,
+            })
         })
-        .filter(|fp| fp.parsed_field.is_some())
-        .collect();
+        .collect::<syn::Result<_>>()?;
+    if let Some(model) = &entail_input.projection_of {
+        if entail_input.namespace.is_some() || entail_input.default_order.is_some() {
+            return Err(syn::Error::new_spanned(
+                name,
+                "A projection is queried in the namespace and with the default order of its \
+                 model, it cannot have its own",
+            ));
+        }
+        return derive_projection(input, model, &all_fields);
+    }
     let parsed_fields: Vec<&ParsedField> = all_fields
         .iter()
         .filter_map(|f| f.parsed_field.as_ref())
         .collect();
-    let key_field: &ParsedField = parsed_fields
+    let key_fields: Vec<&ParsedField> = parsed_fields
         .iter()
+        .copied()
         .filter(|pf| pf.attrs.key || !pf.attrs.field && pf.name.to_string() == "key")
-        .try_fold(None, |acc, item| match &acc {
-            None => Ok(Some(item)),
-            Some(_) => Err(()),
-        })
-        .ok()
-        .unwrap_or_else(|| panic!("Multiple primary keys found on {:?}", &name.span()))
-        .unwrap_or_else(|| panic!("No primary key found on {:?}", &name.span()));
+        .collect();
+    let key_field: &ParsedField = match key_fields.as_slice() {
+        [key_field] => key_field,
+        [] => {
+            return Err(syn::Error::new_spanned(
+                name,
+                "No primary key found, mark a field with #[entail(key)] or name it `key`",
+            ));
+        }
+        [_, second, ..] => {
+            return Err(syn::Error::new_spanned(
+                second.name,
+                "Multiple primary keys found",
+            ));
+        }
+    };
 
     if key_field.is_array() {
-        panic!("Keys cannot be arrays: {:?}", &key_field.name.span());
+        return Err(syn::Error::new_spanned(
+            &key_field.ty_path,
+            "Keys cannot be arrays",
+        ));
+    }
+
+    let checksum_fields: Vec<&&ParsedField> = parsed_fields
+        .iter()
+        .filter(|pf| pf.attrs.checksum)
+        .collect();
+    if let [_, second, ..] = checksum_fields.as_slice() {
+        return Err(syn::Error::new_spanned(
+            second.name,
+            "Multiple checksum fields found",
+        ));
+    }
+    let checksum_field: Option<&ParsedField> = checksum_fields.first().map(|pf| **pf);
+    if checksum_field.is_some_and(|f| std::ptr::eq(key_field, f)) {
+        return Err(syn::Error::new_spanned(
+            key_field.name,
+            "The key cannot be a checksum",
+        ));
+    }
+    let parent_fields: Vec<&&ParsedField> =
+        parsed_fields.iter().filter(|pf| pf.attrs.parent).collect();
+    if let [_, second, ..] = parent_fields.as_slice() {
+        return Err(syn::Error::new_spanned(
+            second.name,
+            "Multiple parent fields found",
+        ));
+    }
+    let parent_field: Option<&ParsedField> = parent_fields.first().map(|pf| **pf);
+    if let Some(parent_field) = parent_field {
+        if std::ptr::eq(key_field, parent_field) || parent_field.attrs.checksum {
+            return Err(syn::Error::new_spanned(
+                parent_field.name,
+                "The parent cannot be the key or a checksum",
+            ));
+        }
+        if parent_field.is_array() || !is_key_type(parent_field.type_path()?) {
+            return Err(syn::Error::new_spanned(
+                &parent_field.ty_path,
+                "Invalid parent type, expected entail::ds::Key, optionally wrapped in an Option",
+            ));
+        }
+    }
+    let version_fields: Vec<&&ParsedField> =
+        parsed_fields.iter().filter(|pf| pf.attrs.version).collect();
+    if let [_, second, ..] = version_fields.as_slice() {
+        return Err(syn::Error::new_spanned(
+            second.name,
+            "Multiple version fields found",
+        ));
+    }
+    let version_field: Option<&ParsedField> = version_fields.first().map(|pf| **pf);
+    if let Some(version_field) = version_field {
+        if std::ptr::eq(key_field, version_field)
+            || version_field.attrs.checksum
+            || version_field.attrs.parent
+        {
+            return Err(syn::Error::new_spanned(
+                version_field.name,
+                "The version cannot be the key, the parent or a checksum",
+            ));
+        }
+        if !version_field.ty_path.is_ident("i64") {
+            return Err(syn::Error::new_spanned(
+                &version_field.ty_path,
+                "Invalid version type, expected i64",
+            ));
+        }
+    }
+    let soft_delete_fields: Vec<&&ParsedField> = parsed_fields
+        .iter()
+        .filter(|pf| pf.attrs.soft_delete)
+        .collect();
+    if let [_, second, ..] = soft_delete_fields.as_slice() {
+        return Err(syn::Error::new_spanned(
+            second.name,
+            "Multiple soft delete fields found",
+        ));
+    }
+    let soft_delete_field: Option<&ParsedField> = soft_delete_fields.first().map(|pf| **pf);
+    if let Some(soft_delete_field) = soft_delete_field {
+        if std::ptr::eq(key_field, soft_delete_field) || !soft_delete_field.is_indexed() {
+            return Err(syn::Error::new_spanned(
+                soft_delete_field.name,
+                "The soft delete flag is filtered on, it must be an indexed property",
+            ));
+        }
+        if !soft_delete_field.ty_path.is_ident("bool") {
+            return Err(syn::Error::new_spanned(
+                &soft_delete_field.ty_path,
+                "Invalid soft delete type, expected bool",
+            ));
+        }
+    }
+    let default_order = match &entail_input.default_order {
+        Some(order) => parse_default_order(order, key_field, &parsed_fields)
+            .map_err(|message| syn::Error::new_spanned(name, message))?,
+        None => Vec::new(),
+    };
+    if let Some(json_field) = parsed_fields.iter().find(|pf| {
+        pf.attrs.json
+            && (std::ptr::eq(key_field, **pf)
+                || pf.attrs.parent
+                || pf.attrs.checksum
+                || pf.attrs.version
+                || pf.attrs.indexed
+                || pf.attrs.coerce)
+    }) {
+        return Err(syn::Error::new_spanned(
+            json_field.name,
+            "A JSON field cannot be the key, the parent, a checksum, a version, indexed or coerced",
+        ));
+    }
+    if let Some(scaled) = parsed_fields.iter().find(|pf| {
+        pf.attrs.decimal_scale.is_some()
+            && (std::ptr::eq(key_field, **pf)
+                || pf.attrs.parent
+                || pf.attrs.checksum
+                || pf.attrs.version
+                || pf.attrs.json
+                || pf.attrs.coerce)
+    }) {
+        return Err(syn::Error::new_spanned(
+            scaled.name,
+            "A scaled decimal cannot be the key, the parent, a checksum, a version, JSON or \
+             coerced",
+        ));
+    }
+    if let Some(scaled) = parsed_fields
+        .iter()
+        .find(|pf| pf.attrs.decimal_scale.is_some_and(|scale| scale > MAX_DECIMAL_SCALE))
+    {
+        return Err(syn::Error::new_spanned(
+            scaled.name,
+            format!("The scale of a decimal is at most {}", MAX_DECIMAL_SCALE),
+        ));
+    }
+    if let Some(aliased) = parsed_fields.iter().find(|pf| {
+        !pf.attrs.alias.is_empty() && (std::ptr::eq(key_field, **pf) || pf.attrs.parent)
+    }) {
+        return Err(syn::Error::new_spanned(
+            aliased.name,
+            "The key and the parent are not properties, they cannot have aliases",
+        ));
     }
+    if let Some(described) = parsed_fields
+        .iter()
+        .find(|pf| pf.attrs.description.is_some() && pf.attrs.parent)
+    {
+        return Err(syn::Error::new_spanned(
+            described.name,
+            "The parent is not a property, it cannot have a description",
+        ));
+    }
+    if let Some(strategy_field) = parsed_fields
+        .iter()
+        .find(|pf| pf.attrs.key_strategy.is_some() && !std::ptr::eq(key_field, **pf))
+    {
+        return Err(syn::Error::new_spanned(
+            strategy_field.name,
+            "Only the key field can have a key_strategy",
+        ));
+    }
+    let key_type_path = key_field.type_path()?;
 
     let key_field_name: &Ident = key_field.name;
     let entity_key_new: proc_macro2::TokenStream =
-        if is_cow_static_str_type(key_field.type_path()) || is_string_type(key_field.type_path()) {
+        if is_cow_static_str_type(key_type_path) || is_string_type(key_type_path) {
             if key_field.is_nullable() {
                 quote! {
                     match &self.#key_field_name {
@@ -443,7 +1544,7 @@ pub fn derive_entail(input: TokenStream) -> TokenStream {
                     entail::ds::Key::new(#kind_str).with_name(self.#key_field_name.clone())
                 }
             }
-        } else if key_field.type_path().is_ident("i64") {
+        } else if key_type_path.is_ident("i64") {
             if key_field.is_nullable() {
                 quote! {
                     match &self.#key_field_name {
@@ -456,7 +1557,34 @@ pub fn derive_entail(input: TokenStream) -> TokenStream {
                     entail::ds::Key::new(#kind_str).with_id(self.#key_field_name)
                 }
             }
-        } else if is_key_type(key_field.type_path()) {
+        } else if is_uuid_type(key_type_path) {
+            // the Display implementation of Uuid is the hyphenated form
+            if key_field.is_nullable() {
+                quote! {
+                    match &self.#key_field_name {
+                        None => entail::ds::Key::new(#kind_str),
+                        Some(uuid) => entail::ds::Key::new(#kind_str).with_name(uuid.to_string()),
+                    }
+                }
+            } else {
+                quote! {
+                    entail::ds::Key::new(#kind_str).with_name(self.#key_field_name.to_string())
+                }
+            }
+        } else if is_non_zero_i64_type(key_type_path) {
+            if key_field.is_nullable() {
+                quote! {
+                    match &self.#key_field_name {
+                        None => entail::ds::Key::new(#kind_str),
+                        Some(id) => entail::ds::Key::new(#kind_str).with_id(id.get()),
+                    }
+                }
+            } else {
+                quote! {
+                    entail::ds::Key::new(#kind_str).with_id(self.#key_field_name.get())
+                }
+            }
+        } else if is_key_type(key_type_path) {
             if key_field.is_nullable() {
                 quote! {
                     match &self.#key_field_name {
@@ -470,194 +1598,353 @@ pub fn derive_entail(input: TokenStream) -> TokenStream {
                 }
             }
         } else {
-            panic!("Invalid key type at {:?}", &key_field.type_path().span());
+            return Err(invalid_key_type(key_field));
         };
-
-    let set_properties: Vec<proc_macro2::TokenStream> = parsed_fields.iter().filter_map(|double_ref_field| {
-        let f: &ParsedField = *double_ref_field;
-        if std::ptr::eq(key_field, f) {
-            // the key is handled separately
-            return None;
-        }
-        let name: &proc_macro2::Ident = f.name;
-        let property_name_lit: syn::LitStr = f.create_property_name_lit();
-        let nullable: bool = f.is_nullable();
-        let path: &syn::Path = f.type_path();
-        let array: bool = !path.is_ident("u8") && f.is_array();
-
-        let index_values = !f.attrs.text && (!f.attrs.unindexed || f.attrs.indexed);
-        let index_nulls = !f.attrs.unindexed_nulls && !f.attrs.unindexed;
-
-        macro_rules! gen_setter {
-                ($ds_value:ident, $conversion:tt, $meaning:tt) => {
-                    if nullable {
-                        Some(quote! {
-                            e.set_advanced(#property_name_lit, match &self.#name {
-                                Some(val) => entail::ds::Value::$ds_value($conversion),
-                                None => entail::ds::Value::null(),
-                            }, #index_values, #index_nulls, $meaning);
-                        })
-                    } else if array {
-                        Some(quote! {
-                            e.set_advanced(#property_name_lit, entail::ds::Value::array(self.#name.iter()
-                                .map(|val| entail::ds::Value::$ds_value($conversion))
-                                .collect()), #index_values, #index_nulls, $meaning);
-                        })
-                    } else {
-                        Some(quote! {{
-                            let val = &self.#name;
-                            e.set_advanced(#property_name_lit,
-                                entail::ds::Value::$ds_value($conversion),
-                                #index_values, #index_nulls, $meaning);
-                        }})
-                    }
-                }
-        }
-
-        // blob implementation is very basic, only Vec<u8>
-        if f.is_array() && path.is_ident("u8") {
-            gen_setter!(blob, (val.clone()), None)
-        } else if is_string_type(path) {
-            let meaning = f.meaning_as_string();
-            gen_setter!(unicode_string, (val.clone()), (#meaning))
-        } else if is_cow_static_str_type(path) {
-            let meaning = f.meaning_as_string();
-            gen_setter!(unicode_string, val, (#meaning))
-        } else if is_integer_type(path) {
-            gen_setter!(integer, (*val as i64), None)
-        } else if path.is_ident("f32") || path.is_ident("f64") {
-            gen_setter!(floating_point, (*val as f64), None)
-        } else if path.is_ident("bool") {
-            gen_setter!(boolean, (*val), None)
-        } else if is_key_type(path) {
-            gen_setter!(key, (val.clone()), None)
+    // the key field is empty if it is None, an empty string or a zero ID
+    let key_is_empty = if key_field.is_nullable() {
+        if is_cow_static_str_type(key_type_path) || is_string_type(key_type_path) {
+            quote! { self.#key_field_name.as_deref().is_none_or(str::is_empty) }
         } else {
-            None
+            quote! { self.#key_field_name.is_none() }
         }
-    }).collect();
-
-    let key_value: proc_macro2::TokenStream =
-        if is_cow_static_str_type(key_field.type_path()) || is_string_type(key_field.type_path()) {
-            let incorrect_key = create_raw_err(
-                format!("Key has no name for entity {}", kind).as_str(),
-                key_field.type_path().span(),
-            );
-            if key_field.is_nullable() {
-                quote! { e.key().name().map(|name| String::from(name).into()) }
-            } else {
-                quote! { String::from(e.key().name().ok_or_else(|| #incorrect_key)?).into() }
+    } else if is_cow_static_str_type(key_type_path) || is_string_type(key_type_path) {
+        quote! { self.#key_field_name.is_empty() }
+    } else if key_type_path.is_ident("i64") {
+        quote! { self.#key_field_name == 0 }
+    } else {
+        quote! { false }
+    };
+    let key_strategy = key_field.attrs.key_strategy;
+    let entity_key_new = match key_strategy {
+        None => entity_key_new,
+        Some(KeyStrategy::Uuid) => {
+            let is_name = is_cow_static_str_type(key_type_path)
+                || is_string_type(key_type_path)
+                || is_uuid_type(key_type_path) && key_field.is_nullable();
+            if !is_name {
+                return Err(syn::Error::new_spanned(
+                    &key_field.ty_path,
+                    "The uuid key_strategy requires a String or Cow<'static, str> key, or an \
+                     Option of uuid::Uuid",
+                ));
             }
-        } else if key_field.type_path().is_ident("i64") {
-            let incorrect_key = create_raw_err(
-                format!("Key has no id for entity {}", kind).as_str(),
-                key_field.type_path().span(),
-            );
-            if key_field.is_nullable() {
-                quote! { e.key().id() }
-            } else {
-                quote! { e.key().id().ok_or_else(|| #incorrect_key)? }
+            quote! {
+                if #key_is_empty {
+                    entail::ds::Key::new(#kind_str).with_uuid_name()
+                } else {
+                    #entity_key_new
+                }
             }
-        } else if is_key_type(key_field.type_path()) {
-            if key_field.is_nullable() {
-                quote! { Some(e.key().clone()) }
+        }
+        Some(KeyStrategy::Allocate) => {
+            let is_id = key_type_path.is_ident("i64")
+                || is_non_zero_i64_type(key_type_path) && key_field.is_nullable();
+            if !is_id {
+                return Err(syn::Error::new_spanned(
+                    &key_field.ty_path,
+                    "The allocate key_strategy requires an i64 key, or an Option of i64 or \
+                     NonZeroI64",
+                ));
+            }
+            quote! {
+                if #key_is_empty {
+                    entail::ds::Key::new(#kind_str)
+                } else {
+                    #entity_key_new
+                }
+            }
+        }
+    };
+    let property_descriptions: Vec<(syn::LitStr, &str)> = parsed_fields
+        .iter()
+        .filter_map(|f| {
+            let description = f.attrs.description.as_deref()?;
+            let property_name = if std::ptr::eq(key_field, *f) {
+                syn::LitStr::new("__key__", f.name.span())
             } else {
-                quote! { e.key().clone() }
+                f.create_property_name_lit()
+            };
+            Some((property_name, description))
+        })
+        .collect();
+    let describe = describe_model(entail_input.description.as_deref(), &property_descriptions);
+    let key_strategy = match key_strategy {
+        None => quote! { entail::KeyStrategy::Manual },
+        Some(KeyStrategy::Uuid) => quote! { entail::KeyStrategy::Uuid },
+        Some(KeyStrategy::Allocate) => quote! { entail::KeyStrategy::Allocate },
+    };
+    let entity_key_new = match parent_field {
+        None => entity_key_new,
+        Some(parent_field) => {
+            let parent_field_name = parent_field.name;
+            if parent_field.is_nullable() {
+                quote! {
+                    match &self.#parent_field_name {
+                        None => #entity_key_new,
+                        Some(parent) => (#entity_key_new).with_parent(parent.clone()),
+                    }
+                }
+            } else {
+                quote! {
+                    (#entity_key_new).with_parent(self.#parent_field_name.clone())
+                }
             }
+        }
+    };
+    // the whole key path is moved to the namespace, including a parent or a key field that
+    // is an entail::ds::Key
+    let (entity_key_new, namespace) = match entail_input.namespace.as_deref() {
+        None => (entity_key_new, None),
+        Some(namespace) => (
+            quote! { (#entity_key_new).with_namespace(#namespace) },
+            Some(quote! {
+                fn namespace() -> Option<&'static str> {
+                    Some(#namespace)
+                }
+            }),
+        ),
+    };
+
+    let validate = |f: &ParsedField, receiver: proc_macro2::TokenStream| {
+        let property_name = if std::ptr::eq(key_field, f) || f.attrs.parent {
+            syn::LitStr::new("__key__", f.name.span())
         } else {
-            panic!("Invalid key type at {:?}", &key_field.type_path().span());
+            f.create_property_name_lit()
         };
-    let key_initializer = quote! { #key_field_name: #key_value };
+        let field_name = f.name;
+        f.validate(quote! { &#receiver.#field_name }, property_name, &kind_str)
+    };
+    let property_names: Vec<syn::LitStr> = parsed_fields
+        .iter()
+        .filter(|f| !std::ptr::eq(key_field, **f) && !f.attrs.parent)
+        .map(|f| f.create_property_name_lit())
+        .collect();
+    if let Some(f) = parsed_fields
+        .iter()
+        .find(|f| f.attrs.sensitive && (std::ptr::eq(key_field, **f) || f.attrs.parent))
+    {
+        return Err(syn::Error::new_spanned(
+            f.name,
+            "The key and the parent are not properties, they cannot be masked",
+        ));
+    }
+    let sensitive_names: Vec<syn::LitStr> = parsed_fields
+        .iter()
+        .filter(|f| f.attrs.sensitive)
+        .flat_map(|f| {
+            std::iter::once(&f.property_name)
+                .chain(&f.attrs.alias)
+                .map(|name| syn::LitStr::new(name, f.name.span()))
+        })
+        .collect();
+    let write_validations: Vec<proc_macro2::TokenStream> = parsed_fields
+        .iter()
+        .filter_map(|f| validate(f, quote! { self }))
+        .collect();
+    let read_validations: Vec<proc_macro2::TokenStream> = parsed_fields
+        .iter()
+        .filter(|f| f.attrs.validate_on_read)
+        .filter_map(|f| validate(f, quote! { model }))
+        .collect();
 
-    let initializers: Vec<proc_macro2::TokenStream> = all_fields.iter().filter_map(|pair| {
-        if let Some(f) = pair.parsed_field.as_ref() {
-            if std::ptr::eq(key_field, f) {
-                // the key is handled separately
+    let set_properties: Vec<proc_macro2::TokenStream> = parsed_fields
+        .iter()
+        .filter_map(|double_ref_field| {
+            let f: &ParsedField = *double_ref_field;
+            if std::ptr::eq(key_field, f) || f.attrs.checksum || f.attrs.parent || f.attrs.version
+            {
+                // the key, the parent, the checksum and the version are handled separately
                 return None;
             }
-            let name: &Ident = f.name;
-            let property_name_lit: syn::LitStr = f.create_property_name_lit();
-            let nullable: bool = f.is_nullable();
-            let path: &syn::Path = f.type_path();
-            let array: bool = !path.is_ident("u8") && f.is_array();
-            let model_name = &raw_name;
-
-            macro_rules! gen_initializer {
-                    ($ds_value:ident, $conversion:tt) => {
-                        if nullable {
-                            let err = create_err(format!("Expected null or a value of {} in {}.{}", stringify!($ds_value), model_name, f.property_name).as_str(), name.span());
-                            quote! {
-                                match e.get_value(#property_name_lit).unwrap_or(&null_value) {
-                                    entail::ds::Value::Null => None,
-                                    entail::ds::Value::$ds_value(val) => Some($conversion),
-                                    _ => return #err,
-                                }
-                            }
-                        } else if array {
-                            let err = create_err(format!("Expected null, an array or single value of {} in {}.{}", stringify!($ds_value), model_name, f.property_name).as_str(), name.span());
-                            quote! {
-                                match e.get_value(#property_name_lit).unwrap_or(&null_value) {
-                                    entail::ds::Value::Null => vec![],
-                                    entail::ds::Value::$ds_value(val) => vec![$conversion],
-                                    entail::ds::Value::Array(arr) => {
-                                        arr.iter().try_fold(Vec::<#path>::new(), |mut acc, item| match &item {
-                                            entail::ds::Value::$ds_value(val) => {
-                                                acc.push($conversion);
-                                                Ok(acc)
-                                            },
-                                            _ => #err
-                                        })?
-                                    },
-                                    _ => return #err
-                                }
-                            }
-                        } else {
-                            let err = create_err(format!("Expected a value of {} in {}.{}", stringify!($ds_value), model_name, f.property_name).as_str(), name.span());
-                            quote! {
-                                match e.get_value(#property_name_lit).unwrap_or(&null_value) {
-                                    entail::ds::Value::$ds_value(val) => $conversion,
-                                    _ => return #err,
-                                }
-                            }
-                        }
-                    }
-            }
-            let initializer =
-                if f.is_array() && path.is_ident("u8") {
-                    gen_initializer!(Blob, (val.clone()))
-                } else if is_string_type(path) {
-                    gen_initializer!(UnicodeString, (String::from(val.as_ref())))
-                } else if is_cow_static_str_type(path) {
-                    gen_initializer!(UnicodeString, val)
-                } else if is_integer_type(path) {
-                    gen_initializer!(Integer, (*val as #path))
-                } else if path.is_ident("f32") || path.is_ident("f64") {
-                    gen_initializer!(FloatingPoint, (*val as #path))
-                } else if path.is_ident("bool") {
-                    gen_initializer!(Boolean, (*val))
-                } else if is_key_type(path) {
-                    gen_initializer!(Key, (val.clone()))
-                } else {
-                    panic!("Unexpected type: {:?} array({}) nullable({})", path, f.is_array(), f.is_nullable());
-                };
+            let name: &proc_macro2::Ident = f.name;
+            Some(f.set_property(quote! { &self.#name }, entail_input.auto_unindex))
+        })
+        .collect();
 
-            Some(quote! { #name: #initializer, })
-        } else {
-            let field: &Field = pair.field;
-            let name: &Ident = field.ident.as_ref().unwrap();
-            Some(quote! { #name: ::std::default::Default::default(), })
+    let key_value = read_key(key_field, kind, quote! { #kind_str })?;
+    let key_initializer = quote! { #key_field_name: #key_value };
+    let (parent_initializer, assign_parent) = match parent_field {
+        None => (quote! {}, quote! {}),
+        Some(parent_field) => {
+            let parent_field_name = parent_field.name;
+            let parent_value = read_parent(parent_field, kind, quote! { #kind_str });
+            (
+                quote! { #parent_field_name: #parent_value, },
+                quote! { self.#parent_field_name = #parent_value; },
+            )
         }
-    }).collect();
+    };
+
+    let initializers: Vec<proc_macro2::TokenStream> = all_fields
+        .iter()
+        .filter_map(|pair| {
+            if let Some(f) = pair.parsed_field.as_ref() {
+                if std::ptr::eq(key_field, f) || f.attrs.parent {
+                    // the key and the parent are handled separately
+                    return None;
+                }
+                let name: &Ident = f.name;
+                let property_name_lit: syn::LitStr = f.create_property_name_lit();
+                let context = format!(" in {}.{}", raw_name, f.property_name);
+                let initializer =
+                    f.read_property(quote! { #property_name_lit }, quote! { #kind_str }, &context);
+                Some(quote! { #name: #initializer, })
+            } else {
+                let field: &Field = pair.field;
+                let name: &Ident = field.ident.as_ref().unwrap();
+                Some(quote! { #name: ::std::default::Default::default(), })
+            }
+        })
+        .collect();
+    let (checksum_property, set_checksum) = match checksum_field {
+        Some(f) => {
+            let property_name_lit = f.create_property_name_lit();
+            (
+                quote! { Some(#property_name_lit) },
+                quote! {
+                    let checksum = e.checksum(#property_name_lit);
+                    e.set_unindexed(#property_name_lit, entail::ds::Value::integer(checksum));
+                },
+            )
+        }
+        None => (quote! { None }, quote! {}),
+    };
+    // the version is set after the checksum, so that it doesn't affect it
+    let (version_property, set_version) = match version_field {
+        Some(f) => {
+            let name = f.name;
+            let property_name_lit = f.create_property_name_lit();
+            (
+                quote! { Some(#property_name_lit) },
+                quote! {
+                    e.set_unindexed(#property_name_lit, entail::ds::Value::integer(self.#name + 1));
+                },
+            )
+        }
+        None => (quote! { None }, quote! {}),
+    };
+    let soft_delete_property = match soft_delete_field {
+        Some(f) => {
+            let property_name_lit = f.create_property_name_lit();
+            quote! { Some(#property_name_lit) }
+        }
+        None => quote! { None },
+    };
     let (impl_generics, type_generics, where_clause) = input.generics.split_for_impl();
     let adapter_name = format_ident!("_{}_ADAPTER", name.to_string().to_case(Case::Constant));
     let mismatch_template = quote::ToTokens::to_token_stream(&format!(
         "Expected an Entity with the kind {}, but got {{}}",
         kind
     ));
+    let vis = &input.vis;
+    let property_constants: Vec<proc_macro2::TokenStream> = parsed_fields
+        .iter()
+        .filter(|f| !f.attrs.parent)
+        .filter_map(|f| {
+            let const_name = f.name.to_string().to_case(Case::Constant);
+            // the constants of EntityModel would be shadowed by the inherent ones
+            if ENTITY_MODEL_CONSTANTS.contains(&const_name.as_str()) {
+                return None;
+            }
+            let const_ident = format_ident!("{}", const_name, span = f.name.span());
+            let property_name = if std::ptr::eq(key_field, *f) {
+                syn::LitStr::new("__key__", f.name.span())
+            } else {
+                f.create_property_name_lit()
+            };
+            let doc = format!("The name of the Datastore property of `{}`.", f.name);
+            Some(quote! {
+                #[doc = #doc]
+                #vis const #const_ident: &'static str = #property_name;
+            })
+        })
+        .collect();
+    // the typed properties can't name a generic model without its parameters
+    let typed_query = if input.generics.params.is_empty() {
+        let fields_name = format_ident!("{}Fields", name);
+        let mut typed_properties: Vec<proc_macro2::TokenStream> = Vec::new();
+        // decimals are not compared by their numeric value: scaled ones are not compared as
+        // their IntoValue representation, and strings are compared character by character
+        for f in parsed_fields
+            .iter()
+            .filter(|f| !f.attrs.parent && !f.is_decimal())
+        {
+            let const_ident = format_ident!(
+                "{}",
+                f.name.to_string().to_case(Case::Constant),
+                span = f.name.span()
+            );
+            let (property_name, value_type) = if std::ptr::eq(key_field, *f) {
+                (
+                    syn::LitStr::new("__key__", f.name.span()),
+                    quote! { entail::ds::Key },
+                )
+            } else {
+                (f.create_property_name_lit(), f.filter_value_type()?)
+            };
+            let doc = format!("The Datastore property of `{}`.", f.name);
+            typed_properties.push(quote! {
+                #[doc = #doc]
+                #vis const #const_ident: entail::TypedProperty<#name, #value_type> =
+                    entail::TypedProperty::new(#property_name);
+            });
+        }
+        let fields_doc = format!(
+            "The typed properties of [`{}`] for [`{}::query`].",
+            name, name
+        );
+        let patch = derive_patch(name, vis, &all_fields, key_field, entail_input.auto_unindex);
+        quote! {
+            #[doc = #fields_doc]
+            #vis struct #fields_name;
+
+            impl #fields_name {
+                #(#typed_properties)*
+            }
+
+            impl #name {
+                /// Creates a query for the model that only accepts its own properties.
+                #vis fn query() -> entail::TypedQuery<#name> {
+                    entail::TypedQuery::new()
+                }
+            }
+
+            #patch
+        }
+    } else {
+        quote! {}
+    };
+    let active_record = active_record_methods(input);
     let generated = quote! {
         static #adapter_name: entail::EntityAdapter<#name> = entail::EntityAdapter::new(#kind_str);
 
+        impl #impl_generics #name #type_generics #where_clause {
+            #(#property_constants)*
+        }
+
+        #typed_query
+
+        #active_record
+
         impl #impl_generics entail::EntityModel for #name #type_generics #where_clause {
             const KIND: &'static str = #kind_str;
+            const CHECKSUM_PROPERTY: Option<&'static str> = #checksum_property;
+            const VERSION_PROPERTY: Option<&'static str> = #version_property;
+            const KEY_STRATEGY: entail::KeyStrategy = #key_strategy;
+            const PROPERTY_NAMES: &'static [&'static str] = &[#(#property_names),*];
+            const SOFT_DELETE_PROPERTY: Option<&'static str> = #soft_delete_property;
+            const DEFAULT_ORDER: &'static [(&'static str, entail::ds::OrderDirection)] =
+                &[#(#default_order),*];
+
+            #namespace
+
+            fn has_property(name: &str) -> bool {
+                name == "__key__" || Self::PROPERTY_NAMES.contains(&name)
+            }
+
+            fn is_sensitive(name: &str) -> bool {
+                false #(|| name == #sensitive_names)*
+            }
 
             fn from_ds_entity(e: &entail::ds::Entity) -> Result<Self, entail::EntailError> {
                 let null_value = entail::ds::Value::Null;
@@ -668,19 +1955,33 @@ pub fn derive_entail(input: TokenStream) -> TokenStream {
                         ..entail::EntailError::default()
                     });
                 }
-                Ok(Self {
+                let model = Self {
                     #key_initializer,
+                    #parent_initializer
                     #(#initializers)*
-                    ..Self::default()
-                })
+                };
+                #(#read_validations)*
+                Ok(model)
             }
 
             fn to_ds_entity(&self) -> Result<entail::ds::Entity, entail::EntailError> {
+                #(#write_validations)*
                 let mut e = entail::ds::Entity::new(#entity_key_new);
                 #(#set_properties)*
+                #set_checksum
+                #set_version
                 Ok(e)
             }
 
+            fn assign_key(&mut self, key: &entail::ds::Key) -> Result<(), entail::EntailError> {
+                let e = entail::ds::Entity::new(key.clone());
+                self.#key_field_name = #key_value;
+                #assign_parent
+                Ok(())
+            }
+
+            #describe
+
             fn adapter() -> &'static entail::EntityAdapter<Self> {
                 &#adapter_name
             }
@@ -689,5 +1990,451 @@ pub fn derive_entail(input: TokenStream) -> TokenStream {
 
     // println!("{}", generated);
 
-    generated.into()
+    Ok(generated)
+}
+
+/// Generates the `{Model}Patch` struct, with an `Option` of every property of the model.
+fn derive_patch(
+    name: &Ident,
+    vis: &syn::Visibility,
+    all_fields: &[ParsedFieldPair],
+    key_field: &ParsedField,
+    auto_unindex: bool,
+) -> proc_macro2::TokenStream {
+    let patch_name = format_ident!("{}Patch", name);
+    let kind_expr = quote! { <#name as entail::EntityModel>::KIND };
+    let mut fields = Vec::new();
+    let mut validations = Vec::new();
+    let mut setters = Vec::new();
+    let mut names = Vec::new();
+    for pair in all_fields {
+        let Some(f) = &pair.parsed_field else {
+            continue;
+        };
+        if std::ptr::eq(key_field, f) || f.attrs.checksum || f.attrs.parent || f.attrs.version {
+            // these are not properties, or are computed from the whole model
+            continue;
+        }
+        let field_name = f.name;
+        let field_vis = &pair.field.vis;
+        let ty = &f.ty_path;
+        let doc = format!("The new value of `{}`, if it is to be changed.", field_name);
+        fields.push(quote! {
+            #[doc = #doc]
+            #field_vis #field_name: Option<#ty>,
+        });
+        let property_name = f.create_property_name_lit();
+        if let Some(validate) = f.validate(quote! { value }, &property_name, &kind_expr) {
+            validations.push(quote! {
+                if let Some(value) = &self.#field_name {
+                    #validate
+                }
+            });
+        }
+        let set_property = f.set_property(quote! { value }, auto_unindex);
+        setters.push(quote! {
+            if let Some(value) = &self.#field_name {
+                #set_property
+            }
+        });
+        names.push(field_name);
+    }
+    let doc = format!(
+        "A partial update of [`{}`], only the fields that are `Some` are written by \
+         [`entail::ModelPatch::apply_to`].",
+        name
+    );
+    quote! {
+        #[doc = #doc]
+        #[derive(Default)]
+        #vis struct #patch_name {
+            #(#fields)*
+        }
+
+        impl entail::ModelPatch for #patch_name {
+            type Model = #name;
+
+            fn apply_to(&self, e: &mut entail::ds::Entity) -> Result<(), entail::EntailError> {
+                // nothing is written unless all the new values are valid
+                #(#validations)*
+                #(#setters)*
+                Ok(())
+            }
+
+            fn is_empty(&self) -> bool {
+                true #(&& self.#names.is_none())*
+            }
+        }
+    }
+}
+
+/// Generates the `ModelProjection` implementation for a struct marked with
+/// `#[entail(projection_of = "Model")]`. The property names are the constants of the model, so
+/// they follow its renaming, and a field the model doesn't have fails to compile.
+fn derive_projection(
+    input: &DeriveInput,
+    model: &syn::Path,
+    all_fields: &[ParsedFieldPair],
+) -> syn::Result<proc_macro2::TokenStream> {
+    let name = &input.ident;
+    let raw_name = name.to_string();
+    let model_name = type_name(model);
+    let kind_expr = quote! { <#model as entail::EntityModel>::KIND };
+    let parsed_fields: Vec<&ParsedField> = all_fields
+        .iter()
+        .filter_map(|f| f.parsed_field.as_ref())
+        .collect();
+    let key_fields: Vec<&ParsedField> = parsed_fields
+        .iter()
+        .copied()
+        .filter(|pf| pf.attrs.key || !pf.attrs.field && *pf.name == "key")
+        .collect();
+    // the key is always returned by a projection query, but the projection may not need it
+    let key_field: Option<&ParsedField> = match key_fields.as_slice() {
+        [] => None,
+        [key_field] => Some(key_field),
+        [_, second, ..] => {
+            return Err(syn::Error::new_spanned(
+                second.name,
+                "Multiple primary keys found",
+            ));
+        }
+    };
+    let parent_fields: Vec<&&ParsedField> =
+        parsed_fields.iter().filter(|pf| pf.attrs.parent).collect();
+    if let [_, second, ..] = parent_fields.as_slice() {
+        return Err(syn::Error::new_spanned(
+            second.name,
+            "Multiple parent fields found",
+        ));
+    }
+    let parent_field: Option<&ParsedField> = parent_fields.first().map(|pf| **pf);
+    if let Some(parent_field) =
+        parent_field.filter(|f| f.is_array() || !f.type_path().is_ok_and(is_key_type))
+    {
+        return Err(syn::Error::new_spanned(
+            &parent_field.ty_path,
+            "Invalid parent type, expected entail::ds::Key, optionally wrapped in an Option",
+        ));
+    }
+
+    let mut properties = Vec::new();
+    let mut initializers = Vec::new();
+    let mut validations = Vec::new();
+    for pair in all_fields {
+        let Some(f) = &pair.parsed_field else {
+            let field_name = pair.field.ident.as_ref().unwrap();
+            initializers.push(quote! { #field_name: ::std::default::Default::default(), });
+            continue;
+        };
+        if key_field.is_some_and(|key_field| std::ptr::eq(key_field, f)) || f.attrs.parent {
+            // the key and the parent are handled separately
+            continue;
+        }
+        if f.attrs.name.is_some() || !f.attrs.alias.is_empty() {
+            return Err(syn::Error::new_spanned(
+                f.name,
+                "The property name of a projection field is the one of the model field with \
+                 the same name, it cannot be renamed or aliased",
+            ));
+        }
+        if f.attrs.json || f.attrs.checksum {
+            return Err(syn::Error::new_spanned(
+                f.name,
+                "JSON and checksum properties are unindexed, so they cannot be projected",
+            ));
+        }
+        if f.is_array() {
+            return Err(syn::Error::new_spanned(
+                &f.ty_path,
+                "Array fields cannot be projected, a projection query returns a separate \
+                 result for every element of an array",
+            ));
+        }
+        let const_name = f.name.to_string().to_case(Case::Constant);
+        if ENTITY_MODEL_CONSTANTS.contains(&const_name.as_str()) {
+            return Err(syn::Error::new_spanned(
+                f.name,
+                format!("The model has no property constant for `{}`", f.name),
+            ));
+        }
+        let const_ident = format_ident!("{}", const_name, span = f.name.span());
+        let property_name = quote_spanned! {f.name.span()=> <#model>::#const_ident };
+        let context = format!(" in {}.{}", raw_name, f.name);
+        let field_name = f.name;
+        let initializer = f.read_property(property_name.clone(), kind_expr.clone(), &context);
+        initializers.push(quote! { #field_name: #initializer, });
+        if f.attrs.validate_on_read {
+            validations.extend(f.validate(
+                quote! { &model.#field_name },
+                &property_name,
+                &kind_expr,
+            ));
+        }
+        properties.push(property_name);
+    }
+    let key_initializer = match key_field {
+        None => quote! {},
+        Some(key_field) => {
+            let key_field_name = key_field.name;
+            let key_value = read_key(key_field, &model_name, kind_expr.clone())?;
+            quote! { #key_field_name: #key_value, }
+        }
+    };
+    let parent_initializer = match parent_field {
+        None => quote! {},
+        Some(parent_field) => {
+            let parent_field_name = parent_field.name;
+            let parent_value = read_parent(parent_field, &model_name, kind_expr.clone());
+            quote! { #parent_field_name: #parent_value, }
+        }
+    };
+    let (impl_generics, type_generics, where_clause) = input.generics.split_for_impl();
+    Ok(quote! {
+        impl #impl_generics entail::ModelProjection for #name #type_generics #where_clause {
+            type Model = #model;
+
+            const PROPERTIES: &'static [&'static str] = &[#(#properties),*];
+
+            fn from_projected(e: &entail::ds::Entity) -> Result<Self, entail::EntailError> {
+                let null_value = entail::ds::Value::Null;
+                if e.kind() != #kind_expr {
+                    return Err(entail::EntailError {
+                        kind: entail::EntailErrorKind::EntityKindMismatch,
+                        message: format!(
+                            "Expected an Entity with the kind {}, but got {}",
+                            #kind_expr,
+                            e.kind(),
+                        ).into(),
+                        ..entail::EntailError::default()
+                    });
+                }
+                let model = Self {
+                    #key_initializer
+                    #parent_initializer
+                    #(#initializers)*
+                };
+                #(#validations)*
+                Ok(model)
+            }
+        }
+    })
+}
+
+/// Generates the `IntoValue` and `FromValue` implementations for a newtype marked with
+/// `#[entail(transparent)]`, delegating to its only field.
+fn derive_transparent(input: &DeriveInput) -> syn::Result<proc_macro2::TokenStream> {
+    let name = &input.ident;
+    let fields = match &input.data {
+        syn::Data::Struct(data) if data.fields.len() == 1 => &data.fields,
+        _ => {
+            return Err(syn::Error::new_spanned(
+                name,
+                "#[entail(transparent)] requires a struct with exactly one field",
+            ));
+        }
+    };
+    let field = fields.iter().next().unwrap();
+    let ty = &field.ty;
+    let (member, construct) = match &field.ident {
+        Some(ident) => (quote! { #ident }, quote! { |val| Self { #ident: val } }),
+        None => (quote! { 0 }, quote! { Self }),
+    };
+    let (impl_generics, type_generics, where_clause) = input.generics.split_for_impl();
+    Ok(quote_spanned! {ty.span()=>
+        impl #impl_generics entail::IntoValue for #name #type_generics #where_clause {
+            fn to_value(&self) -> entail::ds::Value {
+                <#ty as entail::IntoValue>::to_value(&self.#member)
+            }
+
+            fn try_to_value(&self) -> Result<entail::ds::Value, entail::EntailError> {
+                <#ty as entail::IntoValue>::try_to_value(&self.#member)
+            }
+        }
+
+        impl #impl_generics entail::FromValue for #name #type_generics #where_clause {
+            fn from_value(value: &entail::ds::Value) -> Result<Self, entail::EntailError> {
+                <#ty as entail::FromValue>::from_value(value).map(#construct)
+            }
+
+            fn from_value_lenient(value: &entail::ds::Value) -> Result<Self, entail::EntailError> {
+                <#ty as entail::FromValue>::from_value_lenient(value).map(#construct)
+            }
+        }
+    })
+}
+
+/// Generates the `EntityModel` implementation for an enum whose variants each hold a single
+/// model. The variant is stored in the discriminator property, and `from_ds_entity`
+/// dispatches on it.
+fn derive_entail_enum(
+    input: &DeriveInput,
+    data: &syn::DataEnum,
+    entail_input: &EntailContainerAttribute,
+    kind: &str,
+) -> syn::Result<proc_macro2::TokenStream> {
+    let name = &input.ident;
+    let kind_str = syn::LitStr::new(kind, name.span());
+    let discriminator = syn::LitStr::new(
+        entail_input
+            .discriminator
+            .as_deref()
+            .unwrap_or(DEFAULT_DISCRIMINATOR),
+        name.span(),
+    );
+    check_property_name(&discriminator.value())
+        .map_err(|message| syn::Error::new_spanned(name, message))?;
+
+    let mut to_arms: Vec<proc_macro2::TokenStream> = Vec::new();
+    let mut from_arms: Vec<proc_macro2::TokenStream> = Vec::new();
+    let mut model_types: Vec<&syn::Type> = Vec::new();
+    for variant in data.variants.iter() {
+        let variant_name = &variant.ident;
+        let model_type = match &variant.fields {
+            Fields::Unnamed(fields) if fields.unnamed.len() == 1 => &fields.unnamed[0].ty,
+            _ => {
+                return Err(syn::Error::new_spanned(
+                    variant,
+                    "Entail enum variants must hold exactly one model, e.g. `Variant(Model)`",
+                ));
+            }
+        };
+        let attrs = EntailVariantAttribute::from_variant(variant)?;
+        let variant_value = syn::LitStr::new(
+            &attrs.name.unwrap_or_else(|| variant_name.to_string()),
+            variant_name.span(),
+        );
+        to_arms.push(quote! {
+            Self::#variant_name(model) => {
+                let mut e = <#model_type as entail::EntityModel>::to_ds_entity(model)?;
+                e.set_indexed(#discriminator, entail::ds::Value::unicode_string(#variant_value));
+                Ok(e)
+            }
+        });
+        from_arms.push(quote! {
+            Some(#variant_value) => {
+                <#model_type as entail::EntityModel>::from_ds_entity(e).map(Self::#variant_name)
+            }
+        });
+        model_types.push(model_type);
+    }
+
+    let (impl_generics, type_generics, where_clause) = input.generics.split_for_impl();
+    let adapter_name = format_ident!("_{}_ADAPTER", name.to_string().to_case(Case::Constant));
+    let mismatch_template = quote::ToTokens::to_token_stream(&format!(
+        "Expected an Entity with the kind {}, but got {{}}",
+        kind
+    ));
+    let unknown_template = quote::ToTokens::to_token_stream(&format!(
+        "Unknown {} variant {{:?}} in {}.{}",
+        name,
+        name,
+        discriminator.value()
+    ));
+    let mapping = create_mapping_context(
+        kind,
+        discriminator.value(),
+        &name.to_string(),
+        quote! { e.get_value(#discriminator).map(entail::ds::Value::value_type) },
+    );
+    let describe = describe_model(entail_input.description.as_deref(), &[]);
+    let active_record = active_record_methods(input);
+    let generated = quote! {
+        static #adapter_name: entail::EntityAdapter<#name> = entail::EntityAdapter::new(#kind_str);
+
+        #active_record
+
+        impl #impl_generics entail::EntityModel for #name #type_generics #where_clause {
+            const KIND: &'static str = #kind_str;
+
+            fn has_property(name: &str) -> bool {
+                name == #discriminator
+                    #(|| <#model_types as entail::EntityModel>::has_property(name))*
+            }
+
+            fn is_sensitive(name: &str) -> bool {
+                false #(|| <#model_types as entail::EntityModel>::is_sensitive(name))*
+            }
+
+            #describe
+
+            fn from_ds_entity(e: &entail::ds::Entity) -> Result<Self, entail::EntailError> {
+                if e.kind() != #kind_str {
+                    return Err(entail::EntailError {
+                        kind: entail::EntailErrorKind::EntityKindMismatch,
+                        message: format!(#mismatch_template, e.kind()).into(),
+                        ..entail::EntailError::default()
+                    });
+                }
+                let variant = e.get_value(#discriminator).and_then(entail::ds::Value::string_value);
+                match variant {
+                    #(#from_arms)*
+                    _ => Err(entail::EntailError {
+                        kind: entail::EntailErrorKind::PropertyMappingError,
+                        message: format!(#unknown_template, variant).into(),
+                        mapping: #mapping,
+                        ..entail::EntailError::default()
+                    }),
+                }
+            }
+
+            fn to_ds_entity(&self) -> Result<entail::ds::Entity, entail::EntailError> {
+                match self {
+                    #(#to_arms)*
+                }
+            }
+
+            fn adapter() -> &'static entail::EntityAdapter<Self> {
+                &#adapter_name
+            }
+        }
+    };
+
+    Ok(generated)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_name_checks() {
+        assert!(check_kind_name("Task").is_ok());
+        assert!(check_kind_name("__Stat_Kind__").is_err());
+        assert!(check_kind_name("").is_err());
+        assert!(check_kind_name(" Task").is_err());
+        assert!(check_kind_name(&"é".repeat(751)).is_err());
+        assert!(check_kind_name(&"é".repeat(750)).is_ok());
+
+        assert!(check_property_name("displayName").is_ok());
+        assert!(check_property_name(DEFAULT_DISCRIMINATOR).is_ok());
+        assert!(check_property_name("__key__").is_err());
+        assert!(check_property_name("address.city").is_err());
+        assert!(check_property_name("name\t").is_err());
+    }
+
+    #[test]
+    fn test_serde_attributes() {
+        let field: syn::Field = syn::parse_quote! {
+            #[serde(default = "default_scope", rename(serialize = "s", deserialize = "s"))]
+            #[serde(skip_serializing_if = "Vec::is_empty", with = "scopes")]
+            scopes: Vec<String>
+        };
+        let serde = SerdeAttributes::parse(&field.attrs).unwrap();
+        assert_eq!(serde.rename.as_deref(), Some("s"));
+        assert!(!serde.skip && !serde.flatten);
+
+        let field: syn::Field = syn::parse_quote! {
+            #[serde(rename(serialize = "out"))]
+            scopes: Vec<String>
+        };
+        assert!(SerdeAttributes::parse(&field.attrs).is_err());
+
+        let field: syn::Field = syn::parse_quote! {
+            #[serde(skip)]
+            #[entail]
+            cache: u32
+        };
+        assert!(SerdeAttributes::parse(&field.attrs).unwrap().skip);
+    }
 }